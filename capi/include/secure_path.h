@@ -0,0 +1,45 @@
+/*
+ * C API of the secure-path crate, see capi/src/lib.rs.
+ *
+ * Every string returned by the library is allocated by it and must be released
+ * with secure_path_free(). A root handle opened by secure_path_root_open() must
+ * be released with secure_path_root_close(). Strings passed to the library are
+ * borrowed for the duration of the call only.
+ */
+#ifndef SECURE_PATH_H
+#define SECURE_PATH_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define SECURE_PATH_OK 0
+#define SECURE_PATH_ERR_INVALID -1
+#define SECURE_PATH_ERR_IO -2
+#define SECURE_PATH_ERR_ESCAPE -3
+#define SECURE_PATH_ERR_LOOP -4
+#define SECURE_PATH_ERR_MOUNT -5
+#define SECURE_PATH_ERR_PANIC -6
+#define SECURE_PATH_ERR_DENIED -7
+#define SECURE_PATH_ERR_RACE -8
+#define SECURE_PATH_ERR_DOTFILE -9
+#define SECURE_PATH_ERR_LIMIT -10
+
+typedef struct secure_path_root secure_path_root;
+
+int secure_path_join(const char *rootfs, const char *unsafe_path, char **result);
+
+int secure_path_root_open(const char *rootfs, secure_path_root **root);
+int secure_path_root_join(const secure_path_root *root, const char *unsafe_path,
+                          char **result);
+int secure_path_root_check(const secure_path_root *root, const char *unsafe_path,
+                           char **result);
+void secure_path_root_close(secure_path_root *root);
+
+void secure_path_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
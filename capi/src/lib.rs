@@ -0,0 +1,258 @@
+/*!
+# Secure Path C API
+
+A stable C ABI over the secure-path crate, built as `libsecure_path_capi.so` and
+`libsecure_path_capi.a`. The declarations are in `include/secure_path.h`.
+
+All the functions return `SECURE_PATH_OK` or one of the negative `SECURE_PATH_ERR_*` codes.
+The strings returned through the `result` out parameters are owned by the caller and must
+be released with `secure_path_free()`, the root handles with `secure_path_root_close()`.
+*/
+use std::ffi::{CStr, CString, OsStr};
+use std::os::raw::{c_char, c_int};
+use std::os::unix::ffi::OsStrExt;
+use std::panic::{catch_unwind, UnwindSafe};
+use std::path::Path;
+#[cfg(test)]
+use std::ptr;
+
+use secure_path::error::Error;
+use secure_path::root::Root;
+use secure_path::secure_join::secure_join;
+
+pub const SECURE_PATH_OK: c_int = 0;
+pub const SECURE_PATH_ERR_INVALID: c_int = -1;
+pub const SECURE_PATH_ERR_IO: c_int = -2;
+pub const SECURE_PATH_ERR_ESCAPE: c_int = -3;
+pub const SECURE_PATH_ERR_LOOP: c_int = -4;
+pub const SECURE_PATH_ERR_MOUNT: c_int = -5;
+pub const SECURE_PATH_ERR_PANIC: c_int = -6;
+pub const SECURE_PATH_ERR_DENIED: c_int = -7;
+pub const SECURE_PATH_ERR_RACE: c_int = -8;
+pub const SECURE_PATH_ERR_DOTFILE: c_int = -9;
+pub const SECURE_PATH_ERR_LIMIT: c_int = -10;
+
+// The opaque root handle of the C API.
+#[allow(non_camel_case_types)]
+pub struct secure_path_root(Root);
+
+fn error_code(e: &Error) -> c_int {
+    match e {
+        Error::Io(_) => SECURE_PATH_ERR_IO,
+        Error::InvalidPath(_) => SECURE_PATH_ERR_INVALID,
+        Error::Escape(_) => SECURE_PATH_ERR_ESCAPE,
+        Error::TooManySymlinks(_) => SECURE_PATH_ERR_LOOP,
+        Error::CrossMount(_) => SECURE_PATH_ERR_MOUNT,
+        Error::Denied(_) => SECURE_PATH_ERR_DENIED,
+        Error::Race(_) => SECURE_PATH_ERR_RACE,
+        Error::Dotfile(_) => SECURE_PATH_ERR_DOTFILE,
+        Error::LimitExceeded(..) => SECURE_PATH_ERR_LIMIT,
+    }
+}
+
+// Borrow a path from a C string, which must not be NULL.
+unsafe fn path<'a>(s: *const c_char) -> Option<&'a Path> {
+    if s.is_null() {
+        return None;
+    }
+
+    Some(Path::new(OsStr::from_bytes(CStr::from_ptr(s).to_bytes())))
+}
+
+// Hand the path `p` over to the caller through `result`.
+unsafe fn give(p: &Path, result: *mut *mut c_char) -> c_int {
+    match CString::new(p.as_os_str().as_bytes()) {
+        Ok(s) => {
+            *result = s.into_raw();
+            SECURE_PATH_OK
+        }
+        Err(_) => SECURE_PATH_ERR_INVALID,
+    }
+}
+
+// Run `f`, making sure that no panic unwinds into the C caller.
+fn guard<F: FnOnce() -> c_int + UnwindSafe>(f: F) -> c_int {
+    catch_unwind(f).unwrap_or(SECURE_PATH_ERR_PANIC)
+}
+
+/// Resolve `unsafe_path` inside of `rootfs` like the `secure_join` function of the crate.
+///
+/// # Safety
+///
+/// `rootfs` and `unsafe_path` must be valid NUL terminated strings and `result` must be a
+/// valid pointer, which receives a string to release with `secure_path_free()`.
+#[no_mangle]
+pub unsafe extern "C" fn secure_path_join(
+    rootfs: *const c_char,
+    unsafe_path: *const c_char,
+    result: *mut *mut c_char,
+) -> c_int {
+    guard(|| {
+        let (rootfs, unsafe_path) = match (path(rootfs), path(unsafe_path)) {
+            (Some(r), Some(p)) if !result.is_null() => (r, p),
+            _ => return SECURE_PATH_ERR_INVALID,
+        };
+        let (rootfs, unsafe_path) = match (rootfs.to_str(), unsafe_path.to_str()) {
+            (Some(r), Some(p)) => (r, p),
+            _ => return SECURE_PATH_ERR_INVALID,
+        };
+
+        give(Path::new(&secure_join(rootfs, unsafe_path)), result)
+    })
+}
+
+/// Open the rootfs directory `rootfs`.
+///
+/// # Safety
+///
+/// `rootfs` must be a valid NUL terminated string and `root` a valid pointer, which
+/// receives a handle to release with `secure_path_root_close()`.
+#[no_mangle]
+pub unsafe extern "C" fn secure_path_root_open(
+    rootfs: *const c_char,
+    root: *mut *mut secure_path_root,
+) -> c_int {
+    guard(|| {
+        let rootfs = match path(rootfs) {
+            Some(r) if !root.is_null() => r,
+            _ => return SECURE_PATH_ERR_INVALID,
+        };
+
+        match Root::new(rootfs) {
+            Ok(r) => {
+                *root = Box::into_raw(Box::new(secure_path_root(r)));
+                SECURE_PATH_OK
+            }
+            Err(e) => error_code(&e),
+        }
+    })
+}
+
+unsafe fn root_resolve(
+    root: *const secure_path_root,
+    unsafe_path: *const c_char,
+    result: *mut *mut c_char,
+    strict: bool,
+) -> c_int {
+    guard(|| {
+        let (root, unsafe_path) = match (root.as_ref(), path(unsafe_path)) {
+            (Some(r), Some(p)) if !result.is_null() => (r, p),
+            _ => return SECURE_PATH_ERR_INVALID,
+        };
+
+        let resolved = if strict {
+            root.0.check(unsafe_path)
+        } else {
+            root.0.join(unsafe_path)
+        };
+        match resolved {
+            Ok(p) => give(&p, result),
+            Err(e) => error_code(&e),
+        }
+    })
+}
+
+/// Resolve `unsafe_path` inside of the rootfs, clamping any escape, see `Root::join`.
+///
+/// # Safety
+///
+/// `root` must be a handle returned by `secure_path_root_open()`, `unsafe_path` a valid NUL
+/// terminated string and `result` a valid pointer, which receives a string to release with
+/// `secure_path_free()`.
+#[no_mangle]
+pub unsafe extern "C" fn secure_path_root_join(
+    root: *const secure_path_root,
+    unsafe_path: *const c_char,
+    result: *mut *mut c_char,
+) -> c_int {
+    root_resolve(root, unsafe_path, result, false)
+}
+
+/// Resolve `unsafe_path` inside of the rootfs, failing with `SECURE_PATH_ERR_ESCAPE` instead
+/// of clamping an escape, see `Root::check`.
+///
+/// # Safety
+///
+/// Same as `secure_path_root_join()`.
+#[no_mangle]
+pub unsafe extern "C" fn secure_path_root_check(
+    root: *const secure_path_root,
+    unsafe_path: *const c_char,
+    result: *mut *mut c_char,
+) -> c_int {
+    root_resolve(root, unsafe_path, result, true)
+}
+
+/// Release a root handle, NULL is ignored.
+///
+/// # Safety
+///
+/// `root` must be NULL or a handle returned by `secure_path_root_open()` which wasn't
+/// released yet.
+#[no_mangle]
+pub unsafe extern "C" fn secure_path_root_close(root: *mut secure_path_root) {
+    if !root.is_null() {
+        drop(Box::from_raw(root));
+    }
+}
+
+/// Release a string returned by the library, NULL is ignored.
+///
+/// # Safety
+///
+/// `s` must be NULL or a string returned by the library which wasn't released yet.
+#[no_mangle]
+pub unsafe extern "C" fn secure_path_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    unsafe fn take(s: *mut c_char) -> String {
+        let r = CStr::from_ptr(s).to_str().unwrap().to_string();
+        secure_path_free(s);
+        r
+    }
+
+    #[test]
+    fn test_capi() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let rootfs = rootfs_dir.path().canonicalize().unwrap();
+        let c_rootfs = CString::new(rootfs.to_str().unwrap()).unwrap();
+        let c_path = CString::new("../../a/b").unwrap();
+
+        unsafe {
+            let mut result = ptr::null_mut();
+            let ret = secure_path_join(c_rootfs.as_ptr(), c_path.as_ptr(), &mut result);
+            assert_eq!(ret, SECURE_PATH_OK);
+            assert_eq!(take(result), format!("{}/a/b", rootfs.display()));
+
+            let mut root = ptr::null_mut();
+            let ret = secure_path_root_open(c_rootfs.as_ptr(), &mut root);
+            assert_eq!(ret, SECURE_PATH_OK);
+
+            let ret = secure_path_root_join(root, c_path.as_ptr(), &mut result);
+            assert_eq!(ret, SECURE_PATH_OK);
+            assert_eq!(take(result), format!("{}/a/b", rootfs.display()));
+
+            let ret = secure_path_root_check(root, c_path.as_ptr(), &mut result);
+            assert_eq!(ret, SECURE_PATH_ERR_ESCAPE);
+
+            let ret = secure_path_root_join(root, ptr::null(), &mut result);
+            assert_eq!(ret, SECURE_PATH_ERR_INVALID);
+
+            secure_path_root_close(root);
+            secure_path_root_close(ptr::null_mut());
+            secure_path_free(ptr::null_mut());
+
+            let c_missing = CString::new("/nonexistent/rootfs").unwrap();
+            let ret = secure_path_root_open(c_missing.as_ptr(), &mut root);
+            assert_eq!(ret, SECURE_PATH_ERR_IO);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+/*!
+# Secure Path Python bindings
+
+The `secure_path` Python module, built with `maturin build` in this directory.
+
+```python
+import secure_path
+
+secure_path.secure_join("/home/rootfs", "../../../a/b/c")  # "/home/rootfs/a/b/c"
+
+root = secure_path.Root("/home/rootfs")
+root.join("../../etc/passwd")  # "/home/rootfs/etc/passwd"
+root.check("../../etc/passwd")  # raises secure_path.EscapeError
+```
+
+`secure_path.Error` is the base of the errors raised for rejected paths, I/O errors are
+raised as `OSError`.
+*/
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyOSError};
+use pyo3::prelude::*;
+
+use secure_path::error::Error;
+
+create_exception!(secure_path, PyError, PyException, "A path was rejected.");
+create_exception!(
+    secure_path,
+    EscapeError,
+    PyError,
+    "The path escapes from the rootfs."
+);
+create_exception!(
+    secure_path,
+    InvalidPathError,
+    PyError,
+    "The path can't be handled."
+);
+create_exception!(
+    secure_path,
+    CrossMountError,
+    PyError,
+    "The path crosses into another mount."
+);
+create_exception!(
+    secure_path,
+    DeniedError,
+    PyError,
+    "The path is denied by a policy."
+);
+create_exception!(
+    secure_path,
+    RaceError,
+    PyError,
+    "A component of the path changed during the resolution."
+);
+create_exception!(
+    secure_path,
+    DotfileError,
+    PyError,
+    "A component of the path is a dotfile."
+);
+create_exception!(
+    secure_path,
+    LimitError,
+    PyError,
+    "An extraction limit was exceeded."
+);
+
+fn py_err(e: Error) -> PyErr {
+    let msg = e.to_string();
+    match e {
+        Error::Io(e) => e.into(),
+        Error::InvalidPath(_) => InvalidPathError::new_err(msg),
+        Error::Escape(_) => EscapeError::new_err(msg),
+        Error::TooManySymlinks(_) => PyOSError::new_err(msg),
+        Error::CrossMount(_) => CrossMountError::new_err(msg),
+        Error::Denied(_) => DeniedError::new_err(msg),
+        Error::Race(_) => RaceError::new_err(msg),
+        Error::Dotfile(_) => DotfileError::new_err(msg),
+        Error::LimitExceeded(..) => LimitError::new_err(msg),
+    }
+}
+
+// Resolve `unsafe_path` inside of `rootfs`, see `secure_path::secure_join::secure_join`.
+#[pyfunction]
+fn secure_join(rootfs: &str, unsafe_path: &str) -> String {
+    secure_path::secure_join::secure_join(rootfs, unsafe_path)
+}
+
+// A handle to a rootfs directory, see `secure_path::root::Root`.
+#[pyclass(name = "Root", frozen)]
+struct PyRoot(secure_path::root::Root);
+
+#[pymethods]
+impl PyRoot {
+    #[new]
+    fn new(rootfs: PathBuf) -> PyResult<Self> {
+        secure_path::root::Root::new(rootfs)
+            .map(PyRoot)
+            .map_err(py_err)
+    }
+
+    #[getter]
+    fn path(&self) -> PathBuf {
+        self.0.path().to_path_buf()
+    }
+
+    fn join(&self, unsafe_path: PathBuf) -> PyResult<PathBuf> {
+        self.0.join(unsafe_path).map_err(py_err)
+    }
+
+    fn check(&self, unsafe_path: PathBuf) -> PyResult<PathBuf> {
+        self.0.check(unsafe_path).map_err(py_err)
+    }
+
+    fn mkdir_all(&self, unsafe_path: PathBuf) -> PyResult<PathBuf> {
+        self.0.mkdir_all(unsafe_path).map_err(py_err)
+    }
+
+    fn glob(&self, pattern: &str) -> PyResult<Vec<PathBuf>> {
+        self.0.glob(pattern).map_err(py_err)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Root({:?})", self.0.path())
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "secure_path")]
+fn secure_path_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(secure_join, m)?)?;
+    m.add_class::<PyRoot>()?;
+    m.add("Error", m.py().get_type::<PyError>())?;
+    m.add("EscapeError", m.py().get_type::<EscapeError>())?;
+    m.add("InvalidPathError", m.py().get_type::<InvalidPathError>())?;
+    m.add("CrossMountError", m.py().get_type::<CrossMountError>())?;
+    m.add("DeniedError", m.py().get_type::<DeniedError>())?;
+    m.add("RaceError", m.py().get_type::<RaceError>())?;
+    m.add("DotfileError", m.py().get_type::<DotfileError>())?;
+    m.add("LimitError", m.py().get_type::<LimitError>())?;
+
+    Ok(())
+}
@@ -0,0 +1,169 @@
+// The paths a server exposes under a root, like "/static" and "/media/*.jpg", checked by the
+// resolution itself with `RootOptions::allowlist`. The requested path is matched before the
+// filesystem is looked at, with its "." and ".." dropped lexically, and the path it resolves
+// to is matched again, so a symlink can't lead outside of the allowed paths.
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::glob::fnmatch;
+
+// A compiled allowlist. An entry without wildcards is a prefix covering its whole subtree,
+// matching whole components so "/static" covers "/static/a" but not "/statics". An entry with
+// wildcards is a glob matching whole paths, with the "*", "?" and "[...]" of `Root::glob` in
+// a component and "**" for any number of them.
+#[derive(Debug, Clone, Default)]
+pub struct PathAllowlist {
+    // The components of each entry, a prefix ends with "**".
+    entries: Vec<Vec<Vec<u8>>>,
+}
+
+impl PathAllowlist {
+    // Compile the `entries`, paths inside of the rootfs. An entry with a ".." fails with
+    // `Error::InvalidPath`.
+    pub fn new<I, S>(entries: I) -> Result<PathAllowlist>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut compiled = Vec::new();
+        for entry in entries {
+            let entry = entry.as_ref();
+            let components: Vec<Vec<u8>> = entry
+                .split('/')
+                .filter(|c| !c.is_empty() && *c != ".")
+                .map(|c| c.as_bytes().to_vec())
+                .collect();
+            if components.iter().any(|c| c == b"..") {
+                return Err(Error::InvalidPath(PathBuf::from(entry)));
+            }
+
+            let mut components = components;
+            if !components.iter().any(|c| is_pattern(c)) {
+                components.push(b"**".to_vec());
+            }
+            compiled.push(components);
+        }
+
+        Ok(PathAllowlist { entries: compiled })
+    }
+
+    // Whether the path `path` inside of the rootfs is allowed, its "." and ".." are dropped
+    // lexically with ".." stopping at the top like in a resolution.
+    pub fn allows<P: AsRef<Path>>(&self, path: P) -> bool {
+        let mut names: Vec<&[u8]> = Vec::new();
+        for it in path.as_ref().components() {
+            match it {
+                Component::Normal(c) => names.push(c.as_bytes()),
+                Component::ParentDir => {
+                    names.pop();
+                }
+                _ => continue,
+            }
+        }
+
+        self.entries.iter().any(|e| matches(e, &names))
+    }
+}
+
+fn is_pattern(component: &[u8]) -> bool {
+    component.iter().any(|c| b"*?[".contains(c))
+}
+
+// Match the `names` of a path against the components of an entry.
+fn matches(pattern: &[Vec<u8>], names: &[&[u8]]) -> bool {
+    match pattern.split_first() {
+        None => names.is_empty(),
+        Some((first, rest)) if first == b"**" => {
+            (0..=names.len()).any(|i| matches(rest, &names[i..]))
+        }
+        Some((first, rest)) => match names.split_first() {
+            Some((name, names)) => fnmatch(first, name) && matches(rest, names),
+            None => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            path: &'a str,
+            allowed: bool,
+        }
+
+        let allowlist = PathAllowlist::new(["/static", "media/*.jpg", "/docs/**/*.html"]).unwrap();
+
+        let tests = &[
+            TestData {
+                path: "/static",
+                allowed: true,
+            },
+            TestData {
+                path: "/static/css/site.css",
+                allowed: true,
+            },
+            TestData {
+                path: "static/./js/../app.js",
+                allowed: true,
+            },
+            TestData {
+                path: "/statics/x",
+                allowed: false,
+            },
+            TestData {
+                path: "/static/../etc/passwd",
+                allowed: false,
+            },
+            TestData {
+                path: "/../../static/x",
+                allowed: true,
+            },
+            TestData {
+                path: "/media/a.jpg",
+                allowed: true,
+            },
+            TestData {
+                path: "/media/a.png",
+                allowed: false,
+            },
+            TestData {
+                path: "/media/album/a.jpg",
+                allowed: false,
+            },
+            TestData {
+                path: "/docs/index.html",
+                allowed: true,
+            },
+            TestData {
+                path: "/docs/a/b/c.html",
+                allowed: true,
+            },
+            TestData {
+                path: "/docs/a/b/c.txt",
+                allowed: false,
+            },
+            TestData {
+                path: "/",
+                allowed: false,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, t);
+
+            let result = allowlist.allows(t.path);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            assert!(result == t.allowed, "{}", msg);
+        }
+
+        assert!(PathAllowlist::new(["/"]).unwrap().allows("/etc"));
+        assert!(!PathAllowlist::default().allows("/"));
+        assert!(PathAllowlist::new(["/static/../etc"]).is_err());
+    }
+}
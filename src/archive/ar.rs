@@ -0,0 +1,139 @@
+use std::ffi::OsStr;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use ::ar::{Archive, Entry};
+
+use crate::archive::{ArchiveEntrySanitizer, EntryType, ExtractOptions, Extractor};
+use crate::error::Result;
+use crate::root::Root;
+
+// Extract all the members of an `ar` archive, e.g. the outer layer of a .deb package,
+// inside of the rootfs. Member names are resolved inside of the rootfs.
+pub fn extract_secure<R: Read>(archive: &mut Archive<R>, root: &Root) -> Result<()> {
+    extract_secure_with(archive, root, &ExtractOptions::default())
+}
+
+// Like `extract_secure`, with `options`.
+pub fn extract_secure_with<R: Read>(
+    archive: &mut Archive<R>,
+    root: &Root,
+    options: &ExtractOptions,
+) -> Result<()> {
+    let mut extractor = Extractor::new(root, options);
+    while let Some(entry) = archive.next_entry() {
+        extractor.extract(&mut entry?)?;
+    }
+
+    Ok(())
+}
+
+// The members of an ar archive are all files.
+impl<R: Read> ArchiveEntrySanitizer for Entry<'_, R> {
+    fn entry_path(&self) -> Result<PathBuf> {
+        Ok(PathBuf::from(OsStr::from_bytes(self.header().identifier())))
+    }
+
+    fn link_target(&mut self) -> Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    fn entry_type(&self) -> EntryType {
+        EntryType::File
+    }
+
+    fn mode(&self) -> u32 {
+        self.header().mode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ExtractLimits;
+    use crate::error::{Error, Limit};
+    use ::ar::{Builder, Header};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_extract_secure() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            members: &'a [&'a str],
+            exists: &'a [&'a str],
+        }
+
+        let tests = &[
+            TestData {
+                name: "deb members",
+                members: &["debian-binary", "control.tar.gz", "data.tar.xz"],
+                exists: &["debian-binary", "control.tar.gz", "data.tar.xz"],
+            },
+            TestData {
+                name: "member names escaping the rootfs",
+                members: &["../../x", "a/../../y"],
+                exists: &["x", "y"],
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let rootfs_dir = tempdir().expect("failed to create tmpdir");
+            let root = Root::new(rootfs_dir.path()).unwrap();
+
+            let mut builder = Builder::new(Vec::new());
+            for name in t.members {
+                let mut header = Header::new(name.as_bytes().to_vec(), 4);
+                header.set_mode(0o644);
+                builder.append(&header, &b"data"[..]).unwrap();
+            }
+            let data = builder.into_inner().unwrap();
+            let result = extract_secure(&mut Archive::new(data.as_slice()), &root);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.is_ok(), "{}", msg);
+            for p in t.exists {
+                assert!(
+                    fs::symlink_metadata(root.path().join(p)).is_ok(),
+                    "{}: {} is missing",
+                    msg,
+                    p
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_secure_with() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+
+        let mut builder = Builder::new(Vec::new());
+        for name in ["debian-binary", "control.tar.gz", "data.tar.xz"] {
+            let mut header = Header::new(name.as_bytes().to_vec(), 4);
+            header.set_mode(0o644);
+            builder.append(&header, &b"data"[..]).unwrap();
+        }
+        let data = builder.into_inner().unwrap();
+        let options = ExtractOptions {
+            limits: ExtractLimits {
+                files: Some(2),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = extract_secure_with(&mut Archive::new(data.as_slice()), &root, &options);
+
+        assert!(
+            matches!(result, Err(Error::LimitExceeded(Limit::Files(2), _))),
+            "result: {:?}",
+            result
+        );
+        assert!(!root.path().join("data.tar.xz").exists());
+    }
+}
@@ -0,0 +1,199 @@
+use std::io::Read;
+use std::path::PathBuf;
+
+use ::cpio::NewcReader;
+
+use crate::archive::{
+    read_link_target, ArchiveEntrySanitizer, EntryType, ExtractOptions, Extractor,
+};
+use crate::error::Result;
+use crate::root::Root;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+// Extract a "newc" cpio archive, e.g. an initramfs image, inside of the rootfs.
+//
+// Member names are resolved inside of the rootfs and symlink members must point inside of
+// it. Device, fifo and socket members are skipped, hardlinked members are extracted as
+// separate files.
+pub fn extract_secure<R: Read>(archive: R, root: &Root) -> Result<()> {
+    extract_secure_with(archive, root, &ExtractOptions::default())
+}
+
+// Like `extract_secure`, with `options`.
+pub fn extract_secure_with<R: Read>(
+    archive: R,
+    root: &Root,
+    options: &ExtractOptions,
+) -> Result<()> {
+    let mut extractor = Extractor::new(root, options);
+    let mut archive = archive;
+
+    loop {
+        let mut reader = NewcReader::new(archive)?;
+        if reader.entry().is_trailer() {
+            break;
+        }
+
+        extractor.extract(&mut reader)?;
+        // The data left unread, e.g. of a skipped member, is drained.
+        archive = reader.finish()?;
+    }
+
+    Ok(())
+}
+
+// The target of a cpio symlink is stored as its content.
+impl<R: Read> ArchiveEntrySanitizer for NewcReader<R> {
+    fn entry_path(&self) -> Result<PathBuf> {
+        Ok(PathBuf::from(self.entry().name()))
+    }
+
+    fn link_target(&mut self) -> Result<Option<PathBuf>> {
+        let name = self.entry_path()?;
+
+        read_link_target(&name, self).map(Some)
+    }
+
+    fn entry_type(&self) -> EntryType {
+        match self.entry().mode() & S_IFMT {
+            S_IFDIR => EntryType::Directory,
+            S_IFREG => EntryType::File,
+            S_IFLNK => EntryType::Symlink,
+            _ => EntryType::Other,
+        }
+    }
+
+    fn mode(&self) -> u32 {
+        self.entry().mode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ExtractLimits;
+    use crate::error::{Error, Limit};
+    use ::cpio::newc::{trailer, Builder};
+    use std::fs;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn archive(members: &[(&str, u32, &str)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (name, mode, content) in members {
+            let mut writer = Builder::new(name)
+                .mode(*mode)
+                .write(data, content.len() as u32);
+            writer.write_all(content.as_bytes()).unwrap();
+            data = writer.finish().unwrap();
+        }
+
+        trailer(data).unwrap()
+    }
+
+    #[test]
+    fn test_extract_secure() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            members: &'a [(&'a str, u32, &'a str)],
+            ok: bool,
+            exists: &'a [&'a str],
+        }
+
+        let long = "a/".repeat(2500);
+        let tests = &[
+            TestData {
+                name: "plain members",
+                members: &[
+                    ("bin", S_IFDIR | 0o755, ""),
+                    ("bin/busybox", S_IFREG | 0o755, "data"),
+                    ("bin/sh", S_IFLNK | 0o777, "busybox"),
+                ],
+                ok: true,
+                exists: &["bin/busybox", "bin/sh"],
+            },
+            TestData {
+                name: "member names escaping the rootfs",
+                members: &[
+                    ("../../init", S_IFREG | 0o755, "data"),
+                    ("/etc/passwd", S_IFREG | 0o644, "data"),
+                ],
+                ok: true,
+                exists: &["init", "etc/passwd"],
+            },
+            TestData {
+                name: "symlink beyond the rootfs",
+                members: &[("init", S_IFLNK | 0o777, "../../sbin/init")],
+                ok: false,
+                exists: &[],
+            },
+            TestData {
+                name: "symlink target longer than PATH_MAX",
+                members: &[("init", S_IFLNK | 0o777, long.as_str())],
+                ok: false,
+                exists: &[],
+            },
+            TestData {
+                name: "special members skipped",
+                members: &[
+                    ("dev/null", 0o020000 | 0o666, ""),
+                    ("data", S_IFREG | 0o644, "data"),
+                ],
+                ok: true,
+                exists: &["data"],
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let rootfs_dir = tempdir().expect("failed to create tmpdir");
+            let root = Root::new(rootfs_dir.path()).unwrap();
+
+            let data = archive(t.members);
+            let result = extract_secure(data.as_slice(), &root);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            for p in t.exists {
+                assert!(
+                    fs::symlink_metadata(root.path().join(p)).is_ok(),
+                    "{}: {} is missing",
+                    msg,
+                    p
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_secure_with() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        let data = archive(&[
+            ("a", S_IFREG | 0o644, "data"),
+            ("b", S_IFREG | 0o644, "data"),
+        ]);
+        let options = ExtractOptions {
+            limits: ExtractLimits {
+                total_bytes: Some(6),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = extract_secure_with(data.as_slice(), &root, &options);
+
+        assert!(
+            matches!(result, Err(Error::LimitExceeded(Limit::TotalBytes(6), _))),
+            "result: {:?}",
+            result
+        );
+        assert!(root.path().join("a").is_file() && !root.path().join("b").exists());
+    }
+}
@@ -0,0 +1,845 @@
+// Helpers to extract untrusted archives inside of a rootfs. The archive formats live in
+// their own feature gated modules, this module holds the checks they share.
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, OpenOptionsExt};
+use std::path::{Component, Path, PathBuf};
+
+pub use crate::copy::SymlinkPolicy;
+use crate::error::{Error, Limit, Result};
+use crate::root::Root;
+
+#[cfg(feature = "ar")]
+pub mod ar;
+#[cfg(feature = "cpio")]
+pub mod cpio;
+#[cfg(feature = "tar")]
+pub mod tar;
+#[cfg(feature = "zip")]
+pub mod zip;
+
+// The PATH_MAX of Linux, no symlink target longer than that can be created.
+#[cfg(any(feature = "zip", feature = "cpio"))]
+const PATH_MAX: u64 = 4096;
+
+// Lexically clean an archive entry name into a relative path: the root and prefix
+// components are dropped, "." is skipped and ".." can't climb above the top.
+pub fn sanitize(name: &Path) -> PathBuf {
+    let mut path = PathBuf::new();
+
+    for it in name.components() {
+        match it {
+            Component::Normal(c) => path.push(c),
+            Component::ParentDir => {
+                path.pop();
+            }
+            _ => continue,
+        }
+    }
+
+    path
+}
+
+// The kind of an archive entry, as far as the extraction is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    Directory,
+    File,
+    Symlink,
+    Hardlink,
+    // Devices, fifos and the other special entries, which are skipped.
+    Other,
+}
+
+// An entry of any archive format, read by `extract_entry` to create it inside of the rootfs.
+// Implemented for the entries of the tar, zip, cpio and ar crates, other formats plug into the
+// same checks by implementing it for their own entries. The content of a file entry is read from
+// the entry itself.
+pub trait ArchiveEntrySanitizer: Read {
+    // The name of the entry as stored in the archive, it is sanitized by the extraction.
+    fn entry_path(&self) -> Result<PathBuf>;
+
+    // The target of a symlink or hardlink entry, as stored in the archive.
+    fn link_target(&mut self) -> Result<Option<PathBuf>>;
+
+    fn entry_type(&self) -> EntryType;
+
+    // The permission bits of the entry.
+    fn mode(&self) -> u32 {
+        0o644
+    }
+}
+
+// Options of the extractions.
+#[derive(Debug, Clone)]
+pub struct ExtractOptions {
+    // What to do with the symlink entries. Images often hold absolute symlinks like
+    // "/usr/bin/sh -> /bin/busybox", which `SymlinkPolicy::Rewrite` keeps working inside of
+    // the rootfs instead of failing the extraction.
+    pub symlinks: SymlinkPolicy,
+    // Let the hardlink entries point to the files which were in the rootfs before the
+    // extraction, e.g. in the lower layers of an image. Else they may only point to the
+    // files extracted before them from the same archive.
+    pub hardlink_existing: bool,
+    // Treat a "\" in the entry names and link targets as a separator, for the zip files
+    // written on Windows. Else "a\b" is a single name, which breaks on a Windows target.
+    pub backslash_separator: bool,
+    pub limits: ExtractLimits,
+}
+
+// The limits of an extraction, so that a hostile archive like a decompression bomb can't fill
+// the disk of the host. An entry exceeding one fails with `Error::LimitExceeded`, and a file is
+// written no further than one byte above the limit. The limits left to None aren't checked.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractLimits {
+    // The bytes written to all the files.
+    pub total_bytes: Option<u64>,
+    // The entries created, the directories and links included.
+    pub files: Option<u64>,
+    pub file_size: Option<u64>,
+    // The components of an entry name, once sanitized.
+    pub depth: Option<usize>,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        ExtractOptions {
+            symlinks: SymlinkPolicy::Preserve,
+            hardlink_existing: true,
+            backslash_separator: false,
+            limits: ExtractLimits::default(),
+        }
+    }
+}
+
+// The extraction of the entries of one archive, which remembers the files it created to check
+// the hardlink entries against them, and counts them against `options.limits`.
+#[derive(Debug)]
+pub struct Extractor<'a> {
+    root: &'a Root,
+    options: &'a ExtractOptions,
+    // The host paths of the files and symlinks extracted so far.
+    extracted: HashSet<PathBuf>,
+    entries: u64,
+    bytes: u64,
+}
+
+impl<'a> Extractor<'a> {
+    pub fn new(root: &'a Root, options: &'a ExtractOptions) -> Extractor<'a> {
+        Extractor {
+            root,
+            options,
+            extracted: HashSet::new(),
+            entries: 0,
+            bytes: 0,
+        }
+    }
+
+    // Create the `entry` inside of the rootfs, see `extract_entry_with`.
+    pub fn extract<E: ArchiveEntrySanitizer>(&mut self, entry: &mut E) -> Result<()> {
+        let root = self.root;
+        let name = self.name(entry.entry_path()?);
+        let kind = entry.entry_type();
+        if kind != EntryType::Other {
+            self.check_entry(&name)?;
+        }
+
+        let path = match kind {
+            EntryType::Directory => {
+                root.mkdir_all(sanitize(&name))?;
+                None
+            }
+            EntryType::File => {
+                let mode = entry.mode();
+                let mut reader = (&mut *entry).take(self.bytes_allowed().saturating_add(1));
+                let path = create_file(root, &name, mode, &mut reader)?;
+                if let Err(e) = self.count_bytes(&name, fs::symlink_metadata(&path)?.len()) {
+                    let _ = fs::remove_file(&path);
+                    return Err(e);
+                }
+                Some(path)
+            }
+            EntryType::Symlink => {
+                let target = entry
+                    .link_target()?
+                    .map(|t| self.name(t))
+                    .ok_or_else(|| Error::InvalidPath(name.clone()))?;
+                match self.options.symlinks {
+                    SymlinkPolicy::Skip => None,
+                    SymlinkPolicy::Preserve => Some(create_symlink(root, &name, &target)?),
+                    SymlinkPolicy::Rewrite => Some(create_symlink(
+                        root,
+                        &name,
+                        &rewrite_link_target(&name, &target),
+                    )?),
+                }
+            }
+            EntryType::Hardlink => {
+                let target = entry
+                    .link_target()?
+                    .map(|t| self.name(t))
+                    .ok_or_else(|| Error::InvalidPath(name.clone()))?;
+                let target = self.hardlink_target(&target)?;
+                let path = entry_path(root, &name)?;
+                fs::hard_link(target, &path)?;
+                Some(path)
+            }
+            EntryType::Other => None,
+        };
+
+        if let Some(path) = path {
+            self.extracted.insert(path);
+        }
+
+        Ok(())
+    }
+
+    // Count the entry `name` against the number of entries and the depth of `options.limits`.
+    fn check_entry(&mut self, name: &Path) -> Result<()> {
+        let limits = &self.options.limits;
+        let exceeded = |limit| Err(Error::LimitExceeded(limit, name.to_path_buf()));
+
+        if let Some(depth) = limits.depth {
+            if sanitize(name).components().count() > depth {
+                return exceeded(Limit::Depth(depth));
+            }
+        }
+        if let Some(files) = limits.files {
+            if self.entries >= files {
+                return exceeded(Limit::Files(files));
+            }
+        }
+        self.entries += 1;
+
+        Ok(())
+    }
+
+    // The bytes the next file may have before it exceeds a limit.
+    fn bytes_allowed(&self) -> u64 {
+        let limits = &self.options.limits;
+        let total = limits.total_bytes.map(|t| t.saturating_sub(self.bytes));
+
+        limits
+            .file_size
+            .unwrap_or(u64::MAX)
+            .min(total.unwrap_or(u64::MAX))
+    }
+
+    // Count the `size` bytes written to the file `name` against `options.limits`.
+    fn count_bytes(&mut self, name: &Path, size: u64) -> Result<()> {
+        let limits = &self.options.limits;
+        let exceeded = |limit| Err(Error::LimitExceeded(limit, name.to_path_buf()));
+
+        if let Some(file_size) = limits.file_size {
+            if size > file_size {
+                return exceeded(Limit::FileSize(file_size));
+            }
+        }
+        if let Some(total) = limits.total_bytes {
+            if self.bytes + size > total {
+                return exceeded(Limit::TotalBytes(total));
+            }
+        }
+        self.bytes += size;
+
+        Ok(())
+    }
+
+    // The entry name or link target `name` with its backslashes turned into separators, with
+    // `options.backslash_separator`.
+    fn name(&self, name: PathBuf) -> PathBuf {
+        if !self.options.backslash_separator {
+            return name;
+        }
+
+        let bytes: Vec<u8> = name
+            .as_os_str()
+            .as_bytes()
+            .iter()
+            .map(|&c| if c == b'\\' { b'/' } else { c })
+            .collect();
+
+        PathBuf::from(OsStr::from_bytes(&bytes))
+    }
+
+    // Resolve the `target` of a hardlink entry, a name of the archive like the entry names.
+    // It can't climb above the rootfs and must be a file or a symlink extracted before, or
+    // one already in the rootfs with `options.hardlink_existing`.
+    fn hardlink_target(&self, target: &Path) -> Result<PathBuf> {
+        check_link_target(Path::new(""), target.strip_prefix("/").unwrap_or(target))?;
+        let path = self.root.join_parent(sanitize(target))?;
+
+        if !self.extracted.contains(&path) {
+            let found = match fs::symlink_metadata(&path) {
+                Ok(m) => self.options.hardlink_existing && (m.is_file() || m.is_symlink()),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+                Err(e) => return Err(Error::Io(e)),
+            };
+            if !found {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("hardlink target {} wasn't extracted", target.display()),
+                )));
+            }
+        }
+
+        Ok(path)
+    }
+}
+
+// Create the `entry` inside of the rootfs: its name is sanitized and resolved inside of the
+// rootfs, a symlink must point inside of the rootfs and a hardlink must point to a file inside
+// of the rootfs. Special entries are skipped.
+pub fn extract_entry<E: ArchiveEntrySanitizer>(root: &Root, entry: &mut E) -> Result<()> {
+    extract_entry_with(root, entry, &ExtractOptions::default())
+}
+
+// Like `extract_entry`, with `options`. Use an `Extractor` for the entries of one archive, so
+// that their hardlinks can point to the files extracted before them.
+pub fn extract_entry_with<E: ArchiveEntrySanitizer>(
+    root: &Root,
+    entry: &mut E,
+    options: &ExtractOptions,
+) -> Result<()> {
+    Extractor::new(root, options).extract(entry)
+}
+
+// Create all the `entries` inside of the rootfs with `extract_entry`, stopping at the first
+// error.
+pub fn extract_entries<E, I>(root: &Root, entries: I) -> Result<()>
+where
+    E: ArchiveEntrySanitizer,
+    I: IntoIterator<Item = Result<E>>,
+{
+    extract_entries_with(root, entries, &ExtractOptions::default())
+}
+
+// Like `extract_entries`, with `options`, see `Extractor`.
+pub fn extract_entries_with<E, I>(root: &Root, entries: I, options: &ExtractOptions) -> Result<()>
+where
+    E: ArchiveEntrySanitizer,
+    I: IntoIterator<Item = Result<E>>,
+{
+    let mut extractor = Extractor::new(root, options);
+    for entry in entries {
+        extractor.extract(&mut entry?)?;
+    }
+
+    Ok(())
+}
+
+// Check that the symlink `target` stored at the entry `name` stays inside of the rootfs
+// when it is followed. Absolute targets and targets climbing above the rootfs are rejected.
+pub fn check_link_target(name: &Path, target: &Path) -> Result<()> {
+    if target.has_root() {
+        return Err(Error::Escape(target.to_path_buf()));
+    }
+
+    let mut depth = sanitize(name).components().count().saturating_sub(1);
+    for it in target.components() {
+        match it {
+            Component::Normal(_) => depth += 1,
+            Component::ParentDir if depth == 0 => return Err(Error::Escape(target.to_path_buf())),
+            Component::ParentDir => depth -= 1,
+            _ => continue,
+        }
+    }
+
+    Ok(())
+}
+
+// Read the target of the symlink entry `name` stored as its content, like the zip and cpio
+// formats do. A target longer than PATH_MAX fails with `Error::InvalidPath`, without reading
+// the rest of a hostile entry into memory.
+#[cfg(any(feature = "zip", feature = "cpio"))]
+pub(crate) fn read_link_target<R: Read>(name: &Path, reader: &mut R) -> Result<PathBuf> {
+    let mut target = Vec::new();
+    reader.take(PATH_MAX + 1).read_to_end(&mut target)?;
+    if target.len() as u64 > PATH_MAX {
+        return Err(Error::InvalidPath(name.to_path_buf()));
+    }
+
+    Ok(PathBuf::from(OsStr::from_bytes(&target)))
+}
+
+// Rewrite the symlink `target` stored at the entry `name` into a relative target which
+// stays inside of the rootfs: absolute targets are taken relative to the rootfs, and ".."
+// climbing above the rootfs is clamped.
+pub fn rewrite_link_target(name: &Path, target: &Path) -> PathBuf {
+    let parent = sanitize(name.parent().unwrap_or_else(|| Path::new("")));
+    let target = if target.has_root() {
+        sanitize(target)
+    } else {
+        sanitize(&parent.join(target))
+    };
+
+    let common = parent
+        .components()
+        .zip(target.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut path = PathBuf::new();
+    for _ in common..parent.components().count() {
+        path.push("..");
+    }
+    path.extend(target.components().skip(common));
+
+    if path.as_os_str().is_empty() {
+        path.push(".");
+    }
+
+    path
+}
+
+// Resolve where a non-directory entry `name` is created. The parent directories are resolved
+// and created inside of the rootfs, while the last component is kept as is, so that an
+// existing symlink there is replaced instead of followed.
+pub fn entry_path(root: &Root, name: &Path) -> Result<PathBuf> {
+    let name = sanitize(name);
+    let file_name = name
+        .file_name()
+        .ok_or_else(|| Error::InvalidPath(name.clone()))?;
+    let parent = root.mkdir_all(name.parent().unwrap_or_else(|| Path::new("")))?;
+    let path = parent.join(file_name);
+
+    match fs::symlink_metadata(&path) {
+        Ok(m) if m.is_dir() => Err(Error::Io(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} is a directory", path.display()),
+        ))),
+        Ok(_) => {
+            fs::remove_file(&path)?;
+            Ok(path)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(path),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+// Create the regular file entry `name` with the permission bits `mode` and copy its
+// content from `reader`. Returns the path of the created file.
+pub fn create_file<R: Read>(
+    root: &Root,
+    name: &Path,
+    mode: u32,
+    reader: &mut R,
+) -> Result<PathBuf> {
+    Ok(create_file_open(root, name, mode, reader)?.0)
+}
+
+// Like `create_file`, also returning the file opened for writing, e.g. to change its mode
+// through its fd instead of its path.
+pub(crate) fn create_file_open<R: Read>(
+    root: &Root,
+    name: &Path,
+    mode: u32,
+    reader: &mut R,
+) -> Result<(PathBuf, File)> {
+    let path = entry_path(root, name)?;
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode & 0o777)
+        .open(&path)?;
+    io::copy(reader, &mut file)?;
+
+    Ok((path, file))
+}
+
+// Create the symlink entry `name` pointing to `target`, which must stay inside of the rootfs.
+// Returns the path of the created symlink.
+//
+// Counting the components of `target` isn't enough once the rootfs holds symlinks, e.g. the
+// "d/l -> .." of an archive with "d -> .", so `target` is also resolved with `Root::check`
+// from the directory the symlink is created in.
+pub fn create_symlink(root: &Root, name: &Path, target: &Path) -> Result<PathBuf> {
+    check_link_target(name, target)?;
+    let parent = root.mkdir_all(sanitize(name).parent().unwrap_or_else(|| Path::new("")))?;
+    let parent = parent.strip_prefix(root.path()).unwrap_or(&parent);
+    root.check(Path::new("/").join(parent).join(target))
+        .map_err(|e| match e {
+            Error::Escape(_) => Error::Escape(target.to_path_buf()),
+            e => e,
+        })?;
+    let path = entry_path(root, name)?;
+    symlink(target, &path)?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+    use std::io::Cursor;
+
+    // An entry of a made up archive format.
+    #[derive(Debug)]
+    struct TestEntry<'a> {
+        name: &'a str,
+        kind: EntryType,
+        link: &'a str,
+        data: Cursor<&'a [u8]>,
+    }
+
+    impl Read for TestEntry<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.data.read(buf)
+        }
+    }
+
+    impl ArchiveEntrySanitizer for TestEntry<'_> {
+        fn entry_path(&self) -> Result<PathBuf> {
+            Ok(PathBuf::from(self.name))
+        }
+
+        fn link_target(&mut self) -> Result<Option<PathBuf>> {
+            Ok(Some(PathBuf::from(self.link)).filter(|p| !p.as_os_str().is_empty()))
+        }
+
+        fn entry_type(&self) -> EntryType {
+            self.kind
+        }
+    }
+
+    #[test]
+    fn test_extract_entries() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            entries: &'a [(&'a str, EntryType, &'a str)],
+            ok: bool,
+            exists: &'a [&'a str],
+        }
+
+        let tests = &[
+            TestData {
+                name: "plain entries",
+                entries: &[
+                    ("a", EntryType::Directory, ""),
+                    ("a/b", EntryType::File, ""),
+                    ("a/c", EntryType::Symlink, "b"),
+                    ("a/d", EntryType::Hardlink, "a/b"),
+                    ("a/e", EntryType::Other, ""),
+                ],
+                ok: true,
+                exists: &["a/b", "a/c", "a/d"],
+            },
+            TestData {
+                name: "names escaping the rootfs",
+                entries: &[
+                    ("../../x", EntryType::File, ""),
+                    ("/y/../../z", EntryType::Directory, ""),
+                ],
+                ok: true,
+                exists: &["x", "z"],
+            },
+            TestData {
+                name: "symlink beyond the rootfs",
+                entries: &[("s", EntryType::Symlink, "../etc")],
+                ok: false,
+                exists: &[],
+            },
+            TestData {
+                name: "symlink beyond the rootfs through a symlink parent",
+                entries: &[
+                    ("d", EntryType::Symlink, "."),
+                    ("d/l", EntryType::Symlink, ".."),
+                ],
+                ok: false,
+                exists: &["d"],
+            },
+            TestData {
+                name: "symlink beyond the rootfs through a symlink in its target",
+                entries: &[
+                    ("d", EntryType::Symlink, "."),
+                    ("x", EntryType::Symlink, "d/.."),
+                ],
+                ok: false,
+                exists: &["d"],
+            },
+            TestData {
+                name: "symlink without a target",
+                entries: &[("s", EntryType::Symlink, "")],
+                ok: false,
+                exists: &[],
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let tree = TreeBuilder::new().build().unwrap();
+            let root = tree.root().unwrap();
+            let entries = t.entries.iter().map(|(name, kind, link)| {
+                Ok(TestEntry {
+                    name,
+                    kind: *kind,
+                    link,
+                    data: Cursor::new(b"data"),
+                })
+            });
+            let result = extract_entries(&root, entries);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            for p in t.exists {
+                assert!(
+                    fs::symlink_metadata(tree.path().join(p)).is_ok(),
+                    "{}: {} is missing",
+                    msg,
+                    p
+                );
+            }
+            assert!(!tree.path().join("a/e").exists(), "{}", msg);
+            for (p, _, _) in t.entries {
+                if let Ok(p) = tree.path().join(sanitize(Path::new(p))).canonicalize() {
+                    assert!(p.starts_with(tree.path()), "{}: {:?} escapes", msg, p);
+                }
+            }
+        }
+
+        let tree = TreeBuilder::new().file("f", "data").build().unwrap();
+        let root = tree.root().unwrap();
+        let link = |name| TestEntry {
+            name,
+            kind: EntryType::Hardlink,
+            link: "f",
+            data: Cursor::new(b""),
+        };
+        let options = ExtractOptions {
+            hardlink_existing: false,
+            ..Default::default()
+        };
+        assert!(Extractor::new(&root, &options)
+            .extract(&mut link("h"))
+            .is_err());
+        assert!(extract_entry(&root, &mut link("h")).is_ok());
+    }
+
+    #[test]
+    fn test_extract_limits() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            // files of 4 bytes
+            files: &'a [&'a str],
+            limits: ExtractLimits,
+            limit: Option<Limit>,
+            exists: &'a [&'a str],
+        }
+
+        let tests = &[
+            TestData {
+                name: "within the limits",
+                files: &["a", "b/c"],
+                limits: ExtractLimits {
+                    total_bytes: Some(8),
+                    files: Some(2),
+                    file_size: Some(4),
+                    depth: Some(2),
+                },
+                limit: None,
+                exists: &["a", "b/c"],
+            },
+            TestData {
+                name: "too many files",
+                files: &["a", "b", "c"],
+                limits: ExtractLimits {
+                    files: Some(2),
+                    ..Default::default()
+                },
+                limit: Some(Limit::Files(2)),
+                exists: &["a", "b"],
+            },
+            TestData {
+                name: "file too large",
+                files: &["a"],
+                limits: ExtractLimits {
+                    file_size: Some(3),
+                    ..Default::default()
+                },
+                limit: Some(Limit::FileSize(3)),
+                exists: &[],
+            },
+            TestData {
+                name: "too many bytes",
+                files: &["a", "b"],
+                limits: ExtractLimits {
+                    total_bytes: Some(6),
+                    ..Default::default()
+                },
+                limit: Some(Limit::TotalBytes(6)),
+                exists: &["a"],
+            },
+            TestData {
+                name: "too deep",
+                files: &["a/b", "../a/b/c"],
+                limits: ExtractLimits {
+                    depth: Some(2),
+                    ..Default::default()
+                },
+                limit: Some(Limit::Depth(2)),
+                exists: &["a/b"],
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let tree = TreeBuilder::new().build().unwrap();
+            let root = tree.root().unwrap();
+            let entries = t.files.iter().map(|name| {
+                Ok(TestEntry {
+                    name,
+                    kind: EntryType::File,
+                    link: "",
+                    data: Cursor::new(b"data"),
+                })
+            });
+            let options = ExtractOptions {
+                limits: t.limits.clone(),
+                ..Default::default()
+            };
+            let result = extract_entries_with(&root, entries, &options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.limit {
+                None => assert!(result.is_ok(), "{}", msg),
+                Some(limit) => assert!(
+                    matches!(result, Err(Error::LimitExceeded(l, _)) if l == limit),
+                    "{}",
+                    msg
+                ),
+            }
+            for p in t.files {
+                let p = sanitize(Path::new(p));
+                let exists = t.exists.iter().any(|e| p == Path::new(e));
+                assert!(tree.path().join(&p).is_file() == exists, "{}: {:?}", msg, p);
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_link_target() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            entry: &'a str,
+            target: &'a str,
+            result: bool,
+        }
+
+        let tests = &[
+            TestData {
+                name: "sibling",
+                entry: "a/b",
+                target: "c",
+                result: true,
+            },
+            TestData {
+                name: "up to the top",
+                entry: "a/b",
+                target: "../c",
+                result: true,
+            },
+            TestData {
+                name: "beyond the top",
+                entry: "a/b",
+                target: "../../c",
+                result: false,
+            },
+            TestData {
+                name: "sanitized entry name",
+                entry: "/../../a/b",
+                target: "../c",
+                result: true,
+            },
+            TestData {
+                name: "absolute target",
+                entry: "a",
+                target: "/etc/passwd",
+                result: false,
+            },
+            TestData {
+                name: "down and up",
+                entry: "a",
+                target: "b/../../c",
+                result: false,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = check_link_target(Path::new(t.entry), Path::new(t.target));
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.is_ok() == t.result, "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_rewrite_link_target() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            entry: &'a str,
+            target: &'a str,
+            result: &'a str,
+        }
+
+        let tests = &[
+            TestData {
+                entry: "usr/bin/sh",
+                target: "/bin/busybox",
+                result: "../../bin/busybox",
+            },
+            TestData {
+                entry: "usr/bin/sh",
+                target: "/usr/bin/dash",
+                result: "dash",
+            },
+            TestData {
+                entry: "usr/bin/sh",
+                target: "../../../../etc/passwd",
+                result: "../../etc/passwd",
+            },
+            TestData {
+                entry: "sh",
+                target: "busybox",
+                result: "busybox",
+            },
+            TestData {
+                entry: "a/b",
+                target: "/a",
+                result: ".",
+            },
+            TestData {
+                entry: "a/b",
+                target: "/",
+                result: "..",
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, t);
+
+            let result = rewrite_link_target(Path::new(t.entry), Path::new(t.target));
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result == Path::new(t.result), "{}", msg);
+            assert!(
+                check_link_target(Path::new(t.entry), &result).is_ok(),
+                "{}",
+                msg
+            );
+        }
+    }
+}
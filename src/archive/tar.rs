@@ -0,0 +1,263 @@
+use std::io::Read;
+use std::path::PathBuf;
+
+use ::tar::{Archive, Entry};
+
+use crate::archive::{ArchiveEntrySanitizer, EntryType, ExtractOptions, Extractor};
+use crate::error::Result;
+use crate::root::Root;
+
+// Unpack all the entries of the `archive` inside of the rootfs.
+//
+// Every entry name is resolved inside of the rootfs before anything is written, symlink
+// entries must point inside of the rootfs and hardlink entries must point to a file extracted
+// before them or already in the rootfs. Device, fifo and other special entries are skipped.
+pub fn unpack_secure<R: Read>(archive: &mut Archive<R>, root: &Root) -> Result<()> {
+    unpack_secure_with(archive, root, &ExtractOptions::default())
+}
+
+// Like `unpack_secure`, with `options`, e.g. to rewrite the absolute symlinks of an image to
+// stay inside of the rootfs with `SymlinkPolicy::Rewrite`.
+pub fn unpack_secure_with<R: Read>(
+    archive: &mut Archive<R>,
+    root: &Root,
+    options: &ExtractOptions,
+) -> Result<()> {
+    let mut extractor = Extractor::new(root, options);
+    for entry in archive.entries()? {
+        extractor.extract(&mut entry?)?;
+    }
+
+    Ok(())
+}
+
+impl<R: Read> ArchiveEntrySanitizer for Entry<'_, R> {
+    fn entry_path(&self) -> Result<PathBuf> {
+        Ok(self.path()?.into_owned())
+    }
+
+    fn link_target(&mut self) -> Result<Option<PathBuf>> {
+        Ok(self.link_name()?.map(|p| p.into_owned()))
+    }
+
+    fn entry_type(&self) -> EntryType {
+        match self.header().entry_type() {
+            ::tar::EntryType::Directory => EntryType::Directory,
+            ::tar::EntryType::Regular | ::tar::EntryType::Continuous => EntryType::File,
+            ::tar::EntryType::Symlink => EntryType::Symlink,
+            ::tar::EntryType::Link => EntryType::Hardlink,
+            _ => EntryType::Other,
+        }
+    }
+
+    fn mode(&self) -> u32 {
+        self.header().mode().unwrap_or(0o644)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::SymlinkPolicy;
+    use ::tar::{Builder, EntryType, Header};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn append(builder: &mut Builder<Vec<u8>>, name: &str, kind: EntryType, link: &str) {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(kind);
+        header.set_mode(0o644);
+        header.set_size(if kind == EntryType::Regular { 4 } else { 0 });
+        // set_path() refuses the "..", write the raw name to get a hostile archive
+        let raw = &mut header.as_old_mut().name;
+        raw[..name.len()].copy_from_slice(name.as_bytes());
+        if !link.is_empty() {
+            header.set_link_name(link).unwrap();
+        }
+        header.set_cksum();
+        let data: &[u8] = if kind == EntryType::Regular {
+            b"data"
+        } else {
+            b""
+        };
+        builder.append(&header, data).unwrap();
+    }
+
+    #[test]
+    fn test_unpack_secure() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            entries: &'a [(&'a str, EntryType, &'a str)],
+            ok: bool,
+            exists: &'a [&'a str],
+        }
+
+        let tests = &[
+            TestData {
+                name: "plain entries",
+                entries: &[
+                    ("a/", EntryType::Directory, ""),
+                    ("a/b", EntryType::Regular, ""),
+                    ("a/c", EntryType::Symlink, "b"),
+                    ("a/d", EntryType::Link, "a/b"),
+                ],
+                ok: true,
+                exists: &["a/b", "a/c", "a/d"],
+            },
+            TestData {
+                name: "entry names escaping with ..",
+                entries: &[("../../x", EntryType::Regular, "")],
+                ok: true,
+                exists: &["x"],
+            },
+            TestData {
+                name: "absolute entry names",
+                entries: &[("/y", EntryType::Regular, "")],
+                ok: true,
+                exists: &["y"],
+            },
+            TestData {
+                name: "absolute symlink target",
+                entries: &[("s", EntryType::Symlink, "/etc")],
+                ok: false,
+                exists: &[],
+            },
+            TestData {
+                name: "relative symlink target beyond the rootfs",
+                entries: &[("s", EntryType::Symlink, "../../etc")],
+                ok: false,
+                exists: &[],
+            },
+            TestData {
+                name: "hardlink target beyond the rootfs",
+                entries: &[
+                    ("z", EntryType::Regular, ""),
+                    ("h", EntryType::Link, "../../z"),
+                ],
+                ok: false,
+                exists: &["z"],
+            },
+            TestData {
+                name: "hardlink before its target",
+                entries: &[("h", EntryType::Link, "z"), ("z", EntryType::Regular, "")],
+                ok: false,
+                exists: &[],
+            },
+            TestData {
+                name: "hardlink to a directory",
+                entries: &[
+                    ("a/", EntryType::Directory, ""),
+                    ("h", EntryType::Link, "a"),
+                ],
+                ok: false,
+                exists: &["a"],
+            },
+            TestData {
+                name: "hardlink through a symlink",
+                entries: &[
+                    ("a/b", EntryType::Regular, ""),
+                    ("l", EntryType::Symlink, "a"),
+                    ("h", EntryType::Link, "/l/b"),
+                ],
+                ok: true,
+                exists: &["h"],
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let rootfs_dir = tempdir().expect("failed to create tmpdir");
+            let root = Root::new(rootfs_dir.path()).unwrap();
+
+            let mut builder = Builder::new(Vec::new());
+            for (name, kind, link) in t.entries {
+                append(&mut builder, name, *kind, link);
+            }
+            let data = builder.into_inner().unwrap();
+            let result = unpack_secure(&mut Archive::new(data.as_slice()), &root);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            for p in t.exists {
+                assert!(
+                    fs::symlink_metadata(root.path().join(p)).is_ok(),
+                    "{}: {} is missing",
+                    msg,
+                    p
+                );
+            }
+            assert!(!root.path().parent().unwrap().join("x").exists(), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_unpack_secure_with() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            entry: &'a str,
+            target: &'a str,
+            symlinks: SymlinkPolicy,
+            // the target of the created symlink, if any
+            result: Option<&'a str>,
+        }
+
+        let tests = &[
+            TestData {
+                entry: "usr/bin/sh",
+                target: "/bin/busybox",
+                symlinks: SymlinkPolicy::Rewrite,
+                result: Some("../../bin/busybox"),
+            },
+            TestData {
+                entry: "usr/bin/sh",
+                target: "../../../../etc/passwd",
+                symlinks: SymlinkPolicy::Rewrite,
+                result: Some("../../etc/passwd"),
+            },
+            TestData {
+                entry: "usr/bin/sh",
+                target: "dash",
+                symlinks: SymlinkPolicy::Rewrite,
+                result: Some("dash"),
+            },
+            TestData {
+                entry: "usr/bin/sh",
+                target: "/bin/busybox",
+                symlinks: SymlinkPolicy::Skip,
+                result: None,
+            },
+            TestData {
+                entry: "usr/bin/sh",
+                target: "dash",
+                symlinks: SymlinkPolicy::Preserve,
+                result: Some("dash"),
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, t);
+
+            let rootfs_dir = tempdir().expect("failed to create tmpdir");
+            let root = Root::new(rootfs_dir.path()).unwrap();
+
+            let mut builder = Builder::new(Vec::new());
+            append(&mut builder, t.entry, EntryType::Symlink, t.target);
+            let data = builder.into_inner().unwrap();
+            let options = ExtractOptions {
+                symlinks: t.symlinks,
+                ..Default::default()
+            };
+            let result = unpack_secure_with(&mut Archive::new(data.as_slice()), &root, &options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.is_ok(), "{}", msg);
+            let target = fs::read_link(root.path().join(t.entry)).ok();
+            assert!(target.as_deref() == t.result.map(Path::new), "{}", msg);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::{self, Read, Seek};
+use std::path::PathBuf;
+
+use ::zip::read::ZipFile;
+use ::zip::ZipArchive;
+
+use crate::archive::{
+    read_link_target, ArchiveEntrySanitizer, EntryType, ExtractOptions, Extractor,
+};
+use crate::error::Result;
+use crate::root::Root;
+
+// Extract all the entries of the `zip` archive inside of the rootfs.
+//
+// Entry names are sanitized first: absolute names are made relative and ".." can't climb
+// above the rootfs, so the zip-slip class of attacks lands inside of the rootfs. Missing
+// parent directories are created with `Root::mkdir_all`, and symlink entries must point
+// inside of the rootfs.
+pub fn extract_secure<R: Read + Seek>(zip: &mut ZipArchive<R>, root: &Root) -> Result<()> {
+    extract_secure_with(zip, root, &ExtractOptions::default())
+}
+
+// Like `extract_secure`, with `options`.
+pub fn extract_secure_with<R: Read + Seek>(
+    zip: &mut ZipArchive<R>,
+    root: &Root,
+    options: &ExtractOptions,
+) -> Result<()> {
+    let mut extractor = Extractor::new(root, options);
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i).map_err(io::Error::from)?;
+        extractor.extract(&mut file)?;
+    }
+
+    Ok(())
+}
+
+// The target of a zip symlink is stored as its content.
+impl<R: Read> ArchiveEntrySanitizer for ZipFile<'_, R> {
+    fn entry_path(&self) -> Result<PathBuf> {
+        Ok(PathBuf::from(
+            self.name().map_err(io::Error::from)?.as_ref(),
+        ))
+    }
+
+    fn link_target(&mut self) -> Result<Option<PathBuf>> {
+        let name = self.entry_path()?;
+
+        read_link_target(&name, self).map(Some)
+    }
+
+    fn entry_type(&self) -> EntryType {
+        if self.is_dir() {
+            EntryType::Directory
+        } else if self.is_symlink() {
+            EntryType::Symlink
+        } else {
+            EntryType::File
+        }
+    }
+
+    fn mode(&self) -> u32 {
+        self.unix_mode().unwrap_or(0o644)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::zip::write::{SimpleFileOptions, ZipWriter};
+    use std::fs;
+    use std::io::{Cursor, Write};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_extract_secure() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            files: &'a [&'a str],
+            symlinks: &'a [(&'a str, &'a str)],
+            ok: bool,
+            exists: &'a [&'a str],
+        }
+
+        let long = "a/".repeat(2500);
+        let tests = &[
+            TestData {
+                name: "plain entries",
+                files: &["a/b/c", "d"],
+                symlinks: &[("a/e", "b/c")],
+                ok: true,
+                exists: &["a/b/c", "d", "a/e"],
+            },
+            TestData {
+                name: "zip slip",
+                files: &["../../../x", "a/../../y"],
+                symlinks: &[],
+                ok: true,
+                exists: &["x", "y"],
+            },
+            TestData {
+                name: "absolute names",
+                files: &["/etc/z"],
+                symlinks: &[],
+                ok: true,
+                exists: &["etc/z"],
+            },
+            TestData {
+                name: "symlink beyond the rootfs",
+                files: &[],
+                symlinks: &[("s", "../../etc")],
+                ok: false,
+                exists: &[],
+            },
+            TestData {
+                name: "symlink target longer than PATH_MAX",
+                files: &[],
+                symlinks: &[("s", long.as_str())],
+                ok: false,
+                exists: &[],
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let rootfs_dir = tempdir().expect("failed to create tmpdir");
+            let root = Root::new(rootfs_dir.path()).unwrap();
+
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+            for name in t.files {
+                writer
+                    .start_file(*name, SimpleFileOptions::default())
+                    .unwrap();
+                writer.write_all(b"data").unwrap();
+            }
+            for (name, target) in t.symlinks {
+                writer
+                    .add_symlink(*name, *target, SimpleFileOptions::default())
+                    .unwrap();
+            }
+            let mut zip = ZipArchive::new(writer.finish().unwrap()).unwrap();
+            let result = extract_secure(&mut zip, &root);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            for p in t.exists {
+                assert!(
+                    fs::symlink_metadata(root.path().join(p)).is_ok(),
+                    "{}: {} is missing",
+                    msg,
+                    p
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_secure_with() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            file: &'a str,
+            backslash_separator: bool,
+            exists: &'a str,
+        }
+
+        let tests = &[
+            TestData {
+                file: "a\\b\\c",
+                backslash_separator: true,
+                exists: "a/b/c",
+            },
+            TestData {
+                file: "..\\..\\x",
+                backslash_separator: true,
+                exists: "x",
+            },
+            TestData {
+                file: "\\a/b\\..\\c",
+                backslash_separator: true,
+                exists: "a/c",
+            },
+            TestData {
+                file: "a\\b",
+                backslash_separator: false,
+                exists: "a\\b",
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, t);
+
+            let rootfs_dir = tempdir().expect("failed to create tmpdir");
+            let root = Root::new(rootfs_dir.path()).unwrap();
+
+            let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+            writer
+                .start_file(t.file, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(b"data").unwrap();
+            let mut zip = ZipArchive::new(writer.finish().unwrap()).unwrap();
+            let options = ExtractOptions {
+                backslash_separator: t.backslash_separator,
+                ..Default::default()
+            };
+            let result = extract_secure_with(&mut zip, &root, &options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.is_ok(), "{}", msg);
+            assert!(root.path().join(t.exists).is_file(), "{}", msg);
+        }
+    }
+}
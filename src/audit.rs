@@ -0,0 +1,207 @@
+// An audit stream of a rootfs: every mutating operation of a `Root` and every path a
+// resolution rejects is recorded to the `AuditSink` of `RootOptions::audit_sink`. The records
+// are numbered in order across the process, so a gap in a shipped stream reveals a record
+// which was dropped or removed.
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
+
+use crate::error::Error;
+use crate::root::DeviceType;
+
+// The sequence number of the next record.
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+// What an `AuditRecord` records.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditEvent {
+    // `Root::mkdir_all` created the directory, or found it.
+    Mkdir,
+    Mknod {
+        kind: DeviceType,
+        major: u32,
+        minor: u32,
+    },
+    // The owner was changed to these host ids.
+    Chown {
+        uid: u32,
+        gid: u32,
+    },
+    // The permission bits were changed, see `manifest::apply_manifest`.
+    Chmod {
+        mode: u32,
+    },
+    SetXattr {
+        name: OsString,
+    },
+    // The access or modification times were set, see `Root::set_times`.
+    SetTimes,
+    // A temporary directory or file was created.
+    CreateTemp,
+    // The content of the file was written.
+    Write,
+    // The entry was moved from this host path, see `copy::move_entry`.
+    Rename {
+        from: PathBuf,
+    },
+    // The path was rejected for this reason, e.g. "path escapes from the rootfs: ../x".
+    Rejected(String),
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuditEvent::Mkdir => write!(f, "mkdir"),
+            AuditEvent::Mknod { kind, major, minor } => {
+                write!(f, "mknod {:?} {}:{}", kind, major, minor)
+            }
+            AuditEvent::Chown { uid, gid } => write!(f, "chown {}:{}", uid, gid),
+            AuditEvent::Chmod { mode } => write!(f, "chmod {:o}", mode),
+            AuditEvent::SetXattr { name } => write!(f, "setxattr {:?}", name),
+            AuditEvent::SetTimes => write!(f, "set times"),
+            AuditEvent::CreateTemp => write!(f, "create temporary"),
+            AuditEvent::Write => write!(f, "write"),
+            AuditEvent::Rename { from } => write!(f, "rename from {:?}", from),
+            AuditEvent::Rejected(reason) => write!(f, "rejected: {}", reason),
+        }
+    }
+}
+
+// One record of the audit stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    // The sequence number of the record in this process, from 0.
+    pub seq: u64,
+    // The rootfs of the `Root`.
+    pub rootfs: PathBuf,
+    // The resolved path of a mutating operation, or the untrusted path rejected.
+    pub path: PathBuf,
+    pub event: AuditEvent,
+}
+
+impl AuditRecord {
+    pub(crate) fn new(rootfs: &Path, path: &Path, event: AuditEvent) -> Self {
+        AuditRecord {
+            seq: SEQ.fetch_add(1, Ordering::Relaxed),
+            rootfs: rootfs.to_path_buf(),
+            path: path.to_path_buf(),
+            event,
+        }
+    }
+}
+
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#{} {} {:?} in {:?}",
+            self.seq, self.event, self.path, self.rootfs
+        )
+    }
+}
+
+// The destination of the audit records, called inline by the operations.
+pub trait AuditSink: fmt::Debug + Send + Sync {
+    fn record(&self, record: &AuditRecord);
+}
+
+// Whether the error `e` is a rejection of the path, rather than a failure of the filesystem.
+pub(crate) fn is_rejection(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Escape(_)
+            | Error::TooManySymlinks(_)
+            | Error::CrossMount(_)
+            | Error::Denied(_)
+            | Error::Dotfile(_)
+    )
+}
+
+// A sink dropping every record.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopSink;
+
+impl AuditSink for NoopSink {
+    fn record(&self, _record: &AuditRecord) {}
+}
+
+// A sink emitting the records as info-level `log` records, with the `log` feature.
+#[cfg(feature = "log")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogSink;
+
+#[cfg(feature = "log")]
+impl AuditSink for LogSink {
+    fn record(&self, record: &AuditRecord) {
+        log::info!("audit {}", record);
+    }
+}
+
+// A sink sending the records to a channel, for a thread shipping them elsewhere. Records are
+// dropped once the receiver is.
+#[derive(Debug)]
+pub struct ChannelSink(Mutex<Sender<AuditRecord>>);
+
+impl ChannelSink {
+    pub fn new() -> (ChannelSink, Receiver<AuditRecord>) {
+        let (sender, receiver) = mpsc::channel();
+
+        (ChannelSink(Mutex::new(sender)), receiver)
+    }
+}
+
+impl AuditSink for ChannelSink {
+    fn record(&self, record: &AuditRecord) {
+        if let Ok(sender) = self.0.lock() {
+            let _ = sender.send(record.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::root::RootOptions;
+    use crate::test_util::TreeBuilder;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_audit_sink() {
+        let (sink, records) = ChannelSink::new();
+        let tree = TreeBuilder::new().symlink("up", "../..").build().unwrap();
+        let options = RootOptions {
+            audit_sink: Some(Arc::new(sink)),
+            ..Default::default()
+        };
+        let root = crate::root::Root::with_options(tree.path(), options).unwrap();
+
+        let dir = root.mkdir_all("a/b").unwrap();
+        assert!(root.check("up/x").is_err());
+        assert!(root.join("up/x").is_ok());
+        assert!(root.mknod("a/b/c", DeviceType::Block, 8, 0).is_err());
+        let _ = root.chown("a/b", 0, 0);
+        drop(root.tempdir_in("a").unwrap());
+
+        let records: Vec<AuditRecord> = records.try_iter().collect();
+        let events: Vec<String> = records.iter().map(|r| r.event.to_string()).collect();
+        assert_eq!(records[0].path, dir);
+        assert_eq!(records[1].path, Path::new("up/x"));
+        assert!(records.iter().all(|r| r.rootfs == tree.path()));
+        assert!(records.windows(2).all(|w| w[0].seq < w[1].seq));
+        assert_eq!(
+            events[..3],
+            [
+                "mkdir".to_string(),
+                "rejected: path escapes from the rootfs: up/x".to_string(),
+                "rejected: path denied by policy: a/b/c".to_string(),
+            ]
+        );
+        // chown may not be permitted
+        assert_eq!(events.last().unwrap(), "create temporary");
+
+        NoopSink.record(&records[0]);
+    }
+}
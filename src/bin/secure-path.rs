@@ -0,0 +1,676 @@
+// The `secure-path` command, with the `cli` feature: the resolution of the library for the
+// shell scripts and CI jobs handling the paths of a rootfs.
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+use flate2::read::GzDecoder;
+use ruzstd::decoding::StreamingDecoder;
+use secure_path::archive::tar::unpack_secure;
+use secure_path::root::Root;
+use secure_path::trace::Step;
+use serde_json::{json, Value};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "secure-path",
+    version,
+    about = "Resolve untrusted paths inside of a rootfs"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    #[arg(
+        long,
+        global = true,
+        help = "Print the result as a JSON object instead of text"
+    )]
+    json: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[command(about = "Print the host path PATH resolves to inside of ROOT")]
+    Join {
+        root: PathBuf,
+        path: PathBuf,
+        #[arg(
+            long,
+            help = "Reject the paths escaping from ROOT instead of clamping them"
+        )]
+        strict: bool,
+    },
+    #[command(
+        about = "Check that the existing PATH stays inside of ROOT, listing the \
+                       symlinks it traverses and the components untrusted users can write to"
+    )]
+    Check { root: PathBuf, path: PathBuf },
+    #[command(about = "Unpack the tar ARCHIVE, compressed with gzip or zstd or not, into ROOT")]
+    Extract { root: PathBuf, archive: PathBuf },
+    #[command(about = "List the subtree of SUBDIR, or of ROOT, without following its symlinks")]
+    Walk {
+        root: PathBuf,
+        subdir: Option<PathBuf>,
+    },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(&cli.command, cli.json, &mut io::stdout().lock()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("secure-path: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// The result of a subcommand, as text and as JSON. A failure with a result, like a rejected
+// path, is also in both.
+#[derive(Debug)]
+struct Report {
+    text: String,
+    json: Value,
+    failure: Option<String>,
+}
+
+impl Report {
+    fn new(text: String, json: Value) -> Self {
+        Report {
+            text,
+            json,
+            failure: None,
+        }
+    }
+
+    // A failure reported as `{"error": ...}` in JSON along with `json`.
+    fn failed(text: String, mut json: Value, failure: String) -> Self {
+        json["error"] = Value::from(failure.clone());
+        Report {
+            text,
+            json,
+            failure: Some(failure),
+        }
+    }
+}
+
+// Run `command` writing its output to `out`, as JSON with `json`, returns the message of a
+// failure. A failure without a result is written as `{"error": ...}` in JSON.
+fn run(command: &Command, json: bool, out: &mut dyn Write) -> Result<(), String> {
+    let report = match command {
+        Command::Join { root, path, strict } => join(root, path, *strict),
+        Command::Check { root, path } => check(root, path),
+        Command::Extract { root, archive } => extract(root, archive),
+        Command::Walk { root, subdir } => walk(root, subdir.as_deref()),
+    };
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => Report::failed(String::new(), json!({}), e),
+    };
+
+    match json {
+        true => writeln!(out, "{}", report.json),
+        false => out.write_all(report.text.as_bytes()),
+    }
+    .map_err(|e| e.to_string())?;
+
+    match report.failure {
+        Some(failure) => Err(failure),
+        None => Ok(()),
+    }
+}
+
+// A path for the JSON output, which has no way to tell bytes which are not UTF-8.
+fn lossy(path: &Path) -> Value {
+    Value::from(path.to_string_lossy())
+}
+
+fn join(root: &Path, path: &Path, strict: bool) -> Result<Report, String> {
+    let root = Root::new(root).map_err(|e| format!("{}: {}", root.display(), e))?;
+    let resolved = match strict {
+        true => root.check(path),
+        false => root.join(path),
+    };
+
+    match resolved {
+        Ok(resolved) => Ok(Report::new(
+            format!("{}\n", resolved.display()),
+            json!({ "path": lossy(path), "resolved": lossy(&resolved) }),
+        )),
+        // a sentence naming the component which made it fail
+        Err(e) => {
+            let reason = match strict {
+                true => root.explain_check(path),
+                false => root.explain(path),
+            };
+            Ok(Report::failed(
+                String::new(),
+                json!({ "path": lossy(path), "rejected": e.to_string() }),
+                reason,
+            ))
+        }
+    }
+}
+
+fn check(root: &Path, path: &Path) -> Result<Report, String> {
+    let root = Root::new(root).map_err(|e| format!("{}: {}", root.display(), e))?;
+    let in_root = |p: &Path| Path::new("/").join(p.strip_prefix(root.path()).unwrap_or(p));
+
+    let mut steps = Vec::new();
+    let resolved = match root.check_traced(path, |s| steps.push(s)) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            return Ok(Report::failed(
+                String::new(),
+                json!({ "path": lossy(path), "rejected": e.to_string() }),
+                root.explain_check(path),
+            ))
+        }
+    };
+    let m = fs::symlink_metadata(&resolved).map_err(|e| format!("{:?}: {}", path, e))?;
+
+    let mut report = format!("{:?} resolves to {:?}\n", path, in_root(&resolved));
+    let (mut symlinks, mut writable) = (Vec::new(), Vec::new());
+    let mut components = vec![root.path().to_path_buf()];
+    for step in steps {
+        match step {
+            Step::Symlink { link, target } => {
+                report += &format!("symlink {:?} -> {:?}\n", in_root(&link), target);
+                symlinks.push(json!({ "link": lossy(&in_root(&link)), "target": lossy(&target) }));
+            }
+            Step::Enter(p) | Step::File(p) if !components.contains(&p) => components.push(p),
+            _ => {}
+        }
+    }
+
+    // the owners of the rootfs are trusted
+    let top = fs::metadata(root.path()).map_err(|e| e.to_string())?;
+    let mut untrusted = 0;
+    for p in &components {
+        let m = match p == &resolved {
+            true => m.clone(),
+            false => fs::symlink_metadata(p).map_err(|e| format!("{:?}: {}", p, e))?,
+        };
+        if let Some(writer) = untrusted_writer(&m, top.uid(), top.gid()) {
+            report += &format!("{:?} is writable by {}\n", in_root(p), writer);
+            writable.push(json!({ "path": lossy(&in_root(p)), "writer": writer }));
+            untrusted += 1;
+        }
+    }
+    let json = json!({
+        "path": lossy(path),
+        "resolved": lossy(&in_root(&resolved)),
+        "symlinks": symlinks,
+        "writable": writable,
+    });
+
+    match untrusted {
+        0 => Ok(Report::new(report, json)),
+        n => Ok(Report::failed(
+            report,
+            json,
+            format!(
+                "{:?} has {} components writable by untrusted users",
+                path, n
+            ),
+        )),
+    }
+}
+
+// Who other than root and the owners of the rootfs can write to the entry `m`: any user if
+// it's writable by others, except in a directory with the sticky bit, or its owner or group.
+fn untrusted_writer(m: &fs::Metadata, uid: u32, gid: u32) -> Option<String> {
+    let (mode, sticky) = (m.mode(), m.is_dir() && m.mode() & 0o1000 != 0);
+
+    if mode & 0o002 != 0 && !sticky {
+        return Some("others".to_string());
+    }
+    if m.uid() != 0 && m.uid() != uid && mode & 0o200 != 0 {
+        return Some(format!("its owner {}", m.uid()));
+    }
+    if m.gid() != 0 && m.gid() != gid && mode & 0o020 != 0 && !sticky {
+        return Some(format!("its group {}", m.gid()));
+    }
+
+    None
+}
+
+// Unpack with `archive::tar::unpack_secure`, the compression is found from the magic number of
+// the archive rather than from its name.
+fn extract(root: &Path, archive: &Path) -> Result<Report, String> {
+    let root = Root::new(root).map_err(|e| format!("{}: {}", root.display(), e))?;
+    let with_path = |e: io::Error| format!("{}: {}", archive.display(), e);
+
+    let mut file = BufReader::new(File::open(archive).map_err(with_path)?);
+    let (reader, compression): (Box<dyn Read>, _) = match file.fill_buf().map_err(with_path)? {
+        [0x1f, 0x8b, ..] => (Box::new(GzDecoder::new(file)), "gzip"),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => (
+            Box::new(
+                StreamingDecoder::new(file).map_err(|e| format!("{}: {}", archive.display(), e))?,
+            ),
+            "zstd",
+        ),
+        _ => (Box::new(file), "none"),
+    };
+
+    unpack_secure(&mut tar::Archive::new(reader), &root)
+        .map_err(|e| format!("{}: {}", archive.display(), e))?;
+
+    Ok(Report::new(
+        String::new(),
+        json!({ "archive": lossy(archive), "compression": compression }),
+    ))
+}
+
+// A line per entry, its type like the first letter of "ls -l" and its path inside of the
+// rootfs. The target of a symlink follows it, flagged when it would be rejected. In JSON the
+// entries are in an array, with the type named like "dir".
+fn walk(root: &Path, subdir: Option<&Path>) -> Result<Report, String> {
+    let root = Root::new(root).map_err(|e| format!("{}: {}", root.display(), e))?;
+    let subdir = subdir.unwrap_or(Path::new("/"));
+    let walk = root
+        .walk_in(subdir)
+        .map_err(|e| format!("{}: {}", subdir.display(), e))?;
+
+    let (mut text, mut entries) = (String::new(), Vec::new());
+    for entry in walk {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let (path, file_type) = (Path::new("/").join(entry.path()), entry.file_type());
+        let (kind, name) = match () {
+            _ if file_type.is_dir() => ('d', "dir"),
+            _ if file_type.is_symlink() => ('l', "symlink"),
+            _ if file_type.is_block_device() => ('b', "block"),
+            _ if file_type.is_char_device() => ('c', "char"),
+            _ if file_type.is_fifo() => ('p', "fifo"),
+            _ if file_type.is_socket() => ('s', "socket"),
+            _ => ('-', "file"),
+        };
+
+        text += &format!("{} {}", kind, path.display());
+        let mut json = json!({ "path": lossy(&path), "type": name });
+        if file_type.is_symlink() {
+            let target =
+                fs::read_link(root.path().join(entry.path())).map_err(|e| e.to_string())?;
+            text += &format!(" -> {}", target.display());
+            json["target"] = lossy(&target);
+            if let Err(e) = root.check(&path) {
+                text += &format!(" (rejected: {})", e);
+                json["rejected"] = Value::from(e.to_string());
+            }
+        }
+        text += "\n";
+        entries.push(json);
+    }
+
+    Ok(Report::new(text, Value::from(entries)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_join() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            args: &'a [&'a str],
+            result: Result<&'a str, &'a str>,
+        }
+
+        let dir = tempdir().expect("failed to create tmpdir");
+        let rootfs = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        symlink("../../etc", rootfs.join("up")).unwrap();
+        let rootfs = rootfs.to_str().unwrap();
+
+        let tests = &[
+            TestData {
+                name: "inside",
+                args: &["join", rootfs, "etc/passwd"],
+                result: Ok("/etc/passwd"),
+            },
+            TestData {
+                name: "clamped",
+                args: &["join", rootfs, "up/passwd"],
+                result: Ok("/etc/passwd"),
+            },
+            TestData {
+                name: "rejected in strict mode",
+                args: &["join", "--strict", rootfs, "up/passwd"],
+                result: Err("is rejected"),
+            },
+            TestData {
+                name: "missing root",
+                args: &["join", "/nonexistent/rootfs", "etc"],
+                result: Err("/nonexistent/rootfs"),
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let cli =
+                Cli::try_parse_from(std::iter::once("secure-path").chain(t.args.iter().copied()));
+            let mut out = Vec::new();
+            let result = run(&cli.unwrap().command, false, &mut out);
+            let out = String::from_utf8(out).unwrap();
+
+            let msg = format!("{}, result: {:?} {:?}", msg, result, out);
+
+            match t.result {
+                Ok(p) => assert!(
+                    result.is_ok() && out == format!("{}{}\n", rootfs, p),
+                    "{}",
+                    msg
+                ),
+                Err(e) => assert!(result.unwrap_err().contains(e), "{}", msg),
+            }
+        }
+        assert!(Cli::try_parse_from(["secure-path", "join", rootfs]).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            output: &'a [&'a str],
+            error: Option<&'a str>,
+        }
+
+        let dir = tempdir().expect("failed to create tmpdir");
+        let rootfs = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::create_dir_all(rootfs.join("tmp")).unwrap();
+        fs::create_dir_all(rootfs.join("srv/www")).unwrap();
+        fs::write(rootfs.join("etc/passwd"), "").unwrap();
+        fs::write(rootfs.join("tmp/f"), "").unwrap();
+        symlink("/etc", rootfs.join("l")).unwrap();
+        symlink("../../etc", rootfs.join("up")).unwrap();
+        let chmod = |p: &str, mode| {
+            fs::set_permissions(rootfs.join(p), fs::Permissions::from_mode(mode)).unwrap()
+        };
+        chmod("tmp", 0o1777);
+        chmod("srv", 0o777);
+        chmod("srv/www", 0o755);
+
+        let tests = &[
+            TestData {
+                name: "trusted file",
+                path: "etc/passwd",
+                output: &["\"etc/passwd\" resolves to \"/etc/passwd\""],
+                error: None,
+            },
+            TestData {
+                name: "through a symlink",
+                path: "l/passwd",
+                output: &["resolves to \"/etc/passwd\"", "symlink \"/l\" -> \"/etc\""],
+                error: None,
+            },
+            TestData {
+                name: "sticky directory",
+                path: "tmp/f",
+                output: &["resolves to \"/tmp/f\""],
+                error: None,
+            },
+            TestData {
+                name: "below a world writable directory",
+                path: "srv/www",
+                output: &["\"/srv\" is writable by others"],
+                error: Some("1 components writable"),
+            },
+            TestData {
+                name: "escaping",
+                path: "up/passwd",
+                output: &[],
+                error: Some("is rejected"),
+            },
+            TestData {
+                name: "missing",
+                path: "etc/missing",
+                output: &[],
+                error: Some("etc/missing"),
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let mut out = Vec::new();
+            let command = Command::Check {
+                root: rootfs.clone(),
+                path: PathBuf::from(t.path),
+            };
+            let result = run(&command, false, &mut out);
+            let out = String::from_utf8(out).unwrap();
+
+            let msg = format!("{}, result: {:?} {:?}", msg, result, out);
+
+            assert!(t.output.iter().all(|o| out.contains(o)), "{}", msg);
+            match t.error {
+                Some(e) => assert!(result.unwrap_err().contains(e), "{}", msg),
+                None => assert!(result.is_ok() && !out.contains("writable"), "{}", msg),
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            compress: fn(Vec<u8>) -> Vec<u8>,
+            entry: &'a str,
+            ok: bool,
+        }
+
+        // a tar with the file `name`, built by hand as the builder refuses ".."
+        let archive = |name: &str| -> Vec<u8> {
+            let mut header = tar::Header::new_gnu();
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(4);
+            header.set_mode(0o644);
+            header.set_cksum();
+            let mut builder = tar::Builder::new(Vec::new());
+            builder.append(&header, &b"data"[..]).unwrap();
+            builder.into_inner().unwrap()
+        };
+        let gzip = |data: Vec<u8>| {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+            encoder.write_all(&data).unwrap();
+            encoder.finish().unwrap()
+        };
+        let zstd = |data: Vec<u8>| {
+            ruzstd::encoding::compress_to_vec(
+                &data[..],
+                ruzstd::encoding::CompressionLevel::Fastest,
+            )
+        };
+
+        let tests = &[
+            TestData {
+                name: "plain tar",
+                compress: |data| data,
+                entry: "etc/hosts",
+                ok: true,
+            },
+            TestData {
+                name: "gzip",
+                compress: gzip,
+                entry: "etc/hosts",
+                ok: true,
+            },
+            TestData {
+                name: "zstd",
+                compress: zstd,
+                entry: "etc/hosts",
+                ok: true,
+            },
+            TestData {
+                name: "entry climbing out, clamped",
+                compress: gzip,
+                entry: "../../etc/hosts",
+                ok: true,
+            },
+            TestData {
+                name: "not an archive",
+                compress: |_| b"not a tar archive, only some text".repeat(32),
+                entry: "etc/hosts",
+                ok: false,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t.entry);
+
+            let dir = tempdir().expect("failed to create tmpdir");
+            let rootfs = dir.path().join("rootfs");
+            fs::create_dir(&rootfs).unwrap();
+            let path = dir.path().join("archive");
+            fs::write(&path, (t.compress)(archive(t.entry))).unwrap();
+            let result = extract(&rootfs, &path);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            if t.ok {
+                let content = fs::read(rootfs.join("etc/hosts")).unwrap();
+                assert!(content == b"data", "{}", msg);
+                assert!(!dir.path().join("etc").exists(), "{}", msg);
+            }
+        }
+    }
+
+    #[test]
+    fn test_walk() {
+        let dir = tempdir().expect("failed to create tmpdir");
+        let rootfs = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(rootfs.join("etc/ssl")).unwrap();
+        fs::write(rootfs.join("etc/hosts"), "").unwrap();
+        symlink("/etc", rootfs.join("l")).unwrap();
+        symlink("../../..", rootfs.join("etc/up")).unwrap();
+
+        let walk = |subdir: Option<&str>, out: &mut Vec<u8>| {
+            let command = Command::Walk {
+                root: rootfs.clone(),
+                subdir: subdir.map(PathBuf::from),
+            };
+            run(&command, false, out)
+        };
+        let mut out = Vec::new();
+        walk(None, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "d /etc\n- /etc/hosts\nd /etc/ssl\n\
+             l /etc/up -> ../../.. (rejected: path escapes from the rootfs: /etc/up)\n\
+             l /l -> /etc\n"
+        );
+
+        let mut out = Vec::new();
+        walk(Some("l"), &mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with("- /etc/hosts\n"));
+        assert!(walk(Some("etc/hosts"), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_json() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            args: &'a [&'a str],
+            output: Value,
+            ok: bool,
+        }
+
+        let dir = tempdir().expect("failed to create tmpdir");
+        let rootfs = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::write(rootfs.join("etc/passwd"), "").unwrap();
+        symlink("/etc", rootfs.join("l")).unwrap();
+        symlink("../../etc", rootfs.join("up")).unwrap();
+        let archive = dir.path().join("archive.tar");
+        fs::write(
+            &archive,
+            tar::Builder::new(Vec::new()).into_inner().unwrap(),
+        )
+        .unwrap();
+        let (rootfs, archive) = (rootfs.to_str().unwrap(), archive.to_str().unwrap());
+
+        let tests = &[
+            TestData {
+                name: "join",
+                args: &["--json", "join", rootfs, "l/passwd"],
+                output: json!({
+                    "path": "l/passwd",
+                    "resolved": format!("{}/etc/passwd", rootfs),
+                }),
+                ok: true,
+            },
+            TestData {
+                name: "join rejected, flag after the subcommand",
+                args: &["join", "--strict", rootfs, "up/passwd", "--json"],
+                output: json!({
+                    "path": "up/passwd",
+                    "rejected": "path escapes from the rootfs: up/passwd",
+                    "error": "\"up/passwd\" is rejected: path escapes from the rootfs: up/passwd, \
+                              as \"/up\" is a symlink to \"../../etc\"",
+                }),
+                ok: false,
+            },
+            TestData {
+                name: "check",
+                args: &["--json", "check", rootfs, "l/passwd"],
+                output: json!({
+                    "path": "l/passwd",
+                    "resolved": "/etc/passwd",
+                    "symlinks": [{ "link": "/l", "target": "/etc" }],
+                    "writable": [],
+                }),
+                ok: true,
+            },
+            TestData {
+                name: "extract",
+                args: &["--json", "extract", rootfs, archive],
+                output: json!({ "archive": archive, "compression": "none" }),
+                ok: true,
+            },
+            TestData {
+                name: "walk",
+                args: &["--json", "walk", rootfs, "etc"],
+                output: json!([{ "path": "/etc/passwd", "type": "file" }]),
+                ok: true,
+            },
+            TestData {
+                name: "failure without a result",
+                args: &["--json", "walk", "/nonexistent/rootfs"],
+                output: json!({ "error": "/nonexistent/rootfs: No such file or directory (os error 2)" }),
+                ok: false,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let cli =
+                Cli::try_parse_from(std::iter::once("secure-path").chain(t.args.iter().copied()))
+                    .unwrap();
+            let mut out = Vec::new();
+            let result = run(&cli.command, cli.json, &mut out);
+            let output: Result<Value, _> = serde_json::from_slice(&out);
+
+            let msg = format!("{}, result: {:?} {:?}", msg, result, output);
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            assert!(output.ok() == Some(t.output.clone()), "{}", msg);
+        }
+    }
+}
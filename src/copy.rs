@@ -0,0 +1,846 @@
+// Copying content into a rootfs, from the host or from another rootfs.
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::fs::{symlink, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use crate::archive::{create_file_open, create_symlink, rewrite_link_target};
+use crate::audit::AuditEvent;
+use crate::error::{Error, Result};
+use crate::root::Root;
+use crate::sys::{self, WalkDir};
+
+// What to do with the symlinks found in the copied tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    // Don't copy symlinks.
+    Skip,
+    // Copy symlinks as they are, failing with `Error::Escape` if one doesn't stay inside of
+    // the rootfs.
+    Preserve,
+    // Rewrite the symlink targets to stay inside of the rootfs, see
+    // `archive::rewrite_link_target`.
+    Rewrite,
+}
+
+// Options of the copy operations.
+#[derive(Debug, Clone)]
+pub struct CopyOptions {
+    pub symlinks: SymlinkPolicy,
+    // Try to clone the files with the FICLONE ioctl first, sharing their blocks copy on write
+    // on btrfs or XFS. The data is copied when the filesystem doesn't support it, or when the
+    // source is on another one.
+    pub reflink: bool,
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        CopyOptions {
+            symlinks: SymlinkPolicy::Rewrite,
+            reflink: false,
+        }
+    }
+}
+
+// Copy the host directory `src` into the rootfs as the directory `dest`.
+//
+// Every destination path is resolved inside of the rootfs and the permission bits of the
+// directories and files are preserved. Symlinks in `src` are never followed, they are handled
+// according to `options.symlinks`. Special files are skipped.
+pub fn copy_tree_into<P, Q>(src: P, root: &Root, dest: Q, options: &CopyOptions) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let src = src.as_ref();
+    let dest = dest.as_ref();
+
+    let (_, dir) = root.mkdir_all_open(dest)?;
+
+    for it in fs::read_dir(src)? {
+        let it = it?;
+        let src = it.path();
+        let dest = dest.join(it.file_name());
+        let file_type = it.file_type()?;
+
+        if file_type.is_dir() {
+            copy_tree_into(&src, root, &dest, options)?;
+        } else if file_type.is_symlink() {
+            let target = fs::read_link(&src)?;
+            match options.symlinks {
+                SymlinkPolicy::Skip => continue,
+                SymlinkPolicy::Preserve => create_symlink(root, &dest, &target)?,
+                SymlinkPolicy::Rewrite => {
+                    create_symlink(root, &dest, &rewrite_link_target(&dest, &target))?
+                }
+            };
+        } else if file_type.is_file() {
+            let mode = it.metadata()?.permissions().mode();
+            let src = File::open(&src)?;
+            // the mode is applied through the fds, a path may be swapped for a symlink
+            let (_, dst) = match options.reflink {
+                true => {
+                    let created = create_file_open(root, &dest, mode, &mut io::empty())?;
+                    copy_data(&src, &created.1, true)?;
+                    created
+                }
+                false => create_file_open(root, &dest, mode, &mut &src)?,
+            };
+            dst.set_permissions(fs::Permissions::from_mode(mode & 0o7777))?;
+        }
+    }
+
+    // the permissions of the directory are applied last, they may forbid writing into it
+    let mode = fs::metadata(src)?.permissions().mode();
+    dir.set_mode(mode & 0o7777)?;
+
+    Ok(())
+}
+
+// Options of `sync_into`.
+#[derive(Debug, Clone)]
+pub struct SyncOptions {
+    pub symlinks: SymlinkPolicy,
+    // Remove the entries of the destination which aren't in the source, like rsync --delete.
+    pub delete: bool,
+    // Compare the content of the files of the same size instead of their mtimes, like rsync
+    // --checksum.
+    pub checksum: bool,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        SyncOptions {
+            symlinks: SymlinkPolicy::Rewrite,
+            delete: false,
+            checksum: false,
+        }
+    }
+}
+
+// What `sync_into` did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    // The files and symlinks created or replaced.
+    pub copied: u64,
+    pub unchanged: u64,
+    // The entries removed with `options.delete`, a directory counts once.
+    pub removed: u64,
+}
+
+// Sync the host directory `src` into the rootfs as the directory `dest`, copying only the
+// files which changed, e.g. to refresh a rootfs from a build tree. A file is unchanged when it
+// has the size, the permission bits and the mtime of the source, or the same content with
+// `options.checksum`, and the copied files get the mtime of their source. Symlinks are handled
+// according to `options.symlinks` and an entry of another type is replaced. Every destination
+// path is resolved inside of the rootfs, like `copy_tree_into` does.
+pub fn sync_into<P, Q>(src: P, root: &Root, dest: Q, options: &SyncOptions) -> Result<SyncStats>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut stats = SyncStats::default();
+    sync_dir(src.as_ref(), root, dest.as_ref(), options, &mut stats)?;
+
+    Ok(stats)
+}
+
+fn sync_dir(
+    src: &Path,
+    root: &Root,
+    dest: &Path,
+    options: &SyncOptions,
+    stats: &mut SyncStats,
+) -> Result<()> {
+    // the entries are looked up, compared and removed relative to the fd of the directory,
+    // none of them is followed even if it was swapped for a symlink
+    let (_, dir) = root.mkdir_all_open(dest)?;
+
+    let mut names = HashSet::new();
+    for it in fs::read_dir(src)? {
+        let it = it?;
+        let src = it.path();
+        let name = it.file_name();
+        let dest = dest.join(&name);
+        let m = it.metadata()?;
+        let existing = dir.child_metadata(&name).ok();
+        names.insert(name.clone());
+
+        if m.is_dir() {
+            if existing.as_ref().is_some_and(|e| !e.is_dir()) {
+                dir.remove_child(&name, false)?;
+            }
+            sync_dir(&src, root, &dest, options, stats)?;
+        } else if m.file_type().is_symlink() {
+            let target = fs::read_link(&src)?;
+            let target = match options.symlinks {
+                SymlinkPolicy::Skip => continue,
+                SymlinkPolicy::Preserve => target,
+                SymlinkPolicy::Rewrite => rewrite_link_target(&dest, &target),
+            };
+            let same = match &existing {
+                Some(e) if e.file_type().is_symlink() => dir.read_link(&name)? == target,
+                _ => false,
+            };
+            if same {
+                stats.unchanged += 1;
+                continue;
+            }
+            remove_dir(&dir, &name, existing.as_ref())?;
+            create_symlink(root, &dest, &target)?;
+            stats.copied += 1;
+        } else if m.is_file() {
+            let mode = m.permissions().mode() & 0o7777;
+            let same = match &existing {
+                Some(e) if e.is_file() && e.len() == m.len() && e.mode() & 0o7777 == mode => {
+                    match options.checksum {
+                        true => same_content(&src, &dir, &name)?,
+                        false => e.modified()? == m.modified()?,
+                    }
+                }
+                _ => false,
+            };
+            if same {
+                stats.unchanged += 1;
+                continue;
+            }
+            remove_dir(&dir, &name, existing.as_ref())?;
+            let (_, file) = create_file_open(root, &dest, mode, &mut File::open(&src)?)?;
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+            root.set_times(&dest, None, Some(m.modified()?))?;
+            stats.copied += 1;
+        }
+    }
+
+    if options.delete {
+        for (name, m) in dir.entries()? {
+            if names.contains(&name) {
+                continue;
+            }
+            match m.is_dir() {
+                true => sys::remove_all(&dir, &name)?,
+                false => dir.remove_child(&name, false)?,
+            }
+            stats.removed += 1;
+        }
+    }
+
+    // like `copy_tree_into`, the permissions of the directory are applied last
+    let mode = fs::metadata(src)?.permissions().mode();
+    dir.set_mode(mode & 0o7777)?;
+
+    Ok(())
+}
+
+// Remove the directory `name` of `dir` replaced by a file or a symlink, if `existing` is one.
+fn remove_dir(dir: &WalkDir, name: &OsStr, existing: Option<&fs::Metadata>) -> io::Result<()> {
+    match existing {
+        Some(e) if e.is_dir() => sys::remove_all(dir, name),
+        _ => Ok(()),
+    }
+}
+
+// Whether the file `src` and the file `name` of `dir` have the same content, `name` isn't
+// followed nor waited on if it was swapped for a FIFO.
+fn same_content(src: &Path, dir: &WalkDir, name: &OsStr) -> io::Result<bool> {
+    let dest = dir.open_file(name)?;
+    if !dest.metadata()?.is_file() {
+        return Ok(false);
+    }
+    let mut src = BufReader::new(File::open(src)?);
+    let mut dest = BufReader::new(dest);
+
+    loop {
+        let (a, b) = (src.fill_buf()?, dest.fill_buf()?);
+        if a.is_empty() || b.is_empty() {
+            return Ok(a.is_empty() && b.is_empty());
+        }
+        let n = a.len().min(b.len());
+        if a[..n] != b[..n] {
+            return Ok(false);
+        }
+        src.consume(n);
+        dest.consume(n);
+    }
+}
+
+// Copy the regular file `src_path` of `src_root` to `dst_path` of `dst_root`, e.g. from a
+// staging rootfs into a live one. Both paths are resolved inside of their rootfs and opened
+// without following them, and the content is streamed from fd to fd, with copy_file_range()
+// on Linux, skipping the holes of a sparse file. A new destination gets the permission bits
+// of the source, an existing one is truncated. Returns the number of bytes copied. Both must
+// be regular files, a FIFO isn't waited on, and the destination must not be the source.
+pub fn copy<P, Q>(src_root: &Root, src_path: P, dst_root: &Root, dst_path: Q) -> Result<u64>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    copy_with(
+        src_root,
+        src_path,
+        dst_root,
+        dst_path,
+        &CopyOptions::default(),
+    )
+}
+
+// Like `copy`, cloning the file first with the `reflink` of `options`.
+pub fn copy_with<P, Q>(
+    src_root: &Root,
+    src_path: P,
+    dst_root: &Root,
+    dst_path: Q,
+    options: &CopyOptions,
+) -> Result<u64>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let src = src_root.open_with_flags(
+        src_path.as_ref(),
+        OpenOptions::new().read(true),
+        sys::o_nonblock(),
+    )?;
+    let m = src.metadata()?;
+    if !m.is_file() {
+        return Err(Error::InvalidPath(src_path.as_ref().to_path_buf()));
+    }
+
+    // truncated only once it's known not to be the source
+    let mut open = OpenOptions::new();
+    open.write(true)
+        .create(true)
+        .truncate(false)
+        .mode(m.permissions().mode() & 0o777);
+    let dst = dst_root.open_file(dst_path.as_ref(), &open)?;
+    let dm = dst.metadata()?;
+    if (dm.dev(), dm.ino()) == (m.dev(), m.ino()) {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is the source of the copy", dst_path.as_ref().display()),
+        )));
+    }
+    dst.set_len(0)?;
+    let n = copy_data(&src, &dst, options.reflink)?;
+    dst_root.audit(&dst_root.join(dst_path)?, AuditEvent::Write);
+
+    Ok(n)
+}
+
+// Copy the content of `src` to `dst`, both at their start, cloning it first with `reflink`.
+fn copy_data(src: &File, dst: &File, reflink: bool) -> io::Result<u64> {
+    if reflink && sys::reflink(src, dst).is_ok() {
+        return Ok(src.metadata()?.len());
+    }
+
+    sys::copy_file(src, dst)
+}
+
+// Options of `move_entry`.
+#[derive(Debug, Clone)]
+pub struct MoveOptions {
+    // Copy the entry and remove the source when the two paths are on different filesystems,
+    // instead of failing with `Error::CrossMount`.
+    pub copy_fallback: bool,
+}
+
+impl Default for MoveOptions {
+    fn default() -> Self {
+        MoveOptions {
+            copy_fallback: true,
+        }
+    }
+}
+
+// Move the entry `src_path` of `src_root` to `dst_path` of `dst_root`, replacing it like
+// rename(). The parent directories are resolved inside of their rootfs and the last
+// components are not followed, a symlink is moved itself.
+//
+// The entry is renamed with renameat() on the fds of the parent directories. When the two are
+// on different filesystems a regular file or a symlink is copied instead, the copy is synced
+// to disk and only then is the source removed. A directory can't be copied and fails with
+// `Error::CrossMount`, like anything with `options.copy_fallback` unset.
+pub fn move_entry<P, Q>(
+    src_root: &Root,
+    src_path: P,
+    dst_root: &Root,
+    dst_path: Q,
+    options: &MoveOptions,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let from = src_root.join_parent(src_path)?;
+    let to = dst_root.join_parent(&dst_path)?;
+    let m = fs::symlink_metadata(&from)?;
+
+    match sys::rename(&from, &to) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            if !options.copy_fallback || m.is_dir() {
+                return Err(Error::CrossMount(dst_path.as_ref().to_path_buf()));
+            }
+            copy_entry(&from, &to, &m)?;
+            fs::remove_file(&from)?;
+        }
+        Err(e) => return Err(Error::Io(e)),
+    }
+    dst_root.audit(&to, AuditEvent::Rename { from });
+
+    Ok(())
+}
+
+// Copy the regular file or the symlink `from` to `to` without following either, and sync it.
+fn copy_entry(from: &Path, to: &Path, m: &fs::Metadata) -> Result<()> {
+    if let Ok(existing) = fs::symlink_metadata(to) {
+        if existing.is_dir() {
+            return Err(Error::Io(io::ErrorKind::IsADirectory.into()));
+        }
+        fs::remove_file(to)?;
+    }
+
+    if m.file_type().is_symlink() {
+        symlink(fs::read_link(from)?, to)?;
+        return Ok(());
+    }
+    if !m.is_file() {
+        return Err(Error::InvalidPath(from.to_path_buf()));
+    }
+
+    let src = OpenOptions::new()
+        .read(true)
+        .custom_flags(sys::o_nofollow())
+        .open(from)?;
+    let dst = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(m.permissions().mode() & 0o777)
+        .open(to)?;
+    sys::copy_file(&src, &dst)?;
+    dst.sync_all()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+    use std::os::unix::fs::{symlink, FileExt, MetadataExt};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_copy_tree_into() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            dest: &'a str,
+            symlinks: SymlinkPolicy,
+            ok: bool,
+            links: &'a [(&'a str, &'a str)],
+        }
+
+        let src_dir = tempdir().expect("failed to create tmpdir");
+        let src = src_dir.path();
+        fs::create_dir_all(src.join("bin")).unwrap();
+        fs::write(src.join("bin/busybox"), b"data").unwrap();
+        fs::set_permissions(src.join("bin/busybox"), fs::Permissions::from_mode(0o4755)).unwrap();
+        fs::set_permissions(src.join("bin"), fs::Permissions::from_mode(0o700)).unwrap();
+        symlink("/bin/busybox", src.join("bin/sh")).unwrap();
+        symlink("busybox", src.join("bin/ls")).unwrap();
+
+        let tests = &[
+            TestData {
+                name: "rewrite symlinks",
+                dest: "/../opt",
+                symlinks: SymlinkPolicy::Rewrite,
+                ok: true,
+                links: &[
+                    ("opt/bin/sh", "../../bin/busybox"),
+                    ("opt/bin/ls", "busybox"),
+                ],
+            },
+            TestData {
+                name: "skip symlinks",
+                dest: "opt",
+                symlinks: SymlinkPolicy::Skip,
+                ok: true,
+                links: &[],
+            },
+            TestData {
+                name: "preserve symlinks",
+                dest: "opt",
+                symlinks: SymlinkPolicy::Preserve,
+                ok: false,
+                links: &[],
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let rootfs_dir = tempdir().expect("failed to create tmpdir");
+            let root = Root::new(rootfs_dir.path()).unwrap();
+            // the files are cloned if the filesystem supports it, copied otherwise
+            let options = CopyOptions {
+                symlinks: t.symlinks,
+                reflink: i % 2 == 1,
+            };
+            let result = copy_tree_into(src, &root, t.dest, &options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            if !t.ok {
+                continue;
+            }
+            let mode = |p: &str| {
+                fs::symlink_metadata(root.path().join(p))
+                    .unwrap()
+                    .permissions()
+                    .mode()
+                    & 0o7777
+            };
+            assert_eq!(mode("opt/bin/busybox"), 0o4755, "{}", msg);
+            assert_eq!(mode("opt/bin"), 0o700, "{}", msg);
+            assert_eq!(
+                fs::symlink_metadata(root.path().join("opt/bin/sh")).is_ok(),
+                !t.links.is_empty(),
+                "{}",
+                msg
+            );
+            for (link, target) in t.links {
+                let result = fs::read_link(root.path().join(link)).unwrap();
+                assert!(result == Path::new(target), "{}: {:?}", msg, result);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sync_into() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            // change the source or the destination before the sync
+            change: fn(&Path, &Path),
+            options: SyncOptions,
+            stats: SyncStats,
+        }
+
+        let src_dir = tempdir().expect("failed to create tmpdir");
+        let src = src_dir.path();
+        TreeBuilder::new()
+            .file("etc/hosts", "hosts")
+            .file("usr/bin/busybox", "busybox")
+            .symlink("bin", "/usr/bin")
+            .build_in(src)
+            .unwrap();
+        let live = TreeBuilder::new().build().unwrap();
+        let root = live.root().unwrap();
+        let checksum = SyncOptions {
+            checksum: true,
+            ..Default::default()
+        };
+
+        let tests = [
+            TestData {
+                name: "first sync",
+                change: |_, _| {},
+                options: SyncOptions::default(),
+                stats: SyncStats {
+                    copied: 3,
+                    ..Default::default()
+                },
+            },
+            TestData {
+                name: "nothing changed",
+                change: |_, _| {},
+                options: SyncOptions::default(),
+                stats: SyncStats {
+                    unchanged: 3,
+                    ..Default::default()
+                },
+            },
+            TestData {
+                name: "content and mtime changed",
+                change: |src, _| fs::write(src.join("etc/hosts"), "HOSTS").unwrap(),
+                options: SyncOptions::default(),
+                stats: SyncStats {
+                    copied: 1,
+                    unchanged: 2,
+                    ..Default::default()
+                },
+            },
+            TestData {
+                name: "mtime changed, same content",
+                change: |src, _| {
+                    let file = File::options()
+                        .write(true)
+                        .open(src.join("etc/hosts"))
+                        .unwrap();
+                    file.set_modified(std::time::SystemTime::UNIX_EPOCH)
+                        .unwrap();
+                },
+                options: checksum.clone(),
+                stats: SyncStats {
+                    unchanged: 3,
+                    ..Default::default()
+                },
+            },
+            TestData {
+                name: "content changed, same size and mtime",
+                change: |_, live| fs::write(live.join("opt/etc/hosts"), "hosts").unwrap(),
+                options: checksum,
+                stats: SyncStats {
+                    copied: 1,
+                    unchanged: 2,
+                    ..Default::default()
+                },
+            },
+            TestData {
+                name: "extraneous entries kept",
+                change: |_, live| {
+                    fs::write(live.join("opt/extra"), "").unwrap();
+                    fs::create_dir_all(live.join("opt/usr/lib/x")).unwrap();
+                },
+                options: SyncOptions::default(),
+                stats: SyncStats {
+                    unchanged: 3,
+                    ..Default::default()
+                },
+            },
+            TestData {
+                name: "extraneous entries deleted, types replaced",
+                change: |_, live| {
+                    fs::remove_file(live.join("opt/bin")).unwrap();
+                    fs::create_dir(live.join("opt/bin")).unwrap();
+                    fs::remove_dir_all(live.join("opt/etc")).unwrap();
+                    fs::write(live.join("opt/etc"), "").unwrap();
+                },
+                options: SyncOptions {
+                    delete: true,
+                    ..Default::default()
+                },
+                stats: SyncStats {
+                    copied: 2,
+                    unchanged: 1,
+                    removed: 2,
+                },
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            (t.change)(src, live.path());
+            let result = sync_into(src, &root, "/../opt", &t.options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            assert!(result.as_ref().ok() == Some(&t.stats), "{}", msg);
+            let hosts = fs::read(live.path().join("opt/etc/hosts")).unwrap();
+            assert!(hosts == fs::read(src.join("etc/hosts")).unwrap(), "{}", msg);
+        }
+        let target = fs::read_link(live.path().join("opt/bin")).unwrap();
+        assert_eq!(target, Path::new("../usr/bin"));
+        assert!(!live.path().join("opt/extra").exists());
+        assert!(!live.path().join("opt/usr/lib").exists());
+    }
+
+    #[test]
+    fn test_copy() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            src: &'a str,
+            dst: &'a str,
+            result: Option<&'a str>,
+        }
+
+        let staging = TreeBuilder::new()
+            .file("etc/hosts", "127.0.0.1 localhost\n")
+            .symlink("l", "/etc/hosts")
+            .symlink("up", "../../../etc/hosts")
+            .build()
+            .unwrap();
+        let live = TreeBuilder::new()
+            .dir("etc")
+            .file("etc/old", "old content which is longer")
+            .symlink("out", "/etc/copied")
+            .build()
+            .unwrap();
+        let (src_root, dst_root) = (staging.root().unwrap(), live.root().unwrap());
+        fs::set_permissions(
+            staging.path().join("etc/hosts"),
+            fs::Permissions::from_mode(0o640),
+        )
+        .unwrap();
+
+        let tests = &[
+            TestData {
+                name: "new file",
+                src: "etc/hosts",
+                dst: "etc/hosts",
+                result: Some("etc/hosts"),
+            },
+            TestData {
+                name: "through symlinks on both sides",
+                src: "l",
+                dst: "out",
+                result: Some("etc/copied"),
+            },
+            TestData {
+                name: "relative symlink beyond the source rootfs",
+                src: "up",
+                dst: "etc/up",
+                result: Some("etc/up"),
+            },
+            TestData {
+                name: "existing file truncated",
+                src: "etc/hosts",
+                dst: "etc/old",
+                result: Some("etc/old"),
+            },
+            TestData {
+                name: "directory",
+                src: "etc",
+                dst: "etc/dir",
+                result: None,
+            },
+            TestData {
+                name: "missing destination directory",
+                src: "etc/hosts",
+                dst: "missing/hosts",
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = copy(&src_root, t.src, &dst_root, t.dst);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some(p) => {
+                    assert!(result.unwrap() == 20, "{}", msg);
+                    let content = fs::read_to_string(live.path().join(p)).unwrap();
+                    assert!(content == "127.0.0.1 localhost\n", "{}", msg);
+                }
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+        let m = fs::metadata(live.path().join("etc/hosts")).unwrap();
+        assert_eq!(m.permissions().mode() & 0o777, 0o640);
+
+        // onto itself, or from a fifo which would block
+        assert!(copy(&dst_root, "etc/hosts", &dst_root, "/etc/../etc/hosts").is_err());
+        let hosts = fs::read_to_string(live.path().join("etc/hosts")).unwrap();
+        assert_eq!(hosts, "127.0.0.1 localhost\n");
+        let fifo = rustix::fs::mknodat(
+            rustix::fs::CWD,
+            live.path().join("etc/fifo"),
+            rustix::fs::FileType::Fifo,
+            rustix::fs::Mode::from_raw_mode(0o644),
+            0,
+        );
+        if fifo.is_ok() {
+            assert!(copy(&dst_root, "etc/fifo", &dst_root, "etc/from-fifo").is_err());
+            assert!(copy(&dst_root, "etc/hosts", &dst_root, "etc/fifo").is_err());
+        }
+        assert!(fs::symlink_metadata(live.path().join("out"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+
+        // a large blob, with copy_file_range() inside of a rootfs and maybe across to tmpfs
+        let blob: Vec<u8> = (0..3 << 20).map(|i: u32| (i % 251) as u8).collect();
+        fs::write(live.path().join("etc/blob"), &blob).unwrap();
+        let n = copy(&dst_root, "etc/blob", &dst_root, "etc/blob2").unwrap();
+        assert_eq!(n, blob.len() as u64);
+        assert!(fs::read(live.path().join("etc/blob2")).unwrap() == blob);
+        let options = CopyOptions {
+            reflink: true,
+            ..Default::default()
+        };
+        let n = copy_with(&dst_root, "etc/blob", &dst_root, "etc/blob3", &options).unwrap();
+        assert_eq!(n, blob.len() as u64);
+        assert!(fs::read(live.path().join("etc/blob3")).unwrap() == blob);
+
+        // a sparse image, its holes are kept
+        let image = fs::File::create(live.path().join("etc/image")).unwrap();
+        image.set_len(64 << 20).unwrap();
+        image.write_all_at(b"data", 32 << 20).unwrap();
+        assert_eq!(
+            copy(&dst_root, "etc/image", &dst_root, "etc/image2").unwrap(),
+            64 << 20
+        );
+        let blocks = |p: &str| fs::metadata(live.path().join(p)).unwrap().blocks();
+        assert!(blocks("etc/image2") <= blocks("etc/image").max(8) * 2);
+        let copied = fs::read(live.path().join("etc/image2")).unwrap();
+        assert_eq!(copied.len(), 64 << 20);
+        assert_eq!(&copied[32 << 20..(32 << 20) + 4], b"data");
+        assert!(copied[..32 << 20].iter().all(|b| *b == 0));
+
+        if let Ok(shm) = tempfile::tempdir_in("/dev/shm") {
+            let shm_root = Root::new(shm.path()).unwrap();
+            assert_eq!(copy(&dst_root, "etc/blob", &shm_root, "blob").unwrap(), n);
+            assert!(fs::read(shm.path().join("blob")).unwrap() == blob);
+        }
+    }
+
+    #[test]
+    fn test_move_entry() {
+        let src = TreeBuilder::new()
+            .file("a/f", "data")
+            .file("a/g", "data")
+            .symlink("a/l", "/etc/passwd")
+            .symlink("up", "../../a")
+            .build()
+            .unwrap();
+        let dst = TreeBuilder::new().dir("b").build().unwrap();
+        let (src_root, dst_root) = (src.root().unwrap(), dst.root().unwrap());
+        let options = MoveOptions::default();
+
+        // the same filesystem, renamed
+        move_entry(&src_root, "up/f", &dst_root, "../b/f", &options).unwrap();
+        assert!(!src.path().join("a/f").exists());
+        assert_eq!(fs::read(dst.path().join("b/f")).unwrap(), b"data");
+        move_entry(&src_root, "a/l", &dst_root, "b/l", &options).unwrap();
+        assert_eq!(
+            fs::read_link(dst.path().join("b/l")).unwrap(),
+            Path::new("/etc/passwd")
+        );
+        assert!(move_entry(&src_root, "a/missing", &dst_root, "b/x", &options).is_err());
+
+        // another filesystem, when /dev/shm is one
+        let Ok(shm) = tempfile::tempdir_in("/dev/shm") else {
+            return;
+        };
+        let shm_root = Root::new(shm.path()).unwrap();
+        let dev = |p: &Path| fs::metadata(p).unwrap().dev();
+        if dev(shm.path()) == dev(src.path()) {
+            return;
+        }
+        let no_copy = MoveOptions {
+            copy_fallback: false,
+        };
+        assert!(matches!(
+            move_entry(&src_root, "a/g", &shm_root, "g", &no_copy),
+            Err(Error::CrossMount(_))
+        ));
+        assert!(src.path().join("a/g").exists());
+        move_entry(&src_root, "a/g", &shm_root, "g", &options).unwrap();
+        assert!(!src.path().join("a/g").exists());
+        assert_eq!(fs::read(shm.path().join("g")).unwrap(), b"data");
+        move_entry(&dst_root, "b/l", &shm_root, "l", &options).unwrap();
+        assert!(fs::read_link(shm.path().join("l")).is_ok());
+        assert!(matches!(
+            move_entry(&dst_root, "b", &shm_root, "b", &options),
+            Err(Error::CrossMount(_))
+        ));
+    }
+}
@@ -0,0 +1,388 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+// Error is returned by the operations which touch the filesystem under a rootfs.
+#[derive(Debug)]
+pub enum Error {
+    // An I/O error reported by the underlying filesystem.
+    Io(io::Error),
+    // The path can't be handled, e.g. it isn't valid UTF-8 or names nothing.
+    InvalidPath(PathBuf),
+    // The path, or the target of a link, tries to escape from the rootfs.
+    Escape(PathBuf),
+    // Too many symlinks were followed while resolving the path.
+    TooManySymlinks(PathBuf),
+    // The path crosses into another mount.
+    CrossMount(PathBuf),
+    // The path is denied by a policy, see `policy::Policy`.
+    Denied(PathBuf),
+    // A component of the path was renamed or swapped during the resolution, see
+    // `RootOptions::race_retry`.
+    Race(PathBuf),
+    // A component of the path or of a symlink target starts with a ".", like ".git" or
+    // ".env", see `RootOptions::deny_dotfiles`.
+    Dotfile(PathBuf),
+    // An extraction limit was exceeded by the entry, see `archive::ExtractLimits`.
+    LimitExceeded(Limit, PathBuf),
+}
+
+// A limit of an extraction and its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    TotalBytes(u64),
+    Files(u64),
+    FileSize(u64),
+    Depth(usize),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::TotalBytes(n) => write!(f, "more than {} bytes in total", n),
+            Limit::Files(n) => write!(f, "more than {} entries", n),
+            Limit::FileSize(n) => write!(f, "a file of more than {} bytes", n),
+            Limit::Depth(n) => write!(f, "more than {} components deep", n),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+// What an `Error` means for the caller, e.g. for an HTTP layer choosing between 403 and 404,
+// or collapsing them to not reveal which paths exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    // The path exists or not, but going there is refused: it escapes from the rootfs, loops,
+    // crosses a mount, is denied by a policy or by the permissions of a directory.
+    Forbidden,
+    // The path, or one of its parents, doesn't exist or isn't a directory.
+    NotFound,
+    // The path itself can't be handled.
+    Invalid,
+    // Any other failure, like a race or an I/O error.
+    Other,
+}
+
+impl Error {
+    // The errno the kernel would report for the error, for the FFI layers and FUSE servers:
+    // EXDEV for an escape or a mount crossed like openat2() RESOLVE_BENEATH and RESOLVE_NO_XDEV,
+    // ELOOP, EINVAL, EACCES for a policy or a dotfile, EAGAIN for a race and EFBIG for an
+    // extraction limit. An I/O error keeps its errno, or gets the one of its kind.
+    pub fn errno(&self) -> i32 {
+        match self {
+            Error::Io(e) => e
+                .raw_os_error()
+                .unwrap_or_else(|| errno::from_kind(e.kind())),
+            Error::InvalidPath(_) => errno::EINVAL,
+            Error::Escape(_) | Error::CrossMount(_) => errno::EXDEV,
+            Error::TooManySymlinks(_) => errno::ELOOP,
+            Error::Denied(_) | Error::Dotfile(_) => errno::EACCES,
+            Error::Race(_) => errno::EAGAIN,
+            Error::LimitExceeded(..) => errno::EFBIG,
+        }
+    }
+
+    // The `Category` of the error. An ELOOP I/O error, from O_NOFOLLOW on a path swapped for
+    // a symlink, is forbidden like a symlink loop.
+    pub fn category(&self) -> Category {
+        match self {
+            Error::Escape(_)
+            | Error::TooManySymlinks(_)
+            | Error::CrossMount(_)
+            | Error::Denied(_)
+            | Error::Dotfile(_) => Category::Forbidden,
+            Error::InvalidPath(_) => Category::Invalid,
+            Error::Race(_) | Error::LimitExceeded(..) => Category::Other,
+            Error::Io(e) if e.raw_os_error() == Some(errno::ELOOP) => Category::Forbidden,
+            Error::Io(e) => match e.kind() {
+                io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => Category::NotFound,
+                io::ErrorKind::PermissionDenied => Category::Forbidden,
+                _ => Category::Other,
+            },
+        }
+    }
+
+    // Whether the path is refused, see `Category::Forbidden`.
+    pub fn is_forbidden(&self) -> bool {
+        self.category() == Category::Forbidden
+    }
+
+    // Whether the path doesn't exist, see `Category::NotFound`.
+    pub fn is_not_found(&self) -> bool {
+        self.category() == Category::NotFound
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::InvalidPath(p) => write!(f, "invalid path: {}", p.display()),
+            Error::Escape(p) => write!(f, "path escapes from the rootfs: {}", p.display()),
+            Error::TooManySymlinks(p) => {
+                write!(f, "too many levels of symbolic links: {}", p.display())
+            }
+            Error::CrossMount(p) => write!(f, "path crosses into another mount: {}", p.display()),
+            Error::Denied(p) => write!(f, "path denied by policy: {}", p.display()),
+            Error::Race(p) => write!(f, "path changed during the resolution: {}", p.display()),
+            Error::Dotfile(p) => write!(f, "path has a dotfile component: {}", p.display()),
+            Error::LimitExceeded(limit, p) => {
+                write!(f, "extraction limit exceeded, {}: {}", limit, p.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+// The raw OS error of `Error::errno`, so that `raw_os_error()` returns it. The path of the
+// error is lost, the I/O errors are returned as they are.
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::from_raw_os_error(e.errno()),
+        }
+    }
+}
+
+mod errno {
+    use std::io::ErrorKind;
+
+    #[cfg(target_os = "linux")]
+    macro_rules! errno {
+        ($($name:ident = $errno:ident),*) => {
+            $(pub const $name: i32 = rustix::io::Errno::$errno.raw_os_error();)*
+        };
+    }
+    #[cfg(target_os = "linux")]
+    errno!(
+        EIO = IO,
+        ENOENT = NOENT,
+        EACCES = ACCESS,
+        EEXIST = EXIST,
+        EXDEV = XDEV,
+        ENOTDIR = NOTDIR,
+        EISDIR = ISDIR,
+        EINVAL = INVAL,
+        EOPNOTSUPP = OPNOTSUPP,
+        ELOOP = LOOP,
+        EAGAIN = AGAIN,
+        EFBIG = FBIG
+    );
+
+    // the BSD values, like macOS
+    #[cfg(not(target_os = "linux"))]
+    mod values {
+        pub const EIO: i32 = 5;
+        pub const ENOENT: i32 = 2;
+        pub const EACCES: i32 = 13;
+        pub const EEXIST: i32 = 17;
+        pub const EXDEV: i32 = 18;
+        pub const ENOTDIR: i32 = 20;
+        pub const EISDIR: i32 = 21;
+        pub const EINVAL: i32 = 22;
+        pub const EOPNOTSUPP: i32 = 45;
+        pub const ELOOP: i32 = 62;
+        pub const EAGAIN: i32 = 35;
+        pub const EFBIG: i32 = 27;
+    }
+    #[cfg(not(target_os = "linux"))]
+    pub use self::values::*;
+
+    // The errno of an I/O error without one.
+    pub fn from_kind(kind: ErrorKind) -> i32 {
+        match kind {
+            ErrorKind::NotFound => ENOENT,
+            ErrorKind::PermissionDenied => EACCES,
+            ErrorKind::AlreadyExists => EEXIST,
+            ErrorKind::NotADirectory => ENOTDIR,
+            ErrorKind::IsADirectory => EISDIR,
+            ErrorKind::InvalidInput | ErrorKind::InvalidData => EINVAL,
+            ErrorKind::Unsupported => EOPNOTSUPP,
+            ErrorKind::FileTooLarge => EFBIG,
+            _ => EIO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errno() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            error: Error,
+            errno: i32,
+        }
+
+        let p = || PathBuf::from("/x");
+        let tests = &[
+            TestData {
+                name: "escape",
+                error: Error::Escape(p()),
+                errno: errno::EXDEV,
+            },
+            TestData {
+                name: "too many symlinks",
+                error: Error::TooManySymlinks(p()),
+                errno: errno::ELOOP,
+            },
+            TestData {
+                name: "denied",
+                error: Error::Denied(p()),
+                errno: errno::EACCES,
+            },
+            TestData {
+                name: "dotfile",
+                error: Error::Dotfile(p()),
+                errno: errno::EACCES,
+            },
+            TestData {
+                name: "race",
+                error: Error::Race(p()),
+                errno: errno::EAGAIN,
+            },
+            TestData {
+                name: "limit exceeded",
+                error: Error::LimitExceeded(Limit::Files(1), p()),
+                errno: errno::EFBIG,
+            },
+            TestData {
+                name: "invalid path",
+                error: Error::InvalidPath(p()),
+                errno: errno::EINVAL,
+            },
+            TestData {
+                name: "I/O error without errno",
+                error: Error::Io(io::ErrorKind::NotFound.into()),
+                errno: errno::ENOENT,
+            },
+            TestData {
+                name: "I/O error with errno",
+                error: Error::Io(io::Error::from_raw_os_error(errno::EISDIR)),
+                errno: errno::EISDIR,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let errno = t.error.errno();
+
+            let msg = format!("{}, result: {:?}", msg, errno);
+
+            assert!(errno == t.errno, "{}", msg);
+        }
+
+        let e: io::Error = Error::TooManySymlinks(p()).into();
+        assert_eq!(e.raw_os_error(), Some(errno::ELOOP));
+        let e: io::Error = Error::Io(io::Error::other("x")).into();
+        assert_eq!(e.to_string(), "x");
+    }
+
+    #[test]
+    fn test_category() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            error: Error,
+            category: Category,
+        }
+
+        let p = || PathBuf::from("/x");
+        let tests = &[
+            TestData {
+                name: "escape",
+                error: Error::Escape(p()),
+                category: Category::Forbidden,
+            },
+            TestData {
+                name: "denied",
+                error: Error::Denied(p()),
+                category: Category::Forbidden,
+            },
+            TestData {
+                name: "dotfile",
+                error: Error::Dotfile(p()),
+                category: Category::Forbidden,
+            },
+            TestData {
+                name: "permission denied",
+                error: Error::Io(io::ErrorKind::PermissionDenied.into()),
+                category: Category::Forbidden,
+            },
+            TestData {
+                name: "symlink not followed",
+                error: Error::Io(io::Error::from_raw_os_error(errno::ELOOP)),
+                category: Category::Forbidden,
+            },
+            TestData {
+                name: "missing",
+                error: Error::Io(io::Error::from_raw_os_error(errno::ENOENT)),
+                category: Category::NotFound,
+            },
+            TestData {
+                name: "below a file",
+                error: Error::Io(io::Error::from_raw_os_error(errno::ENOTDIR)),
+                category: Category::NotFound,
+            },
+            TestData {
+                name: "invalid path",
+                error: Error::InvalidPath(p()),
+                category: Category::Invalid,
+            },
+            TestData {
+                name: "race",
+                error: Error::Race(p()),
+                category: Category::Other,
+            },
+            TestData {
+                name: "limit exceeded",
+                error: Error::LimitExceeded(Limit::TotalBytes(1), p()),
+                category: Category::Other,
+            },
+            TestData {
+                name: "other I/O error",
+                error: Error::Io(io::Error::from_raw_os_error(errno::EIO)),
+                category: Category::Other,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let category = t.error.category();
+
+            let msg = format!("{}, result: {:?}", msg, category);
+            assert!(category == t.category, "{}", msg);
+            assert!(
+                t.error.is_forbidden() == (category == Category::Forbidden),
+                "{}",
+                msg
+            );
+            assert!(
+                t.error.is_not_found() == (category == Category::NotFound),
+                "{}",
+                msg
+            );
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// Symlink farms, mirroring a directory into a rootfs as a tree of symlinks, e.g. to expose a
+// read-only toolchain to a build sandbox without copying it. The directories are created and
+// every other entry becomes a symlink to the mirrored one, with a target which stays inside of
+// the rootfs, or inside of the host directories allowed outside of it.
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use crate::archive::{create_symlink, entry_path, rewrite_link_target, sanitize};
+use crate::error::{Error, Result};
+use crate::path_ext::PathExt;
+use crate::root::Root;
+
+// Options of `symlink_farm`.
+#[derive(Debug, Clone, Default)]
+pub struct FarmOptions {
+    // The host directories outside of the rootfs the links may point into, e.g. a toolchain
+    // mounted at the same path in the sandbox. Their targets are kept absolute.
+    pub external: Vec<PathBuf>,
+}
+
+struct Farm<'a> {
+    root: &'a Root,
+    dest: &'a Path,
+    options: &'a FarmOptions,
+    src: PathBuf,
+    // The path of `src` inside of the rootfs, if it is in there.
+    src_in_root: Option<PathBuf>,
+    links: u64,
+}
+
+// Mirror the host directory `src` into the rootfs as the directory `dest`, a farm of symlinks.
+// Returns the number of symlinks created, an existing file or symlink is replaced.
+//
+// A `src` inside of the rootfs is linked to with relative targets, and the symlinks of the
+// tree are resolved inside of the rootfs then rewritten like `archive::rewrite_link_target`.
+// A `src` outside of the rootfs must be inside of one of `options.external`, it is linked to
+// with absolute targets, and the symlinks of the tree must point inside of one of them as
+// well, else this fails with `Error::Escape`. Those targets are resolved inside of it.
+pub fn symlink_farm<P, Q>(src: P, root: &Root, dest: Q, options: &FarmOptions) -> Result<u64>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let src = src.as_ref().canonicalize()?;
+    let src_in_root = src
+        .strip_prefix(root.path())
+        .ok()
+        .map(|p| Path::new("/").join(p));
+    if src_in_root.is_none() && !options.external.iter().any(|e| src.is_within(e)) {
+        return Err(Error::Escape(src));
+    }
+
+    let mut farm = Farm {
+        root,
+        dest: dest.as_ref(),
+        options,
+        src,
+        src_in_root,
+        links: 0,
+    };
+    farm.mirror(Path::new(""))?;
+
+    Ok(farm.links)
+}
+
+impl Farm<'_> {
+    // Mirror the directory `rel` of `src`.
+    fn mirror(&mut self, rel: &Path) -> Result<()> {
+        self.root.mkdir_all(self.dest.join(rel))?;
+
+        for it in fs::read_dir(self.src.join(rel))? {
+            let it = it?;
+            let rel = rel.join(it.file_name());
+            let file_type = it.file_type()?;
+            if file_type.is_dir() {
+                self.mirror(&rel)?;
+                continue;
+            }
+
+            let path = self.dest.join(&rel);
+            let target = match file_type.is_symlink() {
+                true => {
+                    let parent = rel.parent().unwrap_or_else(|| Path::new(""));
+                    Some(parent.join(fs::read_link(it.path())?))
+                }
+                false => None,
+            };
+            match (&self.src_in_root, target) {
+                (Some(src), target) => {
+                    let target = Path::new("/").join(sanitize(&src.join(target.unwrap_or(rel))));
+                    create_symlink(self.root, &path, &rewrite_link_target(&path, &target))?;
+                }
+                (None, target) => {
+                    let target = self.external_target(&self.src.join(target.unwrap_or(rel)))?;
+                    symlink(&target, entry_path(self.root, &path)?)?;
+                }
+            }
+            self.links += 1;
+        }
+
+        Ok(())
+    }
+
+    // Resolve the host `target` inside of the first of `options.external` it's lexically
+    // within, failing with `Error::Escape` if there's none. A ".." after a symlink on the way
+    // can't take it out of there, e.g. "sub/../x" with "sub -> /opt/tool" is lexically in
+    // "/opt/tool" but the kernel would go to "/opt/x".
+    fn external_target(&self, target: &Path) -> Result<PathBuf> {
+        let external = match self.options.external.iter().find(|e| target.is_within(e)) {
+            Some(external) => Root::new(external)?,
+            None => return Err(Error::Escape(target.to_path_buf())),
+        };
+        let rel = target
+            .strip_prefix(external.path())
+            .map_err(|_| Error::Escape(target.to_path_buf()))?;
+
+        external.check(rel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+
+    #[test]
+    fn test_symlink_farm() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            // the mirrored directory, inside of the rootfs unless it starts with "host/"
+            src: &'a str,
+            external: bool,
+            ok: bool,
+            links: &'a [(&'a str, &'a str)],
+        }
+
+        let tests = &[
+            TestData {
+                name: "inside of the rootfs",
+                src: "opt/tc",
+                external: false,
+                ok: true,
+                links: &[
+                    ("farm/bin/gcc", "../../opt/tc/bin/gcc"),
+                    ("farm/bin/cc", "../../opt/tc/bin/gcc"),
+                    ("farm/bin/up", "../../etc"),
+                    ("farm/lib/libc.so", "../../opt/tc/lib/libc.so"),
+                ],
+            },
+            TestData {
+                name: "outside of the rootfs, allowed",
+                src: "host/tc/lib",
+                external: true,
+                ok: true,
+                links: &[("farm/libc.so", "host/tc/lib/libc.so")],
+            },
+            TestData {
+                name: "outside of the rootfs",
+                src: "host/tc/lib",
+                external: false,
+                ok: false,
+                links: &[],
+            },
+            TestData {
+                name: "symlink out of the allowed directories",
+                src: "host/tc/bin",
+                external: true,
+                ok: false,
+                links: &[],
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let dir = tempfile::tempdir().expect("failed to create tmpdir");
+            let top = dir.path().canonicalize().unwrap();
+            TreeBuilder::new()
+                .file("tc/bin/ld", "")
+                .symlink("tc/bin/out", "/etc/passwd")
+                .file("tc/lib/libc.so", "")
+                .build_in(top.join("host"))
+                .unwrap();
+            TreeBuilder::new()
+                .file("opt/tc/bin/gcc", "")
+                .symlink("opt/tc/bin/cc", "gcc")
+                .symlink("opt/tc/bin/up", "../../../../../etc")
+                .file("opt/tc/lib/libc.so", "")
+                .build_in(top.join("rootfs"))
+                .unwrap();
+            let root = Root::new(top.join("rootfs")).unwrap();
+            let src = match t.src.strip_prefix("host/") {
+                Some(_) => top.join(t.src),
+                None => top.join("rootfs").join(t.src),
+            };
+            let options = FarmOptions {
+                external: match t.external {
+                    true => vec![top.join("host/tc")],
+                    false => Vec::new(),
+                },
+            };
+
+            let result = symlink_farm(&src, &root, "/farm", &options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            if let Ok(n) = result {
+                assert!(n == t.links.len() as u64, "{}", msg);
+            }
+            for (link, target) in t.links {
+                let result = fs::read_link(top.join("rootfs").join(link)).unwrap();
+                let target = match target.starts_with("host/") {
+                    true => top.join(target),
+                    false => PathBuf::from(target),
+                };
+                assert!(result == target, "{}: {}", msg, link);
+            }
+        }
+
+        // a ".." after a symlink to the allowed directory itself
+        let dir = tempfile::tempdir().expect("failed to create tmpdir");
+        let top = dir.path().canonicalize().unwrap();
+        TreeBuilder::new()
+            .file("tc/bin/ld", "")
+            .symlink("tc/bin/tc", top.join("host/tc").to_str().unwrap())
+            .symlink("tc/bin/up", "tc/../x")
+            .file("x", "")
+            .build_in(top.join("host"))
+            .unwrap();
+        fs::create_dir(top.join("rootfs")).unwrap();
+        let root = Root::new(top.join("rootfs")).unwrap();
+        let options = FarmOptions {
+            external: vec![top.join("host/tc")],
+        };
+        symlink_farm(top.join("host/tc/bin"), &root, "/farm", &options).unwrap();
+        let target = fs::read_link(top.join("rootfs/farm/up")).unwrap();
+        assert!(target.starts_with(top.join("host/tc")), "{:?}", target);
+        assert!(fs::canonicalize(top.join("rootfs/farm/up")).ok() != Some(top.join("host/x")));
+    }
+}
@@ -0,0 +1,275 @@
+// Checking out the entries of an untrusted repository into a work tree, the way git itself
+// protects a checkout. The "." and ".." of an entry path are refused, so is a ".git" component
+// in any of the spellings a case-insensitive filesystem, NTFS or HFS+ would take for it, two
+// entries only differing by their case, and a path going through a symlink.
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use crate::archive::{create_file, create_symlink, rewrite_link_target};
+use crate::copy::SymlinkPolicy;
+use crate::error::{Error, Result};
+use crate::root::Root;
+
+// The code points HFS+ ignores in a name, so ".g\u{200c}it" is ".git" there.
+const HFS_IGNORED: [(char, char); 4] = [
+    ('\u{200c}', '\u{200f}'),
+    ('\u{202a}', '\u{202e}'),
+    ('\u{206a}', '\u{206f}'),
+    ('\u{feff}', '\u{feff}'),
+];
+
+// Options of a `Checkout`.
+#[derive(Debug, Clone)]
+pub struct CheckoutOptions {
+    // What to do with the symlink entries, `SymlinkPolicy::Preserve` refuses the ones which
+    // don't stay inside of the work tree.
+    pub symlinks: SymlinkPolicy,
+}
+
+impl Default for CheckoutOptions {
+    fn default() -> Self {
+        CheckoutOptions {
+            symlinks: SymlinkPolicy::Preserve,
+        }
+    }
+}
+
+// Whether the component `name` is taken for ".git" by some filesystem: in any case, with
+// trailing dots or spaces or an NTFS stream like "::$INDEX_ALLOCATION", as the 8.3 short name
+// "git~1", or with the code points ignored by HFS+.
+pub fn is_dotgit(name: &OsStr) -> bool {
+    let name = name.to_string_lossy().to_lowercase();
+    let name: String = name
+        .chars()
+        .filter(|c| !HFS_IGNORED.iter().any(|(a, b)| (*a..=*b).contains(c)))
+        .collect();
+    let name = name.split(':').next().unwrap_or("");
+
+    name.trim_end_matches(['.', ' ']) == ".git" || name == "git~1"
+}
+
+// Check the entry path `path` of a repository: relative, with no "." or ".." and no ".git"
+// component, which fails with `Error::Denied`. Returns its components as a relative path.
+pub fn check_entry_path(path: &Path) -> Result<PathBuf> {
+    let mut checked = PathBuf::new();
+    for it in path.components() {
+        match it {
+            Component::Normal(c) if is_dotgit(c) => return Err(Error::Denied(path.to_path_buf())),
+            Component::Normal(c) => checked.push(c),
+            _ => return Err(Error::InvalidPath(path.to_path_buf())),
+        }
+    }
+
+    match checked.as_os_str().is_empty() {
+        true => Err(Error::InvalidPath(path.to_path_buf())),
+        false => Ok(checked),
+    }
+}
+
+// The checkout of the entries of a repository into the work tree at the rootfs of `root`.
+#[derive(Debug)]
+pub struct Checkout<'a> {
+    root: &'a Root,
+    options: CheckoutOptions,
+    // The paths checked out so far, by their lowercase spelling.
+    paths: HashMap<String, PathBuf>,
+}
+
+impl<'a> Checkout<'a> {
+    pub fn new(root: &'a Root, options: &CheckoutOptions) -> Checkout<'a> {
+        Checkout {
+            root,
+            options: options.clone(),
+            paths: HashMap::new(),
+        }
+    }
+
+    // Check the entry path `path` of the checkout: see `check_entry_path`, it can't differ only
+    // by its case from another path of the checkout, and none of its parents can be a symlink.
+    // Returns its components as a relative path.
+    pub fn check(&mut self, path: &Path) -> Result<PathBuf> {
+        let checked = check_entry_path(path)?;
+
+        let mut parent = PathBuf::new();
+        for it in checked.parent().into_iter().flat_map(Path::components) {
+            parent.push(it);
+            self.record(&parent)?;
+            let is_symlink = fs::symlink_metadata(self.root.path().join(&parent))
+                .is_ok_and(|m| m.file_type().is_symlink());
+            if is_symlink {
+                return Err(Error::Escape(path.to_path_buf()));
+            }
+        }
+        self.record(&checked)?;
+
+        Ok(checked)
+    }
+
+    // Check out the directory `path`, returns its host path.
+    pub fn dir(&mut self, path: &Path) -> Result<PathBuf> {
+        let path = self.check(path)?;
+
+        self.root.mkdir_all(path)
+    }
+
+    // Check out the file `path` with the permission bits `mode` and the content of `reader`,
+    // returns its host path.
+    pub fn file<R: Read>(&mut self, path: &Path, mode: u32, reader: &mut R) -> Result<PathBuf> {
+        let path = self.check(path)?;
+
+        create_file(self.root, &path, mode, reader)
+    }
+
+    // Check out the symlink `path` to `target` according to `options.symlinks`, returns its
+    // host path unless it was skipped.
+    pub fn symlink(&mut self, path: &Path, target: &Path) -> Result<Option<PathBuf>> {
+        let path = self.check(path)?;
+
+        match self.options.symlinks {
+            SymlinkPolicy::Skip => Ok(None),
+            SymlinkPolicy::Preserve => Ok(Some(create_symlink(self.root, &path, target)?)),
+            SymlinkPolicy::Rewrite => {
+                let target = rewrite_link_target(&path, target);
+                Ok(Some(create_symlink(self.root, &path, &target)?))
+            }
+        }
+    }
+
+    // Remember `path`, failing if another path of the checkout has the same lowercase spelling.
+    fn record(&mut self, path: &Path) -> Result<()> {
+        let key = path.to_string_lossy().to_lowercase();
+        match self.paths.get(&key) {
+            Some(seen) if seen != path => Err(Error::InvalidPath(path.to_path_buf())),
+            Some(_) => Ok(()),
+            None => {
+                self.paths.insert(key, path.to_path_buf());
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+    use std::io;
+
+    #[test]
+    fn test_is_dotgit() {
+        let tests = &[
+            (".git", true),
+            (".GIT", true),
+            (".Git. . ", true),
+            (".git::$INDEX_ALLOCATION", true),
+            ("GIT~1", true),
+            (".g\u{200c}it", true),
+            ("\u{feff}.git", true),
+            (".gitignore", false),
+            ("git", false),
+            ("git~2", false),
+            (".git~1", false),
+        ];
+
+        for (i, (name, result)) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, name);
+
+            assert!(is_dotgit(OsStr::new(name)) == *result, "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_checkout() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            // the entries checked out, with the target of the symlinks
+            entries: &'a [(&'a str, Option<&'a str>)],
+            symlinks: SymlinkPolicy,
+            ok: bool,
+        }
+
+        let tests = &[
+            TestData {
+                name: "plain entries",
+                entries: &[("src/main.rs", None), ("README", Some("src/main.rs"))],
+                symlinks: SymlinkPolicy::Preserve,
+                ok: true,
+            },
+            TestData {
+                name: "the .git directory",
+                entries: &[(".git/hooks/post-checkout", None)],
+                symlinks: SymlinkPolicy::Preserve,
+                ok: false,
+            },
+            TestData {
+                name: "an NTFS spelling of .git",
+                entries: &[("a/git~1/config", None)],
+                symlinks: SymlinkPolicy::Preserve,
+                ok: false,
+            },
+            TestData {
+                name: "path with ..",
+                entries: &[("a/../../x", None)],
+                symlinks: SymlinkPolicy::Preserve,
+                ok: false,
+            },
+            TestData {
+                name: "absolute path",
+                entries: &[("/x", None)],
+                symlinks: SymlinkPolicy::Preserve,
+                ok: false,
+            },
+            TestData {
+                name: "case collision",
+                entries: &[("A/x", None), ("a/y", None)],
+                symlinks: SymlinkPolicy::Preserve,
+                ok: false,
+            },
+            TestData {
+                name: "path through a symlink entry",
+                entries: &[("a", Some("b")), ("a/x", None)],
+                symlinks: SymlinkPolicy::Preserve,
+                ok: false,
+            },
+            TestData {
+                name: "symlink beyond the work tree",
+                entries: &[("a", Some("../../etc"))],
+                symlinks: SymlinkPolicy::Preserve,
+                ok: false,
+            },
+            TestData {
+                name: "symlink beyond the work tree, rewritten",
+                entries: &[("a", Some("/etc"))],
+                symlinks: SymlinkPolicy::Rewrite,
+                ok: true,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let tree = TreeBuilder::new().build().unwrap();
+            let root = tree.root().unwrap();
+            let options = CheckoutOptions {
+                symlinks: t.symlinks,
+            };
+            let mut checkout = Checkout::new(&root, &options);
+            let result = t.entries.iter().try_for_each(|(path, target)| {
+                let path = Path::new(path);
+                match target {
+                    Some(target) => checkout.symlink(path, Path::new(target)).map(|_| ()),
+                    None => checkout.file(path, 0o644, &mut io::empty()).map(|_| ()),
+                }
+            });
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            assert!(!tree.path().join(".git").exists(), "{}", msg);
+            assert!(!tree.path().join("b/x").exists(), "{}", msg);
+        }
+    }
+}
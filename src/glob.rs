@@ -0,0 +1,237 @@
+// Glob pattern expansion confined to a rootfs, see `Root::glob`.
+//
+// The leading components of the pattern without any wildcard are resolved inside of the
+// rootfs like any other path. The rest of the pattern is matched against the directory
+// entries, where symlinks can match but are never followed, so the expansion can't leave
+// the rootfs. A pattern component supports "*", "?" and "[...]" classes (negated with "!"
+// or "^"), and a "**" component matches any number of directories.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::root::Root;
+use crate::sys::WalkDir;
+use crate::walk::open_dir;
+
+// Expand `pattern` inside of the rootfs, returns the sorted matches relative to the rootfs.
+pub(crate) fn glob(root: &Root, pattern: &str) -> Result<Vec<PathBuf>> {
+    let components = pattern
+        .split('/')
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect::<Vec<_>>();
+    let literal = components.iter().take_while(|c| !is_pattern(c)).count();
+    if components[literal..].contains(&"..") {
+        return Err(Error::InvalidPath(PathBuf::from(pattern)));
+    }
+
+    let base = root.join(components[..literal].join("/"))?;
+    let base = base
+        .strip_prefix(root.path())
+        .map_err(|_| Error::Escape(base.clone()))?
+        .to_path_buf();
+
+    let mut matches = Vec::new();
+    if literal == components.len() {
+        if fs::symlink_metadata(root.path().join(&base)).is_ok() {
+            matches.push(base);
+        }
+    } else {
+        if let Some(dir) = skip_missing(open_dir(root, &base))? {
+            expand(&dir, &base, &components[literal..], &mut matches)?;
+        }
+        matches.sort();
+        matches.dedup();
+    }
+
+    Ok(matches)
+}
+
+fn is_pattern(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+// Match `components` under the directory `dir` opened at `path`, relative to the rootfs. The
+// subdirectories are opened relative to its fd without following them, so one swapped for a
+// symlink meanwhile isn't listed.
+fn expand(
+    dir: &WalkDir,
+    path: &Path,
+    components: &[&str],
+    matches: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let (first, rest) = match components.split_first() {
+        Some(it) => it,
+        None => {
+            matches.push(path.to_path_buf());
+            return Ok(());
+        }
+    };
+
+    if *first == "**" {
+        expand(dir, path, rest, matches)?;
+    }
+
+    for (name, m) in dir.entries()? {
+        let name = match name.to_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        let child = path.join(name);
+        let is_dir = m.is_dir();
+
+        if *first == "**" {
+            if is_dir {
+                if let Some(d) = skip_missing(dir.open_child(name.as_ref()).map_err(Error::Io))? {
+                    expand(&d, &child, components, matches)?;
+                }
+            }
+        } else if fnmatch(first.as_bytes(), name.as_bytes()) {
+            if rest.is_empty() {
+                matches.push(child);
+            } else if is_dir {
+                if let Some(d) = skip_missing(dir.open_child(name.as_ref()).map_err(Error::Io))? {
+                    expand(&d, &child, rest, matches)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The directory opened, or None if it's missing or not a directory anymore.
+fn skip_missing(opened: Result<WalkDir>) -> Result<Option<WalkDir>> {
+    match opened {
+        Ok(dir) => Ok(Some(dir)),
+        Err(Error::Io(e))
+            if e.kind() == io::ErrorKind::NotFound || e.kind() == io::ErrorKind::NotADirectory =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// Match a single path component against a pattern component.
+pub(crate) fn fnmatch(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| fnmatch(rest, &name[i..])),
+        Some((b'?', rest)) => !name.is_empty() && fnmatch(rest, &name[1..]),
+        Some((b'[', rest)) => match (class(rest, name.first()), name.split_first()) {
+            (Some((true, rest)), Some((_, name))) => fnmatch(rest, name),
+            (Some(_), _) => false,
+            // an unterminated class is matched literally
+            (None, _) => name.first() == Some(&b'[') && fnmatch(rest, &name[1..]),
+        },
+        Some((c, rest)) => name.first() == Some(c) && fnmatch(rest, &name[1..]),
+    }
+}
+
+// Match `c` against the class at the start of `pattern`, which follows the "[". Returns
+// whether it matched and the rest of the pattern after the "]", or None if the class isn't
+// terminated.
+fn class<'a>(pattern: &'a [u8], c: Option<&u8>) -> Option<(bool, &'a [u8])> {
+    let (negated, mut p) = match pattern.first() {
+        Some(b'!') | Some(b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    let mut matched = false;
+    let mut first = true;
+
+    loop {
+        match p {
+            [] => return None,
+            [b']', rest @ ..] if !first => return Some((c.is_some() && matched != negated, rest)),
+            [lo, b'-', hi, rest @ ..] if *hi != b']' => {
+                matched |= c.is_some_and(|c| lo <= c && c <= hi);
+                p = rest;
+            }
+            [x, rest @ ..] => {
+                matched |= c == Some(x);
+                p = rest;
+            }
+        }
+        first = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_glob() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            pattern: &'a str,
+            result: &'a [&'a str],
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        let outside_dir = tempdir().expect("failed to create tmpdir");
+        fs::write(outside_dir.path().join("leak.json"), b"").unwrap();
+
+        for d in &["logs/a/b", "logs/c", "var"] {
+            root.mkdir_all(d).unwrap();
+        }
+        for f in &[
+            "logs/x.json",
+            "logs/a/y.json",
+            "logs/a/b/z.json",
+            "logs/c/w.txt",
+        ] {
+            fs::write(root.path().join(f), b"").unwrap();
+        }
+        symlink(outside_dir.path(), root.path().join("logs/out")).unwrap();
+        symlink("/logs", root.path().join("var/log")).unwrap();
+
+        let tests = &[
+            TestData {
+                pattern: "logs/**/*.json",
+                result: &["logs/a/b/z.json", "logs/a/y.json", "logs/x.json"],
+            },
+            TestData {
+                pattern: "logs/*",
+                result: &["logs/a", "logs/c", "logs/out", "logs/x.json"],
+            },
+            TestData {
+                pattern: "/../logs/?/*.[jt][!z]*",
+                result: &["logs/a/y.json", "logs/c/w.txt"],
+            },
+            TestData {
+                pattern: "var/log/*.json",
+                result: &["logs/x.json"],
+            },
+            TestData {
+                pattern: "logs/out/*",
+                result: &[],
+            },
+            TestData {
+                pattern: "logs/c",
+                result: &["logs/c"],
+            },
+            TestData {
+                pattern: "nothing/*",
+                result: &[],
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, t);
+
+            let result = root.glob(t.pattern).unwrap();
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            let expected = t.result.iter().map(PathBuf::from).collect::<Vec<_>>();
+            assert!(result == expected, "{}", msg);
+        }
+
+        assert!(root.glob("logs/*/../..").is_err());
+    }
+}
@@ -0,0 +1,206 @@
+// Path validation for the agents running inside of a VM sandbox, like the kata-containers
+// agent: the virtio devices hot-plugged by the host, the storage sources shared below
+// "/run/kata-containers" and the paths relative to the sandbox directory.
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::root::Root;
+
+// The directory of the kata agent inside of the guest.
+pub const KATA_GUEST_DIR: &str = "/run/kata-containers";
+
+// The directories the helpers validate against, the kata ones by default.
+#[derive(Debug, Clone)]
+pub struct GuestPaths {
+    // The device directory, "/dev".
+    pub dev_dir: PathBuf,
+    // The agent directory with the shared storage, `KATA_GUEST_DIR`.
+    pub run_dir: PathBuf,
+}
+
+impl Default for GuestPaths {
+    fn default() -> Self {
+        GuestPaths {
+            dev_dir: PathBuf::from("/dev"),
+            run_dir: PathBuf::from(KATA_GUEST_DIR),
+        }
+    }
+}
+
+impl GuestPaths {
+    // Validate the path of a virtio device passed by the host, e.g. "/dev/vda" or a symlink
+    // like "/dev/disk/by-path/..." to it. The device must resolve to a block device directly
+    // in the device directory named like a virtio-blk ("vd*"), virtio-scsi ("sd*") or
+    // virtio-pmem ("pmem*") disk or partition. Returns the canonical path of the device.
+    pub fn validate_device<P: AsRef<Path>>(&self, device: P) -> Result<PathBuf> {
+        let device = device.as_ref();
+        let dev_dir = self.dev_dir.canonicalize()?;
+        let path = device.canonicalize()?;
+
+        let name = match path.strip_prefix(&dev_dir).ok().and_then(|p| p.to_str()) {
+            Some(name) if is_virtio_disk(name) => name,
+            _ => return Err(Error::InvalidPath(device.to_path_buf())),
+        };
+        if !fs::metadata(&path)?.file_type().is_block_device() {
+            return Err(Error::InvalidPath(device.to_path_buf()));
+        }
+
+        Ok(dev_dir.join(name))
+    }
+
+    // Validate the mount source `source` of a storage shared by the host, an absolute path
+    // which must stay below the agent directory. A symlink or ".." leaving it fails with
+    // `Error::Escape`. Returns the resolved path.
+    pub fn validate_storage_source<P: AsRef<Path>>(&self, source: P) -> Result<PathBuf> {
+        let source = source.as_ref();
+        let relative = source
+            .strip_prefix(&self.run_dir)
+            .map_err(|_| Error::Escape(source.to_path_buf()))?;
+
+        Root::new(&self.run_dir)?.check(relative)
+    }
+
+    // Resolve `path` relative to the directory of the sandbox, "sandbox" in the agent
+    // directory, like `Root::check`.
+    pub fn sandbox_path<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        Root::new(self.run_dir.join("sandbox"))?.check(path)
+    }
+}
+
+// Whether `name` is a virtio disk or partition: "vda", "vdb1", "sdaa", "pmem0" or "pmem0p1".
+fn is_virtio_disk(name: &str) -> bool {
+    let letters = |s: &str, prefix: &str| -> bool {
+        let Some(rest) = s.strip_prefix(prefix) else {
+            return false;
+        };
+        let disk = rest.trim_end_matches(|c: char| c.is_ascii_digit());
+        !disk.is_empty() && disk.bytes().all(|c| c.is_ascii_lowercase())
+    };
+    let pmem = || -> bool {
+        let Some(rest) = name.strip_prefix("pmem") else {
+            return false;
+        };
+        let (disk, partition) = match rest.split_once('p') {
+            Some((disk, partition)) => (disk, Some(partition)),
+            None => (rest, None),
+        };
+        let digits = |s: &str| !s.is_empty() && s.bytes().all(|c| c.is_ascii_digit());
+        digits(disk) && partition.is_none_or(digits)
+    };
+
+    letters(name, "vd") || letters(name, "sd") || pmem()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::root::{Device, DeviceType, RootOptions};
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_guest_paths() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            source: &'a str,
+            result: Option<&'a str>,
+        }
+
+        let dir = tempdir().expect("failed to create tmpdir");
+        let dir_path = dir.path().canonicalize().unwrap();
+        let paths = GuestPaths {
+            dev_dir: dir_path.join("dev"),
+            run_dir: dir_path.join("run"),
+        };
+        fs::create_dir_all(paths.run_dir.join("shared/containers/c1")).unwrap();
+        fs::create_dir_all(paths.run_dir.join("sandbox/storage")).unwrap();
+        symlink("/etc", paths.run_dir.join("shared/out")).unwrap();
+        symlink("../../..", paths.run_dir.join("shared/up")).unwrap();
+
+        let tests = &[
+            TestData {
+                name: "shared container directory",
+                source: "shared/containers/c1",
+                result: Some("shared/containers/c1"),
+            },
+            TestData {
+                name: "missing directory",
+                source: "shared/containers/c2/rootfs",
+                result: Some("shared/containers/c2/rootfs"),
+            },
+            TestData {
+                name: "absolute symlink, resolved in the agent directory",
+                source: "shared/out/passwd",
+                result: Some("etc/passwd"),
+            },
+            TestData {
+                name: "relative symlink climbing out",
+                source: "shared/up/etc",
+                result: None,
+            },
+            TestData {
+                name: ".. climbing out",
+                source: "shared/../../etc",
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = paths.validate_storage_source(paths.run_dir.join(t.source));
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some(p) => assert!(result.unwrap() == paths.run_dir.join(p), "{}", msg),
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+
+        assert!(paths.validate_storage_source("/etc/passwd").is_err());
+        assert_eq!(
+            paths.sandbox_path("../storage/x").unwrap_err().to_string(),
+            Error::Escape(PathBuf::from("../storage/x")).to_string()
+        );
+        assert_eq!(
+            paths.sandbox_path("/storage/x").unwrap(),
+            paths.run_dir.join("sandbox/storage/x")
+        );
+
+        for name in ["vda", "vdb1", "sdaa", "pmem0", "pmem0p1"] {
+            assert!(is_virtio_disk(name), "{}", name);
+        }
+        for name in [
+            "vd", "vd1", "null", "pmem", "pmemp1", "pmem0p", "tty0", "vdA",
+        ] {
+            assert!(!is_virtio_disk(name), "{}", name);
+        }
+
+        // creating a block device needs CAP_MKNOD
+        fs::create_dir_all(paths.dev_dir.join("disk/by-path")).unwrap();
+        fs::write(paths.dev_dir.join("vdz"), b"").unwrap();
+        assert!(paths.validate_device(paths.dev_dir.join("vdz")).is_err());
+        let options = RootOptions {
+            devices: Some(vec![Device {
+                kind: DeviceType::Block,
+                major: 254,
+                minor: 0,
+            }]),
+            ..Default::default()
+        };
+        let root = Root::with_options(&paths.dev_dir, options).unwrap();
+        if root.mknod("vda", DeviceType::Block, 254, 0).is_ok() {
+            symlink("../../vda", paths.dev_dir.join("disk/by-path/pci-0")).unwrap();
+            let device = paths.dev_dir.join("disk/by-path/pci-0");
+            assert_eq!(
+                paths.validate_device(device).unwrap(),
+                paths.dev_dir.join("vda")
+            );
+        }
+        assert!(paths.validate_device("/dev/null").is_err());
+    }
+}
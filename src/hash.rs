@@ -0,0 +1,194 @@
+// Digests of the files inside of a rootfs, see `Root::hash_file`. SHA-256 needs the `sha2`
+// feature and BLAKE3 the `blake3` feature.
+use std::fmt;
+use std::io::{self, Read};
+
+// The hash algorithms of `Root::hash_file`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    #[cfg(feature = "sha2")]
+    Sha256,
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+// The digest of a file, displayed in lowercase hex like sha256sum and b3sum print it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digest {
+    algorithm: HashAlgorithm,
+    bytes: Vec<u8>,
+}
+
+impl Digest {
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in &self.bytes {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+// Stream the content of `reader` into the digest, in blocks of 64KiB.
+pub(crate) fn hash_reader<R: Read>(reader: &mut R, algorithm: HashAlgorithm) -> io::Result<Digest> {
+    let mut hasher = Hasher::new(algorithm);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(Digest {
+        algorithm,
+        bytes: hasher.finish(),
+    })
+}
+
+enum Hasher {
+    #[cfg(feature = "sha2")]
+    Sha256(sha2::Sha256),
+    #[cfg(feature = "blake3")]
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            #[cfg(feature = "sha2")]
+            HashAlgorithm::Sha256 => Hasher::Sha256(sha2::Digest::new()),
+            #[cfg(feature = "blake3")]
+            HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            #[cfg(feature = "sha2")]
+            Hasher::Sha256(h) => sha2::Digest::update(h, data),
+            #[cfg(feature = "blake3")]
+            Hasher::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            #[cfg(feature = "sha2")]
+            Hasher::Sha256(h) => sha2::Digest::finalize(h).to_vec(),
+            #[cfg(feature = "blake3")]
+            Hasher::Blake3(h) => h.finalize().as_bytes().to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+
+    #[test]
+    fn test_hash_file() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            algorithm: HashAlgorithm,
+            result: Option<&'a str>,
+        }
+
+        let tree = TreeBuilder::new()
+            .file("etc/abc", "abc")
+            .file("etc/big", vec![b'x'; 200 * 1024])
+            .symlink("l", "/etc/abc")
+            .symlink("up", "../../../etc")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let _ = rustix::fs::mknodat(
+            rustix::fs::CWD,
+            tree.path().join("etc/fifo"),
+            rustix::fs::FileType::Fifo,
+            rustix::fs::Mode::from_raw_mode(0o644),
+            0,
+        );
+
+        let tests = &[
+            #[cfg(feature = "sha2")]
+            TestData {
+                name: "sha256",
+                path: "etc/abc",
+                algorithm: HashAlgorithm::Sha256,
+                result: Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            },
+            #[cfg(feature = "sha2")]
+            TestData {
+                name: "sha256 through a symlink",
+                path: "l",
+                algorithm: HashAlgorithm::Sha256,
+                result: Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            },
+            #[cfg(feature = "sha2")]
+            TestData {
+                name: "sha256 of several blocks",
+                path: "etc/big",
+                algorithm: HashAlgorithm::Sha256,
+                result: Some("5d4abf60daba0b11555b6da942dd4f40d5f99ee617e14ba4ba686b47d75a5049"),
+            },
+            #[cfg(feature = "blake3")]
+            TestData {
+                name: "blake3",
+                path: "etc/abc",
+                algorithm: HashAlgorithm::Blake3,
+                result: Some("6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+            },
+            #[cfg(feature = "blake3")]
+            TestData {
+                name: "directory",
+                path: "etc",
+                algorithm: HashAlgorithm::Blake3,
+                result: None,
+            },
+            #[cfg(feature = "blake3")]
+            TestData {
+                name: "fifo",
+                path: "etc/fifo",
+                algorithm: HashAlgorithm::Blake3,
+                result: None,
+            },
+            #[cfg(feature = "blake3")]
+            TestData {
+                name: "relative symlink beyond the rootfs, clamped",
+                path: "up/abc",
+                algorithm: HashAlgorithm::Blake3,
+                result: Some("6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"),
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = root.hash_file(t.path, t.algorithm);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some(digest) => assert!(result.unwrap().to_string() == digest, "{}", msg),
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+    }
+}
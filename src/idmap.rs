@@ -0,0 +1,234 @@
+// Uid and gid mappings in the format of "/proc/<pid>/uid_map", for the rootfs on an
+// id-mapped mount or owned by a user namespace: each line maps `count` ids starting at
+// `inside` to the ids starting at `outside`.
+use std::fs;
+use std::io;
+
+use crate::error::{Error, Result};
+
+// One line of a mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdRange {
+    pub inside: u32,
+    pub outside: u32,
+    pub count: u32,
+}
+
+// A uid or gid mapping.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdMap {
+    pub ranges: Vec<IdRange>,
+}
+
+// The uid and gid mappings of a mount or of a user namespace.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdMapping {
+    pub uids: IdMap,
+    pub gids: IdMap,
+}
+
+impl IdMap {
+    // Parse a mapping like "0 100000 65536", one range per line. A range running past the
+    // largest id, inside or outside, is refused.
+    pub fn parse(s: &str) -> Result<IdMap> {
+        let ranges = s
+            .lines()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| {
+                let mut it = l.split_whitespace().map(|n| n.parse::<u32>());
+                match (it.next(), it.next(), it.next(), it.next()) {
+                    (Some(Ok(inside)), Some(Ok(outside)), Some(Ok(count)), None)
+                        if inside.checked_add(count).is_some()
+                            && outside.checked_add(count).is_some() =>
+                    {
+                        Ok(IdRange {
+                            inside,
+                            outside,
+                            count,
+                        })
+                    }
+                    _ => Err(Error::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("invalid id mapping: {:?}", l),
+                    ))),
+                }
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(IdMap { ranges })
+    }
+
+    // Map the id `inside` of the mapping to the id outside of it.
+    pub fn to_outside(&self, inside: u32) -> Option<u32> {
+        self.ranges.iter().find_map(|r| {
+            let offset = inside.checked_sub(r.inside).filter(|o| *o < r.count)?;
+            r.outside.checked_add(offset)
+        })
+    }
+
+    // Map the id `outside` of the mapping back to the id inside of it.
+    pub fn to_inside(&self, outside: u32) -> Option<u32> {
+        self.ranges.iter().find_map(|r| {
+            let offset = outside.checked_sub(r.outside).filter(|o| *o < r.count)?;
+            r.inside.checked_add(offset)
+        })
+    }
+}
+
+impl IdMapping {
+    // The mappings of the user namespace of the process `pid`, e.g. of a container's init
+    // whose user namespace was used to create the id-mapped mount of its rootfs.
+    pub fn from_pid(pid: u32) -> Result<IdMapping> {
+        IdMapping::read(&pid.to_string())
+    }
+
+    // The mappings of the user namespace of this process. Inside of a user namespace, e.g.
+    // when running rootless, chown() takes and stat() returns the ids inside of it.
+    pub fn current() -> Result<IdMapping> {
+        IdMapping::read("self")
+    }
+
+    fn read(pid: &str) -> Result<IdMapping> {
+        Ok(IdMapping {
+            uids: IdMap::parse(&fs::read_to_string(format!("/proc/{}/uid_map", pid))?)?,
+            gids: IdMap::parse(&fs::read_to_string(format!("/proc/{}/gid_map", pid))?)?,
+        })
+    }
+
+    // Map the owner `uid`, `gid` inside of the mapping to the ids outside of it.
+    pub(crate) fn owner_to_outside(&self, uid: u32, gid: u32) -> Result<(u32, u32)> {
+        let uid = self
+            .uids
+            .to_outside(uid)
+            .ok_or_else(|| unmapped("uid", uid, "is not mapped"))?;
+        let gid = self
+            .gids
+            .to_outside(gid)
+            .ok_or_else(|| unmapped("gid", gid, "is not mapped"))?;
+
+        Ok((uid, gid))
+    }
+
+    // Map the owner `uid`, `gid` outside of the mapping back to the ids inside of it.
+    pub(crate) fn owner_to_inside(&self, uid: u32, gid: u32) -> Result<(u32, u32)> {
+        let uid = self
+            .uids
+            .to_inside(uid)
+            .ok_or_else(|| unmapped("uid", uid, "is not mapped"))?;
+        let gid = self
+            .gids
+            .to_inside(gid)
+            .ok_or_else(|| unmapped("gid", gid, "is not mapped"))?;
+
+        Ok((uid, gid))
+    }
+
+    // Map the host owner `uid`, `gid` to the ids of this user namespace, the mapping of
+    // `IdMapping::current`, which chown() takes.
+    pub(crate) fn owner_to_userns(&self, uid: u32, gid: u32) -> Result<(u32, u32)> {
+        let context = "has no mapping in the user namespace of this process";
+        let uid = self
+            .uids
+            .to_inside(uid)
+            .ok_or_else(|| unmapped("uid", uid, context))?;
+        let gid = self
+            .gids
+            .to_inside(gid)
+            .ok_or_else(|| unmapped("gid", gid, context))?;
+
+        Ok((uid, gid))
+    }
+}
+
+fn unmapped(kind: &str, id: u32, context: &str) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} {} {}", kind, id, context),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_id_map() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            inside: u32,
+            outside: Option<u32>,
+        }
+
+        let map = IdMap::parse("0 100000 1000\n1000 1000 1\n\n1001 101001 64535\n").unwrap();
+
+        let tests = &[
+            TestData {
+                name: "root",
+                inside: 0,
+                outside: Some(100000),
+            },
+            TestData {
+                name: "end of the first range",
+                inside: 999,
+                outside: Some(100999),
+            },
+            TestData {
+                name: "identity range",
+                inside: 1000,
+                outside: Some(1000),
+            },
+            TestData {
+                name: "last range",
+                inside: 65535,
+                outside: Some(165535),
+            },
+            TestData {
+                name: "unmapped",
+                inside: 65536,
+                outside: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = map.to_outside(t.inside);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result == t.outside, "{}", msg);
+            if let Some(outside) = t.outside {
+                assert!(map.to_inside(outside) == Some(t.inside), "{}", msg);
+            }
+        }
+
+        assert!(IdMap::parse("0 100000").is_err());
+        assert!(IdMap::parse("0 100000 x").is_err());
+        assert!(IdMap::parse("0 1 2 3").is_err());
+        assert!(IdMap::parse("0 4294967000 65536").is_err());
+        assert!(IdMap::parse("4294967000 0 65536").is_err());
+        assert!(IdMap::parse("0 0 4294967295").is_ok());
+        let overflowing = IdMap {
+            ranges: vec![IdRange {
+                inside: 0,
+                outside: u32::MAX,
+                count: 2,
+            }],
+        };
+        assert_eq!(overflowing.to_outside(1), None);
+        assert_eq!(overflowing.to_inside(u32::MAX), Some(0));
+
+        let mapping = IdMapping::from_pid(std::process::id()).unwrap();
+        assert!(!mapping.uids.ranges.is_empty());
+        assert!(IdMapping::current().unwrap() == mapping);
+
+        let rootless = IdMapping {
+            uids: IdMap::parse("0 1000 1\n1 100000 65536").unwrap(),
+            gids: IdMap::parse("0 1000 1\n1 100000 65536").unwrap(),
+        };
+        assert_eq!(rootless.owner_to_userns(100000, 1000).unwrap(), (1, 0));
+        let err = rootless.owner_to_userns(0, 0).unwrap_err().to_string();
+        assert!(err.contains("uid 0 has no mapping"), "{}", err);
+    }
+}
@@ -0,0 +1,203 @@
+// Application of OCI and Docker image layers onto a rootfs.
+//
+// A layer is a tar archive whose entries are unpacked like `archive::tar::unpack_secure`
+// does, except for the whiteout markers: an entry named ".wh.<name>" deletes <name> from
+// the lower layers, and an entry named ".wh..wh..opq" hides everything the lower layers
+// put in its directory.
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use ::tar::Archive;
+
+use crate::archive::{sanitize, ExtractOptions, Extractor, SymlinkPolicy};
+use crate::error::{Error, Result};
+use crate::root::Root;
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
+
+// Apply the layer `archive` onto the rootfs. The absolute symlinks most images hold, like
+// "bin/sh -> /bin/busybox", are rewritten to stay inside of the rootfs with
+// `SymlinkPolicy::Rewrite`.
+pub fn apply_layer<R: Read>(archive: &mut Archive<R>, root: &Root) -> Result<()> {
+    let options = ExtractOptions {
+        symlinks: SymlinkPolicy::Rewrite,
+        ..Default::default()
+    };
+
+    apply_layer_with(archive, root, &options)
+}
+
+// Like `apply_layer`, with `options`, e.g. to bound the size of the layer with
+// `options.limits`. All the entries of the layer are extracted by one `Extractor`.
+pub fn apply_layer_with<R: Read>(
+    archive: &mut Archive<R>,
+    root: &Root,
+    options: &ExtractOptions,
+) -> Result<()> {
+    let mut extractor = Extractor::new(root, options);
+    // Paths added by this layer, an opaque directory only hides the lower layers.
+    let mut added: HashSet<PathBuf> = HashSet::new();
+    let mut opaque: Vec<PathBuf> = Vec::new();
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = sanitize(&entry.path()?);
+        let file_name = match name.file_name().and_then(|n| n.to_str()) {
+            Some(n) => n.to_string(),
+            None => {
+                extractor.extract(&mut entry)?;
+                continue;
+            }
+        };
+        let parent = name.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+
+        if file_name == WHITEOUT_OPAQUE {
+            opaque.push(parent);
+        } else if let Some(hidden) = file_name.strip_prefix(WHITEOUT_PREFIX) {
+            if hidden.is_empty() || hidden == "." || hidden == ".." {
+                return Err(Error::InvalidPath(name));
+            }
+            remove(&root.join(&parent)?.join(hidden))?;
+        } else {
+            extractor.extract(&mut entry)?;
+            for it in name.ancestors() {
+                added.insert(it.to_path_buf());
+            }
+        }
+    }
+
+    for dir in opaque {
+        prune(root, &dir, &added)?;
+    }
+
+    Ok(())
+}
+
+// Remove everything under `dir` which wasn't added by the current layer.
+fn prune(root: &Root, dir: &Path, added: &HashSet<PathBuf>) -> Result<()> {
+    let path = root.join(dir)?;
+    let entries = match fs::read_dir(&path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    for it in entries {
+        let it = it?;
+        let name = dir.join(it.file_name());
+        if !added.contains(&name) {
+            remove(&it.path())?;
+        } else if it.file_type()?.is_dir() {
+            prune(root, &name, added)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Remove `path` without following it if it is a symlink.
+fn remove(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => fs::remove_dir_all(path)?,
+        Ok(_) => fs::remove_file(path)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ExtractLimits;
+    use ::tar::{Builder, EntryType, Header};
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_apply_layer() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+
+        // the lower layer
+        for d in &["etc", "opt/app/lib", "var"] {
+            root.mkdir_all(d).unwrap();
+        }
+        for f in &[
+            "etc/passwd",
+            "etc/shadow",
+            "opt/app/lib/a.so",
+            "opt/app/bin",
+        ] {
+            fs::write(root.path().join(f), b"lower").unwrap();
+        }
+        symlink("/etc", root.path().join("var/etc")).unwrap();
+
+        let entries = &[
+            "etc/.wh.shadow",
+            "opt/app/new",
+            "opt/app/.wh..wh..opq",
+            "opt/app/lib/b.so",
+            "var/.wh.etc",
+            "../.wh.passwd",
+        ];
+        let hostile = &["etc/.wh..."];
+        let layer = |entries: &[&str]| {
+            let mut builder = Builder::new(Vec::new());
+            for name in entries {
+                let mut header = Header::new_gnu();
+                header.set_entry_type(EntryType::Regular);
+                header.set_mode(0o644);
+                header.set_size(0);
+                let raw = &mut header.as_old_mut().name;
+                raw[..name.len()].copy_from_slice(name.as_bytes());
+                header.set_cksum();
+                builder.append(&header, &b""[..]).unwrap();
+            }
+            builder.into_inner().unwrap()
+        };
+        apply_layer(&mut Archive::new(layer(entries).as_slice()), &root).unwrap();
+        assert!(apply_layer(&mut Archive::new(layer(hostile).as_slice()), &root).is_err());
+
+        let exists = |p: &str| fs::symlink_metadata(root.path().join(p)).is_ok();
+        assert!(exists("etc/passwd"));
+        assert!(!exists("etc/shadow"));
+        assert!(exists("opt/app/new"));
+        assert!(exists("opt/app/lib/b.so"));
+        assert!(!exists("opt/app/lib/a.so"));
+        assert!(!exists("opt/app/bin"));
+        assert!(!exists("var/etc"));
+        assert!(exists("etc"));
+
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        header.set_path("bin/sh").unwrap();
+        header.set_link_name("/bin/busybox").unwrap();
+        header.set_cksum();
+        builder.append(&header, &b""[..]).unwrap();
+        let data = builder.into_inner().unwrap();
+        apply_layer(&mut Archive::new(data.as_slice()), &root).unwrap();
+        let target = fs::read_link(root.path().join("bin/sh")).unwrap();
+        assert_eq!(target, Path::new("busybox"));
+
+        let options = ExtractOptions {
+            limits: ExtractLimits {
+                files: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = apply_layer_with(
+            &mut Archive::new(layer(entries).as_slice()),
+            &root,
+            &options,
+        );
+        assert!(matches!(result, Err(Error::LimitExceeded(..))));
+    }
+}
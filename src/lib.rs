@@ -11,5 +11,156 @@ let p = "../../../a/b/c";
 
 assert_eq!("/home/rootfs/a/b/c", secure_join(rootfs, p));
 ```
+
+For a `root::Root` the path is resolved one component at a time on directory fds, with
+`openat2`, `statx` and `readlinkat` on Linux, so symlinks are never followed by the kernel.
+The same resolver works on any filesystem implementing `vfs::Vfs`, see `vfs::resolve_in`, and
+`vfs::resolve_with` runs it deterministically on the in-memory tree `vfs::MockVfs` for
+fuzzers and property tests.
+The small files of a rootfs, like its configs, are read with `root::Root::read` and
+`root::Root::read_to_string`, written with `root::Root::write` and `root::Root::append`, and
+truncated with `root::Root::set_len`. The times of an entry are restored without following
+it by `root::Root::set_times`. Directories get their exact mode regardless of the
+umask with `root::Root::create_dir_with`. The agents sharing a rootfs coordinate with the
+advisory locks of `root::Root::lock`, and the `mmap` feature maps large files read-only with
+`root::Root::mmap`. Files are hashed inside of the rootfs with `root::Root::hash_file`, with
+the `sha2` and `blake3` features.
+A resolution fails with `error::Error::Race` when one of its components is renamed or
+swapped while it runs, and is retried with a jittered backoff with `root::RaceRetry`.
+`error::Error::category` tells the refused paths from the missing ones, e.g. for a 403 or
+a 404.
+The `test-util` feature provides `test_util::TreeBuilder`, which creates the trees of
+directories, files and symlinks of a test in a temporary directory.
+
+Files are copied from one rootfs to another with `copy::copy`, or cloned copy on write with
+`copy::copy_with` where the filesystem supports it, and moved with `copy::move_entry`. A
+host tree is synced into a rootfs by `copy::sync_into`, which only copies the files that
+changed. A directory is mirrored into a rootfs as a farm of symlinks which stay inside of it
+by `farm::symlink_farm`. The modes, owners and xattrs of a manifest are applied to a rootfs
+all or nothing by `manifest::apply_manifest`, and a restore manifest of directories, files
+and symlinks is materialized with `restore::restore`, which reports each entry on its own.
+An update of a directory is built out of sight with `root::Root::staging` and promoted in
+place at once by `staging::Staging::promote`.
+Untrusted archives can be unpacked inside of a rootfs with the `tar`, `zip`, `cpio` and
+`ar` features, see the `archive` module, and the entries of other formats by implementing
+`archive::ArchiveEntrySanitizer` for them, within the sizes and counts of
+`archive::ExtractLimits`. The entries of an untrusted repository are checked out
+into a work tree with `git::Checkout`, which refuses the ".git" paths and the case
+collisions like git does. The `oci-spec` feature validates the paths of an
+OCI runtime spec against its rootfs, see `oci::validate_spec`, and image layers with
+whiteouts are applied by `layers::apply_layer` with the `tar` feature. The `notify` feature
+watches paths inside of a rootfs, see `watch::RootWatcher`. Web servers can resolve raw URL
+paths inside of a document root with `url::join_url_path`, which normalizes them to NFC
+with the `unicode-normalization` feature, and serve its files with `serve::resolve_request`
+or the `tower` service `serve::service::ServeRoot`. `serve::serve_open` opens the regular
+file of a request refusing every symlink. The index file of a resolved directory is
+found without following symlinks by `serve::resolve_index`. The resolution itself refuses the
+dotfiles like ".git" with `root::RootOptions::deny_dotfiles`, and only goes to the paths of
+an `allowlist::PathAllowlist` with `root::RootOptions::allowlist`. Path policies can be
+loaded from TOML or JSON with the `toml` and `serde_json` features, see `policy::Policy`.
+Seccomp user-notification supervisors resolve the path arguments of a target process with
+`seccomp::resolve_in_target`, and other supervisors resolve paths as a process in another
+mount namespace sees them with `ns::resolve_in_ns`, or from inside of the namespace with the
+`setns` feature. The paths of a container are resolved or opened from the pid of its init
+with `ns::resolve_in_container` and `ns::open_in_container`. The `chroot` feature resolves
+paths with the kernel's own semantics from inside of a chroot, see `ns::resolve_chroot`,
+and the `sandbox` feature opens paths from a child process pivoted into the rootfs, see
+`sandbox::open_sandboxed`. Bind mounts inside of a rootfs exposing directories from outside
+of it are found by `mounts::check_bind_mounts`, and the `procfs` feature reports all the
+mounts a resolved path traverses, see `mounts::traversed_mounts`. The source and
+destination of a bind mount into a rootfs are checked by `mounts::validate_mount`. The
+`pathrs` module mirrors the `Root` and `Handle` API of libpathrs for the projects migrating
+from it. Paths are translated back from the host with
+`secure_join::host_to_root`, and in both directions through several mounts with
+`mapper::PathMapper`. The agents inside of a VM sandbox validate their device, storage
+and sandbox paths with `guest::GuestPaths`.
+
+Every step of a single resolution is reported by `root::Root::join_traced`, see
+`trace::Step`, and explained in a single sentence for the error output of a CLI by
+`root::Root::explain`. The disk usage of a subtree is reported by `root::Root::disk_usage`,
+and its entries matching a predicate by `root::Root::find`. `root::Root::walk_in` walks a
+subtree without leaving the rootfs. A `secure_path_buf::SecurePathBuf` carries a resolved
+path through APIs taking a `Path`. The `secure_path_buf::Resolved` of `root::Root::resolve`
+borrows its `Root` to open the path. With the `serde` feature a `SecurePathBuf` is
+serialized relative to its rootfs and deserialized inside of a
+`Root` with `secure_path_buf::SecurePathSeed`. Guards check the containment of any path with
+`path_ext::PathExt::is_within`. Daemons resolving a path once to use it many times pin it with
+`root::Root::pin`, which fails once its parent directory was replaced. The directories and
+files a create or rename would add are planned without touching the rootfs, see the `plan`
+module. With the `tracing` feature every resolution runs in a `resolve` span, with events
+for each symlink followed, ".." clamped at the rootfs and escape rejected. The `log` feature
+emits warn-level records for the clamped and rejected inputs instead. The `metrics` feature
+counts the resolutions in `securepath_resolutions_total` and the clamped or rejected inputs
+in `securepath_escape_attempts_total`, and records the symlinks followed per resolution in the
+`securepath_symlink_hops` histogram. Every mutating operation of a `root::Root` and every
+rejected path is recorded to the `audit::AuditSink` of its options. The inputs which had to
+be clamped or rejected are also passed inline to the `root::RootOptions::on_escape_attempt`
+callback, see `trace::EscapeEvent`.
+
+Shell scripts use the `secure-path` command built with the `cli` feature, e.g.
+`secure-path join --strict ROOT PATH`, which prints JSON objects for other tools with
+`--json`.
+C and C++ callers can use the C API of the `capi` workspace member, see
+`capi/include/secure_path.h`, and Python callers the `secure_path` module built from the
+`python` workspace member. The `uniffi` workspace member generates Kotlin and Swift
+bindings.
+
+No function of the library panics on the content of a rootfs, hostile names and trees are
+reported as errors.
 */
+// the tests may unwrap, the library may not
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+#![cfg_attr(test, allow(clippy::unwrap_used, clippy::expect_used))]
+pub mod allowlist;
+pub mod archive;
+pub mod audit;
+pub mod copy;
+pub mod error;
+pub mod farm;
+pub mod git;
+mod glob;
+pub mod guest;
+#[cfg(any(feature = "sha2", feature = "blake3"))]
+pub mod hash;
+pub mod idmap;
+#[cfg(feature = "tar")]
+pub mod layers;
+pub mod lock;
+pub mod manifest;
+pub mod mapper;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(target_os = "linux")]
+pub mod mounts;
+#[cfg(target_os = "linux")]
+pub mod ns;
+#[cfg(feature = "oci-spec")]
+pub mod oci;
+pub mod path_ext;
+#[cfg(target_os = "linux")]
+pub mod pathrs;
+pub mod pinned;
+pub mod plan;
+pub mod policy;
+mod resolve;
+pub mod restore;
+pub mod root;
+#[cfg(all(target_os = "linux", feature = "sandbox"))]
+pub mod sandbox;
+#[cfg(target_os = "linux")]
+pub mod seccomp;
 pub mod secure_join;
+pub mod secure_path_buf;
+pub mod serve;
+pub mod share;
+pub mod staging;
+mod sys;
+pub mod temp;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod trace;
+pub mod url;
+pub mod vfs;
+pub mod walk;
+#[cfg(feature = "notify")]
+pub mod watch;
@@ -0,0 +1,138 @@
+// Advisory locks on the files of a rootfs, see `Root::lock`. They are flock() locks on an fd
+// opened through the resolver, so they are owned by the open file: two `FileLock`s of the
+// same process conflict like the ones of two processes do.
+use std::fs::File;
+
+use crate::error::Result;
+
+// The kind of lock taken by `Root::lock`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockKind {
+    // Shared with the other shared locks, for the readers.
+    Shared,
+    Exclusive,
+}
+
+// A lock held on a file inside of a rootfs, released when dropped.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+    kind: LockKind,
+}
+
+impl FileLock {
+    // Block until the `kind` lock of `file` is taken.
+    pub(crate) fn new(file: File, kind: LockKind) -> Result<Self> {
+        match kind {
+            LockKind::Shared => file.lock_shared()?,
+            LockKind::Exclusive => file.lock()?,
+        }
+
+        Ok(FileLock { file, kind })
+    }
+
+    // Take the `kind` lock of `file` if no other one conflicts with it, None otherwise.
+    pub(crate) fn try_new(file: File, kind: LockKind) -> Result<Option<Self>> {
+        let locked = match kind {
+            LockKind::Shared => file.try_lock_shared(),
+            LockKind::Exclusive => file.try_lock(),
+        };
+
+        match locked {
+            Ok(()) => Ok(Some(FileLock { file, kind })),
+            Err(std::fs::TryLockError::WouldBlock) => Ok(None),
+            Err(std::fs::TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    // The locked file, opened read-only.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    pub fn kind(&self) -> LockKind {
+        self.kind
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+
+    #[test]
+    fn test_lock() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            held: LockKind,
+            wanted: LockKind,
+            locked: bool,
+        }
+
+        let tree = TreeBuilder::new()
+            .file("run/app.lock", "")
+            .symlink("l", "../../run/app.lock")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+
+        let tests = &[
+            TestData {
+                name: "shared locks",
+                held: LockKind::Shared,
+                wanted: LockKind::Shared,
+                locked: true,
+            },
+            TestData {
+                name: "exclusive lock held",
+                held: LockKind::Exclusive,
+                wanted: LockKind::Shared,
+                locked: false,
+            },
+            TestData {
+                name: "shared lock held",
+                held: LockKind::Shared,
+                wanted: LockKind::Exclusive,
+                locked: false,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let held = root.lock("run/app.lock", t.held).unwrap();
+            let result = root.try_lock("l", t.wanted);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            let lock = result.unwrap();
+            assert!(lock.is_some() == t.locked, "{}", msg);
+            assert!(held.kind() == t.held, "{}", msg);
+        }
+
+        let lock = root.lock("l", LockKind::Exclusive).unwrap();
+        drop(lock);
+        let lock = root.try_lock("run/app.lock", LockKind::Exclusive).unwrap();
+        assert!(lock.unwrap().file().metadata().unwrap().is_file());
+        assert!(root.lock("run/missing", LockKind::Shared).is_err());
+        assert!(root.lock("run", LockKind::Shared).is_err());
+        let fifo = rustix::fs::mknodat(
+            rustix::fs::CWD,
+            tree.path().join("run/fifo"),
+            rustix::fs::FileType::Fifo,
+            rustix::fs::Mode::from_raw_mode(0o644),
+            0,
+        );
+        if fifo.is_ok() {
+            assert!(root.lock("run/fifo", LockKind::Exclusive).is_err());
+            assert!(root.try_lock("run/fifo", LockKind::Exclusive).is_err());
+        }
+    }
+}
@@ -0,0 +1,248 @@
+// Permission and ownership manifests, like the ones of a package or an image build: the mode,
+// owner and xattrs of each listed entry of a rootfs are applied all or nothing. The entries
+// are never followed, a symlink gets its own owner and xattrs, and any failure restores the
+// entries changed before it.
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::fs::{self as unix_fs, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use crate::audit::AuditEvent;
+use crate::error::{Error, Result};
+use crate::root::Root;
+use crate::sys;
+
+// The permission bits with the setuid, setgid and sticky ones.
+const MODE_MASK: u32 = 0o7777;
+
+// An entry of a manifest, the attributes left to None are not changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestEntry {
+    // The path of the entry inside of the rootfs, its last component is not followed.
+    pub path: PathBuf,
+    pub mode: Option<u32>,
+    // The owner inside of the rootfs, mapped to the host like `Root::chown` does.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    // The xattrs to set, e.g. ("security.capability", ...). The other ones are kept.
+    pub xattrs: Vec<(OsString, Vec<u8>)>,
+}
+
+// The attributes of an entry before the manifest, to restore on a failure.
+#[derive(Debug)]
+struct Snapshot {
+    path: PathBuf,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    xattrs: Vec<(OsString, Option<Vec<u8>>)>,
+}
+
+// Apply the modes, owners and xattrs of `entries` to the rootfs of `root`, in order. All the
+// entries are resolved and checked before anything is changed: a missing entry fails, so does
+// a mode for a symlink, which has none. If an attribute can't be applied, the entries already
+// changed are restored and the error is returned.
+pub fn apply_manifest(root: &Root, entries: &[ManifestEntry]) -> Result<()> {
+    let mut snapshots = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let path = root.join_parent(&entry.path)?;
+        let meta = fs::symlink_metadata(&path)?;
+        if entry.mode.is_some() && meta.file_type().is_symlink() {
+            return Err(Error::InvalidPath(entry.path.clone()));
+        }
+
+        let mut xattrs = Vec::with_capacity(entry.xattrs.len());
+        for (name, _) in &entry.xattrs {
+            xattrs.push((name.clone(), sys::get_xattr(&path, name)?));
+        }
+        snapshots.push(Snapshot {
+            path,
+            mode: meta.mode() & MODE_MASK,
+            uid: meta.uid(),
+            gid: meta.gid(),
+            xattrs,
+        });
+    }
+
+    let mut events = Vec::new();
+    for (i, (entry, snapshot)) in entries.iter().zip(&snapshots).enumerate() {
+        // an entry may be half applied
+        if let Err(e) = apply_entry(root, entry, snapshot, &mut events) {
+            for snapshot in snapshots[..=i].iter().rev() {
+                let _ = restore(snapshot);
+            }
+            return Err(e);
+        }
+    }
+
+    for (path, event) in events {
+        root.audit(path, event);
+    }
+
+    Ok(())
+}
+
+fn apply_entry<'a>(
+    root: &Root,
+    entry: &ManifestEntry,
+    snapshot: &'a Snapshot,
+    events: &mut Vec<(&'a Path, AuditEvent)>,
+) -> Result<()> {
+    let path = snapshot.path.as_path();
+
+    if entry.uid.is_some() || entry.gid.is_some() {
+        let (uid, gid) = root.host_owner(
+            entry.uid.unwrap_or(snapshot.uid),
+            entry.gid.unwrap_or(snapshot.gid),
+        )?;
+        unix_fs::lchown(path, Some(uid), Some(gid))?;
+        events.push((path, AuditEvent::Chown { uid, gid }));
+    }
+    // after the owner, chown() clears the setuid and setgid bits
+    if let Some(mode) = entry.mode {
+        sys::lchmod(path, mode & MODE_MASK)?;
+        events.push((path, AuditEvent::Chmod { mode }));
+    }
+    for (name, value) in &entry.xattrs {
+        sys::set_xattr(path, name, value)?;
+        let name = name.clone();
+        events.push((path, AuditEvent::SetXattr { name }));
+    }
+
+    Ok(())
+}
+
+// Restore the attributes of `snapshot`, in the reverse order of `apply_entry`.
+fn restore(snapshot: &Snapshot) -> Result<()> {
+    let path = snapshot.path.as_path();
+
+    for (name, value) in snapshot.xattrs.iter().rev() {
+        match value {
+            Some(value) => sys::set_xattr(path, name, value)?,
+            None => sys::remove_xattr(path, name)?,
+        }
+    }
+    unix_fs::lchown(path, Some(snapshot.uid), Some(snapshot.gid))?;
+    if !fs::symlink_metadata(path)?.file_type().is_symlink() {
+        sys::lchmod(path, snapshot.mode)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+
+    #[test]
+    fn test_apply_manifest() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            entries: Vec<ManifestEntry>,
+            ok: bool,
+        }
+
+        let tree = TreeBuilder::new()
+            .file("bin/sh", "")
+            .file("etc/shadow", "")
+            .symlink("etc/out", "/../../shadow")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let mode = |p: &str| fs::symlink_metadata(tree.path().join(p)).unwrap().mode() & 0o7777;
+        let uid = |p: &str| fs::symlink_metadata(tree.path().join(p)).unwrap().uid();
+        let entry = |path: &str, mode: Option<u32>| ManifestEntry {
+            path: PathBuf::from(path),
+            mode,
+            ..Default::default()
+        };
+        let (sh_mode, sh_uid) = (mode("bin/sh"), uid("bin/sh"));
+
+        let tests = [
+            TestData {
+                name: "modes",
+                entries: vec![
+                    entry("bin/sh", Some(0o755)),
+                    entry("/etc/shadow", Some(0o600)),
+                ],
+                ok: true,
+            },
+            TestData {
+                name: "mode of a symlink",
+                entries: vec![entry("bin/sh", Some(0o700)), entry("etc/out", Some(0o644))],
+                ok: false,
+            },
+            TestData {
+                name: "missing entry",
+                entries: vec![entry("bin/sh", Some(0o700)), entry("etc/missing", None)],
+                ok: false,
+            },
+            TestData {
+                name: "clamped entry",
+                entries: vec![entry("../../etc/shadow", Some(0o600))],
+                ok: true,
+            },
+            TestData {
+                name: "below a symlink",
+                entries: vec![entry("bin/sh", Some(0o700)), entry("etc/out/x", None)],
+                ok: false,
+            },
+            TestData {
+                name: "invalid xattr, rolled back",
+                entries: vec![
+                    ManifestEntry {
+                        uid: Some(1),
+                        ..entry("bin/sh", Some(0o4711))
+                    },
+                    ManifestEntry {
+                        xattrs: vec![("invalid.name".into(), b"x".to_vec())],
+                        ..entry("etc/shadow", None)
+                    },
+                ],
+                ok: false,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+            let before = (mode("bin/sh"), mode("etc/shadow"));
+
+            let result = apply_manifest(&root, &t.entries);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            if !t.ok {
+                assert!((mode("bin/sh"), mode("etc/shadow")) == before, "{}", msg);
+                assert!(uid("bin/sh") == sh_uid, "{}", msg);
+            }
+        }
+        assert_ne!(sh_mode, 0o755);
+        assert_eq!(mode("bin/sh"), 0o755);
+        assert_eq!(mode("etc/shadow"), 0o600);
+
+        // only root may chown, and xattrs depend on the filesystem
+        let entries = [ManifestEntry {
+            uid: Some(1),
+            gid: Some(2),
+            ..entry("etc/out", None)
+        }];
+        if apply_manifest(&root, &entries).is_ok() {
+            let meta = fs::symlink_metadata(tree.path().join("etc/out")).unwrap();
+            assert_eq!((meta.uid(), meta.gid()), (1, 2));
+            assert_eq!(uid("etc/shadow"), sh_uid);
+        }
+
+        let entries = [ManifestEntry {
+            xattrs: vec![("user.secure-path".into(), b"1".to_vec())],
+            ..entry("etc/shadow", Some(0o640))
+        }];
+        if apply_manifest(&root, &entries).is_ok() {
+            let path = tree.path().join("etc/shadow");
+            let value = sys::get_xattr(&path, "user.secure-path".as_ref()).unwrap();
+            assert_eq!(value, Some(b"1".to_vec()));
+            assert_eq!(mode("etc/shadow"), 0o640);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+// Translation of paths between the host and a container through the mounts making up the
+// container's filesystem, e.g. to rewrite the paths in the errors shown to a user or passed
+// to hooks.
+use std::path::{Component, Path, PathBuf};
+
+use crate::resolve::resolve;
+use crate::secure_join::host_to_root;
+
+// A mount of the host directory `host` at `container` inside of the container.
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub host: PathBuf,
+    pub container: PathBuf,
+}
+
+// PathMapper translates paths in both directions through the most specific mapping, i.e.
+// the one with the longest matching prefix. Each translation is secured like `Root::join`
+// and `host_to_root` do, so it never leaves the mapped directory.
+#[derive(Debug, Clone, Default)]
+pub struct PathMapper {
+    mappings: Vec<Mapping>,
+}
+
+impl PathMapper {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add the mount of the host directory `host` at the absolute path `container`.
+    pub fn mount<P: AsRef<Path>, Q: AsRef<Path>>(mut self, host: P, container: Q) -> Self {
+        self.mappings.push(Mapping {
+            host: host.as_ref().to_path_buf(),
+            container: clean(container.as_ref()),
+        });
+        self
+    }
+
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    // Translate the path `container_path` of the container into the host path it is backed
+    // by. The path is cleaned lexically first, then the rest after the mount point is
+    // resolved inside of the mounted host directory. Returns None if no mapping covers it.
+    pub fn to_host<P: AsRef<Path>>(&self, container_path: P) -> Option<PathBuf> {
+        let path = clean(container_path.as_ref());
+
+        let m = self
+            .mappings
+            .iter()
+            .filter(|m| path.starts_with(&m.container))
+            .max_by_key(|m| m.container.components().count())?;
+        let rest = path.strip_prefix(&m.container).ok()?;
+        let host = m.host.canonicalize().ok()?;
+
+        resolve(&host, rest, false).ok()
+    }
+
+    // Translate the host path `host_path` into its path inside of the container. Returns
+    // None if it is outside of every mapped host directory.
+    pub fn to_container<P: AsRef<Path>>(&self, host_path: P) -> Option<PathBuf> {
+        let host_path = host_path.as_ref();
+
+        let (m, relative, _) = self
+            .mappings
+            .iter()
+            .filter_map(|m| {
+                let relative = host_to_root(&m.host, host_path)?;
+                let depth = m.host.canonicalize().ok()?.components().count();
+                Some((m, relative, depth))
+            })
+            .max_by_key(|(_, _, depth)| *depth)?;
+
+        Some(m.container.join(relative.strip_prefix("/").ok()?))
+    }
+}
+
+// Make `path` absolute and drop its "." and ".." components, ".." stops at the root.
+fn clean(path: &Path) -> PathBuf {
+    let mut cleaned = PathBuf::from("/");
+    for it in path.components() {
+        match it {
+            Component::Normal(c) => cleaned.push(c),
+            Component::ParentDir => {
+                cleaned.pop();
+            }
+            _ => continue,
+        }
+    }
+
+    cleaned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_path_mapper() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            container: &'a str,
+            host: Option<&'a str>,
+        }
+
+        let dir = tempdir().expect("failed to create tmpdir");
+        let host = dir.path().canonicalize().unwrap();
+        fs::create_dir_all(host.join("rootfs/etc")).unwrap();
+        fs::create_dir_all(host.join("data/sub")).unwrap();
+        fs::create_dir_all(host.join("other")).unwrap();
+        symlink("/../../etc", host.join("data/link")).unwrap();
+
+        let mapper = PathMapper::new()
+            .mount(host.join("rootfs"), "/")
+            .mount(host.join("data"), "/mnt/data")
+            .mount(host.join("other"), "/mnt/data/sub");
+
+        let tests = &[
+            TestData {
+                name: "path on the rootfs",
+                container: "/etc/passwd",
+                host: Some("rootfs/etc/passwd"),
+            },
+            TestData {
+                name: "path on a mount",
+                container: "/mnt/data/file",
+                host: Some("data/file"),
+            },
+            TestData {
+                name: "most specific mount",
+                container: "/mnt/data/sub/file",
+                host: Some("other/file"),
+            },
+            TestData {
+                name: "mount point itself",
+                container: "/mnt/data",
+                host: Some("data"),
+            },
+            TestData {
+                name: ".. cleaned before picking the mount",
+                container: "/mnt/data/../../etc",
+                host: Some("rootfs/etc"),
+            },
+            TestData {
+                name: "symlink confined to the mounted directory",
+                container: "/mnt/data/link/passwd",
+                host: Some("data/etc/passwd"),
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = mapper.to_host(t.container);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result == t.host.map(|p| host.join(p)), "{}", msg);
+        }
+
+        let to_container = |p: &str| mapper.to_container(host.join(p));
+        assert_eq!(
+            to_container("rootfs/etc/passwd").unwrap(),
+            Path::new("/etc/passwd")
+        );
+        assert_eq!(
+            to_container("data/file").unwrap(),
+            Path::new("/mnt/data/file")
+        );
+        assert_eq!(
+            to_container("other/x").unwrap(),
+            Path::new("/mnt/data/sub/x")
+        );
+        assert_eq!(
+            to_container("data/sub/x").unwrap(),
+            Path::new("/mnt/data/sub/x")
+        );
+        assert_eq!(to_container("data").unwrap(), Path::new("/mnt/data"));
+        assert!(to_container("elsewhere").is_none());
+        assert!(PathMapper::new().to_host("/etc").is_none());
+    }
+}
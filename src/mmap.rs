@@ -0,0 +1,129 @@
+// Read-only memory maps of the files of a rootfs, see `Root::mmap`, with the `mmap` feature.
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+pub use memmap2::Mmap;
+
+use crate::error::{Error, Result};
+
+// The bounds of the size of a file mapped by `Root::mmap`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MmapOptions {
+    // A larger file fails with a `FileTooLarge` I/O error, e.g. for a manifest which can't
+    // be that big. Any size is mapped when None.
+    pub max_len: Option<u64>,
+    // A smaller file fails with an `InvalidData` I/O error, e.g. for a file which must at
+    // least hold a header.
+    pub min_len: u64,
+}
+
+// Map `file`, opened at `path` inside of the rootfs, once checked against `options`.
+pub(crate) fn map(file: &File, path: &Path, options: &MmapOptions) -> Result<Mmap> {
+    let m = file.metadata()?;
+    // a device or a fifo has no size to check, and mapping /dev/mem is never what's asked
+    if !m.is_file() {
+        return Err(Error::InvalidPath(path.to_path_buf()));
+    }
+    if options.max_len.is_some_and(|max| m.len() > max) {
+        return Err(Error::Io(io::ErrorKind::FileTooLarge.into()));
+    }
+    if m.len() < options.min_len {
+        return Err(Error::Io(io::ErrorKind::InvalidData.into()));
+    }
+
+    // SAFETY: the map is read-only and private, it's only undefined if the file is truncated
+    // or written by another process while mapped, which the callers accept with the feature
+    Ok(unsafe { memmap2::MmapOptions::new().map_copy_read_only(file)? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+
+    #[test]
+    fn test_mmap() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            options: MmapOptions,
+            result: Option<&'a [u8]>,
+        }
+
+        let tree = TreeBuilder::new()
+            .file("var/lib/manifest.json", "{}")
+            .file("var/lib/empty", "")
+            .symlink("l", "../../var/lib")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let _ = rustix::fs::mknodat(
+            rustix::fs::CWD,
+            tree.path().join("var/lib/fifo"),
+            rustix::fs::FileType::Fifo,
+            rustix::fs::Mode::from_raw_mode(0o644),
+            0,
+        );
+        let bounds = |min_len, max_len| MmapOptions { max_len, min_len };
+
+        let tests = &[
+            TestData {
+                name: "file",
+                path: "var/lib/manifest.json",
+                options: MmapOptions::default(),
+                result: Some(b"{}"),
+            },
+            TestData {
+                name: "through a symlink, within the bounds",
+                path: "l/manifest.json",
+                options: bounds(2, Some(2)),
+                result: Some(b"{}"),
+            },
+            TestData {
+                name: "empty file",
+                path: "var/lib/empty",
+                options: MmapOptions::default(),
+                result: Some(b""),
+            },
+            TestData {
+                name: "too large",
+                path: "var/lib/manifest.json",
+                options: bounds(0, Some(1)),
+                result: None,
+            },
+            TestData {
+                name: "too small",
+                path: "var/lib/manifest.json",
+                options: bounds(3, None),
+                result: None,
+            },
+            TestData {
+                name: "directory",
+                path: "var/lib",
+                options: MmapOptions::default(),
+                result: None,
+            },
+            TestData {
+                name: "fifo",
+                path: "var/lib/fifo",
+                options: MmapOptions::default(),
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = root.mmap(t.path, &t.options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some(content) => assert!(&result.unwrap()[..] == content, "{}", msg),
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+    }
+}
@@ -0,0 +1,482 @@
+// The mounts a resolved path traverses. A prefix check only proves that a path is
+// lexically inside of the rootfs, while a bind mount inside of the rootfs can still expose a
+// directory from anywhere on the host. `check_bind_mounts` finds the mounts with the mount
+// ids of statx(), the `procfs` feature adds their details from `/proc/self/mountinfo`.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "procfs")]
+use procfs::process::{MountInfo, Process};
+
+use crate::error::{Error, Result};
+use crate::root::Root;
+use crate::sys::mount_id;
+
+// Options of `check_bind_mounts`.
+#[derive(Debug, Clone, Default)]
+pub struct BindMountOptions {
+    // Fail with `Error::Escape` instead of returning the bind mount.
+    pub escape: bool,
+}
+
+// Find the first mount below the rootfs on the way to `resolved`, a path returned by
+// `Root::join` or `Root::check`, whose source lies outside of the rootfs. Returns its mount
+// point on the host.
+//
+// The mounts are told apart by the STATX_MNT_ID of each existing directory on the way.
+// Without the `procfs` feature the source of a mount can't be found, so every mount on the
+// way is reported. With it, only the bind mounts whose source is outside of the rootfs or
+// unknown are, like the `source_outside` of `traversed_mounts`.
+pub fn check_bind_mounts<P: AsRef<Path>>(
+    root: &Root,
+    resolved: P,
+    options: &BindMountOptions,
+) -> Result<Option<PathBuf>> {
+    let resolved = resolved.as_ref();
+    let relative = resolved
+        .strip_prefix(root.path())
+        .map_err(|_| Error::Escape(resolved.to_path_buf()))?;
+
+    let root_id = mount_id(root.path())?;
+    let mut path = root.path().to_path_buf();
+    let mut mount = None;
+    for it in relative.components() {
+        path.push(it);
+        match mount_id(&path) {
+            Ok(id) if id != root_id => {
+                mount = Some(path);
+                break;
+            }
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => break,
+            Err(e) => return Err(Error::Io(e)),
+        }
+    }
+
+    #[cfg(feature = "procfs")]
+    if mount.is_some() {
+        mount = traversed_mounts(root, resolved)?
+            .into_iter()
+            .find(|m| m.source_outside)
+            .map(|m| m.mount_point);
+    }
+
+    match mount {
+        Some(p) if options.escape => Err(Error::Escape(p)),
+        mount => Ok(mount),
+    }
+}
+
+// A mount traversed by a resolved path.
+#[cfg(feature = "procfs")]
+#[derive(Debug, Clone)]
+pub struct TraversedMount {
+    pub mount_id: i32,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    // For a bind mount, the directory of the source filesystem which is mounted.
+    pub bind_root: Option<PathBuf>,
+    // The host path of the bind mount's source, if that filesystem is mounted elsewhere.
+    pub source: Option<PathBuf>,
+    // Whether this is a bind mount inside of the rootfs whose source lies outside of it, or
+    // can't be found.
+    pub source_outside: bool,
+}
+
+// The mounts traversed by `resolved`, a path returned by `Root::join` or `Root::check`,
+// starting with the mount the rootfs is on.
+#[cfg(feature = "procfs")]
+pub fn traversed_mounts<P: AsRef<Path>>(root: &Root, resolved: P) -> Result<Vec<TraversedMount>> {
+    let mounts = Process::myself()
+        .and_then(|p| p.mountinfo())
+        .map_err(io::Error::other)?;
+
+    Ok(traversed(&mounts.0, root.path(), resolved.as_ref()))
+}
+
+#[cfg(feature = "procfs")]
+fn traversed(mounts: &[MountInfo], rootfs: &Path, resolved: &Path) -> Vec<TraversedMount> {
+    // The mounts visible on the way, a later mount hides the earlier ones on and below its
+    // mount point.
+    let mut visible: Vec<&MountInfo> = Vec::new();
+    for m in mounts {
+        if resolved.starts_with(&m.mount_point) {
+            visible.retain(|v| !v.mount_point.starts_with(&m.mount_point));
+            visible.push(m);
+        }
+    }
+    visible.sort_by_key(|m| m.mount_point.components().count());
+
+    let first = visible
+        .iter()
+        .rposition(|m| rootfs.starts_with(&m.mount_point))
+        .unwrap_or(0);
+
+    visible[first..]
+        .iter()
+        .enumerate()
+        .map(|(i, m)| {
+            let bind_root = Some(PathBuf::from(&m.root)).filter(|r| r != Path::new("/"));
+            let source = bind_root.as_ref().and_then(|_| bind_source(mounts, m));
+            let source_outside = i > 0
+                && bind_root.is_some()
+                && source.as_ref().is_none_or(|s| !s.starts_with(rootfs));
+
+            TraversedMount {
+                mount_id: m.mnt_id,
+                mount_point: m.mount_point.clone(),
+                fs_type: m.fs_type.clone(),
+                bind_root,
+                source,
+                source_outside,
+            }
+        })
+        .collect()
+}
+
+// Find where the source of the bind mount `bind` is visible, through another mount of the
+// same filesystem covering its root.
+#[cfg(feature = "procfs")]
+fn bind_source(mounts: &[MountInfo], bind: &MountInfo) -> Option<PathBuf> {
+    let root = Path::new(&bind.root);
+
+    mounts
+        .iter()
+        .filter(|m| m.mnt_id != bind.mnt_id && m.majmin == bind.majmin)
+        .filter(|m| root.starts_with(&m.root) && !m.mount_point.starts_with(&bind.mount_point))
+        .max_by_key(|m| Path::new(&m.root).components().count())
+        .and_then(|m| Some(m.mount_point.join(root.strip_prefix(&m.root).ok()?)))
+}
+
+// Options of `validate_mount`.
+#[derive(Debug, Clone, Default)]
+pub struct MountOptions {
+    // Create a missing destination, a directory for a directory source and an empty file
+    // for anything else, like the runtimes do before mounting.
+    pub create_destination: bool,
+}
+
+// A mount checked by `validate_mount`, with its host paths ready to be passed to mount().
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatedMount {
+    // The canonicalized source on the host.
+    pub source: PathBuf,
+    // The destination resolved inside of the rootfs.
+    pub destination: PathBuf,
+    pub is_dir: bool,
+}
+
+// Validate a bind mount of the host path `source` on `destination`, a path inside of the
+// rootfs. The destination is resolved like `Root::check`, and must not be the rootfs itself
+// nor, when it exists, of another type than the source.
+//
+// The source must exist and be neither inside of the rootfs nor one of its parents: mounting
+// the rootfs or a parent of it below itself makes the rootfs recursively visible in the
+// container, so it's rejected with `Error::InvalidPath`.
+pub fn validate_mount<P: AsRef<Path>, Q: AsRef<Path>>(
+    root: &Root,
+    source: P,
+    destination: Q,
+    options: &MountOptions,
+) -> Result<ValidatedMount> {
+    let source = source.as_ref().canonicalize()?;
+    if source.starts_with(root.path()) || root.path().starts_with(&source) {
+        return Err(Error::InvalidPath(source));
+    }
+    let is_dir = source.is_dir();
+
+    let destination = root.check(destination)?;
+    if destination == root.path() {
+        return Err(Error::InvalidPath(destination));
+    }
+    match destination.symlink_metadata() {
+        Ok(m) if m.is_dir() != is_dir => return Err(Error::InvalidPath(destination)),
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound && options.create_destination => {
+            let relative = destination
+                .strip_prefix(root.path())
+                .unwrap_or(&destination);
+            if is_dir {
+                root.mkdir_all(relative)?;
+            } else {
+                if let Some(parent) = relative.parent() {
+                    root.mkdir_all(parent)?;
+                }
+                // not following a symlink planted since the check, an entry created
+                // meanwhile is checked again
+                let created = root.open_with(
+                    relative,
+                    fs::OpenOptions::new().write(true).create_new(true),
+                );
+                match created {
+                    Err(Error::Io(e)) if e.kind() == io::ErrorKind::AlreadyExists => {
+                        let m = destination.symlink_metadata()?;
+                        if m.is_dir() || m.file_type().is_symlink() {
+                            return Err(Error::InvalidPath(destination));
+                        }
+                    }
+                    created => {
+                        created?;
+                    }
+                }
+            }
+        }
+        Err(e) => return Err(Error::Io(e)),
+    }
+
+    Ok(ValidatedMount {
+        source,
+        destination,
+        is_dir,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_check_bind_mounts() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        root.mkdir_all("a/b").unwrap();
+        let options = BindMountOptions::default();
+        let resolved = root.join("a/b/missing").unwrap();
+        assert!(check_bind_mounts(&root, resolved, &options)
+            .unwrap()
+            .is_none());
+
+        // /proc is another mount, but no bind mount.
+        let root = Root::new("/").unwrap();
+        let result = check_bind_mounts(&root, "/proc/self", &options).unwrap();
+        #[cfg(not(feature = "procfs"))]
+        assert_eq!(result.as_deref(), Some(Path::new("/proc")));
+        #[cfg(feature = "procfs")]
+        assert!(result.is_none());
+
+        let options = BindMountOptions { escape: true };
+        let result = check_bind_mounts(&root, "/proc/self", &options);
+        assert!(result.is_err() != cfg!(feature = "procfs"));
+    }
+
+    #[cfg(feature = "procfs")]
+    #[test]
+    fn test_traversed_mounts() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            resolved: &'a str,
+            mounts: &'a [i32],
+            source: Option<&'a str>,
+            source_outside: bool,
+        }
+
+        let mounts: Vec<MountInfo> = [
+            "22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw",
+            "30 22 8:1 /srv/data /rootfs/data rw,relatime shared:1 - ext4 /dev/sda1 rw",
+            "31 22 8:1 /rootfs/a /rootfs/b rw - ext4 /dev/sda1 rw",
+            "32 22 0:40 / /rootfs/tmp rw - tmpfs tmpfs rw",
+            "33 22 0:41 /x /rootfs/unknown rw - ext4 /dev/sdb1 rw",
+            "34 22 0:42 / /rootfs/over rw - tmpfs tmpfs rw",
+            "35 22 0:43 / /rootfs/over rw - tmpfs tmpfs rw",
+        ]
+        .iter()
+        .map(|l| MountInfo::from_line(l).unwrap())
+        .collect();
+
+        let tests = &[
+            TestData {
+                name: "no mount inside of the rootfs",
+                resolved: "/rootfs/etc/passwd",
+                mounts: &[22],
+                source: None,
+                source_outside: false,
+            },
+            TestData {
+                name: "bind mount from outside",
+                resolved: "/rootfs/data/file",
+                mounts: &[22, 30],
+                source: Some("/srv/data"),
+                source_outside: true,
+            },
+            TestData {
+                name: "bind mount from inside",
+                resolved: "/rootfs/b/file",
+                mounts: &[22, 31],
+                source: Some("/rootfs/a"),
+                source_outside: false,
+            },
+            TestData {
+                name: "tmpfs",
+                resolved: "/rootfs/tmp/file",
+                mounts: &[22, 32],
+                source: None,
+                source_outside: false,
+            },
+            TestData {
+                name: "bind mount of an invisible filesystem",
+                resolved: "/rootfs/unknown/file",
+                mounts: &[22, 33],
+                source: None,
+                source_outside: true,
+            },
+            TestData {
+                name: "overmounted",
+                resolved: "/rootfs/over/file",
+                mounts: &[22, 35],
+                source: None,
+                source_outside: false,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = traversed(&mounts, Path::new("/rootfs"), Path::new(t.resolved));
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            let ids: Vec<i32> = result.iter().map(|m| m.mount_id).collect();
+            assert!(ids == t.mounts, "{}", msg);
+            let last = result.last().unwrap();
+            assert!(last.source.as_deref() == t.source.map(Path::new), "{}", msg);
+            assert!(
+                result.iter().any(|m| m.source_outside) == t.source_outside,
+                "{}",
+                msg
+            );
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        let result = traversed_mounts(&root, root.join("a").unwrap()).unwrap();
+        assert!(!result.is_empty());
+        assert!(!result.iter().any(|m| m.source_outside));
+    }
+
+    #[test]
+    fn test_validate_mount() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            source: &'a str,
+            destination: &'a str,
+            create: bool,
+            result: Option<&'a str>,
+        }
+
+        let dir = tempdir().expect("failed to create tmpdir");
+        let dir_path = dir.path().canonicalize().unwrap();
+        let rootfs = dir_path.join("rootfs");
+        fs::create_dir_all(rootfs.join("mnt")).unwrap();
+        fs::create_dir_all(dir_path.join("data")).unwrap();
+        fs::write(dir_path.join("file"), b"").unwrap();
+        fs::write(rootfs.join("etc"), b"").unwrap();
+        std::os::unix::fs::symlink("/mnt", rootfs.join("link")).unwrap();
+        let root = Root::new(&rootfs).unwrap();
+
+        let tests = &[
+            TestData {
+                name: "existing directory",
+                source: "data",
+                destination: "/mnt",
+                create: false,
+                result: Some("mnt"),
+            },
+            TestData {
+                name: "through a symlink",
+                source: "data",
+                destination: "/link",
+                create: false,
+                result: Some("mnt"),
+            },
+            TestData {
+                name: "missing destination",
+                source: "data",
+                destination: "/a/b",
+                create: true,
+                result: Some("a/b"),
+            },
+            TestData {
+                name: "missing file destination",
+                source: "file",
+                destination: "/c/file",
+                create: true,
+                result: Some("c/file"),
+            },
+            TestData {
+                name: "missing destination not created",
+                source: "data",
+                destination: "/d",
+                create: false,
+                result: None,
+            },
+            TestData {
+                name: "directory on a file",
+                source: "data",
+                destination: "/etc",
+                create: false,
+                result: None,
+            },
+            TestData {
+                name: "missing source",
+                source: "missing",
+                destination: "/mnt",
+                create: true,
+                result: None,
+            },
+            TestData {
+                name: "source inside of the rootfs",
+                source: "rootfs/mnt",
+                destination: "/mnt",
+                create: false,
+                result: None,
+            },
+            TestData {
+                name: "source is a parent of the rootfs",
+                source: "",
+                destination: "/mnt",
+                create: false,
+                result: None,
+            },
+            TestData {
+                name: "the rootfs as destination",
+                source: "data",
+                destination: "/",
+                create: false,
+                result: None,
+            },
+            TestData {
+                name: "destination escaping",
+                source: "data",
+                destination: "/../mnt",
+                create: false,
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let options = MountOptions {
+                create_destination: t.create,
+            };
+            let result = validate_mount(&root, dir_path.join(t.source), t.destination, &options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some(p) => {
+                    let m = result.unwrap();
+                    assert!(m.destination == rootfs.join(p), "{}", msg);
+                    assert!(m.source == dir_path.join(t.source), "{}", msg);
+                    assert!(m.destination.exists(), "{}", msg);
+                    assert!(m.destination.is_dir() == m.is_dir, "{}", msg);
+                }
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+    }
+}
@@ -0,0 +1,271 @@
+// Resolution inside of the mount namespace of another process, for the supervising daemons
+// validating the paths as a containerized process would see them, and with the kernel's own
+// path walk inside of a chroot for the cases the resolver can't model.
+use std::io;
+use std::os::unix::io::OwnedFd;
+use std::path::{Path, PathBuf};
+
+use rustix::fs::{open, openat2, Mode, OFlags, ResolveFlags};
+use rustix::io::{retry_on_intr, Errno};
+
+use crate::error::Result;
+use crate::resolve::resolve;
+
+// Resolve `path` inside of the rootfs `rootfs_in_ns`, a path as seen by the process `pid`,
+// through the "/proc/<pid>/root" of the process. The rootfs path is resolved first inside of
+// the root of the process, then `path` inside of the rootfs. Returns the path on the host,
+// below "/proc/<pid>/root".
+pub fn resolve_in_ns<P: AsRef<Path>, Q: AsRef<Path>>(
+    pid: i32,
+    rootfs_in_ns: P,
+    path: Q,
+) -> Result<PathBuf> {
+    let proc_root = PathBuf::from(format!("/proc/{}/root", pid));
+    let rootfs = resolve(&proc_root, rootfs_in_ns.as_ref(), false)?;
+
+    resolve(&rootfs, path.as_ref(), false)
+}
+
+// Resolve the path `path` of the container whose init process is `pid`, like
+// `resolve_in_ns` with the root of the process as the rootfs. Returns the path on the host,
+// below "/proc/<pid>/root".
+pub fn resolve_in_container<P: AsRef<Path>>(pid: i32, path: P) -> Result<PathBuf> {
+    resolve_in_ns(pid, "/", path)
+}
+
+// Open the path `path` of the container whose init process is `pid` with `flags`, O_CLOEXEC
+// is always added. The kernel resolves it with openat2() RESOLVE_IN_ROOT from the root of the
+// process, so no component can be swapped between the resolution and the open, and procfs
+// magic links are refused. Before Linux 5.6 the path is resolved like
+// `resolve_in_container` and opened without following a trailing symlink.
+pub fn open_in_container<P: AsRef<Path>>(pid: i32, path: P, flags: OFlags) -> Result<OwnedFd> {
+    let proc_root = PathBuf::from(format!("/proc/{}/root", pid));
+    let (path, flags) = (path.as_ref(), flags | OFlags::CLOEXEC);
+
+    let root_flags = OFlags::PATH | OFlags::DIRECTORY | OFlags::CLOEXEC;
+    let root =
+        retry_on_intr(|| open(&proc_root, root_flags, Mode::empty())).map_err(io::Error::from)?;
+    let resolve_flags = ResolveFlags::IN_ROOT | ResolveFlags::NO_MAGICLINKS;
+    match retry_on_intr(|| openat2(&root, path, flags, Mode::empty(), resolve_flags)) {
+        Err(Errno::NOSYS) => {}
+        result => return Ok(result.map_err(io::Error::from)?),
+    }
+
+    let resolved = resolve(&proc_root, path, false)?;
+    let flags = flags | OFlags::NOFOLLOW;
+
+    Ok(retry_on_intr(|| open(&resolved, flags, Mode::empty())).map_err(io::Error::from)?)
+}
+
+// Resolve `path` inside of the rootfs `rootfs_in_ns` like `resolve_in_ns`, but from a scoped
+// thread which joins the mount namespace of the process `pid` with setns(), so the rootfs
+// is found exactly like the process finds it. Returns the path as seen in the namespace.
+// It needs CAP_SYS_ADMIN and CAP_SYS_CHROOT.
+#[cfg(feature = "setns")]
+pub fn resolve_in_ns_setns<P: AsRef<Path>, Q: AsRef<Path>>(
+    pid: i32,
+    rootfs_in_ns: P,
+    path: Q,
+) -> Result<PathBuf> {
+    use rustix::fd::AsFd;
+    use rustix::thread::{
+        move_into_link_name_space, unshare_unsafe, LinkNameSpaceType, UnshareFlags,
+    };
+    use std::{fs::File, thread};
+
+    use crate::error::Error;
+    use crate::root::Root;
+
+    let ns = File::open(format!("/proc/{}/ns/mnt", pid))?;
+    let (rootfs, path) = (rootfs_in_ns.as_ref(), path.as_ref());
+
+    let resolver = || -> Result<PathBuf> {
+        // SAFETY: only the filesystem attributes are unshared, not the fd table, so this
+        // thread stops sharing its root and working directory with the others, which setns()
+        // of a mount namespace requires
+        unsafe { unshare_unsafe(UnshareFlags::FS) }.map_err(io::Error::from)?;
+        move_into_link_name_space(ns.as_fd(), Some(LinkNameSpaceType::Mount))
+            .map_err(io::Error::from)?;
+
+        Root::new(rootfs)?.join(path)
+    };
+
+    thread::scope(|s| s.spawn(resolver).join())
+        .unwrap_or_else(|_| Err(Error::Io(io::Error::other("resolver thread panicked"))))
+}
+
+// Resolve `path` inside of `rootfs` with the kernel's own semantics, from a scoped thread
+// which chroots into the rootfs. Every mount, symlink and ".." is handled by the kernel
+// exactly as a process chrooted in the rootfs sees it. The missing trailing components are
+// joined as is. Returns the path on the host. It needs CAP_SYS_CHROOT.
+#[cfg(feature = "chroot")]
+pub fn resolve_chroot<P: AsRef<Path>, Q: AsRef<Path>>(rootfs: P, path: Q) -> Result<PathBuf> {
+    use rustix::process::{chdir, chroot};
+    use rustix::thread::{unshare_unsafe, UnshareFlags};
+    use std::thread;
+
+    use crate::error::Error;
+
+    let rootfs = rootfs.as_ref().canonicalize()?;
+    let path = Path::new("/").join(path);
+
+    let resolver = || -> Result<PathBuf> {
+        // SAFETY: only the filesystem attributes are unshared, so the chroot() below changes
+        // the root of this thread alone
+        unsafe { unshare_unsafe(UnshareFlags::FS) }.map_err(io::Error::from)?;
+        chroot(&rootfs).map_err(io::Error::from)?;
+        chdir("/").map_err(io::Error::from)?;
+
+        let resolved = canonicalize_missing(&path)?;
+
+        Ok(rootfs.join(resolved.strip_prefix("/").unwrap_or(&resolved)))
+    };
+
+    thread::scope(|s| s.spawn(resolver).join())
+        .unwrap_or_else(|_| Err(Error::Io(io::Error::other("resolver thread panicked"))))
+}
+
+// Canonicalize the longest existing prefix of the absolute `path` and append the missing
+// names to it. A dangling symlink is followed by hand, as realpath() stops at it.
+#[cfg(feature = "chroot")]
+fn canonicalize_missing(path: &Path) -> Result<PathBuf> {
+    use std::fs;
+
+    use crate::error::Error;
+    use crate::resolve::MAX_SYMLINKS;
+
+    let mut path = path.to_path_buf();
+    let mut symlinks = 0;
+    loop {
+        let mut missing = Vec::new();
+        let mut existing = path.as_path();
+        let mut resolved = loop {
+            match existing.canonicalize() {
+                Ok(p) => break p,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    let name = existing
+                        .file_name()
+                        .ok_or_else(|| Error::InvalidPath(path.clone()))?;
+                    missing.push(name.to_os_string());
+                    existing = existing.parent().unwrap_or_else(|| Path::new("/"));
+                }
+                Err(e) => return Err(Error::Io(e)),
+            }
+        };
+
+        // the first missing name may be a dangling symlink
+        let link = missing.last().map(|name| resolved.join(name));
+        match link.map(fs::read_link) {
+            Some(Ok(target)) => {
+                symlinks += 1;
+                if symlinks > MAX_SYMLINKS {
+                    return Err(Error::TooManySymlinks(path));
+                }
+                missing.pop();
+                resolved.push(target);
+            }
+            _ => {
+                for it in missing.iter().rev() {
+                    resolved.push(it);
+                }
+                return Ok(resolved);
+            }
+        }
+        for it in missing.iter().rev() {
+            resolved.push(it);
+        }
+        path = resolved;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, MetadataExt};
+    use std::process;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_open_in_container() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let rootfs = rootfs_dir.path().canonicalize().unwrap();
+        std::fs::write(rootfs.join("f"), b"").unwrap();
+        symlink(rootfs.join("f"), rootfs.join("l")).unwrap();
+        let pid = process::id() as i32;
+
+        let expected = std::fs::metadata(rootfs.join("f")).unwrap().ino();
+        let escaping = format!("../../../../../../{}/f", rootfs.display());
+        for path in [
+            &format!("{}/f", rootfs.display()),
+            &format!("{}/l", rootfs.display()),
+            &escaping,
+        ] {
+            let fd = open_in_container(pid, path, OFlags::RDONLY).unwrap();
+            assert_eq!(rustix::fs::fstat(&fd).unwrap().st_ino, expected, "{}", path);
+        }
+        let path = rootfs.join("missing");
+        assert!(open_in_container(pid, path, OFlags::RDONLY).is_err());
+        assert!(open_in_container(pid, "/proc/self/root/etc", OFlags::PATH).is_err());
+
+        let result = resolve_in_container(pid, rootfs.join("f")).unwrap();
+        let expected = format!("/proc/{}/root{}/f", pid, rootfs.display());
+        assert_eq!(result, Path::new(&expected));
+    }
+
+    #[test]
+    fn test_resolve_in_ns() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            result: &'a str,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let rootfs = rootfs_dir.path().canonicalize().unwrap();
+        symlink("/etc", rootfs.join("l")).unwrap();
+        let pid = process::id() as i32;
+
+        let tests = &[
+            TestData {
+                name: "relative path",
+                path: "../../a/b",
+                result: "a/b",
+            },
+            TestData {
+                name: "absolute symlink",
+                path: "l/passwd",
+                result: "etc/passwd",
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = resolve_in_ns(pid, &rootfs, t.path);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            let expected = format!("/proc/{}/root{}/{}", pid, rootfs.display(), t.result);
+            assert!(result.unwrap() == Path::new(&expected), "{}", msg);
+
+            #[cfg(feature = "setns")]
+            match resolve_in_ns_setns(pid, &rootfs, t.path) {
+                Ok(result) => assert!(result == rootfs.join(t.result), "{}", msg),
+                // not privileged enough to join a mount namespace
+                Err(crate::error::Error::Io(e))
+                    if e.kind() == std::io::ErrorKind::PermissionDenied => {}
+                Err(e) => panic!("{}, setns: {:?}", msg, e),
+            }
+
+            #[cfg(feature = "chroot")]
+            match resolve_chroot(&rootfs, t.path) {
+                Ok(result) => assert!(result == rootfs.join(t.result), "{}", msg),
+                // not privileged enough to chroot
+                Err(crate::error::Error::Io(e))
+                    if e.kind() == std::io::ErrorKind::PermissionDenied => {}
+                Err(e) => panic!("{}, chroot: {:?}", msg, e),
+            }
+        }
+    }
+}
@@ -0,0 +1,101 @@
+// Validation of the paths an OCI runtime `Spec` places inside of the container rootfs.
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::Spec;
+
+use crate::error::Error;
+use crate::root::Root;
+
+// Where in the `Spec` the offending path comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecPath {
+    MountDestination,
+    MaskedPath,
+    ReadonlyPath,
+}
+
+// Violation describes a path of the `Spec` which doesn't resolve inside of the rootfs.
+#[derive(Debug)]
+pub struct Violation {
+    pub kind: SpecPath,
+    pub path: PathBuf,
+    pub error: Error,
+}
+
+// Validate that every mount destination, masked path and readonly path of `spec` resolves
+// inside of the rootfs without being clamped, see `Root::check`. An empty list means the
+// spec is fine.
+pub fn validate_spec(spec: &Spec, root: &Root) -> Vec<Violation> {
+    let mut paths: Vec<(SpecPath, &Path)> = Vec::new();
+
+    if let Some(mounts) = spec.mounts() {
+        for m in mounts {
+            paths.push((SpecPath::MountDestination, m.destination()));
+        }
+    }
+    if let Some(linux) = spec.linux() {
+        for p in linux.masked_paths().iter().flatten() {
+            paths.push((SpecPath::MaskedPath, Path::new(p)));
+        }
+        for p in linux.readonly_paths().iter().flatten() {
+            paths.push((SpecPath::ReadonlyPath, Path::new(p)));
+        }
+    }
+
+    paths
+        .into_iter()
+        .filter_map(|(kind, path)| {
+            root.check(path).err().map(|error| Violation {
+                kind,
+                path: path.to_path_buf(),
+                error,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::{LinuxBuilder, MountBuilder, SpecBuilder};
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_validate_spec() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        symlink("../../../../etc", root.path().join("evil")).unwrap();
+        symlink("/sys/proc", root.path().join("proc")).unwrap();
+
+        let mounts = ["/proc", "/dev/../../etc", "/evil/shadow"]
+            .iter()
+            .map(|d| MountBuilder::default().destination(d).build().unwrap())
+            .collect::<Vec<_>>();
+        let linux = LinuxBuilder::default()
+            .masked_paths(vec!["/proc/kcore".to_string(), "/evil".to_string()])
+            .readonly_paths(vec!["/proc/sys".to_string(), "/../sys".to_string()])
+            .build()
+            .unwrap();
+        let spec = SpecBuilder::default()
+            .mounts(mounts)
+            .linux(linux)
+            .build()
+            .unwrap();
+
+        let violations = validate_spec(&spec, &root)
+            .into_iter()
+            .map(|v| (v.kind, v.path))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            violations,
+            vec![
+                (SpecPath::MountDestination, PathBuf::from("/dev/../../etc")),
+                (SpecPath::MountDestination, PathBuf::from("/evil/shadow")),
+                (SpecPath::MaskedPath, PathBuf::from("/evil")),
+                (SpecPath::ReadonlyPath, PathBuf::from("/../sys")),
+            ]
+        );
+    }
+}
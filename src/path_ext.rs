@@ -0,0 +1,153 @@
+// Containment checks on any `Path`, for the assertions and guards of the code handling the
+// paths resolved by this crate:
+//
+// ```
+// use secure_path::path_ext::PathExt;
+//
+// assert!(Path::new("/rootfs/etc/../usr").is_within("/rootfs"));
+// ```
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::Result;
+
+pub trait PathExt {
+    // Whether the path is `root` or below it, comparing them lexically: "." and ".." are
+    // dropped without looking at the filesystem, so a symlink may still lead elsewhere. A
+    // relative path is only within a relative `root`.
+    fn is_within<R: AsRef<Path>>(&self, root: R) -> bool;
+
+    // Whether the path is `root` or below it once both are canonicalized, following their
+    // symlinks. Both must exist.
+    fn is_within_resolved<R: AsRef<Path>>(&self, root: R) -> Result<bool>;
+}
+
+impl PathExt for Path {
+    fn is_within<R: AsRef<Path>>(&self, root: R) -> bool {
+        let (path, root) = (clean(self), clean(root.as_ref()));
+
+        match path.strip_prefix(&root) {
+            Ok(rest) => !rest.components().any(|c| c == Component::ParentDir),
+            Err(_) => false,
+        }
+    }
+
+    fn is_within_resolved<R: AsRef<Path>>(&self, root: R) -> Result<bool> {
+        let root = root.as_ref().canonicalize()?;
+
+        Ok(self.canonicalize()?.starts_with(root))
+    }
+}
+
+// Drop the "." and the ".." following a name of `path`, like Go's `filepath.Clean`. The ".."
+// of a relative path climbing above it are kept in front, the ones of "/" stay there.
+fn clean(path: &Path) -> PathBuf {
+    let mut components: Vec<Component> = Vec::new();
+    for it in path.components() {
+        match it {
+            Component::CurDir => continue,
+            Component::ParentDir => match components.last() {
+                Some(Component::Normal(_)) => {
+                    components.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => continue,
+                _ => components.push(it),
+            },
+            _ => components.push(it),
+        }
+    }
+
+    components.iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_is_within() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            path: &'a str,
+            root: &'a str,
+            within: bool,
+        }
+
+        let tests = &[
+            TestData {
+                path: "/rootfs/etc",
+                root: "/rootfs",
+                within: true,
+            },
+            TestData {
+                path: "/rootfs",
+                root: "/rootfs/",
+                within: true,
+            },
+            TestData {
+                path: "/rootfs/etc/../../etc",
+                root: "/rootfs",
+                within: false,
+            },
+            TestData {
+                path: "/rootfs/./a/../b",
+                root: "/rootfs/b/.",
+                within: true,
+            },
+            TestData {
+                path: "/rootfs2/etc",
+                root: "/rootfs",
+                within: false,
+            },
+            TestData {
+                path: "/../rootfs/etc",
+                root: "/rootfs",
+                within: true,
+            },
+            TestData {
+                path: "a/b",
+                root: "a",
+                within: true,
+            },
+            TestData {
+                path: "a/../../b",
+                root: ".",
+                within: false,
+            },
+            TestData {
+                path: "../x",
+                root: "..",
+                within: true,
+            },
+            TestData {
+                path: "etc",
+                root: "/etc",
+                within: false,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, t);
+
+            let result = Path::new(t.path).is_within(t.root);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            assert!(result == t.within, "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_is_within_resolved() {
+        let dir = tempdir().expect("failed to create tmpdir");
+        let rootfs = dir.path().join("rootfs");
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        symlink(dir.path(), rootfs.join("up")).unwrap();
+
+        assert!(rootfs.join("etc").is_within_resolved(&rootfs).unwrap());
+        assert!(rootfs.join("up").is_within(&rootfs));
+        assert!(!rootfs.join("up").is_within_resolved(&rootfs).unwrap());
+        assert!(rootfs.join("missing").is_within_resolved(&rootfs).is_err());
+    }
+}
@@ -0,0 +1,292 @@
+// A compatibility layer with the Rust bindings of libpathrs, for the projects migrating
+// from it or sharing rootfs handling with runc. The names and semantics follow libpathrs:
+// the paths are resolved inside of the `Root` like with RESOLVE_IN_ROOT, so absolute
+// symlinks and ".." stay in the root, every component must exist, and the results are
+// `Handle`s, O_PATH fds which are reopened to do I/O.
+//
+// The fds are checked against the resolved path after opening them, a component swapped
+// during the resolution is reported as `Error::Escape` instead of being used.
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, DirBuilder, File, Permissions};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Component, Path, PathBuf};
+
+use rustix::fd::{AsFd, BorrowedFd, OwnedFd};
+use rustix::fs::{openat, readlinkat, renameat_with, unlinkat, AtFlags, Mode, CWD};
+use rustix::io::retry_on_intr;
+
+pub use rustix::fs::{OFlags as OpenFlags, RenameFlags};
+
+use crate::error::{Error, Result};
+use crate::root;
+
+// A root directory, like `pathrs::Root`.
+#[derive(Debug, Clone)]
+pub struct Root(root::Root);
+
+// An O_PATH fd of a resolved path, like `pathrs::Handle`.
+#[derive(Debug)]
+pub struct Handle(OwnedFd);
+
+impl Root {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Root> {
+        Ok(Root(root::Root::new(path)?))
+    }
+
+    // Resolve `path` inside of the root, following a trailing symlink.
+    pub fn resolve<P: AsRef<Path>>(&self, path: P) -> Result<Handle> {
+        let path = path.as_ref();
+
+        self.open_path(path, self.0.join(path)?)
+    }
+
+    // Resolve `path` inside of the root, a trailing symlink is opened itself.
+    pub fn resolve_nofollow<P: AsRef<Path>>(&self, path: P) -> Result<Handle> {
+        let path = path.as_ref();
+
+        let resolved = match split(path) {
+            Some((parent, name)) => self.0.join(parent)?.join(name),
+            None => self.0.join(path)?,
+        };
+        self.open_path(path, resolved)
+    }
+
+    // Read the symlink `path`.
+    pub fn readlink<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let handle = self.resolve_nofollow(path)?;
+        let target = retry_on_intr(|| readlinkat(&handle.0, "", Vec::new())).map_err(errno)?;
+
+        Ok(PathBuf::from(OsString::from_vec(target.into_bytes())))
+    }
+
+    // Create the file `path` and open it with `flags`, its parent directory must exist.
+    pub fn create_file<P: AsRef<Path>>(
+        &self,
+        path: P,
+        flags: OpenFlags,
+        perm: &Permissions,
+    ) -> Result<File> {
+        let (dir, name) = self.parent(path.as_ref())?;
+        let flags = flags | OpenFlags::CREATE | OpenFlags::NOFOLLOW | OpenFlags::CLOEXEC;
+        let mode = Mode::from_raw_mode(perm.mode());
+        let fd = retry_on_intr(|| openat(&dir.0, name, flags, mode)).map_err(errno)?;
+
+        Ok(File::from(fd))
+    }
+
+    // Create the directory `path` and its missing parents with `perm`.
+    pub fn mkdir_all<P: AsRef<Path>>(&self, path: P, perm: &Permissions) -> Result<Handle> {
+        let path = path.as_ref();
+        DirBuilder::new()
+            .recursive(true)
+            .mode(perm.mode())
+            .create(self.0.join(path)?)?;
+
+        self.resolve(path)
+    }
+
+    pub fn remove_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let (dir, name) = self.parent(path.as_ref())?;
+
+        retry_on_intr(|| unlinkat(&dir.0, name, AtFlags::empty())).map_err(errno)
+    }
+
+    pub fn remove_dir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let (dir, name) = self.parent(path.as_ref())?;
+
+        retry_on_intr(|| unlinkat(&dir.0, name, AtFlags::REMOVEDIR)).map_err(errno)
+    }
+
+    // Remove `path` and everything below it, symlinks are removed rather than followed.
+    pub fn remove_all<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let handle = self.resolve_nofollow(path)?;
+        let path = fd_path(&handle.0)?;
+
+        if fs::symlink_metadata(&path)?.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        source: P,
+        destination: Q,
+        flags: RenameFlags,
+    ) -> Result<()> {
+        let (src_dir, src_name) = self.parent(source.as_ref())?;
+        let (dst_dir, dst_name) = self.parent(destination.as_ref())?;
+
+        retry_on_intr(|| renameat_with(&src_dir.0, src_name, &dst_dir.0, dst_name, flags))
+            .map_err(errno)
+    }
+
+    // The parent directory and the name of `path`, which must end with a normal component.
+    fn parent<'a>(&self, path: &'a Path) -> Result<(Handle, &'a OsStr)> {
+        let (parent, name) = split(path).ok_or_else(|| Error::InvalidPath(path.to_path_buf()))?;
+
+        Ok((self.resolve(parent)?, name))
+    }
+
+    fn open_path(&self, path: &Path, resolved: PathBuf) -> Result<Handle> {
+        let flags = OpenFlags::PATH | OpenFlags::NOFOLLOW | OpenFlags::CLOEXEC;
+        let fd = retry_on_intr(|| openat(CWD, &resolved, flags, Mode::empty())).map_err(errno)?;
+
+        if fd_path(&fd)? != resolved {
+            return Err(Error::Escape(path.to_path_buf()));
+        }
+
+        Ok(Handle(fd))
+    }
+}
+
+impl Handle {
+    // Open the resolved file again with `flags` to do I/O on it.
+    pub fn reopen(&self, flags: OpenFlags) -> Result<File> {
+        let flags = (flags - OpenFlags::NOFOLLOW) | OpenFlags::CLOEXEC;
+        let proc_path = format!("/proc/self/fd/{}", self.0.as_raw_fd());
+        let fd = retry_on_intr(|| openat(CWD, proc_path.as_str(), flags, Mode::empty()))
+            .map_err(errno)?;
+
+        Ok(File::from(fd))
+    }
+
+    pub fn try_clone(&self) -> Result<Handle> {
+        Ok(Handle(self.0.try_clone()?))
+    }
+}
+
+impl AsFd for Handle {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.0.as_fd()
+    }
+}
+
+impl From<Handle> for OwnedFd {
+    fn from(handle: Handle) -> OwnedFd {
+        handle.0
+    }
+}
+
+fn split(path: &Path) -> Option<(&Path, &OsStr)> {
+    match path.components().next_back() {
+        Some(Component::Normal(name)) => Some((path.parent().unwrap_or(Path::new("")), name)),
+        _ => None,
+    }
+}
+
+fn fd_path(fd: &OwnedFd) -> Result<PathBuf> {
+    Ok(fs::read_link(format!("/proc/self/fd/{}", fd.as_raw_fd()))?)
+}
+
+fn errno(e: rustix::io::Errno) -> Error {
+    Error::Io(e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_pathrs_root() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            follow: bool,
+            result: Option<&'a str>,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::open(rootfs_dir.path()).unwrap();
+        let rootfs = rootfs_dir.path().canonicalize().unwrap();
+        let perm = Permissions::from_mode(0o755);
+        root.mkdir_all("etc", &perm).unwrap();
+        fs::write(rootfs.join("etc/passwd"), b"root").unwrap();
+        symlink("/etc/passwd", rootfs.join("etc/link")).unwrap();
+        symlink("../../../etc", rootfs.join("up")).unwrap();
+
+        let tests = &[
+            TestData {
+                name: "regular file",
+                path: "/etc/passwd",
+                follow: true,
+                result: Some("etc/passwd"),
+            },
+            TestData {
+                name: "trailing symlink followed",
+                path: "etc/link",
+                follow: true,
+                result: Some("etc/passwd"),
+            },
+            TestData {
+                name: "trailing symlink not followed",
+                path: "etc/link",
+                follow: false,
+                result: Some("etc/link"),
+            },
+            TestData {
+                name: "relative symlink above the root",
+                path: "up/passwd",
+                follow: false,
+                result: Some("etc/passwd"),
+            },
+            TestData {
+                name: "missing component",
+                path: "etc/missing",
+                follow: true,
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = if t.follow {
+                root.resolve(t.path)
+            } else {
+                root.resolve_nofollow(t.path)
+            };
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some(p) => assert!(
+                    fd_path(&result.unwrap().0).unwrap() == rootfs.join(p),
+                    "{}",
+                    msg
+                ),
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+
+        let mut data = String::new();
+        let handle = root.resolve("etc/link").unwrap();
+        let mut file = handle.reopen(OpenFlags::RDONLY).unwrap();
+        file.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "root");
+        assert_eq!(root.readlink("etc/link").unwrap(), Path::new("/etc/passwd"));
+
+        let mut file = root
+            .create_file("up/new", OpenFlags::WRONLY | OpenFlags::EXCL, &perm)
+            .unwrap();
+        file.write_all(b"new").unwrap();
+        assert_eq!(fs::read(rootfs.join("etc/new")).unwrap(), b"new");
+        root.rename("etc/new", "/new", RenameFlags::NOREPLACE)
+            .unwrap();
+        root.remove_file("new").unwrap();
+        assert!(root.remove_file("etc/..").is_err());
+        root.remove_all("up").unwrap();
+        assert!(rootfs.join("etc/passwd").exists());
+        root.remove_all("etc").unwrap();
+        assert!(!rootfs.join("etc").exists());
+    }
+}
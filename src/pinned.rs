@@ -0,0 +1,222 @@
+// A path resolved once and operated on many times, by the daemons holding on to the paths of
+// a rootfs. The parent directory of a `PinnedPath` is kept open, and before each operation the
+// parent is resolved again and checked to still be the same directory, so a directory renamed
+// away or replaced since is reported with `Error::Race` instead of being operated in.
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+#[cfg(target_os = "linux")]
+use std::os::fd::AsRawFd;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::root::Root;
+use crate::sys;
+
+#[derive(Debug)]
+pub struct PinnedPath {
+    root: Root,
+    // The resolved parent directory, on the host and inside of the rootfs.
+    parent: PathBuf,
+    parent_in_root: PathBuf,
+    name: OsString,
+    fd: OwnedFd,
+    dev: u64,
+    ino: u64,
+}
+
+impl PinnedPath {
+    // Resolve the parent of `unsafe_path` and open it, its last component isn't followed.
+    pub(crate) fn new<P: AsRef<Path>>(root: &Root, unsafe_path: P) -> Result<PinnedPath> {
+        let path = root.join_parent(unsafe_path)?;
+        let (parent, name) = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => (parent.to_path_buf(), name.to_os_string()),
+            _ => return Err(Error::InvalidPath(path)),
+        };
+        let parent_in_root =
+            Path::new("/").join(parent.strip_prefix(root.path()).unwrap_or(&parent));
+
+        let fd = sys::open_dir(&parent)?;
+        let m = File::from(fd.try_clone()?).metadata()?;
+
+        Ok(PinnedPath {
+            root: root.clone(),
+            parent,
+            parent_in_root,
+            name,
+            fd,
+            dev: m.dev(),
+            ino: m.ino(),
+        })
+    }
+
+    // The host path of the entry, as it was resolved.
+    pub fn path(&self) -> PathBuf {
+        self.parent.join(&self.name)
+    }
+
+    // The opened parent directory.
+    pub fn parent_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+
+    // Check that the parent still resolves to the opened directory, which wasn't renamed
+    // or replaced, else fail with `Error::Race`.
+    pub fn verify(&self) -> Result<()> {
+        let parent = self.root.join(&self.parent_in_root)?;
+        let same = match fs::symlink_metadata(&parent) {
+            Ok(m) => parent == self.parent && (m.dev(), m.ino()) == (self.dev, self.ino),
+            Err(_) => false,
+        };
+
+        match same {
+            true => Ok(()),
+            false => Err(Error::Race(self.parent_in_root.join(&self.name))),
+        }
+    }
+
+    // Open the entry with `options` after `verify`, without following it.
+    pub fn open(&self, options: &OpenOptions) -> Result<File> {
+        self.verify()?;
+        let mut options = options.clone();
+        options.custom_flags(sys::o_nofollow());
+
+        Ok(options.open(self.at())?)
+    }
+
+    // The metadata of the entry after `verify`, without following it.
+    pub fn metadata(&self) -> Result<fs::Metadata> {
+        self.verify()?;
+
+        Ok(fs::symlink_metadata(self.at())?)
+    }
+
+    // Remove the entry after `verify`, a file or a symlink.
+    pub fn remove_file(&self) -> Result<()> {
+        self.verify()?;
+
+        Ok(fs::remove_file(self.at())?)
+    }
+
+    // The entry through the opened parent, which the kernel looks up from the fd rather than
+    // from the path of the parent.
+    fn at(&self) -> PathBuf {
+        #[cfg(target_os = "linux")]
+        return PathBuf::from(format!("/proc/self/fd/{}", self.fd.as_raw_fd())).join(&self.name);
+        #[cfg(not(target_os = "linux"))]
+        return self.parent.join(&self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+    use std::io::{Read, Write};
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_pinned_path() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            // change the tree after the path was pinned
+            change: fn(&Path),
+            ok: bool,
+        }
+
+        let tests = &[
+            TestData {
+                name: "unchanged",
+                change: |_| {},
+                ok: true,
+            },
+            TestData {
+                name: "file replaced",
+                change: |top| fs::write(top.join("a/b/f"), "other").unwrap(),
+                ok: true,
+            },
+            TestData {
+                name: "parent renamed and replaced",
+                change: |top| {
+                    fs::rename(top.join("a/b"), top.join("a/old")).unwrap();
+                    fs::create_dir(top.join("a/b")).unwrap();
+                    fs::write(top.join("a/b/f"), "other").unwrap();
+                },
+                ok: false,
+            },
+            TestData {
+                name: "parent swapped for a symlink",
+                change: |top| {
+                    fs::rename(top.join("a/b"), top.join("a/old")).unwrap();
+                    symlink("old", top.join("a/b")).unwrap();
+                },
+                ok: false,
+            },
+            TestData {
+                name: "ancestor moved out of the rootfs",
+                change: |top| {
+                    let outside = top.parent().unwrap().join("outside");
+                    fs::rename(top.join("a"), &outside).unwrap();
+                    symlink(&outside, top.join("a")).unwrap();
+                },
+                ok: false,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}", i, t.name);
+
+            let dir = tempfile::tempdir().expect("failed to create tmpdir");
+            let top = dir.path().canonicalize().unwrap().join("rootfs");
+            TreeBuilder::new()
+                .file("a/b/f", "data")
+                .symlink("l", "/a/b")
+                .build_in(&top)
+                .unwrap();
+            let root = Root::new(&top).unwrap();
+            let pinned = root.pin("l/f").unwrap();
+            assert!(pinned.path() == top.join("a/b/f"), "{}", msg);
+
+            (t.change)(&top);
+            let result = pinned.open(OpenOptions::new().read(true));
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            match result {
+                Ok(mut file) => {
+                    let mut content = String::new();
+                    file.read_to_string(&mut content).unwrap();
+                    assert!(
+                        content == fs::read_to_string(top.join("a/b/f")).unwrap(),
+                        "{}",
+                        msg
+                    );
+                }
+                Err(e) => {
+                    assert!(matches!(e, Error::Race(_)), "{}", msg);
+                    assert!(
+                        pinned.metadata().is_err() && pinned.remove_file().is_err(),
+                        "{}",
+                        msg
+                    );
+                }
+            }
+        }
+
+        let tree = TreeBuilder::new().file("f", "").build().unwrap();
+        let root = tree.root().unwrap();
+        let pinned = root.pin("/new").unwrap();
+        pinned
+            .open(OpenOptions::new().write(true).create_new(true))
+            .unwrap()
+            .write_all(b"new")
+            .unwrap();
+        assert_eq!(pinned.metadata().unwrap().len(), 3);
+        pinned.remove_file().unwrap();
+        assert!(!tree.path().join("new").exists());
+        assert!(root.pin("/").is_err());
+        assert!(root.pin("f/x").is_err());
+    }
+}
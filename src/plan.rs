@@ -0,0 +1,213 @@
+// A dry run of the operations creating or renaming a path inside of a rootfs. Nothing is
+// created, the plans only report what `Root::mkdir_all` or a create or rename would do, so
+// an orchestration tool can show the changes before applying them.
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::root::Root;
+use crate::trace::Step;
+
+// An operation of a plan, the paths are inside of the rootfs like "/a/b".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    // Create the directory.
+    Mkdir(PathBuf),
+    // Create the file.
+    Create(PathBuf),
+    // Remove the existing entry replaced by a rename.
+    Replace(PathBuf),
+    Rename { from: PathBuf, to: PathBuf },
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operation::Mkdir(p) => write!(f, "mkdir {}", p.display()),
+            Operation::Create(p) => write!(f, "create {}", p.display()),
+            Operation::Replace(p) => write!(f, "replace {}", p.display()),
+            Operation::Rename { from, to } => {
+                write!(f, "rename {} -> {}", from.display(), to.display())
+            }
+        }
+    }
+}
+
+// The directories `Root::mkdir_all` would create for `unsafe_path`, parents first. An
+// existing directory needs none.
+pub fn plan_mkdir_all<P: AsRef<Path>>(root: &Root, unsafe_path: P) -> Result<Vec<Operation>> {
+    let (path, missing) = missing(root, unsafe_path.as_ref())?;
+    if missing.is_empty() && !path.is_dir() {
+        return Err(Error::Io(io::ErrorKind::AlreadyExists.into()));
+    }
+
+    Ok(missing.into_iter().map(Operation::Mkdir).collect())
+}
+
+// The operations creating the file `unsafe_path` with its missing parents. An existing
+// file needs none, an existing directory fails.
+pub fn plan_create<P: AsRef<Path>>(root: &Root, unsafe_path: P) -> Result<Vec<Operation>> {
+    let (path, mut missing) = missing(root, unsafe_path.as_ref())?;
+    if path.is_dir() {
+        return Err(Error::Io(io::ErrorKind::IsADirectory.into()));
+    }
+
+    let file = missing.pop().map(Operation::Create);
+    let mut operations: Vec<Operation> = missing.into_iter().map(Operation::Mkdir).collect();
+    operations.extend(file);
+
+    Ok(operations)
+}
+
+// The operations renaming `from` to `to`. The last component of both is not followed, like
+// rename() does. `from` and the parent directory of `to` must exist, an existing `to` is
+// replaced.
+pub fn plan_rename<P: AsRef<Path>, Q: AsRef<Path>>(
+    root: &Root,
+    from: P,
+    to: Q,
+) -> Result<Vec<Operation>> {
+    let from = root.join_parent(from)?;
+    let to = root.join_parent(to)?;
+    let from_meta = fs::symlink_metadata(&from)?;
+    if !to.parent().is_some_and(Path::is_dir) {
+        return Err(Error::Io(io::ErrorKind::NotFound.into()));
+    }
+
+    let mut operations = Vec::new();
+    match fs::symlink_metadata(&to) {
+        Ok(m) if m.is_dir() != from_meta.is_dir() => {
+            return Err(Error::InvalidPath(in_root(root, &to)));
+        }
+        Ok(_) => operations.push(Operation::Replace(in_root(root, &to))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(Error::Io(e)),
+    }
+    operations.push(Operation::Rename {
+        from: in_root(root, &from),
+        to: in_root(root, &to),
+    });
+
+    Ok(operations)
+}
+
+// Resolve `unsafe_path`, returns the resolved path and its missing paths inside of the rootfs,
+// parents first.
+fn missing(root: &Root, unsafe_path: &Path) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let mut pinned = None;
+    let path = root.join_traced(unsafe_path, |s| {
+        if let Step::Pinned(p) = s {
+            pinned = Some(p);
+        }
+    })?;
+    let pinned = pinned.unwrap_or_else(|| root.path().to_path_buf());
+
+    let mut missing: Vec<PathBuf> = path
+        .ancestors()
+        .take_while(|p| *p != pinned)
+        .map(|p| in_root(root, p))
+        .collect();
+    missing.reverse();
+
+    Ok((path, missing))
+}
+
+fn in_root(root: &Root, path: &Path) -> PathBuf {
+    Path::new("/").join(path.strip_prefix(root.path()).unwrap_or(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_plan() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            mkdir_all: Option<&'a [&'a str]>,
+            create: Option<&'a [&'a str]>,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        root.mkdir_all("a/b").unwrap();
+        fs::write(root.path().join("a/f"), b"").unwrap();
+        symlink("/a", root.path().join("l")).unwrap();
+
+        let tests = &[
+            TestData {
+                name: "existing directory",
+                path: "a/b",
+                mkdir_all: Some(&[]),
+                create: None,
+            },
+            TestData {
+                name: "missing directories",
+                path: "a/b/c/d",
+                mkdir_all: Some(&["mkdir /a/b/c", "mkdir /a/b/c/d"]),
+                create: Some(&["mkdir /a/b/c", "create /a/b/c/d"]),
+            },
+            TestData {
+                name: "through a symlink",
+                path: "l/../../l/x",
+                mkdir_all: Some(&["mkdir /a/x"]),
+                create: Some(&["create /a/x"]),
+            },
+            TestData {
+                name: "existing file",
+                path: "a/f",
+                mkdir_all: None,
+                create: Some(&[]),
+            },
+            TestData {
+                name: "below a file",
+                path: "a/f/x",
+                mkdir_all: None,
+                create: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let mkdir_all = plan_mkdir_all(&root, t.path);
+            let create = plan_create(&root, t.path);
+
+            let msg = format!("{}, result: {:?} {:?}", msg, mkdir_all, create);
+
+            let display = |ops: Result<Vec<Operation>>| -> Option<Vec<String>> {
+                ops.ok()
+                    .map(|ops| ops.iter().map(|o| o.to_string()).collect())
+            };
+            let expected = |ops: Option<&[&str]>| -> Option<Vec<String>> {
+                ops.map(|ops| ops.iter().map(|o| o.to_string()).collect())
+            };
+            assert!(display(mkdir_all) == expected(t.mkdir_all), "{}", msg);
+            assert!(display(create) == expected(t.create), "{}", msg);
+        }
+        assert!(!root.path().join("a/b/c").exists());
+
+        let rename = plan_rename(&root, "l", "a/b/l").unwrap();
+        assert_eq!(
+            rename,
+            vec![Operation::Rename {
+                from: PathBuf::from("/l"),
+                to: PathBuf::from("/a/b/l"),
+            }]
+        );
+        let rename = plan_rename(&root, "/a/f", "l/g").unwrap();
+        assert_eq!(rename[0].to_string(), "rename /a/f -> /a/g");
+        fs::write(root.path().join("a/g"), b"").unwrap();
+        let rename = plan_rename(&root, "/a/f", "l/g").unwrap();
+        assert_eq!(rename[0], Operation::Replace(PathBuf::from("/a/g")));
+        assert!(plan_rename(&root, "a/missing", "a/x").is_err());
+        assert!(plan_rename(&root, "a/f", "a/missing/x").is_err());
+        assert!(plan_rename(&root, "a/f", "a/b").is_err());
+    }
+}
@@ -0,0 +1,294 @@
+// Path policies declared as data, e.g. in a TOML or JSON file shipped with a platform,
+// instead of being coded into every user of a rootfs:
+//
+// ```toml
+// symlinks = "deny"
+// deny = ["/data/secrets"]
+//
+// [[allow]]
+// prefix = "/data"
+// ops = ["read", "write"]
+//
+// [[allow]]
+// prefix = "/etc"
+// ops = ["read"]
+// ```
+//
+// The prefixes are paths inside of the rootfs and match whole components, so "/data" covers
+// "/data/x" but not "/database".
+use std::path::{Component, Path, PathBuf};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::resolve::resolve_counting;
+use crate::root::Root;
+pub use crate::serve::Symlinks;
+
+// The operations checked by a `Policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Op {
+    Read,
+    Write,
+}
+
+// The operations allowed below `prefix`.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Rule {
+    pub prefix: PathBuf,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub ops: Vec<Op>,
+}
+
+// A policy of the paths inside of a rootfs. A path is allowed the operations of the longest
+// `allow` prefix it is below, unless it is below a `deny` prefix. Everything else is denied.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Policy {
+    pub allow: Vec<Rule>,
+    pub deny: Vec<PathBuf>,
+    // Whether paths going through a symlink are resolved by `resolve`.
+    pub symlinks: Symlinks,
+}
+
+impl Policy {
+    // Load a policy from a TOML document.
+    #[cfg(feature = "toml")]
+    pub fn from_toml(s: &str) -> Result<Policy> {
+        toml::from_str(s).map_err(|e| invalid_data(e.to_string()))
+    }
+
+    // Load a policy from a JSON document.
+    #[cfg(feature = "serde_json")]
+    pub fn from_json(s: &str) -> Result<Policy> {
+        serde_json::from_str(s).map_err(|e| invalid_data(e.to_string()))
+    }
+
+    // Check the operation `op` on the resolved path `path`, relative to the rootfs or
+    // absolute from its top, fails with `Error::Denied`. A path still containing ".." is
+    // rejected with `Error::InvalidPath`, as it can't be matched without resolving it.
+    pub fn check<P: AsRef<Path>>(&self, path: P, op: Op) -> Result<()> {
+        let path = path.as_ref();
+        let normalized = normalize(path).ok_or_else(|| Error::InvalidPath(path.to_path_buf()))?;
+        let denied = || Error::Denied(path.to_path_buf());
+        let below = |prefix: &Path| normalize(prefix).is_some_and(|p| normalized.starts_with(p));
+
+        if self.deny.iter().any(|p| below(p)) {
+            return Err(denied());
+        }
+
+        let rule = self
+            .allow
+            .iter()
+            .filter(|r| below(&r.prefix))
+            .max_by_key(|r| normalize(&r.prefix).map_or(0, |p| p.components().count()))
+            .ok_or_else(denied)?;
+        if !rule.ops.contains(&op) {
+            return Err(denied());
+        }
+
+        Ok(())
+    }
+
+    // Resolve `unsafe_path` inside of `root` like `Root::check` and check the operation `op`
+    // on the result. Returns the resolved path on the host.
+    pub fn resolve<P: AsRef<Path>>(&self, root: &Root, unsafe_path: P, op: Op) -> Result<PathBuf> {
+        let unsafe_path = unsafe_path.as_ref();
+
+        let (path, symlinks) = resolve_counting(root.path(), unsafe_path, true, root.options())?;
+        if symlinks > 0 && self.symlinks == Symlinks::Deny {
+            return Err(Error::Denied(unsafe_path.to_path_buf()));
+        }
+        self.check(path.strip_prefix(root.path()).unwrap_or(&path), op)?;
+
+        Ok(path)
+    }
+}
+
+// The normal components of `path`, None if it has a "..".
+fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for it in path.components() {
+        match it {
+            Component::Normal(c) => normalized.push(c),
+            Component::ParentDir => return None,
+            _ => continue,
+        }
+    }
+
+    Some(normalized)
+}
+
+#[cfg(any(feature = "toml", feature = "serde_json"))]
+fn invalid_data(msg: String) -> Error {
+    Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    fn policy() -> Policy {
+        Policy {
+            allow: vec![
+                Rule {
+                    prefix: PathBuf::from("/data"),
+                    ops: vec![Op::Read, Op::Write],
+                },
+                Rule {
+                    prefix: PathBuf::from("/data/ro"),
+                    ops: vec![Op::Read],
+                },
+                Rule {
+                    prefix: PathBuf::from("etc"),
+                    ops: vec![Op::Read],
+                },
+            ],
+            deny: vec![PathBuf::from("/data/secrets")],
+            symlinks: Symlinks::Deny,
+        }
+    }
+
+    #[test]
+    fn test_policy_check() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            op: Op,
+            allowed: bool,
+        }
+
+        let tests = &[
+            TestData {
+                name: "allowed write",
+                path: "/data/x",
+                op: Op::Write,
+                allowed: true,
+            },
+            TestData {
+                name: "relative path",
+                path: "data/x",
+                op: Op::Read,
+                allowed: true,
+            },
+            TestData {
+                name: "longest prefix wins",
+                path: "/data/ro/x",
+                op: Op::Write,
+                allowed: false,
+            },
+            TestData {
+                name: "read below the longest prefix",
+                path: "/data/ro/x",
+                op: Op::Read,
+                allowed: true,
+            },
+            TestData {
+                name: "deny wins",
+                path: "/data/secrets/key",
+                op: Op::Read,
+                allowed: false,
+            },
+            TestData {
+                name: "prefix matches whole components",
+                path: "/database",
+                op: Op::Read,
+                allowed: false,
+            },
+            TestData {
+                name: "no rule",
+                path: "/usr/bin/sh",
+                op: Op::Read,
+                allowed: false,
+            },
+            TestData {
+                name: "unresolved ..",
+                path: "/data/../secrets",
+                op: Op::Read,
+                allowed: false,
+            },
+        ];
+
+        let policy = policy();
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = policy.check(t.path, t.op);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.is_ok() == t.allowed, "{}", msg);
+        }
+
+        // the longest prefix, whether the prefixes are absolute or relative
+        let mixed = Policy {
+            allow: vec![
+                Rule {
+                    prefix: PathBuf::from("data/ro"),
+                    ops: vec![Op::Read],
+                },
+                Rule {
+                    prefix: PathBuf::from("/data"),
+                    ops: vec![Op::Read, Op::Write],
+                },
+            ],
+            ..Default::default()
+        };
+        assert!(mixed.check("data/ro/x", Op::Write).is_err());
+        assert!(mixed.check("/data/ro/x", Op::Read).is_ok());
+        assert!(mixed.check("/data/x", Op::Write).is_ok());
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        symlink("/data/secrets", root.path().join("l")).unwrap();
+        assert_eq!(
+            policy.resolve(&root, "/data/../data/x", Op::Write).unwrap(),
+            root.path().join("data/x")
+        );
+        assert!(matches!(
+            policy.resolve(&root, "l/key", Op::Read),
+            Err(Error::Denied(_))
+        ));
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn test_policy_from_toml() {
+        let policy = Policy::from_toml(
+            r#"
+            symlinks = "deny"
+            deny = ["/data/secrets"]
+
+            [[allow]]
+            prefix = "/data"
+            ops = ["read", "write"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(policy.symlinks, Symlinks::Deny);
+        assert!(policy.check("/data/x", Op::Write).is_ok());
+        assert!(policy.check("/data/secrets/x", Op::Read).is_err());
+
+        assert!(Policy::from_toml("symlink = \"deny\"").is_err());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_policy_from_json() {
+        let policy =
+            Policy::from_json(r#"{"allow": [{"prefix": "/etc", "ops": ["read"]}]}"#).unwrap();
+        assert_eq!(policy.symlinks, Symlinks::Follow);
+        assert!(policy.check("/etc/passwd", Op::Read).is_ok());
+        assert!(policy.check("/etc/passwd", Op::Write).is_err());
+
+        assert!(Policy::from_json(r#"{"allow": [{"prefix": "/etc", "ops": ["exec"]}]}"#).is_err());
+    }
+}
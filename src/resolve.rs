@@ -0,0 +1,861 @@
+// The resolver behind `Root`. It walks the untrusted path one component at a time and
+// follows symlinks itself, so that neither ".." nor a symlink target can leave the rootfs.
+use std::ffi::OsStr;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use std::thread;
+
+use crate::audit::{is_rejection, AuditEvent, AuditRecord};
+use crate::error::{Error, Result};
+use crate::root::{FsType, RaceRetry, RootOptions};
+use crate::sys::{self, Dir, Entry};
+use crate::trace::{EscapeAction, EscapeEvent, Step};
+use crate::vfs::{FileKind, Vfs};
+
+// The maximum number of symlinks followed during one resolution, like Linux's MAXSYMLINKS.
+pub const MAX_SYMLINKS: u32 = 40;
+
+// The metrics recorded with the `metrics` feature.
+#[cfg(feature = "metrics")]
+const METRIC_RESOLUTIONS: &str = "securepath_resolutions_total";
+#[cfg(feature = "metrics")]
+const METRIC_ESCAPE_ATTEMPTS: &str = "securepath_escape_attempts_total";
+#[cfg(feature = "metrics")]
+const METRIC_SYMLINK_HOPS: &str = "securepath_symlink_hops";
+
+// Resolve `unsafe_path` inside of `rootfs`, which must be a canonicalized path.
+//
+// Absolute symlink targets are resolved relative to the rootfs and ".." is clamped at the
+// rootfs, which is exactly what the kernel does inside of a chroot. With `strict` set, a
+// ".." climbing above the rootfs is reported as `Error::Escape` instead of being clamped.
+// The missing trailing components are joined as is.
+pub(crate) fn resolve(rootfs: &Path, unsafe_path: &Path, strict: bool) -> Result<PathBuf> {
+    let options = RootOptions::default();
+
+    resolve_counting(rootfs, unsafe_path, strict, &options).map(|(path, _)| path)
+}
+
+// Resolve `unsafe_path` like `resolve` with the `options` of a `Root`, also returns the
+// number of symlinks followed.
+pub(crate) fn resolve_counting(
+    rootfs: &Path,
+    unsafe_path: &Path,
+    strict: bool,
+    options: &RootOptions,
+) -> Result<(PathBuf, u32)> {
+    resolve_traced(rootfs, unsafe_path, strict, options, &mut |_| {})
+}
+
+// Resolve `unsafe_path` like `resolve_counting`, calling `on_step` for each step taken. A
+// resolution which raced with a rename is retried with `options.race_retry`, the steps of
+// every attempt are reported.
+pub(crate) fn resolve_traced(
+    rootfs: &Path,
+    unsafe_path: &Path,
+    strict: bool,
+    options: &RootOptions,
+    on_step: &mut dyn FnMut(Step),
+) -> Result<(PathBuf, u32)> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!(
+        "resolve",
+        rootfs = %rootfs.display(),
+        path = %unsafe_path.display(),
+        strict
+    )
+    .entered();
+
+    let mut symlinks = 0;
+    let result = check_allowlist(unsafe_path, unsafe_path, options).and_then(|_| {
+        retry_races(options.race_retry.as_ref(), || {
+            symlinks = 0;
+            Dir::open(rootfs).map_err(Error::Io).and_then(|dir| {
+                walk(
+                    dir,
+                    rootfs,
+                    unsafe_path,
+                    strict,
+                    options,
+                    &mut symlinks,
+                    on_step,
+                )
+            })
+        })
+    });
+    let result = result.and_then(|path| {
+        let resolved = path.strip_prefix(rootfs).unwrap_or(&path);
+        check_allowlist(resolved, unsafe_path, options).map(|_| path)
+    });
+
+    if let (Err(e), Some(sink)) = (&result, &options.audit_sink) {
+        if is_rejection(e) {
+            let event = AuditEvent::Rejected(e.to_string());
+            sink.record(&AuditRecord::new(rootfs, unsafe_path, event));
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    {
+        metrics::counter!(METRIC_RESOLUTIONS).increment(1);
+        metrics::histogram!(METRIC_SYMLINK_HOPS).record(symlinks as f64);
+    }
+
+    result.map(|path| (path, symlinks))
+}
+
+// Resolve `unsafe_path` inside of `rootfs` like `resolve_traced`, on the filesystem `vfs`
+// instead of the real one.
+pub(crate) fn resolve_vfs<V: Vfs + ?Sized>(
+    vfs: &V,
+    rootfs: &Path,
+    unsafe_path: &Path,
+    strict: bool,
+    on_step: &mut dyn FnMut(Step),
+) -> Result<(PathBuf, u32)> {
+    let options = RootOptions::default();
+    let dir = VfsDir {
+        vfs,
+        path: rootfs.to_path_buf(),
+    };
+
+    let mut symlinks = 0;
+    let result = walk(
+        dir,
+        rootfs,
+        unsafe_path,
+        strict,
+        &options,
+        &mut symlinks,
+        on_step,
+    );
+
+    result.map(|path| (path, symlinks))
+}
+
+// A directory the resolver can look up the entries of, one component at a time.
+pub(crate) trait Lookup: Sized {
+    fn lookup(&self, name: &OsStr) -> io::Result<Entry<Self>>;
+
+    // The statfs() magic number of the filesystem, 0 when unknown.
+    fn fs_type(&self) -> io::Result<u64>;
+
+    // Whether `path` still names this directory or file once the walk is done.
+    fn is_at(&self, _path: &Path) -> io::Result<bool> {
+        Ok(true)
+    }
+}
+
+impl Lookup for Dir {
+    fn lookup(&self, name: &OsStr) -> io::Result<Entry> {
+        Dir::lookup(self, name)
+    }
+
+    fn fs_type(&self) -> io::Result<u64> {
+        Dir::fs_type(self)
+    }
+
+    fn is_at(&self, path: &Path) -> io::Result<bool> {
+        Dir::is_at(self, path)
+    }
+}
+
+// A directory of a `Vfs`, by its path.
+struct VfsDir<'a, V: ?Sized> {
+    vfs: &'a V,
+    path: PathBuf,
+}
+
+impl<V: Vfs + ?Sized> Lookup for VfsDir<'_, V> {
+    fn lookup(&self, name: &OsStr) -> io::Result<Entry<Self>> {
+        let path = self.path.join(name);
+        let entry = |path| VfsDir {
+            vfs: self.vfs,
+            path,
+        };
+
+        match self.vfs.lstat(&path)? {
+            FileKind::Dir => Ok(Entry::Dir(entry(path))),
+            FileKind::Symlink => Ok(Entry::Symlink {
+                target: self.vfs.readlink(&path)?,
+                magic: false,
+            }),
+            FileKind::File => Ok(Entry::Other(entry(path))),
+        }
+    }
+
+    fn fs_type(&self) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+fn walk<D: Lookup>(
+    root_dir: D,
+    rootfs: &Path,
+    unsafe_path: &Path,
+    strict: bool,
+    options: &RootOptions,
+    symlinks: &mut u32,
+    on_step: &mut dyn FnMut(Step),
+) -> Result<PathBuf> {
+    let mut path = rootfs.to_path_buf();
+    let mut pending: Vec<PathBuf> = components(unsafe_path);
+
+    // The opened directories along `path`, followed by `tail` components which don't exist
+    // or, with `tail_is_file`, start with the non-directory `file`.
+    let mut dirs = vec![root_dir];
+    let mut tail = 0;
+    let mut tail_is_file = false;
+    let mut file = None;
+
+    while let Some(it) = pending.pop() {
+        if it.as_os_str() == ".." {
+            if tail > 0 {
+                tail -= 1;
+                path.pop();
+                if tail == 0 {
+                    file = None;
+                }
+                on_step(Step::Parent(path.clone()));
+            } else if dirs.len() > 1 {
+                dirs.pop();
+                path.pop();
+                on_step(Step::Parent(path.clone()));
+            } else {
+                report_escape(rootfs, unsafe_path, strict, options);
+                if strict {
+                    return Err(Error::Escape(unsafe_path.to_path_buf()));
+                }
+                on_step(Step::Clamp);
+            }
+            continue;
+        }
+
+        if options.deny_dotfiles && it.as_os_str().as_bytes().first() == Some(&b'.') {
+            return Err(Error::Dotfile(unsafe_path.to_path_buf()));
+        }
+
+        path.push(&it);
+        if tail > 0 {
+            if tail_is_file {
+                return Err(Error::Io(sys::not_a_directory()));
+            }
+            tail += 1;
+            continue;
+        }
+
+        let target = match dirs[dirs.len() - 1].lookup(it.as_os_str()) {
+            Ok(Entry::Symlink { magic: true, .. }) if options.deny_magic_links => {
+                report_magic_link(rootfs, unsafe_path, options);
+                return Err(Error::Escape(unsafe_path.to_path_buf()));
+            }
+            Ok(Entry::Symlink { target, .. }) => target,
+            Ok(Entry::Dir(dir)) => {
+                if !options.deny_fs_types.is_empty() || options.deny_fuse {
+                    let fs_type = dir.fs_type()?;
+                    if options.deny_fs_types.iter().any(|t| t.magic() == fs_type)
+                        || (options.deny_fuse && is_fuse(fs_type))
+                    {
+                        return Err(Error::Denied(unsafe_path.to_path_buf()));
+                    }
+                }
+                on_step(Step::Enter(path.clone()));
+                dirs.push(dir);
+                continue;
+            }
+            // a deleted entry of an overlayfs layer
+            Ok(Entry::Whiteout) if options.overlay_whiteouts => {
+                on_step(Step::Missing(path.clone()));
+                tail = 1;
+                tail_is_file = false;
+                continue;
+            }
+            Ok(Entry::Whiteout) => {
+                on_step(Step::File(path.clone()));
+                tail = 1;
+                tail_is_file = true;
+                continue;
+            }
+            Ok(Entry::Other(fd)) => {
+                // a single file may be bind mounted from FUSE too
+                if options.deny_fuse && is_fuse(fd.fs_type()?) {
+                    return Err(Error::Denied(unsafe_path.to_path_buf()));
+                }
+                on_step(Step::File(path.clone()));
+                tail = 1;
+                tail_is_file = true;
+                file = Some(fd);
+                continue;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                on_step(Step::Missing(path.clone()));
+                tail = 1;
+                tail_is_file = false;
+                continue;
+            }
+            Err(e) => return Err(Error::Io(e)),
+        };
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            link = %path.display(),
+            target = %target.display(),
+            "symlink followed"
+        );
+
+        *symlinks += 1;
+        if *symlinks > MAX_SYMLINKS {
+            report_loop(rootfs, unsafe_path, options);
+            return Err(Error::TooManySymlinks(unsafe_path.to_path_buf()));
+        }
+
+        on_step(Step::Symlink {
+            link: path.clone(),
+            target: target.clone(),
+        });
+
+        path.pop();
+        if target.has_root() {
+            path = rootfs.to_path_buf();
+            dirs.truncate(1);
+        }
+        pending.extend(components(&target));
+    }
+
+    // the file itself, or the directory a missing path would be created in
+    if let Some(assertion) = &options.target_fs {
+        let fs_type = file.as_ref().unwrap_or(&dirs[dirs.len() - 1]).fs_type()?;
+        if !assertion.allows(fs_type) {
+            return Err(Error::Denied(unsafe_path.to_path_buf()));
+        }
+    }
+
+    // the fd the result is anchored to, the file or the last existing directory, which a
+    // rename or a swap of any component during the walk moves away from the path
+    let pinned = tail - file.as_ref().map_or(0, |_| 1);
+    let pinned = path.ancestors().nth(pinned).unwrap_or(rootfs).to_path_buf();
+    if !file
+        .as_ref()
+        .unwrap_or(&dirs[dirs.len() - 1])
+        .is_at(&pinned)?
+    {
+        return Err(Error::Race(unsafe_path.to_path_buf()));
+    }
+    on_step(Step::Pinned(pinned));
+
+    Ok(path)
+}
+
+// Run the resolution `resolve` again while it fails with `Error::Race`, at most `retry`
+// times, sleeping a jittered exponential backoff in between.
+fn retry_races<T, F>(retry: Option<&RaceRetry>, mut resolve: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let mut attempt = 0;
+    loop {
+        match (resolve(), retry) {
+            (Err(Error::Race(_)), Some(retry)) if attempt < retry.attempts => {
+                thread::sleep(retry.delay(attempt));
+                attempt += 1;
+            }
+            (result, _) => return result,
+        }
+    }
+}
+
+// Check the path `path` inside of the rootfs against `options.allowlist`.
+fn check_allowlist(path: &Path, unsafe_path: &Path, options: &RootOptions) -> Result<()> {
+    match &options.allowlist {
+        Some(allowlist) if !allowlist.allows(path) => Err(Error::Denied(unsafe_path.to_path_buf())),
+        _ => Ok(()),
+    }
+}
+
+// Whether the statfs() magic number `fs_type` is the one of FUSE, for `deny_fuse`.
+fn is_fuse(fs_type: u64) -> bool {
+    fs_type == FsType::Fuse.magic()
+}
+
+// Report a ".." climbing above the rootfs, which is clamped or rejected when `strict`. The
+// paths are logged with their debug representation, which escapes any control characters.
+#[cfg_attr(not(feature = "log"), allow(unused_variables))]
+fn report_escape(rootfs: &Path, unsafe_path: &Path, strict: bool, options: &RootOptions) {
+    let action = if strict { "rejected" } else { "clamped" };
+    match strict {
+        true => call_hook(rootfs, unsafe_path, EscapeAction::Rejected, options),
+        false => call_hook(rootfs, unsafe_path, EscapeAction::Clamped, options),
+    }
+
+    #[cfg(feature = "metrics")]
+    metrics::counter!(METRIC_ESCAPE_ATTEMPTS, "action" => action).increment(1);
+    #[cfg(feature = "tracing")]
+    tracing::warn!(action, "\"..\" climbs above the rootfs");
+    #[cfg(feature = "log")]
+    log::warn!(
+        "{} \"..\" climbing above the rootfs {:?} in {:?}",
+        action,
+        rootfs,
+        unsafe_path
+    );
+}
+
+// Report a resolution rejected because of too many symlinks.
+fn report_loop(rootfs: &Path, unsafe_path: &Path, options: &RootOptions) {
+    call_hook(rootfs, unsafe_path, EscapeAction::Loop, options);
+    #[cfg(feature = "metrics")]
+    metrics::counter!(METRIC_ESCAPE_ATTEMPTS, "action" => "loop").increment(1);
+    #[cfg(feature = "tracing")]
+    tracing::warn!("rejected: too many symlinks");
+    #[cfg(feature = "log")]
+    log::warn!(
+        "rejected {:?} in the rootfs {:?}: too many symlinks",
+        unsafe_path,
+        rootfs
+    );
+}
+
+// Report a resolution rejected because of a procfs magic link.
+fn report_magic_link(rootfs: &Path, unsafe_path: &Path, options: &RootOptions) {
+    call_hook(rootfs, unsafe_path, EscapeAction::MagicLink, options);
+    #[cfg(feature = "metrics")]
+    metrics::counter!(METRIC_ESCAPE_ATTEMPTS, "action" => "magic-link").increment(1);
+    #[cfg(feature = "tracing")]
+    tracing::warn!("rejected: procfs magic link");
+    #[cfg(feature = "log")]
+    log::warn!(
+        "rejected {:?} in the rootfs {:?}: procfs magic link",
+        unsafe_path,
+        rootfs
+    );
+}
+
+// Call the `on_escape_attempt` callback of the options.
+fn call_hook(rootfs: &Path, unsafe_path: &Path, action: EscapeAction, options: &RootOptions) {
+    if let Some(hook) = &options.on_escape_attempt {
+        hook.call(&EscapeEvent {
+            rootfs,
+            path: unsafe_path,
+            action,
+        });
+    }
+}
+
+// Split `path` into its normal and ".." components, in reversed order so that they can be
+// popped from the end.
+fn components(path: &Path) -> Vec<PathBuf> {
+    path.components()
+        .rev()
+        .filter_map(|it| match it {
+            Component::Normal(c) => Some(PathBuf::from(c)),
+            Component::ParentDir => Some(PathBuf::from("..")),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allowlist::PathAllowlist;
+    use crate::root::FsAssertion;
+    use crate::test_util::TreeBuilder;
+    use rustix::fs::{mknodat, FileType, Mode, CWD};
+
+    #[test]
+    fn test_resolve() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            symlinks: &'a [(&'a str, &'a str)],
+            unsafe_path: &'a str,
+            strict: bool,
+            result: Option<&'a str>,
+        }
+
+        let tests = &[
+            TestData {
+                name: "plain path",
+                symlinks: &[],
+                unsafe_path: "a/b/c",
+                strict: true,
+                result: Some("a/b/c"),
+            },
+            TestData {
+                name: "clamp ..",
+                symlinks: &[],
+                unsafe_path: "../../a/../../b",
+                strict: false,
+                result: Some("b"),
+            },
+            TestData {
+                name: "reject .. in strict mode",
+                symlinks: &[],
+                unsafe_path: "a/../../b",
+                strict: true,
+                result: None,
+            },
+            TestData {
+                name: "absolute symlink",
+                symlinks: &[("l", "/etc")],
+                unsafe_path: "l/passwd",
+                strict: true,
+                result: Some("etc/passwd"),
+            },
+            TestData {
+                name: "chained absolute symlinks",
+                symlinks: &[("etc", "/x"), ("l", "/etc")],
+                unsafe_path: "l/passwd",
+                strict: true,
+                result: Some("x/passwd"),
+            },
+            TestData {
+                name: "relative symlink beyond the rootfs",
+                symlinks: &[("l", "../../../etc")],
+                unsafe_path: "l/passwd",
+                strict: false,
+                result: Some("etc/passwd"),
+            },
+            TestData {
+                name: "relative symlink beyond the rootfs in strict mode",
+                symlinks: &[("l", "../../../etc")],
+                unsafe_path: "l/passwd",
+                strict: true,
+                result: None,
+            },
+            TestData {
+                name: "dangling symlink followed by ..",
+                symlinks: &[("l", "/missing/x")],
+                unsafe_path: "l/../y",
+                strict: true,
+                result: Some("missing/y"),
+            },
+            TestData {
+                name: "symlink loop",
+                symlinks: &[("a", "b"), ("b", "a")],
+                unsafe_path: "a",
+                strict: false,
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let tree = t
+                .symlinks
+                .iter()
+                .fold(TreeBuilder::new(), |tree, (link, target)| {
+                    tree.symlink(link, target)
+                })
+                .build()
+                .unwrap();
+            let rootfs = tree.path();
+            let result = resolve(rootfs, Path::new(t.unsafe_path), t.strict);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some(p) => assert!(result.unwrap() == rootfs.join(p), "{}", msg),
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+
+        let tree = TreeBuilder::new().file("f", "").build().unwrap();
+        let rootfs = tree.path();
+        assert_eq!(
+            resolve(rootfs, Path::new("f/../g"), true).unwrap(),
+            rootfs.join("g")
+        );
+        assert!(resolve(rootfs, Path::new("f/x"), true).is_err());
+
+        // creating an overlayfs whiteout needs CAP_MKNOD
+        let whiteout = rootfs.join("w");
+        let mode = Mode::from_raw_mode(0o600);
+        if mknodat(CWD, &whiteout, FileType::CharacterDevice, mode, 0).is_ok() {
+            let options = RootOptions {
+                overlay_whiteouts: true,
+                ..Default::default()
+            };
+            let result = resolve_counting(rootfs, Path::new("w/../x"), true, &options);
+            assert_eq!(result.unwrap().0, rootfs.join("x"));
+            assert!(resolve(rootfs, Path::new("w/x"), true).is_err());
+            assert!(resolve_counting(rootfs, Path::new("w/x"), true, &options).is_ok());
+        }
+
+        // "/" as the rootfs to go through the magic links of the real /proc
+        let rootfs = Path::new("/");
+        let options = RootOptions {
+            deny_magic_links: true,
+            ..Default::default()
+        };
+        let resolve_magic = |p: &str, options: &RootOptions| {
+            resolve_counting(rootfs, Path::new(p), false, options).map(|(path, _)| path)
+        };
+        assert_eq!(
+            resolve_magic("proc/self/root/etc", &RootOptions::default()).unwrap(),
+            Path::new("/etc")
+        );
+        assert!(resolve_magic("proc/self/root/etc", &options).is_err());
+        assert!(resolve_magic("proc/self/cwd", &options).is_err());
+        assert!(resolve_magic("proc/self/fd/0", &options).is_err());
+        assert!(resolve_magic("proc/self/mounts", &options).is_ok());
+
+        let options = RootOptions {
+            deny_fs_types: vec![FsType::Proc, FsType::from_name("sysfs").unwrap()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            resolve_magic("proc/self", &options),
+            Err(Error::Denied(_))
+        ));
+        assert!(resolve_magic("etc/passwd", &options).is_ok());
+        // the rootfs itself is never checked
+        assert!(resolve_counting(Path::new("/proc"), Path::new("x"), false, &options).is_ok());
+
+        let tree = TreeBuilder::new()
+            .file("srv/.env", "")
+            .file("srv/.git/config", "")
+            .file("srv/a..b", "")
+            .symlink("srv/env", ".env")
+            .build()
+            .unwrap();
+        let options = RootOptions {
+            deny_dotfiles: true,
+            ..Default::default()
+        };
+        let resolve_dotfile =
+            |p: &str| resolve_counting(tree.path(), Path::new(p), false, &options);
+        for p in [
+            "srv/.env",
+            "srv/.git/config",
+            "srv/env",
+            "srv/.missing",
+            "..",
+        ] {
+            let result = resolve_dotfile(p);
+            match p {
+                ".." => assert!(result.is_ok(), "{}: {:?}", p, result),
+                _ => assert!(
+                    matches!(result, Err(Error::Dotfile(_))),
+                    "{}: {:?}",
+                    p,
+                    result
+                ),
+            }
+        }
+        assert!(resolve_dotfile("srv/./a..b").is_ok());
+
+        let tree = TreeBuilder::new()
+            .file("static/site.css", "")
+            .file("etc/passwd", "")
+            .symlink("static/passwd", "/etc/passwd")
+            .symlink("static/up", "..")
+            .build()
+            .unwrap();
+        let options = RootOptions {
+            allowlist: Some(PathAllowlist::new(["/static", "/media"]).unwrap()),
+            ..Default::default()
+        };
+        let resolve_allowed =
+            |p: &str| resolve_counting(tree.path(), Path::new(p), false, &options);
+        assert!(resolve_allowed("static/site.css").is_ok());
+        assert!(resolve_allowed("/media/missing").is_ok());
+        for p in [
+            "etc/passwd",
+            "static/../etc/passwd",
+            "static/passwd",
+            "static/up/etc/passwd",
+        ] {
+            let result = resolve_allowed(p);
+            assert!(
+                matches!(result, Err(Error::Denied(_))),
+                "{}: {:?}",
+                p,
+                result
+            );
+        }
+        assert!(resolve_counting(
+            tree.path(),
+            Path::new("srv/env"),
+            false,
+            &RootOptions::default()
+        )
+        .is_ok());
+
+        // no FUSE mount to refuse here
+        let options = RootOptions {
+            deny_fuse: true,
+            ..Default::default()
+        };
+        assert!(resolve_magic("proc/self/status", &options).is_ok());
+        assert!(resolve_magic("etc/passwd", &options).is_ok());
+        assert!(is_fuse(0x6573_5546));
+
+        let options = RootOptions {
+            target_fs: Some(FsAssertion::NoneOf(vec![FsType::Proc])),
+            ..Default::default()
+        };
+        assert!(resolve_magic("proc/self/status", &options).is_err());
+        assert!(resolve_magic("proc/missing", &options).is_err());
+        assert!(resolve_magic("proc/../etc", &options).is_ok());
+        let options = RootOptions {
+            target_fs: Some(FsAssertion::OneOf(vec![FsType::Proc])),
+            ..Default::default()
+        };
+        assert!(resolve_magic("proc/self/status", &options).is_ok());
+        assert!(resolve_magic("proc/self/status/../../uptime", &options).is_ok());
+        assert!(matches!(
+            resolve_magic("etc", &options),
+            Err(Error::Denied(_))
+        ));
+    }
+
+    // A directory calling `on_lookup` after each lookup, to rename the tree mid-walk.
+    struct Swapping<'a> {
+        dir: Dir,
+        on_lookup: &'a dyn Fn(&OsStr),
+    }
+
+    impl Lookup for Swapping<'_> {
+        fn lookup(&self, name: &OsStr) -> io::Result<Entry<Self>> {
+            let entry = self.dir.lookup(name)?;
+            (self.on_lookup)(name);
+            let wrap = |dir| Swapping {
+                dir,
+                on_lookup: self.on_lookup,
+            };
+
+            Ok(match entry {
+                Entry::Dir(dir) => Entry::Dir(wrap(dir)),
+                Entry::Other(dir) => Entry::Other(wrap(dir)),
+                Entry::Symlink { target, magic } => Entry::Symlink { target, magic },
+                Entry::Whiteout => Entry::Whiteout,
+            })
+        }
+
+        fn fs_type(&self) -> io::Result<u64> {
+            self.dir.fs_type()
+        }
+
+        fn is_at(&self, path: &Path) -> io::Result<bool> {
+            self.dir.is_at(path)
+        }
+    }
+
+    #[test]
+    fn test_resolve_race() {
+        let tree = TreeBuilder::new().dir("a/b/c").build().unwrap();
+        let rootfs = tree.path();
+        let options = RootOptions::default();
+        let swapped = std::cell::Cell::new(false);
+        let swap = |name: &OsStr| {
+            if name == "b" && !swapped.replace(true) {
+                std::fs::rename(rootfs.join("a"), rootfs.join("old")).unwrap();
+                std::fs::create_dir_all(rootfs.join("a/b/c")).unwrap();
+            }
+        };
+        let resolve_swapping = |p: &str| {
+            let dir = Swapping {
+                dir: Dir::open(rootfs).unwrap(),
+                on_lookup: &swap,
+            };
+            walk(
+                dir,
+                rootfs,
+                Path::new(p),
+                false,
+                &options,
+                &mut 0,
+                &mut |_| {},
+            )
+        };
+
+        assert!(matches!(resolve_swapping("a/b/c"), Err(Error::Race(_))));
+        // the tree doesn't change anymore
+        assert_eq!(resolve_swapping("a/b/c").unwrap(), rootfs.join("a/b/c"));
+        assert_eq!(
+            resolve(rootfs, Path::new("old/b/c/d"), false).unwrap(),
+            rootfs.join("old/b/c/d")
+        );
+
+        let raced = |races: u32, retry: Option<RaceRetry>| {
+            let mut attempts = 0;
+            let result = retry_races(retry.as_ref(), || {
+                attempts += 1;
+                match attempts > races {
+                    true => Ok(attempts),
+                    false => Err(Error::Race(PathBuf::from("x"))),
+                }
+            });
+            result.ok()
+        };
+        let retry = |attempts| RaceRetry {
+            attempts,
+            backoff: std::time::Duration::ZERO,
+        };
+        assert_eq!(raced(0, None), Some(1));
+        assert_eq!(raced(1, None), None);
+        assert_eq!(raced(2, Some(retry(2))), Some(3));
+        assert_eq!(raced(3, Some(retry(2))), None);
+
+        let retry = RaceRetry {
+            attempts: 3,
+            backoff: std::time::Duration::from_millis(8),
+        };
+        for attempt in 0..3 {
+            let delay = retry.delay(attempt).as_millis();
+            let max = 8 << attempt;
+            assert!(delay >= max / 2 && delay <= max, "{} {}", attempt, delay);
+        }
+    }
+
+    #[test]
+    fn test_escape_hook() {
+        use crate::trace::EscapeHook;
+        use std::sync::{Arc, Mutex};
+
+        let tree = TreeBuilder::new()
+            .symlink("up", "../..")
+            .symlink("loop", "loop")
+            .build()
+            .unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&events);
+        let options = RootOptions {
+            deny_magic_links: true,
+            on_escape_attempt: Some(EscapeHook::new(move |e| {
+                let event = (e.rootfs.to_path_buf(), e.path.to_path_buf(), e.action);
+                recorded.lock().unwrap().push(event);
+            })),
+            ..Default::default()
+        };
+
+        let events_of = |rootfs: &Path, p: &str, strict: bool| {
+            let _ = resolve_counting(rootfs, Path::new(p), strict, &options);
+            std::mem::take(&mut *events.lock().unwrap())
+        };
+        let event =
+            |rootfs: &Path, p: &str, action| (rootfs.to_path_buf(), PathBuf::from(p), action);
+        let rootfs = tree.path();
+
+        assert!(events_of(rootfs, "a/../b", false).is_empty());
+        assert_eq!(
+            events_of(rootfs, "up", false),
+            vec![event(rootfs, "up", EscapeAction::Clamped); 2]
+        );
+        assert_eq!(
+            events_of(rootfs, "../x", true),
+            vec![event(rootfs, "../x", EscapeAction::Rejected)]
+        );
+        assert_eq!(
+            events_of(rootfs, "loop", false),
+            vec![event(rootfs, "loop", EscapeAction::Loop)]
+        );
+        let rootfs = Path::new("/");
+        assert_eq!(
+            events_of(rootfs, "proc/self/root", false),
+            vec![event(rootfs, "proc/self/root", EscapeAction::MagicLink)]
+        );
+    }
+}
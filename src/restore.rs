@@ -0,0 +1,227 @@
+// Restoring a set of files into a rootfs, like a backup tool applying a restore manifest. Each
+// entry is validated and created inside of the rootfs on its own, and reported with its own
+// result: a failed entry doesn't stop the ones after it.
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Cursor;
+use std::path::{Component, Path, PathBuf};
+
+use crate::archive::{create_file, create_symlink};
+use crate::error::{Error, Result};
+use crate::manifest::{apply_manifest, ManifestEntry};
+use crate::root::Root;
+
+// Where the content of a restored file comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreSource {
+    Bytes(Vec<u8>),
+    // A file of the host, e.g. in the backup storage, which is trusted.
+    HostFile(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestoreKind {
+    Directory,
+    File(RestoreSource),
+    // A symlink to the target, which must stay inside of the rootfs.
+    Symlink(PathBuf),
+}
+
+// An entry of a restore manifest, the attributes left to None keep their defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoreEntry {
+    // The path of the entry inside of the rootfs, it can't have a "..". An existing file or
+    // symlink there is replaced, its parent directories are created.
+    pub path: PathBuf,
+    pub kind: RestoreKind,
+    pub mode: Option<u32>,
+    // The owner inside of the rootfs, see `ManifestEntry`.
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub xattrs: Vec<(OsString, Vec<u8>)>,
+}
+
+impl RestoreEntry {
+    pub fn new<P: AsRef<Path>>(path: P, kind: RestoreKind) -> RestoreEntry {
+        RestoreEntry {
+            path: path.as_ref().to_path_buf(),
+            kind,
+            mode: None,
+            uid: None,
+            gid: None,
+            xattrs: Vec::new(),
+        }
+    }
+}
+
+// The outcome of restoring one entry.
+#[derive(Debug)]
+pub struct RestoreResult {
+    pub path: PathBuf,
+    pub result: Result<()>,
+}
+
+// Restore the `entries` inside of the rootfs of `root`, in order, and return the result of
+// each one. The content is created like an archive entry, then the mode, owner and xattrs are
+// applied with `manifest::apply_manifest`.
+pub fn restore(root: &Root, entries: &[RestoreEntry]) -> Vec<RestoreResult> {
+    entries
+        .iter()
+        .map(|entry| RestoreResult {
+            path: entry.path.clone(),
+            result: restore_entry(root, entry),
+        })
+        .collect()
+}
+
+fn restore_entry(root: &Root, entry: &RestoreEntry) -> Result<()> {
+    let path = &entry.path;
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(Error::InvalidPath(path.clone()));
+    }
+
+    match &entry.kind {
+        RestoreKind::Directory => {
+            root.mkdir_all(path)?;
+        }
+        RestoreKind::File(source) => {
+            let mode = entry.mode.unwrap_or(0o644);
+            match source {
+                RestoreSource::Bytes(data) => {
+                    create_file(root, path, mode, &mut Cursor::new(data))?;
+                }
+                RestoreSource::HostFile(src) => {
+                    create_file(root, path, mode, &mut File::open(src)?)?;
+                }
+            }
+        }
+        RestoreKind::Symlink(target) => {
+            if entry.mode.is_some() {
+                return Err(Error::InvalidPath(path.clone()));
+            }
+            create_symlink(root, path, target)?;
+        }
+    }
+
+    let attributes = (entry.mode, entry.uid, entry.gid) != (None, None, None);
+    if !attributes && entry.xattrs.is_empty() {
+        return Ok(());
+    }
+
+    apply_manifest(
+        root,
+        &[ManifestEntry {
+            path: path.clone(),
+            mode: entry.mode,
+            uid: entry.uid,
+            gid: entry.gid,
+            xattrs: entry.xattrs.clone(),
+        }],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_restore() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            entry: RestoreEntry,
+            ok: bool,
+        }
+
+        let dir = tempfile::tempdir().expect("failed to create tmpdir");
+        let backup = dir.path().join("backup");
+        fs::write(&backup, "backup").unwrap();
+        let bytes = |data: &str| RestoreKind::File(RestoreSource::Bytes(data.into()));
+
+        let tests = vec![
+            TestData {
+                name: "directory",
+                entry: RestoreEntry {
+                    mode: Some(0o700),
+                    ..RestoreEntry::new("/var/lib", RestoreKind::Directory)
+                },
+                ok: true,
+            },
+            TestData {
+                name: "file from bytes",
+                entry: RestoreEntry {
+                    mode: Some(0o4755),
+                    ..RestoreEntry::new("/usr/bin/su", bytes("su"))
+                },
+                ok: true,
+            },
+            TestData {
+                name: "file from the host",
+                entry: RestoreEntry::new(
+                    "/etc/hosts",
+                    RestoreKind::File(RestoreSource::HostFile(backup.clone())),
+                ),
+                ok: true,
+            },
+            TestData {
+                name: "symlink",
+                entry: RestoreEntry::new("/bin", RestoreKind::Symlink("usr/bin".into())),
+                ok: true,
+            },
+            TestData {
+                name: "symlink beyond the rootfs",
+                entry: RestoreEntry::new("/out", RestoreKind::Symlink("../../etc".into())),
+                ok: false,
+            },
+            TestData {
+                name: "path with ..",
+                entry: RestoreEntry::new("/etc/../../x", bytes("x")),
+                ok: false,
+            },
+            TestData {
+                name: "file through a symlink",
+                entry: RestoreEntry::new("/bin/sh", bytes("sh")),
+                ok: true,
+            },
+            TestData {
+                name: "file over a directory",
+                entry: RestoreEntry::new("/var/lib", bytes("lib")),
+                ok: false,
+            },
+            TestData {
+                name: "mode of a symlink",
+                entry: RestoreEntry {
+                    mode: Some(0o644),
+                    ..RestoreEntry::new("/lib", RestoreKind::Symlink("usr/lib".into()))
+                },
+                ok: false,
+            },
+        ];
+
+        let tree = TreeBuilder::new().file("etc/hosts", "old").build().unwrap();
+        let root = tree.root().unwrap();
+        let entries: Vec<RestoreEntry> = tests.iter().map(|t| t.entry.clone()).collect();
+        let results = restore(&root, &entries);
+        assert_eq!(results.len(), tests.len());
+
+        for (i, (t, result)) in tests.iter().zip(&results).enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.path == t.entry.path, "{}", msg);
+            assert!(result.result.is_ok() == t.ok, "{}", msg);
+        }
+
+        let mode = |p: &str| fs::symlink_metadata(tree.path().join(p)).unwrap().mode() & 0o7777;
+        assert_eq!(mode("var/lib"), 0o700);
+        assert_eq!(mode("usr/bin/su"), 0o4755);
+        let hosts = fs::read_to_string(tree.path().join("etc/hosts")).unwrap();
+        assert_eq!(hosts, "backup");
+        assert!(tree.path().join("usr/bin/sh").is_file());
+        assert!(!tree.path().join("out").exists() && !dir.path().join("x").exists());
+        assert!(fs::symlink_metadata(tree.path().join("lib")).is_err());
+    }
+}
@@ -0,0 +1,1680 @@
+use std::collections::hash_map::RandomState;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{
+    self as unix_fs, DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt,
+};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::allowlist::PathAllowlist;
+use crate::audit::{AuditEvent, AuditRecord, AuditSink};
+use crate::error::{Error, Result};
+use crate::glob::glob;
+#[cfg(any(feature = "sha2", feature = "blake3"))]
+use crate::hash::{hash_reader, Digest, HashAlgorithm};
+use crate::idmap::IdMapping;
+use crate::lock::{FileLock, LockKind};
+#[cfg(feature = "mmap")]
+use crate::mmap::{Mmap, MmapOptions};
+use crate::pinned::PinnedPath;
+use crate::resolve::{resolve_counting, resolve_traced};
+use crate::secure_join::host_to_root;
+use crate::secure_path_buf::Resolved;
+use crate::staging::Staging;
+use crate::sys::{self, WalkDir};
+use crate::temp::{tempdir_in, tempfile_in, TempDir, TempFile};
+use crate::trace::{self, EscapeHook, Step};
+use crate::walk::{disk_usage, find, open_dir, DiskUsage, FindOptions, Walk, WalkEntry};
+
+// Root is a handle to the root filesystem directory of a container. Every path passed
+// to its methods is untrusted and gets resolved inside of the rootfs.
+#[derive(Debug, Clone)]
+pub struct Root {
+    path: PathBuf,
+    options: RootOptions,
+}
+
+// Options of the resolution inside of a `Root`.
+#[derive(Debug, Clone, Default)]
+pub struct RootOptions {
+    // Refuse to go through the procfs magic links like "/proc/<pid>/root", "/proc/<pid>/cwd"
+    // or "/proc/<pid>/fd/<n>" with `Error::Escape`. They are followed as the path they read
+    // as otherwise, which rarely names what the kernel would open.
+    pub deny_magic_links: bool,
+    // Refuse the paths with a component starting with a ".", in the path itself or in the
+    // target of a symlink it goes through, with `Error::Dotfile`. Web servers never serve
+    // ".git", ".env" or ".htaccess", whether they exist or not.
+    pub deny_dotfiles: bool,
+    // Refuse the paths outside of the allowlist with `Error::Denied`: the requested path
+    // before any lookup, and the path it resolves to.
+    pub allowlist: Option<PathAllowlist>,
+    // Refuse to enter a directory on one of these filesystems with `Error::Denied`, as
+    // reported by statfs() for each directory the resolution goes through.
+    pub deny_fs_types: Vec<FsType>,
+    // Refuse to enter a FUSE mount with `Error::Denied`, like `deny_fs_types` with
+    // `FsType::Fuse` but also for a single file bind mounted from FUSE. The FUSE daemon
+    // answers every lookup, so it can report any type or symlink target and swap them
+    // between two calls, which no check in userspace can catch.
+    pub deny_fuse: bool,
+    // The id mapping of the id-mapped mount, or of the user namespace, the rootfs is owned
+    // through. `Root::owner` and `Root::chown` take and return the ids inside of it, e.g.
+    // 0 for the root user of the container.
+    pub id_mapping: Option<IdMapping>,
+    // Translate the host ids through the user namespace of this process, see
+    // `IdMapping::current`, for the callers running rootless or in a user namespace.
+    // `Root::chown` then fails with an `InvalidInput` I/O error naming the owner which has
+    // no mapping in it, instead of the EINVAL of chown().
+    pub userns: bool,
+    // Treat the overlayfs whiteouts, the character devices 0/0 of an upper or lower layer,
+    // as the missing entries the kernel shows in the merged tree. `Root::walk` skips them.
+    pub overlay_whiteouts: bool,
+    // The devices `Root::mknod` may create, `DEFAULT_DEVICES` when None.
+    pub devices: Option<Vec<Device>>,
+    // Assert the filesystem type of the resolved target, or of the directory a missing
+    // target would be created in, with `Error::Denied`. It's checked with fstatfs() on the
+    // fd the resolution ended on, for the callers relying on local filesystem semantics.
+    pub target_fs: Option<FsAssertion>,
+    // Retry a resolution which raced with a rename or a swap of one of its components, e.g.
+    // by an image being unpacked concurrently. It fails with `Error::Race` right away when
+    // None.
+    pub race_retry: Option<RaceRetry>,
+    // Called with each input which had to be clamped or rejected, see `EscapeEvent`.
+    pub on_escape_attempt: Option<EscapeHook>,
+    // Record every mutating operation and every rejected path to this sink, see the `audit`
+    // module.
+    pub audit_sink: Option<Arc<dyn AuditSink>>,
+    // The largest file `Root::read`, `Root::read_to_string` and the `ServeRoot` service of
+    // the `tower` feature read, `DEFAULT_READ_LIMIT` when None.
+    pub read_limit: Option<u64>,
+}
+
+// A `Root` stays usable across catch_unwind(), like in the C API, a sink panicking leaves at
+// worst its own state inconsistent.
+impl std::panic::UnwindSafe for RootOptions {}
+impl std::panic::RefUnwindSafe for RootOptions {}
+
+// How a resolution which raced is retried, see `RootOptions::race_retry`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaceRetry {
+    // The number of retries before failing with `Error::Race`.
+    pub attempts: u32,
+    // The backoff before the first retry, doubled for each next one. Each sleep is jittered
+    // randomly between half of it and all of it.
+    pub backoff: Duration,
+}
+
+impl Default for RaceRetry {
+    fn default() -> Self {
+        RaceRetry {
+            attempts: 3,
+            backoff: Duration::from_millis(1),
+        }
+    }
+}
+
+impl RaceRetry {
+    // The jittered sleep before the retry `attempt`, counted from 0.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff.saturating_mul(1 << attempt.min(16));
+        let jitter = RandomState::new().build_hasher().finish() % 1024;
+
+        backoff / 2 + (backoff / 2).mul_f64(jitter as f64 / 1024.0)
+    }
+}
+
+// Options of `Root::create_dir_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirOptions {
+    pub mode: u32,
+    // Set `mode` as is after mkdir(), instead of letting the umask of the process clear bits
+    // of it.
+    pub exact_mode: bool,
+}
+
+impl Default for DirOptions {
+    fn default() -> Self {
+        DirOptions {
+            mode: 0o755,
+            exact_mode: false,
+        }
+    }
+}
+
+// Options of `Root::write_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteOptions {
+    // Append to the file instead of truncating it.
+    pub append: bool,
+    // Create the missing parent directories, like `Root::mkdir_all`.
+    pub create_parents: bool,
+    // The mode of a created file, set as is without the umask. An existing file keeps its own.
+    pub mode: u32,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            append: false,
+            create_parents: false,
+            mode: 0o644,
+        }
+    }
+}
+
+// The filesystem types allowed for the target of a resolution, see `RootOptions::target_fs`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsAssertion {
+    // The target must be on one of these filesystems, e.g. ext4 or xfs.
+    OneOf(Vec<FsType>),
+    // The target must not be on any of these filesystems, e.g. nfs.
+    NoneOf(Vec<FsType>),
+}
+
+impl FsAssertion {
+    // Whether the statfs() magic number `fs_type` is allowed.
+    pub fn allows(&self, fs_type: u64) -> bool {
+        match self {
+            FsAssertion::OneOf(types) => types.iter().any(|t| t.magic() == fs_type),
+            FsAssertion::NoneOf(types) => !types.iter().any(|t| t.magic() == fs_type),
+        }
+    }
+}
+
+// The type of a device node.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    Char,
+    Block,
+}
+
+// A device number allowed by `RootOptions::devices`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Device {
+    pub kind: DeviceType,
+    pub major: u32,
+    pub minor: u32,
+}
+
+// The devices every container gets in the OCI runtime spec: /dev/null, /dev/zero,
+// /dev/full, /dev/random, /dev/urandom and /dev/tty.
+pub const DEFAULT_DEVICES: [Device; 6] = [
+    Device::char(1, 3),
+    Device::char(1, 5),
+    Device::char(1, 7),
+    Device::char(1, 8),
+    Device::char(1, 9),
+    Device::char(5, 0),
+];
+
+// The default of `RootOptions::read_limit`, 16 MiB.
+pub const DEFAULT_READ_LIMIT: u64 = 16 << 20;
+
+impl Device {
+    // The character device `major`:`minor`.
+    pub const fn char(major: u32, minor: u32) -> Device {
+        Device {
+            kind: DeviceType::Char,
+            major,
+            minor,
+        }
+    }
+}
+
+// The filesystem types of `RootOptions::deny_fs_types`, mostly pseudo-filesystems, and of
+// `FsAssertion`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsType {
+    Proc,
+    Sysfs,
+    // statfs() reports devtmpfs as tmpfs, so this refuses every tmpfs.
+    Devtmpfs,
+    Cgroup,
+    Cgroup2,
+    Debugfs,
+    Tracefs,
+    Securityfs,
+    Bpf,
+    // ext2, ext3 and ext4 share their magic number.
+    Ext4,
+    Xfs,
+    Btrfs,
+    Tmpfs,
+    Overlay,
+    Nfs,
+    Fuse,
+    // Any other filesystem, by its statfs() magic number.
+    Magic(u64),
+}
+
+impl FsType {
+    // The filesystem type of the name in "/proc/filesystems", e.g. "proc" or "cgroup2".
+    pub fn from_name(name: &str) -> Option<FsType> {
+        match name {
+            "proc" => Some(FsType::Proc),
+            "sysfs" => Some(FsType::Sysfs),
+            "devtmpfs" => Some(FsType::Devtmpfs),
+            "cgroup" => Some(FsType::Cgroup),
+            "cgroup2" => Some(FsType::Cgroup2),
+            "debugfs" => Some(FsType::Debugfs),
+            "tracefs" => Some(FsType::Tracefs),
+            "securityfs" => Some(FsType::Securityfs),
+            "bpf" => Some(FsType::Bpf),
+            "ext2" | "ext3" | "ext4" => Some(FsType::Ext4),
+            "xfs" => Some(FsType::Xfs),
+            "btrfs" => Some(FsType::Btrfs),
+            "tmpfs" => Some(FsType::Tmpfs),
+            "overlay" => Some(FsType::Overlay),
+            "nfs" | "nfs4" => Some(FsType::Nfs),
+            "fuse" | "fuseblk" => Some(FsType::Fuse),
+            _ => None,
+        }
+    }
+
+    // The statfs() magic number of the filesystem type, from linux/magic.h.
+    pub fn magic(self) -> u64 {
+        match self {
+            FsType::Proc => 0x9fa0,
+            FsType::Sysfs => 0x6265_6572,
+            FsType::Devtmpfs => 0x0102_1994,
+            FsType::Cgroup => 0x0027_e0eb,
+            FsType::Cgroup2 => 0x6367_7270,
+            FsType::Debugfs => 0x6462_6720,
+            FsType::Tracefs => 0x7472_6163,
+            FsType::Securityfs => 0x7363_6673,
+            FsType::Bpf => 0xcafe_4a11,
+            FsType::Ext4 => 0xef53,
+            FsType::Xfs => 0x5846_5342,
+            FsType::Btrfs => 0x9123_683e,
+            FsType::Tmpfs => 0x0102_1994,
+            FsType::Overlay => 0x794c_7630,
+            FsType::Nfs => 0x6969,
+            FsType::Fuse => 0x6573_5546,
+            FsType::Magic(magic) => magic,
+        }
+    }
+}
+
+impl Root {
+    // Open the rootfs directory. The path is canonicalized, so it must exist.
+    pub fn new<P: AsRef<Path>>(rootfs: P) -> Result<Root> {
+        Root::with_options(rootfs, RootOptions::default())
+    }
+
+    // Open the rootfs directory like `new`, resolving the paths with `options`.
+    pub fn with_options<P: AsRef<Path>>(rootfs: P, options: RootOptions) -> Result<Root> {
+        let path = rootfs.as_ref().canonicalize()?;
+        if !path.is_dir() {
+            return Err(Error::Io(io::Error::other(format!(
+                "{} is not a directory",
+                path.display()
+            ))));
+        }
+
+        Ok(Root { path, options })
+    }
+
+    // The canonicalized path of the rootfs on the host.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn options(&self) -> &RootOptions {
+        &self.options
+    }
+
+    // Resolve `unsafe_path` inside of the rootfs. Like `secure_join`, absolute symlinks are
+    // resolved relative to the rootfs and any ".." climbing above the rootfs is clamped.
+    pub fn join<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<PathBuf> {
+        resolve_counting(&self.path, unsafe_path.as_ref(), false, &self.options).map(|(p, _)| p)
+    }
+
+    // Resolve `unsafe_path` like `join`, the returned path borrows this `Root`.
+    pub fn resolve<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<Resolved<'_>> {
+        Resolved::new(self, unsafe_path)
+    }
+
+    // Resolve the parent of `unsafe_path` and keep it open, to operate on the path many times
+    // checking each time that the parent wasn't replaced, see `pinned::PinnedPath`.
+    pub fn pin<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<PinnedPath> {
+        PinnedPath::new(self, unsafe_path)
+    }
+
+    // Resolve `unsafe_path` like `join`, but fail with `Error::Escape` if a ".." in the path
+    // or in a symlink target climbs above the rootfs instead of clamping it.
+    pub fn check<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<PathBuf> {
+        resolve_counting(&self.path, unsafe_path.as_ref(), true, &self.options).map(|(p, _)| p)
+    }
+
+    // Resolve `unsafe_path` like `join`, calling `on_step` with each step taken, in order.
+    // The steps up to the failure are reported for a failed resolution too.
+    pub fn join_traced<P, F>(&self, unsafe_path: P, mut on_step: F) -> Result<PathBuf>
+    where
+        P: AsRef<Path>,
+        F: FnMut(Step),
+    {
+        let path = unsafe_path.as_ref();
+        resolve_traced(&self.path, path, false, &self.options, &mut on_step).map(|(p, _)| p)
+    }
+
+    // Resolve `unsafe_path` like `check`, calling `on_step` with each step taken.
+    pub fn check_traced<P, F>(&self, unsafe_path: P, mut on_step: F) -> Result<PathBuf>
+    where
+        P: AsRef<Path>,
+        F: FnMut(Step),
+    {
+        let path = unsafe_path.as_ref();
+        resolve_traced(&self.path, path, true, &self.options, &mut on_step).map(|(p, _)| p)
+    }
+
+    // Convert the host path `host_path` back into its path inside of the rootfs, see
+    // `secure_join::host_to_root`.
+    pub fn host_to_root<P: AsRef<Path>>(&self, host_path: P) -> Option<PathBuf> {
+        host_to_root(&self.path, host_path)
+    }
+
+    // Look up the single component `name` in the directory `parent`, as a FUSE `lookup`
+    // does. `parent` is a path relative to the rootfs returned by an earlier lookup, it must
+    // not contain symlinks or ".." anymore, otherwise `Error::Escape` is returned. The final
+    // component isn't followed, so a symlink is looked up as itself, and ".." stops at the
+    // rootfs. Returns the path of the child relative to the rootfs, its backing path on the
+    // host is `self.path().join(child)`.
+    pub fn resolve_component<P: AsRef<Path>>(&self, parent: P, name: &OsStr) -> Result<PathBuf> {
+        let parent = parent.as_ref();
+
+        let mut child = PathBuf::new();
+        for it in parent.components() {
+            match it {
+                Component::Normal(c) => child.push(c),
+                Component::CurDir => continue,
+                _ => return Err(Error::Escape(parent.to_path_buf())),
+            }
+        }
+        if self.join(&child)? != self.path.join(&child) {
+            return Err(Error::Escape(parent.to_path_buf()));
+        }
+
+        if name.is_empty() || name.as_bytes().contains(&b'/') || name.as_bytes().contains(&0) {
+            return Err(Error::InvalidPath(PathBuf::from(name)));
+        }
+        if name == ".." {
+            child.pop();
+        } else if name != "." {
+            child.push(name);
+        }
+
+        Ok(child)
+    }
+
+    // Resolve the parent of `unsafe_path` and join its last component unresolved, for the
+    // operations which don't follow it like rename().
+    pub(crate) fn join_parent<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<PathBuf> {
+        let unsafe_path = unsafe_path.as_ref();
+        let name = unsafe_path
+            .file_name()
+            .ok_or_else(|| Error::InvalidPath(unsafe_path.to_path_buf()))?;
+        let parent = unsafe_path.parent().unwrap_or_else(|| Path::new(""));
+
+        Ok(self.join(parent)?.join(name))
+    }
+
+    // Create the directory `unsafe_path` and all of its missing parents inside of the rootfs,
+    // returns the resolved path of the directory.
+    pub fn mkdir_all<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<PathBuf> {
+        Ok(self.mkdir_all_open(unsafe_path)?.0)
+    }
+
+    // Like `mkdir_all`, also returning the directory opened. The directories are created one
+    // component at a time relative to the fd of their parent, which is opened without following
+    // it, so a missing component created as a symlink since the resolution fails instead of
+    // creating directories outside of the rootfs.
+    pub(crate) fn mkdir_all_open<P: AsRef<Path>>(
+        &self,
+        unsafe_path: P,
+    ) -> Result<(PathBuf, WalkDir)> {
+        let path = self.join(unsafe_path)?;
+        let rel = path
+            .strip_prefix(&self.path)
+            .map_err(|_| Error::Escape(path.clone()))?;
+
+        let mut dir = WalkDir::open(&self.path)?;
+        for it in rel.components() {
+            let Component::Normal(name) = it else {
+                return Err(Error::Escape(path));
+            };
+            dir = match dir.open_child(name) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    match dir.create_dir(name, 0o777) {
+                        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                        result => result?,
+                    }
+                    dir.open_child(name)?
+                }
+                result => result?,
+            };
+        }
+        self.audit(&path, AuditEvent::Mkdir);
+
+        Ok((path, dir))
+    }
+
+    // Create the directory `unsafe_path` inside of the rootfs with `mode`, minus the umask
+    // like mkdir(). Its parent must exist and its last component is not followed, an existing
+    // entry fails. Returns the resolved path of the directory.
+    pub fn create_dir<P: AsRef<Path>>(&self, unsafe_path: P, mode: u32) -> Result<PathBuf> {
+        let options = DirOptions {
+            mode,
+            ..Default::default()
+        };
+
+        self.create_dir_with(unsafe_path, &options)
+    }
+
+    // Like `create_dir`, with `options`.
+    pub fn create_dir_with<P: AsRef<Path>>(
+        &self,
+        unsafe_path: P,
+        options: &DirOptions,
+    ) -> Result<PathBuf> {
+        let path = self.join_parent(unsafe_path)?;
+        fs::DirBuilder::new().mode(options.mode).create(&path)?;
+
+        if options.exact_mode {
+            // through an fd opened one component at a time without following them, in case
+            // the directory was swapped for a symlink or a FIFO since
+            let rel = path.strip_prefix(&self.path).unwrap_or(&path);
+            let dir = open_dir(self, rel).map_err(|e| match e {
+                Error::Io(e) if e.kind() == io::ErrorKind::NotADirectory => {
+                    Error::Race(path.clone())
+                }
+                e => e,
+            })?;
+            dir.set_mode(options.mode & 0o7777)?;
+        }
+        self.audit(&path, AuditEvent::Mkdir);
+
+        Ok(path)
+    }
+
+    // Create the device node `unsafe_path` inside of the rootfs with the mode 0666, e.g. to
+    // populate "/dev" of a container. A device missing from the `devices` of the options is
+    // refused with `Error::Denied`, and an existing entry is never replaced. Returns the
+    // resolved path of the node.
+    pub fn mknod<P: AsRef<Path>>(
+        &self,
+        unsafe_path: P,
+        kind: DeviceType,
+        major: u32,
+        minor: u32,
+    ) -> Result<PathBuf> {
+        let unsafe_path = unsafe_path.as_ref();
+        let device = Device { kind, major, minor };
+        let allowed = match &self.options.devices {
+            Some(devices) => devices.contains(&device),
+            None => DEFAULT_DEVICES.contains(&device),
+        };
+        if !allowed {
+            let e = Error::Denied(unsafe_path.to_path_buf());
+            self.audit(unsafe_path, AuditEvent::Rejected(e.to_string()));
+            return Err(e);
+        }
+
+        let path = self.join(unsafe_path)?;
+        sys::mknod(&path, kind == DeviceType::Block, major, minor)?;
+        self.audit(&path, AuditEvent::Mknod { kind, major, minor });
+
+        Ok(path)
+    }
+
+    // Expand the glob `pattern` inside of the rootfs, e.g. "logs/**/*.json". The matches are
+    // sorted and relative to the rootfs, see the `glob` module for the syntax.
+    pub fn glob(&self, pattern: &str) -> Result<Vec<PathBuf>> {
+        glob(self, pattern)
+    }
+
+    // Create a uniquely named temporary directory at the top of the rootfs, removed when the
+    // returned handle is dropped.
+    pub fn tempdir(&self) -> Result<TempDir> {
+        self.tempdir_in("")
+    }
+
+    // Create a uniquely named temporary directory in the directory `dir` of the rootfs.
+    pub fn tempdir_in<P: AsRef<Path>>(&self, dir: P) -> Result<TempDir> {
+        let temp = tempdir_in(self, dir.as_ref())?;
+        self.audit(temp.path(), AuditEvent::CreateTemp);
+
+        Ok(temp)
+    }
+
+    // Create and open a uniquely named temporary file in the directory `dir` of the rootfs,
+    // removed when the returned handle is dropped.
+    pub fn tempfile_in<P: AsRef<Path>>(&self, dir: P) -> Result<TempFile> {
+        let temp = tempfile_in(self, dir.as_ref())?;
+        self.audit(temp.path(), AuditEvent::CreateTemp);
+
+        Ok(temp)
+    }
+
+    // Stage an update of the directory `dest` in a hidden directory next to it, to promote it
+    // in place at once, see `Staging`.
+    pub fn staging<P: AsRef<Path>>(&self, dest: P) -> Result<Staging> {
+        Staging::new(self, dest)
+    }
+
+    // Explain in a single sentence how `unsafe_path` is resolved like `join`, or why it is
+    // rejected, for the error output of a CLI: e.g. `"l/x" resolves to "/etc/x", as "/l" is
+    // a symlink to "/etc" outside of the root, followed from the top of the root`.
+    pub fn explain<P: AsRef<Path>>(&self, unsafe_path: P) -> String {
+        self.explain_resolution(unsafe_path.as_ref(), false)
+    }
+
+    // Explain how `unsafe_path` is resolved like `check`, see `explain`.
+    pub fn explain_check<P: AsRef<Path>>(&self, unsafe_path: P) -> String {
+        self.explain_resolution(unsafe_path.as_ref(), true)
+    }
+
+    fn explain_resolution(&self, unsafe_path: &Path, strict: bool) -> String {
+        let mut steps = Vec::new();
+        let result = resolve_traced(&self.path, unsafe_path, strict, &self.options, &mut |s| {
+            steps.push(s)
+        })
+        .map(|(p, _)| p);
+
+        trace::explain(&self.path, unsafe_path, &steps, &result)
+    }
+
+    // Whether `a` and `b` name the same file or directory inside of the rootfs once resolved,
+    // compared by device and inode so hardlinks and different spellings match. A missing path
+    // names nothing and is equal to none, e.g. for the "destination equals source" checks.
+    pub fn paths_equal<P: AsRef<Path>, Q: AsRef<Path>>(&self, a: P, b: Q) -> Result<bool> {
+        let id = |p: &Path| -> Result<Option<(u64, u64)>> {
+            match fs::symlink_metadata(self.join(p)?) {
+                Ok(m) => Ok(Some((m.dev(), m.ino()))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(Error::Io(e)),
+            }
+        };
+        let a = id(a.as_ref())?;
+
+        Ok(a.is_some() && a == id(b.as_ref())?)
+    }
+
+    // The owner uid and gid of `unsafe_path` inside of the rootfs, mapped with the
+    // `id_mapping` of the options. A trailing symlink is followed.
+    pub fn owner<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<(u32, u32)> {
+        let m = fs::symlink_metadata(self.join(unsafe_path)?)?;
+        let (uid, gid) = match self.options.userns {
+            true => IdMapping::current()?.owner_to_outside(m.uid(), m.gid())?,
+            false => (m.uid(), m.gid()),
+        };
+
+        match &self.options.id_mapping {
+            Some(mapping) => mapping.owner_to_inside(uid, gid),
+            None => Ok((uid, gid)),
+        }
+    }
+
+    // Change the owner of `unsafe_path` inside of the rootfs to `uid` and `gid`, mapped with
+    // the `id_mapping` of the options first, and then into the user namespace of this
+    // process with `userns`. A trailing symlink is followed. An id which isn't mapped fails
+    // with an `InvalidInput` I/O error.
+    pub fn chown<P: AsRef<Path>>(&self, unsafe_path: P, uid: u32, gid: u32) -> Result<()> {
+        let path = self.join(unsafe_path)?;
+        let (uid, gid) = self.host_owner(uid, gid)?;
+
+        unix_fs::lchown(&path, Some(uid), Some(gid))?;
+        self.audit(&path, AuditEvent::Chown { uid, gid });
+
+        Ok(())
+    }
+
+    // The host ids of the owner `uid` and `gid` inside of the rootfs, mapped through
+    // `RootOptions::id_mapping` and the user namespace of the process with `RootOptions::userns`.
+    pub(crate) fn host_owner(&self, uid: u32, gid: u32) -> Result<(u32, u32)> {
+        let (uid, gid) = match &self.options.id_mapping {
+            Some(mapping) => mapping.owner_to_outside(uid, gid)?,
+            None => (uid, gid),
+        };
+
+        match self.options.userns {
+            true => Ok(IdMapping::current()?.owner_to_userns(uid, gid)?),
+            false => Ok((uid, gid)),
+        }
+    }
+
+    // Whether the directory `unsafe_path` inside of the rootfs is marked opaque by overlayfs
+    // with the "trusted.overlay.opaque" or "user.overlay.opaque" xattr, so the lower layers
+    // below it are hidden in the merged tree. Always false on other platforms than Linux.
+    pub fn is_opaque<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<bool> {
+        Ok(sys::is_opaque(&self.join(unsafe_path)?)?)
+    }
+
+    // Stream the content of the regular file `unsafe_path` into a digest with `algorithm`, for
+    // the integrity checkers verifying the content of a container. The file is opened at its
+    // resolved path without following it or waiting on a FIFO.
+    #[cfg(any(feature = "sha2", feature = "blake3"))]
+    pub fn hash_file<P: AsRef<Path>>(
+        &self,
+        unsafe_path: P,
+        algorithm: HashAlgorithm,
+    ) -> Result<Digest> {
+        let mut file = self.open_file(unsafe_path, OpenOptions::new().read(true))?;
+
+        Ok(hash_reader(&mut file, algorithm)?)
+    }
+
+    // Open the file `unsafe_path` resolves to with `options`, failing with an ELOOP I/O
+    // error if it was swapped for a symlink since the resolution.
+    pub fn open_with<P: AsRef<Path>>(&self, unsafe_path: P, options: &OpenOptions) -> Result<File> {
+        self.open_with_flags(unsafe_path, options, 0)
+    }
+
+    // Like `open_with`, with the open() `flags` added to O_NOFOLLOW, e.g. O_NONBLOCK not to
+    // wait on a FIFO. The custom flags of `options` are replaced.
+    pub(crate) fn open_with_flags<P: AsRef<Path>>(
+        &self,
+        unsafe_path: P,
+        options: &OpenOptions,
+        flags: i32,
+    ) -> Result<File> {
+        let path = self.join(unsafe_path)?;
+        let mut options = options.clone();
+        options.custom_flags(sys::o_nofollow() | flags);
+
+        Ok(options.open(path)?)
+    }
+
+    // Open the regular file `unsafe_path` like `open_with` does without waiting on a FIFO,
+    // anything else fails with `Error::InvalidPath`.
+    pub(crate) fn open_file<P: AsRef<Path>>(
+        &self,
+        unsafe_path: P,
+        options: &OpenOptions,
+    ) -> Result<File> {
+        let unsafe_path = unsafe_path.as_ref();
+        let file = self.open_with_flags(unsafe_path, options, sys::o_nonblock())?;
+        if !file.metadata()?.is_file() {
+            return Err(Error::InvalidPath(unsafe_path.to_path_buf()));
+        }
+
+        Ok(file)
+    }
+
+    // The content of the regular file `unsafe_path`, opened like `open_with` does without
+    // waiting on a FIFO. A file larger than the `read_limit` of the options fails with a
+    // `FileTooLarge` I/O error.
+    pub fn read<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<Vec<u8>> {
+        let file = self.open_file(unsafe_path, OpenOptions::new().read(true))?;
+        let limit = self.options.read_limit.unwrap_or(DEFAULT_READ_LIMIT);
+
+        let mut content = Vec::new();
+        file.take(limit.saturating_add(1))
+            .read_to_end(&mut content)?;
+        if content.len() as u64 > limit {
+            return Err(Error::Io(io::ErrorKind::FileTooLarge.into()));
+        }
+
+        Ok(content)
+    }
+
+    // Like `read`, failing with an `InvalidData` I/O error if the content isn't UTF-8.
+    pub fn read_to_string<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<String> {
+        let content = self.read(unsafe_path)?;
+
+        String::from_utf8(content)
+            .map_err(|e| Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+
+    // Write `contents` to the file `unsafe_path`, creating it or truncating it, like
+    // `std::fs::write` inside of the rootfs.
+    pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(&self, unsafe_path: P, contents: C) -> Result<()> {
+        self.write_with(unsafe_path, contents, &WriteOptions::default())
+    }
+
+    // Append `contents` to the file `unsafe_path`, creating it if it's missing.
+    pub fn append<P: AsRef<Path>, C: AsRef<[u8]>>(
+        &self,
+        unsafe_path: P,
+        contents: C,
+    ) -> Result<()> {
+        let options = WriteOptions {
+            append: true,
+            ..Default::default()
+        };
+
+        self.write_with(unsafe_path, contents, &options)
+    }
+
+    // Write `contents` to the file `unsafe_path` with `options`. The file is opened like
+    // `open_with` does, so it's written at its resolved path without following it, and an
+    // existing one must be a regular file, which isn't waited on if it's a FIFO.
+    pub fn write_with<P: AsRef<Path>, C: AsRef<[u8]>>(
+        &self,
+        unsafe_path: P,
+        contents: C,
+        options: &WriteOptions,
+    ) -> Result<()> {
+        let unsafe_path = unsafe_path.as_ref();
+        if options.create_parents {
+            if let Some(parent) = unsafe_path.parent() {
+                self.mkdir_all(parent)?;
+            }
+        }
+
+        let mut open = OpenOptions::new();
+        open.write(true).append(options.append);
+        let mut file = match self.open_with(unsafe_path, open.clone().create_new(true)) {
+            Ok(file) => {
+                file.set_permissions(fs::Permissions::from_mode(options.mode))?;
+                file
+            }
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::AlreadyExists => {
+                self.open_file(unsafe_path, open.truncate(!options.append))?
+            }
+            Err(e) => return Err(e),
+        };
+        file.write_all(contents.as_ref())?;
+        self.audit(&self.join(unsafe_path)?, AuditEvent::Write);
+
+        Ok(())
+    }
+
+    // Truncate or extend the regular file `unsafe_path` to `len` bytes with ftruncate() on an
+    // fd opened like `open_with` does without waiting on a FIFO, the extension reading as
+    // zeros. It's never created.
+    pub fn set_len<P: AsRef<Path>>(&self, unsafe_path: P, len: u64) -> Result<()> {
+        let file = self.open_file(&unsafe_path, OpenOptions::new().write(true))?;
+        file.set_len(len)?;
+        self.audit(&self.join(unsafe_path)?, AuditEvent::Write);
+
+        Ok(())
+    }
+
+    // Map the file `unsafe_path`, opened like `open_with` does without waiting on a FIFO,
+    // read-only into memory once its size is checked against `options`, with the `mmap`
+    // feature. It must be a regular file.
+    #[cfg(feature = "mmap")]
+    pub fn mmap<P: AsRef<Path>>(&self, unsafe_path: P, options: &MmapOptions) -> Result<Mmap> {
+        let unsafe_path = unsafe_path.as_ref();
+        let file = self.open_with_flags(
+            unsafe_path,
+            OpenOptions::new().read(true),
+            sys::o_nonblock(),
+        )?;
+
+        crate::mmap::map(&file, unsafe_path, options)
+    }
+
+    // Set the access and modification times of `unsafe_path` inside of the rootfs, e.g. to
+    // restore the ones of an archive. Its last component is not followed, so a symlink gets
+    // its own times. A time left to None is kept.
+    pub fn set_times<P: AsRef<Path>>(
+        &self,
+        unsafe_path: P,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> Result<()> {
+        let path = self.join_parent(unsafe_path)?;
+        sys::set_times(&path, atime, mtime)?;
+        self.audit(&path, AuditEvent::SetTimes);
+
+        Ok(())
+    }
+
+    // Take the advisory `kind` lock of the regular file `unsafe_path`, opened like `open_with`
+    // does without waiting on a FIFO, blocking until no other one conflicts with it. See the
+    // `lock` module.
+    pub fn lock<P: AsRef<Path>>(&self, unsafe_path: P, kind: LockKind) -> Result<FileLock> {
+        let file = self.open_file(unsafe_path, OpenOptions::new().read(true))?;
+
+        FileLock::new(file, kind)
+    }
+
+    // Like `lock`, returns None right away if another lock conflicts with it.
+    pub fn try_lock<P: AsRef<Path>>(
+        &self,
+        unsafe_path: P,
+        kind: LockKind,
+    ) -> Result<Option<FileLock>> {
+        let file = self.open_file(unsafe_path, OpenOptions::new().read(true))?;
+
+        FileLock::try_new(file, kind)
+    }
+
+    // Record `event` on `path` to the audit sink of the options.
+    pub(crate) fn audit(&self, path: &Path, event: AuditEvent) {
+        if let Some(sink) = &self.options.audit_sink {
+            sink.record(&AuditRecord::new(&self.path, path, event));
+        }
+    }
+
+    // The apparent size, allocated blocks and entry counts of `unsafe_path` and of its
+    // subtree inside of the rootfs, e.g. for the quotas of a container. The subtree is walked
+    // with `Walk`, without following its symlinks.
+    pub fn disk_usage<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<DiskUsage> {
+        let path = self.join(unsafe_path)?;
+        let path = path.strip_prefix(&self.path).unwrap_or(Path::new(""));
+
+        disk_usage(self, path)
+    }
+
+    // The paths relative to the rootfs of the entries for which `predicate` returns true, in
+    // the order of `Walk`, e.g. to locate a config file in a container.
+    pub fn find<F: FnMut(&WalkEntry) -> bool>(&self, predicate: F) -> Result<Vec<PathBuf>> {
+        self.find_with(&FindOptions::default(), predicate)
+    }
+
+    // Like `find`, with the depth and the symlinks followed of `options`.
+    pub fn find_with<F: FnMut(&WalkEntry) -> bool>(
+        &self,
+        options: &FindOptions,
+        mut predicate: F,
+    ) -> Result<Vec<PathBuf>> {
+        find(self, Path::new(""), options, &mut predicate)
+    }
+
+    // Walk the whole rootfs recursively, see `Walk`.
+    pub fn walk(&self) -> Walk<'_> {
+        Walk::new(self)
+    }
+
+    // Walk the subtree of the directory `unsafe_path` inside of the rootfs, see `Walk`. The
+    // paths of the entries are still relative to the rootfs, and the children of the
+    // directory have depth 1.
+    pub fn walk_in<P: AsRef<Path>>(&self, unsafe_path: P) -> Result<Walk<'_>> {
+        let path = self.join(unsafe_path)?;
+        if !fs::metadata(&path)?.is_dir() {
+            return Err(Error::Io(sys::not_a_directory()));
+        }
+        let path = path.strip_prefix(&self.path).unwrap_or(Path::new(""));
+
+        Ok(Walk::new_in(self, path, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::idmap::IdMap;
+    use std::os::unix::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_root_join() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            unsafe_path: &'a str,
+            symlink_path: &'a str,
+            result: &'a str,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        let rootfs_path = root.path().to_str().unwrap();
+
+        let tests = &[
+            TestData {
+                name: "relative path",
+                unsafe_path: "../../a/b",
+                symlink_path: "",
+                result: &format!("{}/a/b", rootfs_path),
+            },
+            TestData {
+                name: "absolute path",
+                unsafe_path: "/a/b",
+                symlink_path: "",
+                result: &format!("{}/a/b", rootfs_path),
+            },
+            TestData {
+                name: "relative softlink beyond container rootfs",
+                unsafe_path: "1",
+                symlink_path: "../../../",
+                result: rootfs_path,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            if !t.symlink_path.is_empty() {
+                fs::symlink(t.symlink_path, root.path().join(t.unsafe_path)).unwrap();
+            }
+            let result = root.join(t.unsafe_path).unwrap();
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result == Path::new(t.result), "{}", msg);
+        }
+
+        let dir = root.mkdir_all("../x/y").unwrap();
+        assert_eq!(dir, root.path().join("x/y"));
+        assert!(dir.is_dir());
+
+        let mut steps = Vec::new();
+        let result = root.join_traced("1/x/../x/y/z", |s| steps.push(s));
+        let p = |s: &str| root.path().join(s);
+        let expected = vec![
+            Step::Symlink {
+                link: p("1"),
+                target: PathBuf::from("../../../"),
+            },
+            Step::Clamp,
+            Step::Clamp,
+            Step::Clamp,
+            Step::Enter(p("x")),
+            Step::Parent(p("")),
+            Step::Enter(p("x")),
+            Step::Enter(p("x/y")),
+            Step::Missing(p("x/y/z")),
+            Step::Pinned(p("x/y")),
+        ];
+        assert_eq!(result.unwrap(), p("x/y/z"));
+        assert_eq!(steps, expected);
+        let mut steps = Vec::new();
+        assert!(root.check_traced("1", |s| steps.push(s)).is_err());
+        assert_eq!(steps.len(), 1);
+
+        assert!(Root::new(rootfs_dir.path().join("nonexistent")).is_err());
+    }
+
+    #[test]
+    fn test_root_paths_equal() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            a: &'a str,
+            b: &'a str,
+            result: bool,
+        }
+
+        let tree = crate::test_util::TreeBuilder::new()
+            .file("etc/passwd", "")
+            .file("etc/group", "")
+            .symlink("l", "/etc")
+            .symlink("up", "../../etc/passwd")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        std::fs::hard_link(root.path().join("etc/passwd"), root.path().join("hard")).unwrap();
+
+        let tests = &[
+            TestData {
+                name: "same path",
+                a: "etc/passwd",
+                b: "etc/passwd",
+                result: true,
+            },
+            TestData {
+                name: "different spellings",
+                a: "/etc/./passwd",
+                b: "../etc/../etc/passwd",
+                result: true,
+            },
+            TestData {
+                name: "through symlinks",
+                a: "l/passwd",
+                b: "up",
+                result: true,
+            },
+            TestData {
+                name: "hardlink",
+                a: "hard",
+                b: "etc/passwd",
+                result: true,
+            },
+            TestData {
+                name: "different files",
+                a: "etc/passwd",
+                b: "etc/group",
+                result: false,
+            },
+            TestData {
+                name: "missing paths",
+                a: "missing",
+                b: "missing",
+                result: false,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = root.paths_equal(t.a, t.b);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.unwrap() == t.result, "{}", msg);
+        }
+        assert!(root.paths_equal("etc/passwd/x", "etc").is_err());
+    }
+
+    #[test]
+    fn test_root_read() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            result: Option<&'a str>,
+        }
+
+        let tree = crate::test_util::TreeBuilder::new()
+            .file("etc/hostname", "c1\n")
+            .file("etc/big", vec![b'x'; 9])
+            .file("etc/binary", [0xff, 0xfe])
+            .symlink("l", "../../etc")
+            .build()
+            .unwrap();
+        let options = RootOptions {
+            read_limit: Some(8),
+            ..Default::default()
+        };
+        let root = Root::with_options(tree.path(), options).unwrap();
+        let _ = rustix::fs::mknodat(
+            rustix::fs::CWD,
+            tree.path().join("etc/fifo"),
+            rustix::fs::FileType::Fifo,
+            rustix::fs::Mode::from_raw_mode(0o644),
+            0,
+        );
+
+        let tests = &[
+            TestData {
+                name: "small file",
+                path: "etc/hostname",
+                result: Some("c1\n"),
+            },
+            TestData {
+                name: "through a symlink",
+                path: "l/hostname",
+                result: Some("c1\n"),
+            },
+            TestData {
+                name: "larger than the limit",
+                path: "etc/big",
+                result: None,
+            },
+            TestData {
+                name: "not UTF-8",
+                path: "etc/binary",
+                result: None,
+            },
+            TestData {
+                name: "directory",
+                path: "etc",
+                result: None,
+            },
+            TestData {
+                name: "missing",
+                path: "etc/missing",
+                result: None,
+            },
+            TestData {
+                name: "fifo",
+                path: "etc/fifo",
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = root.read_to_string(t.path);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.ok().as_deref() == t.result, "{}", msg);
+        }
+
+        assert_eq!(root.read("etc/binary").unwrap(), [0xff, 0xfe]);
+        let err = root.read("etc/big").unwrap_err();
+        assert!(matches!(err, Error::Io(e) if e.kind() == io::ErrorKind::FileTooLarge));
+    }
+
+    #[test]
+    fn test_root_write() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            options: WriteOptions,
+            result: Option<(&'a str, &'a str)>,
+        }
+
+        let tree = crate::test_util::TreeBuilder::new()
+            .file("etc/hosts", "127.0.0.1\n")
+            .symlink("l", "/etc")
+            .symlink("out", "../../tmp/out")
+            .dir("tmp")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let append = WriteOptions {
+            append: true,
+            ..Default::default()
+        };
+        let _ = rustix::fs::mknodat(
+            rustix::fs::CWD,
+            tree.path().join("tmp/fifo"),
+            rustix::fs::FileType::Fifo,
+            rustix::fs::Mode::from_raw_mode(0o644),
+            0,
+        );
+
+        let tests = &[
+            TestData {
+                name: "new file",
+                path: "etc/hostname",
+                options: WriteOptions::default(),
+                result: Some(("etc/hostname", "x")),
+            },
+            TestData {
+                name: "truncated",
+                path: "l/hosts",
+                options: WriteOptions::default(),
+                result: Some(("etc/hosts", "x")),
+            },
+            TestData {
+                name: "appended",
+                path: "etc/hosts",
+                options: append,
+                result: Some(("etc/hosts", "xx")),
+            },
+            TestData {
+                name: "symlink out of the rootfs, created inside",
+                path: "out",
+                options: WriteOptions::default(),
+                result: Some(("tmp/out", "x")),
+            },
+            TestData {
+                name: "missing parent",
+                path: "a/b/c",
+                options: WriteOptions::default(),
+                result: None,
+            },
+            TestData {
+                name: "parents created",
+                path: "a/b/c",
+                options: WriteOptions {
+                    create_parents: true,
+                    mode: 0o600,
+                    ..append
+                },
+                result: Some(("a/b/c", "x")),
+            },
+            TestData {
+                name: "directory",
+                path: "etc",
+                options: WriteOptions::default(),
+                result: None,
+            },
+            TestData {
+                name: "fifo",
+                path: "tmp/fifo",
+                options: append,
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = root.write_with(t.path, "x", &t.options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some((p, content)) => {
+                    assert!(result.is_ok(), "{}", msg);
+                    let written = std::fs::read_to_string(tree.path().join(p)).unwrap();
+                    assert!(written == content, "{}", msg);
+                }
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+
+        let mode = |p: &str| std::fs::metadata(tree.path().join(p)).unwrap().mode() & 0o777;
+        assert_eq!(mode("a/b/c"), 0o600);
+        assert_eq!(mode("etc/hostname"), 0o644);
+        root.write("a/b/c", "y").unwrap();
+        root.append("a/b/c", "z").unwrap();
+        assert_eq!(root.read_to_string("a/b/c").unwrap(), "yz");
+        assert_eq!(mode("a/b/c"), 0o600);
+    }
+
+    #[test]
+    fn test_root_create_dir() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            options: DirOptions,
+            result: Option<(&'a str, u32)>,
+        }
+
+        let tree = crate::test_util::TreeBuilder::new()
+            .dir("var")
+            .symlink("l", "/var")
+            .symlink("out", "../../srv")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let exact = |mode| DirOptions {
+            mode,
+            exact_mode: true,
+        };
+
+        let tests = &[
+            TestData {
+                name: "exact mode",
+                path: "var/a",
+                options: exact(0o777),
+                result: Some(("var/a", 0o777)),
+            },
+            TestData {
+                name: "setgid and sticky bits",
+                path: "l/b",
+                options: exact(0o3770),
+                result: Some(("var/b", 0o3770)),
+            },
+            TestData {
+                name: "existing directory",
+                path: "var/a",
+                options: exact(0o700),
+                result: None,
+            },
+            TestData {
+                name: "trailing symlink not followed",
+                path: "out",
+                options: exact(0o700),
+                result: None,
+            },
+            TestData {
+                name: "missing parent",
+                path: "var/c/d",
+                options: DirOptions::default(),
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = root.create_dir_with(t.path, &t.options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some((p, mode)) => {
+                    assert!(result.unwrap() == tree.path().join(p), "{}", msg);
+                    let m = std::fs::metadata(tree.path().join(p)).unwrap();
+                    assert!(m.mode() & 0o7777 == mode, "{}", msg);
+                }
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+
+        let dir = root.create_dir("var/e", 0o700).unwrap();
+        assert_eq!(std::fs::metadata(dir).unwrap().mode() & 0o077, 0);
+        assert!(!tree.path().join("srv").exists());
+    }
+
+    #[test]
+    fn test_root_set_len() {
+        let tree = crate::test_util::TreeBuilder::new()
+            .file("var/log/app.log", "0123456789")
+            .symlink("l", "../../var/log")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let len = |p: &str| std::fs::metadata(tree.path().join(p)).unwrap().len();
+
+        root.set_len("var/log/app.log", 4).unwrap();
+        assert_eq!(root.read("var/log/app.log").unwrap(), b"0123");
+        root.set_len("l/app.log", 1 << 20).unwrap();
+        assert_eq!(len("var/log/app.log"), 1 << 20);
+        root.set_len("/l/app.log", 0).unwrap();
+        assert_eq!(len("var/log/app.log"), 0);
+
+        assert!(root.set_len("var/log/missing", 0).is_err());
+        assert!(!tree.path().join("var/log/missing").exists());
+        assert!(root.set_len("var/log", 0).is_err());
+        let fifo = rustix::fs::mknodat(
+            rustix::fs::CWD,
+            tree.path().join("var/log/fifo"),
+            rustix::fs::FileType::Fifo,
+            rustix::fs::Mode::from_raw_mode(0o644),
+            0,
+        );
+        if fifo.is_ok() {
+            assert!(root.set_len("var/log/fifo", 0).is_err());
+        }
+    }
+
+    #[test]
+    fn test_root_set_times() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            atime: Option<i64>,
+            mtime: Option<i64>,
+            // the path inside of the rootfs and its times
+            result: Option<(&'a str, i64, i64)>,
+        }
+
+        let tree = crate::test_util::TreeBuilder::new()
+            .file("etc/hosts", "")
+            .symlink("l", "/etc/hosts")
+            .symlink("out", "../../etc/hosts")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let time = |secs: i64| match secs {
+            0.. => SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64),
+            _ => SystemTime::UNIX_EPOCH - Duration::from_secs(-secs as u64),
+        };
+        let times = |p: &str| {
+            let m = std::fs::symlink_metadata(tree.path().join(p)).unwrap();
+            (m.atime(), m.mtime())
+        };
+
+        let tests = &[
+            TestData {
+                name: "both times",
+                path: "etc/hosts",
+                atime: Some(1000),
+                mtime: Some(2000),
+                result: Some(("etc/hosts", 1000, 2000)),
+            },
+            TestData {
+                name: "mtime only, before 1970",
+                path: "/etc/../etc/hosts",
+                atime: None,
+                mtime: Some(-86400),
+                result: Some(("etc/hosts", 1000, -86400)),
+            },
+            TestData {
+                name: "symlink not followed",
+                path: "l",
+                atime: Some(3000),
+                mtime: Some(4000),
+                result: Some(("l", 3000, 4000)),
+            },
+            TestData {
+                name: "symlink out of the rootfs",
+                path: "out",
+                atime: Some(5000),
+                mtime: Some(6000),
+                result: Some(("out", 5000, 6000)),
+            },
+            TestData {
+                name: "missing",
+                path: "etc/missing",
+                atime: Some(0),
+                mtime: Some(0),
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = root.set_times(t.path, t.atime.map(time), t.mtime.map(time));
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some((p, atime, mtime)) => {
+                    assert!(result.is_ok(), "{}", msg);
+                    assert!(times(p) == (atime, mtime), "{}", msg);
+                }
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+        assert_eq!(times("etc/hosts"), (1000, -86400));
+    }
+
+    #[test]
+    fn test_root_hostile_tree() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::PermissionsExt;
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        let name = OsStr::from_bytes(b"\xff\xfe");
+        std::fs::create_dir_all(root.path().join(name).join("d")).unwrap();
+        fs::symlink(Path::new("/").join(name), root.path().join("abs")).unwrap();
+        fs::symlink(Path::new(name).join("../.."), root.path().join("up")).unwrap();
+        fs::symlink("loop", root.path().join("loop")).unwrap();
+        let locked = root.path().join("locked");
+        std::fs::create_dir_all(locked.join("d")).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o0)).unwrap();
+
+        // the errors depend on the privileges of the test, none of them may panic
+        for p in [
+            Path::new("abs/d"),
+            Path::new("up/x"),
+            Path::new("loop/x"),
+            Path::new("locked/d/x"),
+            &Path::new(name).join("d/\u{fffd}"),
+        ] {
+            let msg = format!("{:?}", p);
+            if let Ok(path) = root.join(p) {
+                assert!(path.starts_with(root.path()), "{} {:?}", msg, path);
+            }
+            let _ = root.check(p);
+            let _ = root.owner(p);
+            let _ = root.mkdir_all(p.join("new"));
+        }
+        assert!(root.walk().count() > 0);
+        let _ = root.glob("*/*");
+
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_root_chown() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            owner: (u32, u32),
+            host_owner: Option<(u32, u32)>,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let options = RootOptions {
+            id_mapping: Some(IdMapping {
+                uids: IdMap::parse("0 100000 65536").unwrap(),
+                gids: IdMap::parse("0 200000 65536").unwrap(),
+            }),
+            // the identity mapping outside of a user namespace
+            userns: true,
+            ..Default::default()
+        };
+        let root = Root::with_options(rootfs_dir.path(), options).unwrap();
+        std::fs::write(root.path().join("f"), b"").unwrap();
+
+        let tests = &[
+            TestData {
+                name: "container root",
+                owner: (0, 0),
+                host_owner: Some((100000, 200000)),
+            },
+            TestData {
+                name: "container user",
+                owner: (1000, 100),
+                host_owner: Some((101000, 200100)),
+            },
+            TestData {
+                name: "unmapped uid",
+                owner: (65536, 0),
+                host_owner: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = root.chown("/f", t.owner.0, t.owner.1);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.host_owner {
+                Some((uid, gid)) => {
+                    // changing the owner needs CAP_CHOWN
+                    if result.is_err() {
+                        continue;
+                    }
+                    let m = std::fs::metadata(root.path().join("f")).unwrap();
+                    assert!((m.uid(), m.gid()) == (uid, gid), "{}", msg);
+                    assert!(root.owner("f").unwrap() == t.owner, "{}", msg);
+                }
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+    }
+
+    #[test]
+    fn test_root_mknod() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            device: Device,
+            allowed: bool,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        std::fs::write(root.path().join("f"), b"").unwrap();
+
+        let tests = &[
+            TestData {
+                name: "/dev/null",
+                path: "/null",
+                device: Device::char(1, 3),
+                allowed: true,
+            },
+            TestData {
+                name: "/dev/mem",
+                path: "/mem",
+                device: Device::char(1, 1),
+                allowed: false,
+            },
+            TestData {
+                name: "block device",
+                path: "/sda",
+                device: Device {
+                    kind: DeviceType::Block,
+                    major: 8,
+                    minor: 0,
+                },
+                allowed: false,
+            },
+            TestData {
+                name: "existing entry",
+                path: "/f",
+                device: Device::char(1, 5),
+                allowed: true,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let d = t.device;
+            let result = root.mknod(t.path, d.kind, d.major, d.minor);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match result {
+                Err(Error::Denied(_)) => assert!(!t.allowed, "{}", msg),
+                // creating a device node needs CAP_MKNOD, an existing entry is kept
+                Err(Error::Io(_)) => assert!(t.allowed, "{}", msg),
+                Ok(path) => {
+                    assert!(t.allowed, "{}", msg);
+                    let m = std::fs::metadata(path).unwrap();
+                    assert!(m.rdev() == rustix::fs::makedev(d.major, d.minor), "{}", msg);
+                }
+                Err(_) => panic!("{}", msg),
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve_component() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            parent: &'a str,
+            name: &'a str,
+            result: Option<&'a str>,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        root.mkdir_all("a/b").unwrap();
+        fs::symlink("/", root.path().join("a/l")).unwrap();
+
+        let tests = &[
+            TestData {
+                parent: "",
+                name: "a",
+                result: Some("a"),
+            },
+            TestData {
+                parent: "a",
+                name: "b",
+                result: Some("a/b"),
+            },
+            TestData {
+                parent: "a",
+                name: "l",
+                result: Some("a/l"),
+            },
+            TestData {
+                parent: "a/b",
+                name: "..",
+                result: Some("a"),
+            },
+            TestData {
+                parent: "",
+                name: "..",
+                result: Some(""),
+            },
+            TestData {
+                parent: "a/l",
+                name: "etc",
+                result: None,
+            },
+            TestData {
+                parent: "a/../..",
+                name: "etc",
+                result: None,
+            },
+            TestData {
+                parent: "a",
+                name: "../etc",
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, t);
+
+            let result = root.resolve_component(t.parent, OsStr::new(t.name));
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some(p) => assert!(result.unwrap() == Path::new(p), "{}", msg),
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+    }
+}
@@ -0,0 +1,265 @@
+// A hardened resolver which opens a path from a short-lived child process. The child
+// unshares its mount namespace, closes every inherited fd, pivots into the rootfs and sets
+// no_new_privs before resolving, so a bug in the resolver has no host path left to reach, and
+// a memory bug only corrupts the child's own copy of the memory. The opened fd is handed back
+// over a socket with SCM_RIGHTS, or the errno of the failure.
+//
+// The child is forked from a program which may have other threads, so until it exits with
+// _exit() it only allocates, which the libc keeps working after fork(), and makes syscalls.
+use std::fs;
+use std::io::{self, IoSlice, IoSliceMut};
+use std::mem::MaybeUninit;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use rustix::cmsg_space;
+use rustix::fs::{open, Mode};
+use rustix::mount::{
+    mount_bind_recursive, mount_change, unmount, MountPropagationFlags, UnmountFlags,
+};
+use rustix::net::{
+    recvmsg, sendmsg, socketpair, AddressFamily, RecvAncillaryBuffer, RecvAncillaryMessage,
+    RecvFlags, SendAncillaryBuffer, SendAncillaryMessage, SendFlags, SocketFlags, SocketType,
+};
+use rustix::process::{chdir, pivot_root};
+use rustix::thread::{set_no_new_privs, unshare_unsafe, UnshareFlags};
+
+pub use rustix::fs::OFlags as OpenFlags;
+
+use crate::error::{Error, Result};
+use crate::root::Root;
+
+// Open `path` inside of `rootfs` with `flags` from a confined child process, O_CLOEXEC is
+// always added. The path is resolved like `Root::join` with the rootfs as "/", and must
+// exist. It needs CAP_SYS_ADMIN.
+pub fn open_sandboxed<P: AsRef<Path>, Q: AsRef<Path>>(
+    rootfs: P,
+    path: Q,
+    flags: OpenFlags,
+) -> Result<OwnedFd> {
+    let rootfs = rootfs.as_ref().canonicalize()?;
+    let path = path.as_ref();
+    let (parent, child) = socketpair(
+        AddressFamily::UNIX,
+        SocketType::SEQPACKET,
+        SocketFlags::CLOEXEC,
+        None,
+    )
+    .map_err(io::Error::from)?;
+
+    // SAFETY: the child only runs `confined_open`, see the module comment, and never returns
+    // from this function
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(Error::Io(io::Error::last_os_error()));
+    }
+    if pid == 0 {
+        drop(parent);
+        let sock = child.as_raw_fd();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            confined_open(&rootfs, path, flags, sock)
+        }));
+        let errno = match result {
+            Ok(Ok(())) => 0,
+            Ok(Err(e)) => {
+                send_errno(sock, e.errno());
+                1
+            }
+            Err(_) => 2,
+        };
+        // SAFETY: the child exits without running the destructors or atexit handlers of the
+        // parent's copy of the program
+        unsafe { libc::_exit(errno) };
+    }
+    // the child keeps its copy, so the socket reads EOF once it exited
+    drop(child);
+
+    let mut space = [MaybeUninit::uninit(); cmsg_space!(ScmRights(1))];
+    let mut control = RecvAncillaryBuffer::new(&mut space);
+    let mut errno = [0; 4];
+    let received = recvmsg(
+        &parent,
+        &mut [IoSliceMut::new(&mut errno)],
+        &mut control,
+        RecvFlags::CMSG_CLOEXEC,
+    )
+    .map_err(io::Error::from);
+    let fd = control.drain().find_map(|msg| match msg {
+        RecvAncillaryMessage::ScmRights(mut fds) => fds.next(),
+        _ => None,
+    });
+    let status = wait(pid)?;
+    received?;
+
+    match (fd, i32::from_ne_bytes(errno)) {
+        (Some(fd), _) => Ok(fd),
+        (None, errno) if errno > 0 => Err(Error::Io(io::Error::from_raw_os_error(errno))),
+        _ => Err(Error::Io(io::Error::other(format!(
+            "the resolver process failed with the status {}",
+            status
+        )))),
+    }
+}
+
+// Reap the child `pid`, returns its wait status.
+fn wait(pid: libc::pid_t) -> Result<i32> {
+    let mut status = 0;
+    loop {
+        // SAFETY: `pid` is a child of this process which wasn't reaped yet
+        if unsafe { libc::waitpid(pid, &mut status, 0) } >= 0 {
+            return Ok(status);
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(Error::Io(e));
+        }
+    }
+}
+
+// Report the failure `errno` of the child over the socket `sock`, without an fd.
+fn send_errno(sock: RawFd, errno: i32) {
+    // SAFETY: `sock` is the child's copy of the socket, open until it exits
+    let sock = unsafe { BorrowedFd::borrow_raw(sock) };
+    let mut control = SendAncillaryBuffer::new(&mut []);
+    let data = errno.to_ne_bytes();
+    let _ = sendmsg(
+        sock,
+        &[IoSlice::new(&data)],
+        &mut control,
+        SendFlags::empty(),
+    );
+}
+
+// The body of the confined child, sends the opened fd over the socket `sock`.
+fn confined_open(rootfs: &Path, path: &Path, flags: OpenFlags, sock: RawFd) -> Result<()> {
+    // SAFETY: the child is the only user of its fd table, and only uses the fds it opens
+    // itself after that, plus its copy of `sock`
+    unsafe { unshare_unsafe(UnshareFlags::FS | UnshareFlags::FILES | UnshareFlags::NEWNS) }
+        .map_err(io::Error::from)?;
+    close_inherited(sock)?;
+    // SAFETY: `sock` is kept open until the child exits
+    let sock = unsafe { BorrowedFd::borrow_raw(sock) };
+
+    // keep the mounts below from propagating back, then make the rootfs the only visible
+    // mount; the old root is stacked on "." by pivot_root() and detached
+    mount_change(
+        "/",
+        MountPropagationFlags::PRIVATE | MountPropagationFlags::REC,
+    )
+    .map_err(io::Error::from)?;
+    mount_bind_recursive(rootfs, rootfs).map_err(io::Error::from)?;
+    chdir(rootfs).map_err(io::Error::from)?;
+    pivot_root(".", ".").map_err(io::Error::from)?;
+    unmount(".", UnmountFlags::DETACH).map_err(io::Error::from)?;
+    chdir("/").map_err(io::Error::from)?;
+    set_no_new_privs(true).map_err(io::Error::from)?;
+
+    let resolved = Root::new("/")?.join(path)?;
+    let fd = open(&resolved, flags | OpenFlags::CLOEXEC, Mode::empty());
+    let fd = fd.map_err(io::Error::from)?;
+
+    let fds = [fd.as_fd()];
+    let mut space = [MaybeUninit::uninit(); cmsg_space!(ScmRights(1))];
+    let mut control = SendAncillaryBuffer::new(&mut space);
+    control.push(SendAncillaryMessage::ScmRights(&fds));
+    sendmsg(
+        sock,
+        &[IoSlice::new(&0i32.to_ne_bytes())],
+        &mut control,
+        SendFlags::empty(),
+    )
+    .map_err(io::Error::from)?;
+
+    Ok(())
+}
+
+// Close every fd of the child's fd table but `keep`.
+fn close_inherited(keep: RawFd) -> Result<()> {
+    let fds: Vec<RawFd> = fs::read_dir("/proc/thread-self/fd")?
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().to_str().and_then(|n| n.parse().ok()))
+        .collect();
+
+    for fd in fds {
+        if fd == keep {
+            continue;
+        }
+        // SAFETY: the fd is a copy in the child's fd table which nothing else uses, the one
+        // of the listing itself is already closed and fails fcntl()
+        let open = rustix::io::fcntl_getfd(unsafe { BorrowedFd::borrow_raw(fd) }).is_ok();
+        if open {
+            drop(unsafe { OwnedFd::from_raw_fd(fd) });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, MetadataExt};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_open_sandboxed() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            result: Option<&'a str>,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let rootfs = rootfs_dir.path().canonicalize().unwrap();
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::write(rootfs.join("etc/passwd"), b"").unwrap();
+        symlink("/etc", rootfs.join("l")).unwrap();
+        symlink("../../../etc", rootfs.join("r")).unwrap();
+
+        let tests = &[
+            TestData {
+                name: "plain path",
+                path: "etc/passwd",
+                result: Some("etc/passwd"),
+            },
+            TestData {
+                name: "absolute symlink",
+                path: "l/passwd",
+                result: Some("etc/passwd"),
+            },
+            TestData {
+                name: "relative symlink beyond the rootfs",
+                path: "r/passwd",
+                result: Some("etc/passwd"),
+            },
+            TestData {
+                name: "missing path",
+                path: "missing",
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = open_sandboxed(&rootfs, t.path, OpenFlags::PATH);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match (result, t.result) {
+                // not privileged enough to unshare the mount namespace
+                (Err(Error::Io(e)), _) if e.kind() == io::ErrorKind::PermissionDenied => return,
+                (Ok(fd), Some(p)) => {
+                    let stat = rustix::fs::fstat(&fd).unwrap();
+                    let expected = fs::metadata(rootfs.join(p)).unwrap();
+                    assert!(stat.st_ino == expected.ino(), "{}", msg);
+                    assert!(stat.st_dev == expected.dev(), "{}", msg);
+                }
+                (result, None) => assert!(result.is_err(), "{}", msg),
+                (Err(_), Some(_)) => panic!("{}", msg),
+            }
+        }
+    }
+}
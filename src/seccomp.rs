@@ -0,0 +1,138 @@
+// Path resolution for seccomp user-notification supervisors. A supervisor emulating a
+// syscall like openat() of a target process gets the target's pid, the raw dirfd number
+// and path arguments, and has to resolve them as the target would: relative to the target's
+// fd or working directory, and never above the target's root directory.
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use rustix::fd::OwnedFd;
+use rustix::process::{pidfd_getfd, pidfd_open, Pid, PidfdFlags, PidfdGetfdFlags};
+
+use crate::error::{Error, Result};
+use crate::resolve::resolve;
+
+// The dirfd argument naming the working directory, like the AT_FDCWD of the syscalls.
+pub const AT_FDCWD: i32 = -100;
+
+// Resolve the `path` argument of a syscall of the process `pid`, relative to its `dirfd`
+// argument, inside of the root directory of the process. Returns the path on the host,
+// below "/proc/<pid>/root", which can be opened by the supervisor to emulate the syscall.
+//
+// The directory fd is duplicated from the target with pidfd_getfd(), and its path read back
+// from "/proc/self/fd" is only trusted once it resolves to the very same directory inside of
+// the target's root, otherwise `Error::Escape` is returned. As with all the seccomp
+// notifications, the supervisor must still check that the notification is valid after the
+// resolution, in case the target exited and its pid got reused.
+pub fn resolve_in_target(pid: i32, dirfd: i32, path: &Path) -> Result<PathBuf> {
+    let pid = Pid::from_raw(pid).ok_or_else(|| Error::InvalidPath(path.to_path_buf()))?;
+    let pidfd = pidfd_open(pid, PidfdFlags::empty()).map_err(io::Error::from)?;
+
+    let proc_root = PathBuf::from(format!("/proc/{}/root", pid));
+    if path.has_root() {
+        return resolve(&proc_root, path, false);
+    }
+
+    let dir = if dirfd == AT_FDCWD {
+        fs::File::open(format!("/proc/{}/cwd", pid))?.into()
+    } else {
+        pidfd_getfd(&pidfd, dirfd, PidfdGetfdFlags::empty()).map_err(io::Error::from)?
+    };
+    let base = base_dir(&proc_root, &dir)?;
+
+    resolve(&proc_root, &base.join(path), false)
+}
+
+// The path of the directory `dir` relative to the root directory `proc_root`, checked to be
+// the same directory when resolved again.
+fn base_dir(proc_root: &Path, dir: &OwnedFd) -> Result<PathBuf> {
+    let m = fs::File::from(dir.try_clone()?).metadata()?;
+    if !m.is_dir() {
+        return Err(Error::Io(crate::sys::not_a_directory()));
+    }
+
+    let path = fs::read_link(format!("/proc/self/fd/{}", dir.as_raw_fd()))?;
+    let base = path
+        .strip_prefix(fs::read_link(proc_root)?)
+        .map_err(|_| Error::Escape(path.clone()))?
+        .to_path_buf();
+
+    let resolved = fs::metadata(resolve(proc_root, &base, true)?)?;
+    if (resolved.dev(), resolved.ino()) != (m.dev(), m.ino()) {
+        return Err(Error::Escape(path));
+    }
+
+    Ok(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resolve_in_target() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            dirfd: bool,
+            path: &'a str,
+            result: &'a str,
+        }
+
+        let dir = tempdir().expect("failed to create tmpdir");
+        let dir_path = dir.path().canonicalize().unwrap();
+        fs::create_dir(dir_path.join("a")).unwrap();
+        let file = fs::File::open(&dir_path).unwrap();
+
+        let pid = process::id() as i32;
+        let proc_root = format!("/proc/{}/root", pid);
+        let in_dir = format!("{}{}", proc_root, dir_path.display());
+        let cwd = std::env::current_dir().unwrap().canonicalize().unwrap();
+
+        let tests = &[
+            TestData {
+                name: "relative to the dirfd",
+                dirfd: true,
+                path: "a/../b",
+                result: &format!("{}/b", in_dir),
+            },
+            TestData {
+                name: "relative to the working directory",
+                dirfd: false,
+                path: "x",
+                result: &format!("{}{}/x", proc_root, cwd.display()),
+            },
+            TestData {
+                name: "absolute path",
+                dirfd: true,
+                path: "/../../etc/passwd",
+                result: &format!("{}/etc/passwd", proc_root),
+            },
+            TestData {
+                name: ".. clamped at the root",
+                dirfd: true,
+                path: "../../../../../../../../etc",
+                result: &format!("{}/etc", proc_root),
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let dirfd = if t.dirfd { file.as_raw_fd() } else { AT_FDCWD };
+            let result = resolve_in_target(pid, dirfd, Path::new(t.path));
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.unwrap() == Path::new(t.result), "{}", msg);
+        }
+
+        let file = fs::File::open(dir_path.join("a")).unwrap();
+        fs::remove_dir(dir_path.join("a")).unwrap();
+        assert!(resolve_in_target(pid, file.as_raw_fd(), Path::new("x")).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use std::ffi::OsString;
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -9,6 +10,9 @@ use std::path::PathBuf;
 // - `rootfs` is the absolute path to the root of the containers root filesystem directory.
 // - `unsafe_path` is path inside a container. It is unsafe since it may try to "escape" from the containers
 //    rootfs by using one or more "../" path elements or is its a symlink to path.
+//
+// A path which isn't valid UTF-8, from the name of a symlink target, is returned lossily, and
+// a directory which can't be canonicalized clamps the path at the `rootfs` like an escape.
 pub fn secure_join(rootfs: &str, unsafe_path: &str) -> String {
     let mut path = PathBuf::from(format!("{}/", rootfs));
     let unsafe_p = Path::new(&unsafe_path);
@@ -24,13 +28,17 @@ pub fn secure_join(rootfs: &str, unsafe_path: &str) -> String {
         path.push(it);
         if let Ok(v) = path.read_link() {
             if v.is_absolute() {
-                path = PathBuf::from(format!("{}{}", rootfs, v.to_str().unwrap().to_string()));
+                let mut target = OsString::from(rootfs);
+                target.push(v.as_os_str());
+                path = PathBuf::from(target);
             } else {
                 path.pop();
                 for it in v.iter() {
                     path.push(it);
                     if path.exists() {
-                        path = path.canonicalize().unwrap();
+                        path = path
+                            .canonicalize()
+                            .unwrap_or_else(|_| PathBuf::from(rootfs));
                         if !path.starts_with(rootfs) {
                             path = PathBuf::from(rootfs.to_string());
                         }
@@ -44,7 +52,42 @@ pub fn secure_join(rootfs: &str, unsafe_path: &str) -> String {
         }
     }
 
-    path.to_str().unwrap().to_string()
+    path.to_string_lossy().into_owned()
+}
+
+// This function is the reverse of `secure_join`: it converts the absolute `host_path` back
+// into the path it has inside of the container, e.g. "/home/rootfs/a/b" into "/a/b", or
+// returns None if `host_path` is outside of the `rootfs` directory.
+//
+// The prefix is matched after resolving the symlinks on the host, from both `rootfs` and
+// the existing part of `host_path`, so a symlink pointing out of the rootfs isn't mistaken
+// for a path inside of it, nor is a path through a symlinked parent of the rootfs missed.
+pub fn host_to_root<P: AsRef<Path>, Q: AsRef<Path>>(rootfs: P, host_path: Q) -> Option<PathBuf> {
+    let rootfs = rootfs.as_ref().canonicalize().ok()?;
+    let host_path = host_path.as_ref();
+    if !host_path.is_absolute() {
+        return None;
+    }
+
+    // canonicalize the longest existing prefix and append the missing names to it
+    let mut missing = Vec::new();
+    let mut existing = host_path;
+    let mut path = loop {
+        match existing.canonicalize() {
+            Ok(p) => break p,
+            Err(_) => {
+                missing.push(existing.file_name()?);
+                existing = existing.parent()?;
+            }
+        }
+    };
+    for it in missing.iter().rev() {
+        path.push(it);
+    }
+
+    let relative = path.strip_prefix(&rootfs).ok()?;
+
+    Some(Path::new("/").join(relative))
 }
 
 #[cfg(test)]
@@ -53,6 +96,7 @@ mod tests {
     use std::os::unix::fs;
     use tempfile::tempdir;
     #[test]
+    #[allow(dead_code, clippy::unnecessary_to_owned, clippy::comparison_to_empty)]
     fn test_secure_join() {
         #[derive(Debug)]
         struct TestData<'a> {
@@ -136,4 +180,109 @@ mod tests {
             assert!(result == t.result, "{}", msg);
         }
     }
+
+    #[test]
+    fn test_secure_join_hostile_tree() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::fs::PermissionsExt;
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let rootfs = rootfs_dir.path().to_str().unwrap();
+        let name = OsStr::from_bytes(b"\xff\xfe");
+        fs::symlink(Path::new("/").join(name), rootfs_dir.path().join("abs")).unwrap();
+        fs::symlink(name, rootfs_dir.path().join("rel")).unwrap();
+        std::fs::create_dir_all(rootfs_dir.path().join(name)).unwrap();
+        let locked = rootfs_dir.path().join("locked");
+        std::fs::create_dir_all(locked.join("d")).unwrap();
+        fs::symlink("d/..", locked.join("l")).unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o0)).unwrap();
+
+        for p in ["abs/x", "rel/x", "locked/l/x", "locked/d/../.."] {
+            let result = secure_join(rootfs, p);
+            assert!(result.starts_with(rootfs), "{}: {}", p, result);
+        }
+        assert_eq!(
+            secure_join(rootfs, "abs"),
+            format!("{}/\u{fffd}\u{fffd}", rootfs)
+        );
+
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_host_to_root() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            host_path: &'a str,
+            result: Option<&'a str>,
+        }
+
+        let dir = tempdir().expect("failed to create tmpdir");
+        let dir_path = dir.path().canonicalize().unwrap();
+        let rootfs = dir_path.join("rootfs");
+        std::fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::symlink(&rootfs, dir_path.join("alias")).unwrap();
+        fs::symlink("/etc", rootfs.join("out")).unwrap();
+        fs::symlink("etc", rootfs.join("in")).unwrap();
+
+        let tests = &[
+            TestData {
+                name: "path inside of the rootfs",
+                host_path: &format!("{}/etc/passwd", rootfs.display()),
+                result: Some("/etc/passwd"),
+            },
+            TestData {
+                name: "the rootfs itself",
+                host_path: &format!("{}", rootfs.display()),
+                result: Some("/"),
+            },
+            TestData {
+                name: "missing components",
+                host_path: &format!("{}/a/b/c", rootfs.display()),
+                result: Some("/a/b/c"),
+            },
+            TestData {
+                name: "through a symlinked rootfs",
+                host_path: &format!("{}/alias/etc", dir_path.display()),
+                result: Some("/etc"),
+            },
+            TestData {
+                name: "through a symlink inside of the rootfs",
+                host_path: &format!("{}/in/x", rootfs.display()),
+                result: Some("/etc/x"),
+            },
+            TestData {
+                name: "through a symlink out of the rootfs",
+                host_path: &format!("{}/out/passwd", rootfs.display()),
+                result: None,
+            },
+            TestData {
+                name: ".. out of the rootfs",
+                host_path: &format!("{}/../x", rootfs.display()),
+                result: None,
+            },
+            TestData {
+                name: "outside of the rootfs",
+                host_path: "/usr/bin",
+                result: None,
+            },
+            TestData {
+                name: "relative path",
+                host_path: "etc/passwd",
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = host_to_root(&rootfs, t.host_path);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.as_deref() == t.result.map(Path::new), "{}", msg);
+        }
+    }
 }
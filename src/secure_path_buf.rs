@@ -0,0 +1,661 @@
+// A path validated by a `Root`: the host path an untrusted path resolves to inside of the
+// rootfs. It derefs to the host `Path`, so it goes to the std fs functions as is, while the
+// functions taking a `SecurePathBuf` know it was resolved rather than passed through.
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::os::fd::OwnedFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{self, Path, PathBuf};
+
+#[cfg(feature = "serde")]
+use serde::de::{self, DeserializeSeed, Deserializer};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::error::{Error, Result};
+use crate::root::Root;
+use crate::sys;
+
+// The path is absolute, has no "." or ".." and is below the rootfs, or is the rootfs itself.
+// Its components which exist were resolved at the time, a symlink replacing one of them later
+// isn't followed from it again.
+#[derive(Debug, Clone)]
+pub struct SecurePathBuf {
+    // The canonicalized rootfs, a prefix of `path`.
+    rootfs: PathBuf,
+    path: PathBuf,
+}
+
+impl SecurePathBuf {
+    // Resolve `unsafe_path` inside of `root` like `Root::join`.
+    pub fn new<P: AsRef<Path>>(root: &Root, unsafe_path: P) -> Result<SecurePathBuf> {
+        let path = root.join(unsafe_path)?;
+
+        Ok(SecurePathBuf {
+            rootfs: root.path().to_path_buf(),
+            path,
+        })
+    }
+
+    // The path `path` resolved inside of `rootfs` by the caller.
+    pub(crate) fn from_resolved(rootfs: &Path, path: PathBuf) -> SecurePathBuf {
+        SecurePathBuf {
+            rootfs: rootfs.to_path_buf(),
+            path,
+        }
+    }
+
+    // The rootfs the path was resolved in.
+    pub fn rootfs(&self) -> &Path {
+        &self.rootfs
+    }
+
+    // The host path, like the deref.
+    pub fn as_path(&self) -> &Path {
+        &self.path
+    }
+
+    // The path relative to the rootfs, like "etc/hosts", or an empty path for the rootfs
+    // itself. Joined to the same rootfs it gives the host path back.
+    pub fn strip_root(&self) -> &Path {
+        self.path
+            .strip_prefix(&self.rootfs)
+            .unwrap_or(Path::new(""))
+    }
+
+    // The same path inside of the rootfs of `new_root`, e.g. of an image staged in one
+    // directory and run from another. It's resolved again, as the symlinks of the new rootfs
+    // may differ from the ones of this one.
+    pub fn reanchor(&self, new_root: &Root) -> Result<SecurePathBuf> {
+        SecurePathBuf::new(new_root, Path::new("/").join(self.strip_root()))
+    }
+
+    // The components of `strip_root`, each a name: never "/", "." or "..", e.g. to map the
+    // path to the segments of a route or to a database key.
+    pub fn root_components(&self) -> RootComponents<'_> {
+        RootComponents(self.strip_root().components())
+    }
+
+    // The host path, keeping the invariants above as long as the rootfs isn't changed.
+    pub fn into_inner(self) -> PathBuf {
+        self.path
+    }
+}
+
+impl Deref for SecurePathBuf {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for SecurePathBuf {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Borrow<Path> for SecurePathBuf {
+    fn borrow(&self) -> &Path {
+        &self.path
+    }
+}
+
+// `SecurePathBuf::new` for the generic conversions, e.g. `(&root, path).try_into()?`.
+impl<P: AsRef<Path>> TryFrom<(&Root, P)> for SecurePathBuf {
+    type Error = Error;
+
+    fn try_from((root, unsafe_path): (&Root, P)) -> Result<SecurePathBuf> {
+        SecurePathBuf::new(root, unsafe_path)
+    }
+}
+
+// The paths compare, order and hash like their host paths, which are resolved and normalized:
+// the inputs resolving to the same entry are equal, and a `SecurePathBuf` is found in a map
+// by a `&Path` as `Borrow` requires.
+impl PartialEq for SecurePathBuf {
+    fn eq(&self, other: &SecurePathBuf) -> bool {
+        self.path == other.path
+    }
+}
+
+impl Eq for SecurePathBuf {}
+
+impl PartialOrd for SecurePathBuf {
+    fn partial_cmp(&self, other: &SecurePathBuf) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SecurePathBuf {
+    fn cmp(&self, other: &SecurePathBuf) -> Ordering {
+        self.path.cmp(&other.path)
+    }
+}
+
+impl Hash for SecurePathBuf {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.path.hash(state)
+    }
+}
+
+// The host path, like `Path::display`, which is lossy for the names that are not UTF-8.
+impl fmt::Display for SecurePathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.path.display(), f)
+    }
+}
+
+// An iterator over the components of a `SecurePathBuf` below its rootfs.
+#[derive(Debug, Clone)]
+pub struct RootComponents<'a>(path::Components<'a>);
+
+// A name of a component below the rootfs, which is never empty, has no "/" and isn't "." or "..".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RootComponent<'a>(&'a OsStr);
+
+impl<'a> RootComponent<'a> {
+    pub fn as_os_str(&self) -> &'a OsStr {
+        self.0
+    }
+
+    // The name if it's valid UTF-8.
+    pub fn to_str(&self) -> Option<&'a str> {
+        self.0.to_str()
+    }
+}
+
+impl AsRef<OsStr> for RootComponent<'_> {
+    fn as_ref(&self) -> &OsStr {
+        self.0
+    }
+}
+
+impl AsRef<Path> for RootComponent<'_> {
+    fn as_ref(&self) -> &Path {
+        Path::new(self.0)
+    }
+}
+
+impl fmt::Display for RootComponent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&Path::new(self.0).display(), f)
+    }
+}
+
+// The path is normalized, its components are all names. Any other one is skipped in case.
+impl<'a> Iterator for RootComponents<'a> {
+    type Item = RootComponent<'a>;
+
+    fn next(&mut self) -> Option<RootComponent<'a>> {
+        self.0.by_ref().find_map(normal)
+    }
+}
+
+impl<'a> DoubleEndedIterator for RootComponents<'a> {
+    fn next_back(&mut self) -> Option<RootComponent<'a>> {
+        self.0.by_ref().rev().find_map(normal)
+    }
+}
+
+fn normal(component: path::Component<'_>) -> Option<RootComponent<'_>> {
+    match component {
+        path::Component::Normal(name) => Some(RootComponent(name)),
+        _ => None,
+    }
+}
+
+// With the `serde` feature the path is serialized relative to the rootfs, like `strip_root`,
+// and deserialized with a `SecurePathSeed` resolving it again inside of the current rootfs of
+// the state file, so an edited one can't name a path outside of it.
+#[cfg(feature = "serde")]
+impl Serialize for SecurePathBuf {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        self.strip_root().serialize(serializer)
+    }
+}
+
+// The `DeserializeSeed` of a `SecurePathBuf` inside of its `Root`.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Copy)]
+pub struct SecurePathSeed<'a>(pub &'a Root);
+
+#[cfg(feature = "serde")]
+impl<'de> DeserializeSeed<'de> for SecurePathSeed<'_> {
+    type Value = SecurePathBuf;
+
+    fn deserialize<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<SecurePathBuf, D::Error> {
+        let path = PathBuf::deserialize(deserializer)?;
+
+        SecurePathBuf::new(self.0, &path)
+            .map_err(|e| de::Error::custom(format!("{:?}: {}", path, e)))
+    }
+}
+
+// A path resolved by `Root::resolve`, borrowing the `Root` which resolved it. The operations
+// through it don't follow its last component, replaced by a symlink since the resolution.
+#[derive(Debug, Clone)]
+pub struct Resolved<'a> {
+    root: &'a Root,
+    path: SecurePathBuf,
+}
+
+impl<'a> Resolved<'a> {
+    pub(crate) fn new<P: AsRef<Path>>(root: &'a Root, unsafe_path: P) -> Result<Resolved<'a>> {
+        let path = SecurePathBuf::new(root, unsafe_path)?;
+
+        Ok(Resolved { root, path })
+    }
+
+    pub fn root(&self) -> &'a Root {
+        self.root
+    }
+
+    pub fn path(&self) -> &SecurePathBuf {
+        &self.path
+    }
+
+    // Open the file for reading.
+    pub fn open(&self) -> Result<File> {
+        let mut options = OpenOptions::new();
+        options.read(true).custom_flags(sys::o_nofollow());
+
+        Ok(options.open(&self.path)?)
+    }
+
+    pub fn metadata(&self) -> Result<fs::Metadata> {
+        Ok(fs::symlink_metadata(&self.path)?)
+    }
+
+    // An O_PATH fd of the parent directory, for the *at() calls on the entry. The parent of
+    // the rootfs itself is the rootfs.
+    pub fn parent_fd(&self) -> Result<OwnedFd> {
+        let parent = match self.path.parent() {
+            Some(parent) if self.path.as_path() != self.root.path() => parent,
+            _ => self.root.path(),
+        };
+
+        Ok(sys::open_dir(parent)?)
+    }
+}
+
+// `Root::resolve` for the generic conversions.
+impl<'a, P: AsRef<Path>> TryFrom<(&'a Root, P)> for Resolved<'a> {
+    type Error = Error;
+
+    fn try_from((root, unsafe_path): (&'a Root, P)) -> Result<Resolved<'a>> {
+        Resolved::new(root, unsafe_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+    use std::collections::HashSet;
+    use std::convert::TryInto;
+    use std::io::Read;
+    use std::os::unix::fs::{symlink, MetadataExt};
+
+    #[test]
+    fn test_secure_path_buf() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            result: &'a str,
+        }
+
+        let tree = TreeBuilder::new()
+            .file("etc/hosts", "127.0.0.1 localhost\n")
+            .symlink("l", "/etc")
+            .symlink("up", "../../etc")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+
+        let tests = &[
+            TestData {
+                name: "inside",
+                path: "etc/hosts",
+                result: "etc/hosts",
+            },
+            TestData {
+                name: "through a symlink",
+                path: "/l/hosts",
+                result: "etc/hosts",
+            },
+            TestData {
+                name: "clamped",
+                path: "../up/hosts",
+                result: "etc/hosts",
+            },
+            TestData {
+                name: "missing",
+                path: "etc/missing",
+                result: "etc/missing",
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = SecurePathBuf::new(&root, t.path);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            let path = result.unwrap();
+            let expected = tree.path().join(t.result);
+            assert!(*path == *expected, "{}", msg);
+            assert!(path.as_ref() == expected.as_path(), "{}", msg);
+            assert!(
+                path.to_string() == expected.display().to_string(),
+                "{}",
+                msg
+            );
+            assert!(path.rootfs() == tree.path(), "{}", msg);
+            assert!(path.strip_root() == Path::new(t.result), "{}", msg);
+            assert!(tree.path().join(path.strip_root()) == *path, "{}", msg);
+            assert!(path.clone().into_inner() == expected, "{}", msg);
+        }
+
+        // the std fs functions take it as is
+        let path = SecurePathBuf::new(&root, "l/hosts").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "127.0.0.1 localhost\n");
+        assert!(path.is_file());
+        let borrowed: &Path = path.borrow();
+        assert_eq!(borrowed, tree.path().join("etc/hosts"));
+        assert!(SecurePathBuf::new(&root, "etc/hosts/x").is_err());
+        let top = SecurePathBuf::new(&root, "up/..").unwrap();
+        assert_eq!(top.strip_root(), Path::new(""));
+        assert_eq!(top.into_inner(), tree.path());
+    }
+
+    #[test]
+    fn test_try_from() {
+        let tree = TreeBuilder::new()
+            .file("etc/hosts", "")
+            .symlink("l", "../etc")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let hosts = tree.path().join("etc/hosts");
+
+        let path = SecurePathBuf::try_from((&root, Path::new("l/hosts"))).unwrap();
+        assert_eq!(path.as_path(), hosts);
+        let path: SecurePathBuf = (&root, "/l/hosts").try_into().unwrap();
+        assert_eq!(path.as_path(), hosts);
+        let path: SecurePathBuf = (&root, PathBuf::from("../etc/hosts")).try_into().unwrap();
+        assert_eq!(path.as_path(), hosts);
+        let resolved: Resolved = (&root, "l/hosts").try_into().unwrap();
+        assert_eq!(resolved.path().as_path(), hosts);
+
+        let convert = |p: &str| -> Result<PathBuf> {
+            let path: SecurePathBuf = (&root, p).try_into()?;
+            Ok(path.strip_root().to_path_buf())
+        };
+        assert!(convert("etc/hosts").is_ok());
+        assert!(matches!(convert("etc/hosts/x"), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn test_root_components() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            components: &'a [&'a str],
+        }
+
+        let tree = TreeBuilder::new()
+            .file("srv/www/index.html", "")
+            .symlink("www", "../srv/www")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+
+        let tests = &[
+            TestData {
+                name: "rootfs",
+                path: "/",
+                components: &[],
+            },
+            TestData {
+                name: "names",
+                path: "srv/www/index.html",
+                components: &["srv", "www", "index.html"],
+            },
+            TestData {
+                name: "dots and a symlink",
+                path: "/../www/./../www/index.html/",
+                components: &["srv", "www", "index.html"],
+            },
+            TestData {
+                name: "missing",
+                path: "srv/api/v1",
+                components: &["srv", "api", "v1"],
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let path = SecurePathBuf::new(&root, t.path).unwrap();
+            let result: Vec<String> = path.root_components().map(|c| c.to_string()).collect();
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            assert!(result == t.components, "{}", msg);
+            let reversed: Vec<&str> = path
+                .root_components()
+                .rev()
+                .map(|c| c.to_str().unwrap())
+                .collect();
+            assert!(reversed.iter().rev().eq(t.components.iter()), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_eq_hash_ord() {
+        let tree = TreeBuilder::new()
+            .file("etc/hosts", "")
+            .file("etc/passwd", "")
+            .symlink("l", "/etc")
+            .symlink("up", "../../etc")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let path = |p: &str| SecurePathBuf::new(&root, p).unwrap();
+
+        let inputs = ["etc/hosts", "/l/hosts", "up/./hosts", "../etc/../etc/hosts"];
+        let set: HashSet<SecurePathBuf> = inputs.iter().map(|p| path(p)).collect();
+        assert_eq!(set.len(), 1, "{:?}", set);
+        assert!(set.contains(tree.path().join("etc/hosts").as_path()));
+        assert!(path("l/hosts") == path("etc/hosts"));
+        assert!(path("l/passwd") != path("l/hosts"));
+
+        let mut sorted: Vec<SecurePathBuf> = ["l/passwd", "up/hosts", "/", "etc"]
+            .iter()
+            .map(|p| path(p))
+            .collect();
+        sorted.sort();
+        let sorted: Vec<&Path> = sorted.iter().map(|p| p.strip_root()).collect();
+        assert_eq!(
+            sorted,
+            ["", "etc", "etc/hosts", "etc/passwd"].map(Path::new)
+        );
+    }
+
+    #[test]
+    fn test_reanchor() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            result: Option<&'a str>,
+        }
+
+        let staged = TreeBuilder::new()
+            .file("etc/hosts", "")
+            .file("etc/ssl/cert.pem", "")
+            .file("usr/bin/sh", "")
+            .build()
+            .unwrap();
+        let run = TreeBuilder::new()
+            .file("etc/hosts", "")
+            .symlink("etc/ssl", "../../../usr/share/ssl")
+            .file("usr/share/ssl/cert.pem", "")
+            .file("usr/bin", "")
+            .build()
+            .unwrap();
+        let (staged_root, run_root) = (staged.root().unwrap(), run.root().unwrap());
+
+        let tests = &[
+            TestData {
+                name: "same tree",
+                path: "etc/hosts",
+                result: Some("etc/hosts"),
+            },
+            TestData {
+                name: "symlink in the new rootfs, resolved inside of it",
+                path: "etc/ssl/cert.pem",
+                result: Some("usr/share/ssl/cert.pem"),
+            },
+            TestData {
+                name: "below a file in the new rootfs",
+                path: "usr/bin/sh",
+                result: None,
+            },
+            TestData {
+                name: "rootfs",
+                path: "/",
+                result: Some(""),
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let path = SecurePathBuf::new(&staged_root, t.path).unwrap();
+            let result = path.reanchor(&run_root);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            match t.result {
+                Some(r) => {
+                    let result = result.unwrap();
+                    assert!(result.rootfs() == run.path(), "{}", msg);
+                    assert!(result.strip_root() == Path::new(r), "{}", msg);
+                }
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolved() {
+        let tree = TreeBuilder::new()
+            .file("etc/hosts", "127.0.0.1 localhost\n")
+            .symlink("l", "../../etc")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let same_file = |fd: &OwnedFd, path: &Path| {
+            let opened = File::from(fd.try_clone().unwrap()).metadata().unwrap();
+            opened.ino() == fs::metadata(path).unwrap().ino()
+        };
+
+        let hosts = root.resolve("l/hosts").unwrap();
+        assert_eq!(hosts.path().as_path(), tree.path().join("etc/hosts"));
+        assert_eq!(hosts.root().path(), tree.path());
+        assert!(hosts.metadata().unwrap().is_file());
+        let mut content = String::new();
+        hosts.open().unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "127.0.0.1 localhost\n");
+        assert!(same_file(
+            &hosts.parent_fd().unwrap(),
+            &tree.path().join("etc")
+        ));
+
+        let top = root.resolve("/").unwrap();
+        assert!(same_file(&top.parent_fd().unwrap(), tree.path()));
+        assert!(root.resolve("etc/missing").unwrap().open().is_err());
+
+        // swapped for a symlink since the resolution
+        let etc = root.resolve("etc").unwrap();
+        fs::rename(tree.path().join("etc"), tree.path().join("etc.old")).unwrap();
+        symlink("etc.old", tree.path().join("etc")).unwrap();
+        assert!(etc.metadata().unwrap().file_type().is_symlink());
+        assert!(hosts.parent_fd().is_err());
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn test_serde() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            json: &'a str,
+            result: Option<&'a str>,
+        }
+
+        let staged = TreeBuilder::new().file("etc/hosts", "").build().unwrap();
+        let tree = TreeBuilder::new()
+            .file("etc/hosts", "")
+            .symlink("up", "../../../etc")
+            .file("etc/passwd/x", "")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+
+        let path = SecurePathBuf::new(&staged.root().unwrap(), "etc/hosts").unwrap();
+        assert_eq!(serde_json::to_string(&path).unwrap(), r#""etc/hosts""#);
+
+        let tests = &[
+            TestData {
+                name: "relative",
+                json: r#""etc/hosts""#,
+                result: Some("etc/hosts"),
+            },
+            TestData {
+                name: "edited to escape",
+                json: r#""up/../../hosts""#,
+                result: Some("hosts"),
+            },
+            TestData {
+                name: "not a path",
+                json: "1",
+                result: None,
+            },
+            TestData {
+                name: "below a file",
+                json: r#""etc/hosts/x""#,
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let mut deserializer = serde_json::Deserializer::from_str(t.json);
+            let result = SecurePathSeed(&root).deserialize(&mut deserializer);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            match t.result {
+                Some(r) => {
+                    let result = result.unwrap();
+                    assert!(result.rootfs() == tree.path(), "{}", msg);
+                    assert!(result.strip_root() == Path::new(r), "{}", msg);
+                }
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+    }
+}
@@ -0,0 +1,588 @@
+// Static file serving out of a document root. Request paths are decoded with the `url`
+// module and resolved inside of the root like `Root::check` does, then the symlink, dotfile
+// and directory index policies are applied. The `tower` feature wraps it into a service,
+// see `service::ServeRoot`.
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{self, Category, Error};
+use crate::resolve::resolve_counting;
+use crate::root::Root;
+use crate::secure_path_buf::SecurePathBuf;
+use crate::url::{decode_path, DecodeOptions};
+use crate::walk::open_dir;
+
+#[cfg(feature = "tower")]
+pub mod service;
+
+// The index files of a directory most static servers look for, in order.
+pub const DEFAULT_INDEX_FILES: [&str; 2] = ["index.html", "index.htm"];
+
+// Whether the symlinks inside of the document root are served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Symlinks {
+    // Follow them, as long as they resolve inside of the document root.
+    #[default]
+    Follow,
+    // Refuse any path going through a symlink.
+    Deny,
+}
+
+// How the names starting with a "." are served, e.g. ".git" or ".env".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dotfiles {
+    Allow,
+    // Refuse them with `Rejection::Forbidden`.
+    Deny,
+    // Pretend they don't exist with `Rejection::NotFound`.
+    #[default]
+    Hide,
+}
+
+// Options of `resolve_request`.
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub decode: DecodeOptions,
+    pub symlinks: Symlinks,
+    pub dotfiles: Dotfiles,
+    // The files served for a directory, the first found of them with `resolve_index`.
+    // Directories are refused when it's empty.
+    pub index: Vec<String>,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        ServeOptions {
+            decode: DecodeOptions::default(),
+            symlinks: Symlinks::default(),
+            dotfiles: Dotfiles::default(),
+            index: DEFAULT_INDEX_FILES.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+// Why a request isn't served, each maps to an HTTP status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    BadRequest,
+    Forbidden,
+    NotFound,
+    Internal,
+}
+
+impl Rejection {
+    // The HTTP status code of the rejection.
+    pub fn status(self) -> u16 {
+        match self {
+            Rejection::BadRequest => 400,
+            Rejection::Forbidden => 403,
+            Rejection::NotFound => 404,
+            Rejection::Internal => 500,
+        }
+    }
+}
+
+impl From<Error> for Rejection {
+    fn from(e: Error) -> Self {
+        match e.category() {
+            Category::Invalid => Rejection::BadRequest,
+            Category::Forbidden => Rejection::Forbidden,
+            Category::NotFound => Rejection::NotFound,
+            Category::Other => Rejection::Internal,
+        }
+    }
+}
+
+// Resolve the raw URL path `raw` of a request to the regular file to serve in the document
+// root. The dotfile policy applies to both the requested path and the one it resolves to,
+// so a symlink can't expose a hidden file. A directory is served its index file picked by
+// `resolve_index` out of `options.index`.
+pub fn resolve_request(
+    root: &Root,
+    raw: &str,
+    options: &ServeOptions,
+) -> std::result::Result<PathBuf, Rejection> {
+    let decoded = PathBuf::from(decode_path(raw, &options.decode)?);
+
+    let (path, m) = lookup(root, &decoded, options)?;
+    if !m.is_dir() {
+        return Ok(path);
+    }
+
+    if options.index.is_empty() {
+        return Err(Rejection::Forbidden);
+    }
+    let dir = SecurePathBuf::from_resolved(root.path(), path);
+
+    Ok(resolve_index(&dir, &options.index)?.into_inner())
+}
+
+// Resolve the raw URL path `raw` like `resolve_request` with `Symlinks::Deny` whatever the
+// options, and open the file. It's opened without following it or waiting on a FIFO, and
+// refused unless it's a regular file, so the returned metadata is the file's own.
+pub fn serve_open(
+    root: &Root,
+    raw: &str,
+    options: &ServeOptions,
+) -> std::result::Result<(File, Metadata), Rejection> {
+    let options = ServeOptions {
+        symlinks: Symlinks::Deny,
+        ..options.clone()
+    };
+    let path = resolve_request(root, raw, &options)?;
+
+    open_file(root, &path)
+}
+
+// Open the file `path` returned by `resolve_request` without following it or waiting on a
+// FIFO, refusing it unless it's a regular file. It's opened relative to the fd of its
+// directory, opened one component at a time from the rootfs, so a component swapped for a
+// symlink since `path` was resolved fails instead of being followed.
+pub(crate) fn open_file(
+    root: &Root,
+    path: &Path,
+) -> std::result::Result<(File, Metadata), Rejection> {
+    let rel = path
+        .strip_prefix(root.path())
+        .map_err(|_| Error::Escape(path.to_path_buf()))?;
+    let name = rel
+        .file_name()
+        .ok_or_else(|| Error::InvalidPath(path.to_path_buf()))?;
+    let dir = open_dir(root, rel.parent().unwrap_or(Path::new("")))?;
+
+    let file = dir.open_file(name).map_err(Error::Io)?;
+    let m = file.metadata().map_err(Error::Io)?;
+    if !m.is_file() {
+        return Err(Rejection::Forbidden);
+    }
+
+    Ok((file, m))
+}
+
+// The first of the files `names` found in the directory `dir`, e.g. `DEFAULT_INDEX_FILES`.
+// Nothing is followed: `dir` must still be a directory and not a symlink swapped in since it
+// was resolved, and a name is skipped unless it's a regular file. A name must be a single
+// component, none found fails with a `NotFound` I/O error.
+pub fn resolve_index<S: AsRef<str>>(
+    dir: &SecurePathBuf,
+    names: &[S],
+) -> error::Result<SecurePathBuf> {
+    if !fs::symlink_metadata(dir)?.is_dir() {
+        return Err(Error::Io(crate::sys::not_a_directory()));
+    }
+
+    for name in names {
+        let name = Path::new(name.as_ref());
+        if !matches!(
+            name.components().collect::<Vec<_>>()[..],
+            [Component::Normal(_)]
+        ) {
+            return Err(Error::InvalidPath(name.to_path_buf()));
+        }
+
+        let path = dir.join(name);
+        match fs::symlink_metadata(&path) {
+            Ok(m) if m.is_file() => return Ok(SecurePathBuf::from_resolved(dir.rootfs(), path)),
+            Ok(_) => continue,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(Error::Io(e)),
+        }
+    }
+
+    Err(Error::Io(io::ErrorKind::NotFound.into()))
+}
+
+fn lookup(
+    root: &Root,
+    path: &Path,
+    options: &ServeOptions,
+) -> std::result::Result<(PathBuf, Metadata), Rejection> {
+    check_dotfiles(path, options.dotfiles)?;
+
+    let (resolved, symlinks) = resolve_counting(root.path(), path, true, root.options())?;
+    if symlinks > 0 && options.symlinks == Symlinks::Deny {
+        return Err(Rejection::Forbidden);
+    }
+    check_dotfiles(
+        resolved.strip_prefix(root.path()).unwrap_or(&resolved),
+        options.dotfiles,
+    )?;
+
+    let m = fs::metadata(&resolved).map_err(Error::Io)?;
+
+    Ok((resolved, m))
+}
+
+fn check_dotfiles(path: &Path, dotfiles: Dotfiles) -> std::result::Result<(), Rejection> {
+    let dotfile = path.components().any(|it| match it {
+        Component::Normal(c) => c.to_string_lossy().starts_with('.'),
+        _ => false,
+    });
+
+    match dotfiles {
+        _ if !dotfile => Ok(()),
+        Dotfiles::Allow => Ok(()),
+        Dotfiles::Deny => Err(Rejection::Forbidden),
+        Dotfiles::Hide => Err(Rejection::NotFound),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_resolve_request() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            raw: &'a str,
+            symlinks: Symlinks,
+            dotfiles: Dotfiles,
+            index: &'a [&'a str],
+            result: Result<&'a str, Rejection>,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        root.mkdir_all("docs/empty").unwrap();
+        root.mkdir_all(".git").unwrap();
+        fs::write(root.path().join("docs/index.html"), b"").unwrap();
+        fs::write(root.path().join("docs/a.txt"), b"").unwrap();
+        fs::write(root.path().join(".git/config"), b"").unwrap();
+        symlink("/docs/a.txt", root.path().join("link")).unwrap();
+        symlink(".git/config", root.path().join("config")).unwrap();
+
+        let tests = &[
+            TestData {
+                name: "regular file",
+                raw: "/docs/a.txt",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Hide,
+                index: &["index.html"],
+                result: Ok("docs/a.txt"),
+            },
+            TestData {
+                name: "directory index",
+                raw: "/docs/",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Hide,
+                index: &["index.html"],
+                result: Ok("docs/index.html"),
+            },
+            TestData {
+                name: "directory without index",
+                raw: "/docs/empty",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Hide,
+                index: &["index.html"],
+                result: Err(Rejection::NotFound),
+            },
+            TestData {
+                name: "directory index disabled",
+                raw: "/docs",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Hide,
+                index: &[],
+                result: Err(Rejection::Forbidden),
+            },
+            TestData {
+                name: "missing file",
+                raw: "/docs/b.txt",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Hide,
+                index: &[],
+                result: Err(Rejection::NotFound),
+            },
+            TestData {
+                name: "escape",
+                raw: "/docs/%2e%2e/%2e%2e/etc/passwd",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Hide,
+                index: &[],
+                result: Err(Rejection::Forbidden),
+            },
+            TestData {
+                name: "bad encoding",
+                raw: "/docs/%zz",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Hide,
+                index: &[],
+                result: Err(Rejection::BadRequest),
+            },
+            TestData {
+                name: "symlink followed",
+                raw: "/link",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Hide,
+                index: &[],
+                result: Ok("docs/a.txt"),
+            },
+            TestData {
+                name: "symlink denied",
+                raw: "/link",
+                symlinks: Symlinks::Deny,
+                dotfiles: Dotfiles::Hide,
+                index: &[],
+                result: Err(Rejection::Forbidden),
+            },
+            TestData {
+                name: "hidden dotfile",
+                raw: "/.git/config",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Hide,
+                index: &[],
+                result: Err(Rejection::NotFound),
+            },
+            TestData {
+                name: "denied dotfile",
+                raw: "/.git/config",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Deny,
+                index: &[],
+                result: Err(Rejection::Forbidden),
+            },
+            TestData {
+                name: "allowed dotfile",
+                raw: "/.git/config",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Allow,
+                index: &[],
+                result: Ok(".git/config"),
+            },
+            TestData {
+                name: "symlink to a dotfile",
+                raw: "/config",
+                symlinks: Symlinks::Follow,
+                dotfiles: Dotfiles::Hide,
+                index: &[],
+                result: Err(Rejection::NotFound),
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let options = ServeOptions {
+                symlinks: t.symlinks,
+                dotfiles: t.dotfiles,
+                index: t.index.iter().map(|s| s.to_string()).collect(),
+                ..Default::default()
+            };
+            let result = resolve_request(&root, t.raw, &options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Ok(p) => assert!(result.unwrap() == root.path().join(p), "{}", msg),
+                Err(e) => assert!(result == Err(e), "{}", msg),
+            }
+        }
+    }
+
+    #[test]
+    fn test_serve_open() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            raw: &'a str,
+            result: Result<&'a str, Rejection>,
+        }
+
+        let tree = TreeBuilder::new()
+            .file("docs/index.html", "<html>")
+            .file("docs/a.txt", "a")
+            .symlink("link", "/docs/a.txt")
+            .symlink("docs/linked", "/docs")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let fifo = rustix::fs::mknodat(
+            rustix::fs::CWD,
+            tree.path().join("docs/fifo"),
+            rustix::fs::FileType::Fifo,
+            rustix::fs::Mode::from_raw_mode(0o644),
+            0,
+        );
+
+        let tests = &[
+            TestData {
+                name: "regular file",
+                raw: "/docs/a.txt",
+                result: Ok("a"),
+            },
+            TestData {
+                name: "directory index",
+                raw: "/docs/",
+                result: Ok("<html>"),
+            },
+            TestData {
+                name: "symlink",
+                raw: "/link",
+                result: Err(Rejection::Forbidden),
+            },
+            TestData {
+                name: "through a symlinked directory",
+                raw: "/docs/linked/a.txt",
+                result: Err(Rejection::Forbidden),
+            },
+            TestData {
+                name: "missing",
+                raw: "/docs/b.txt",
+                result: Err(Rejection::NotFound),
+            },
+            TestData {
+                name: "fifo",
+                raw: "/docs/fifo",
+                result: Err(match fifo {
+                    Ok(_) => Rejection::Forbidden,
+                    Err(_) => Rejection::NotFound,
+                }),
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let options = ServeOptions {
+                symlinks: Symlinks::Follow,
+                ..Default::default()
+            };
+            let result = serve_open(&root, t.raw, &options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            match (t.result, result) {
+                (Ok(content), Ok((mut file, m))) => {
+                    let mut data = String::new();
+                    std::io::Read::read_to_string(&mut file, &mut data).unwrap();
+                    assert!(
+                        data == content && m.len() == content.len() as u64,
+                        "{}",
+                        msg
+                    );
+                }
+                (Err(e), Err(result)) => assert!(result == e, "{}", msg),
+                _ => panic!("{}", msg),
+            }
+        }
+    }
+
+    #[test]
+    fn test_open_file_swapped() {
+        let tree = TreeBuilder::new()
+            .file("docs/a.txt", "a")
+            .file("secret/a.txt", "secret")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+
+        let path = resolve_request(&root, "/docs/a.txt", &ServeOptions::default()).unwrap();
+        // Swap the resolved directory for a symlink before the file is opened.
+        fs::rename(tree.path().join("docs"), tree.path().join("old")).unwrap();
+        symlink(tree.path().join("secret"), tree.path().join("docs")).unwrap();
+
+        let result = open_file(&root, &path);
+        assert!(result.is_err(), "result: {:?}", result);
+    }
+
+    #[test]
+    fn test_resolve_index() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            dir: &'a str,
+            names: &'a [&'a str],
+            result: Option<&'a str>,
+        }
+
+        let tree = TreeBuilder::new()
+            .file("both/index.html", "")
+            .file("both/index.htm", "")
+            .file("htm/index.htm", "")
+            .symlink("linked/index.html", "/etc/passwd")
+            .file("linked/index.htm", "")
+            .dir("dir/index.html")
+            .dir("empty")
+            .file("file", "")
+            .symlink("l", "both")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+
+        let tests = &[
+            TestData {
+                name: "first of the names",
+                dir: "both",
+                names: &DEFAULT_INDEX_FILES,
+                result: Some("both/index.html"),
+            },
+            TestData {
+                name: "second of the names",
+                dir: "htm",
+                names: &DEFAULT_INDEX_FILES,
+                result: Some("htm/index.htm"),
+            },
+            TestData {
+                name: "configured names",
+                dir: "both",
+                names: &["default.html", "index.htm"],
+                result: Some("both/index.htm"),
+            },
+            TestData {
+                name: "symlink skipped",
+                dir: "linked",
+                names: &DEFAULT_INDEX_FILES,
+                result: Some("linked/index.htm"),
+            },
+            TestData {
+                name: "directory skipped",
+                dir: "dir",
+                names: &DEFAULT_INDEX_FILES,
+                result: None,
+            },
+            TestData {
+                name: "none",
+                dir: "empty",
+                names: &DEFAULT_INDEX_FILES,
+                result: None,
+            },
+            TestData {
+                name: "not a directory",
+                dir: "file",
+                names: &DEFAULT_INDEX_FILES,
+                result: None,
+            },
+            TestData {
+                name: "name climbing out",
+                dir: "both",
+                names: &["../htm/index.htm"],
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let dir = SecurePathBuf::new(&root, t.dir).unwrap();
+            let result = resolve_index(&dir, t.names);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            match t.result {
+                Some(p) => assert!(result.unwrap().strip_root() == Path::new(p), "{}", msg),
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+
+        // swapped for a symlink since it was resolved
+        let dir = SecurePathBuf::new(&root, "l").unwrap();
+        fs::rename(tree.path().join("both"), tree.path().join("old")).unwrap();
+        symlink("old", tree.path().join("both")).unwrap();
+        assert!(resolve_index(&dir, &DEFAULT_INDEX_FILES).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+// A tower `Service` serving the files of a document root, so it can be mounted in any
+// tower-based server like axum or hyper, or wrapped by tower-http layers.
+use std::convert::Infallible;
+use std::fs::File;
+use std::future::{ready, Ready};
+use std::io::{self, Read};
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http::{header, HeaderValue, Method, Request, Response, StatusCode};
+use http_body_util::Full;
+use tower_service::Service;
+
+use super::{open_file, resolve_request, Rejection, ServeOptions};
+use crate::error::Error;
+use crate::root::{Root, DEFAULT_READ_LIMIT};
+
+// Serve GET and HEAD requests from the document root, see `resolve_request`. Only regular
+// files are served, opened like `serve_open` does so a FIFO can't block the service. The
+// service blocks: the files are read whole into memory while the request is handled, before
+// the returned future resolves, and not at all for a HEAD request. A file larger than the
+// `read_limit` of the root options is refused with 500, like `Root::read` refuses it.
+#[derive(Debug, Clone)]
+pub struct ServeRoot {
+    root: Root,
+    options: ServeOptions,
+}
+
+impl ServeRoot {
+    pub fn new(root: Root, options: ServeOptions) -> ServeRoot {
+        ServeRoot { root, options }
+    }
+
+    fn respond<B>(&self, req: &Request<B>) -> Response<Full<Bytes>> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            let mut resp = status(StatusCode::METHOD_NOT_ALLOWED);
+            resp.headers_mut()
+                .insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+            return resp;
+        }
+
+        let opened = resolve_request(&self.root, req.uri().path(), &self.options)
+            .and_then(|path| open_file(&self.root, &path));
+        let (file, m) = match opened {
+            Ok(opened) => opened,
+            Err(rejection) => return status(status_code(rejection)),
+        };
+
+        let limit = self.root.options().read_limit.unwrap_or(DEFAULT_READ_LIMIT);
+        if m.len() > limit {
+            let e = Error::Io(io::ErrorKind::FileTooLarge.into());
+            return status(status_code(Rejection::from(e)));
+        }
+
+        let body = match req.method() == Method::HEAD {
+            true => Bytes::new(),
+            false => match read_body(file, m.len()) {
+                Ok(data) => Bytes::from(data),
+                Err(e) => return status(status_code(Rejection::from(e))),
+            },
+        };
+        let mut resp = Response::new(Full::new(body));
+        resp.headers_mut()
+            .insert(header::CONTENT_LENGTH, HeaderValue::from(m.len()));
+
+        resp
+    }
+}
+
+// The `len` bytes of the opened file, a file which shrank since its metadata was read fails.
+fn read_body(file: File, len: u64) -> Result<Vec<u8>, Error> {
+    let mut data = Vec::new();
+    file.take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+
+    Ok(data)
+}
+
+impl<B> Service<Request<B>> for ServeRoot {
+    type Response = Response<Full<Bytes>>;
+    type Error = Infallible;
+    type Future = Ready<Result<Self::Response, Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        ready(Ok(self.respond(&req)))
+    }
+}
+
+fn status_code(rejection: Rejection) -> StatusCode {
+    StatusCode::from_u16(rejection.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn status(code: StatusCode) -> Response<Full<Bytes>> {
+    let mut resp = Response::new(Full::default());
+    *resp.status_mut() = code;
+
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::root::RootOptions;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_serve_root() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            method: Method,
+            uri: &'a str,
+            status: StatusCode,
+            len: Option<&'a str>,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let options = RootOptions {
+            read_limit: Some(8),
+            ..Default::default()
+        };
+        let root = Root::with_options(rootfs_dir.path(), options).unwrap();
+        fs::write(root.path().join("index.html"), b"hello").unwrap();
+        fs::write(root.path().join("big"), b"012345678").unwrap();
+        let fifo = rustix::fs::mknodat(
+            rustix::fs::CWD,
+            root.path().join("fifo"),
+            rustix::fs::FileType::Fifo,
+            rustix::fs::Mode::from_raw_mode(0o644),
+            0,
+        );
+        let mut service = ServeRoot::new(root, ServeOptions::default());
+
+        let tests = &[
+            TestData {
+                method: Method::GET,
+                uri: "/",
+                status: StatusCode::OK,
+                len: Some("5"),
+            },
+            TestData {
+                method: Method::HEAD,
+                uri: "/index.html?x=1",
+                status: StatusCode::OK,
+                len: Some("5"),
+            },
+            TestData {
+                method: Method::GET,
+                uri: "/../../etc/passwd",
+                status: StatusCode::FORBIDDEN,
+                len: None,
+            },
+            TestData {
+                method: Method::GET,
+                uri: "/missing",
+                status: StatusCode::NOT_FOUND,
+                len: None,
+            },
+            TestData {
+                method: Method::GET,
+                uri: "/fifo",
+                status: match fifo {
+                    Ok(_) => StatusCode::FORBIDDEN,
+                    Err(_) => StatusCode::NOT_FOUND,
+                },
+                len: None,
+            },
+            TestData {
+                method: Method::GET,
+                uri: "/big",
+                status: StatusCode::INTERNAL_SERVER_ERROR,
+                len: None,
+            },
+            TestData {
+                method: Method::POST,
+                uri: "/index.html",
+                status: StatusCode::METHOD_NOT_ALLOWED,
+                len: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, t);
+
+            let req = Request::builder()
+                .method(t.method.clone())
+                .uri(t.uri)
+                .body(())
+                .unwrap();
+            let result = service.call(req).into_inner().unwrap();
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result.status() == t.status, "{}", msg);
+            let len = result.headers().get(header::CONTENT_LENGTH);
+            assert!(len.map(|l| l.to_str().unwrap()) == t.len, "{}", msg);
+        }
+    }
+}
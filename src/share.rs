@@ -0,0 +1,153 @@
+// Helpers for the daemons sharing a host directory with a VM guest, like virtiofsd or a 9p
+// server. The paths come from the guest, so they are untrusted and resolved inside of the
+// shared directory.
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::root::Root;
+use crate::sys;
+
+// Options of `open_shared`.
+#[derive(Debug, Clone, Default)]
+pub struct ShareOptions {
+    // Refuse paths crossing into another mount inside of the shared directory.
+    pub same_mount: bool,
+}
+
+// A guest path opened inside of the shared directory.
+#[derive(Debug)]
+pub struct SharedPath {
+    // The opened file or directory.
+    pub file: File,
+    // The canonical host path of `file`, as reported by the kernel after it was opened.
+    pub path: PathBuf,
+}
+
+// Resolve `guest_path` inside of the shared directory `root` and open it read-only.
+//
+// After the file is opened, its canonical path is read back from the kernel and checked to
+// still be inside of the shared directory, so a component swapped for a symlink after the
+// resolution is detected as `Error::Escape`. With `options.same_mount` set, every directory
+// on the way must be on the same mount as the shared directory, otherwise
+// `Error::CrossMount` is returned.
+pub fn open_shared<P: AsRef<Path>>(
+    root: &Root,
+    guest_path: P,
+    options: &ShareOptions,
+) -> Result<SharedPath> {
+    let guest_path = guest_path.as_ref();
+    let path = root.join(guest_path)?;
+
+    if options.same_mount {
+        let dev = fs::metadata(root.path())?.dev();
+        for it in path.ancestors().take_while(|p| p.starts_with(root.path())) {
+            match fs::symlink_metadata(it) {
+                Ok(m) if m.dev() != dev => return Err(Error::CrossMount(it.to_path_buf())),
+                _ => continue,
+            }
+        }
+    }
+
+    // not following a symlink swapped in since, nor waiting on a FIFO
+    let file =
+        root.open_with_flags(guest_path, OpenOptions::new().read(true), sys::o_nonblock())?;
+    let path = fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+    if !path.starts_with(root.path()) {
+        return Err(Error::Escape(path));
+    }
+    if options.same_mount && file.metadata()?.dev() != fs::metadata(root.path())?.dev() {
+        return Err(Error::CrossMount(path));
+    }
+
+    Ok(SharedPath { file, path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_open_shared() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            guest_path: &'a str,
+            same_mount: bool,
+            result: Option<&'a str>,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        root.mkdir_all("a/b").unwrap();
+        fs::write(root.path().join("a/b/f"), b"").unwrap();
+        symlink("/a/b", root.path().join("l")).unwrap();
+        symlink("/proc/self", root.path().join("p")).unwrap();
+
+        let tests = &[
+            TestData {
+                guest_path: "a/b/f",
+                same_mount: true,
+                result: Some("a/b/f"),
+            },
+            TestData {
+                guest_path: "../../l/f",
+                same_mount: true,
+                result: Some("a/b/f"),
+            },
+            TestData {
+                guest_path: "/a",
+                same_mount: false,
+                result: Some("a"),
+            },
+            TestData {
+                guest_path: "p/status",
+                same_mount: false,
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}]: {:?}", i, t);
+
+            let options = ShareOptions {
+                same_mount: t.same_mount,
+            };
+            let result = open_shared(&root, t.guest_path, &options);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some(p) => assert!(result.unwrap().path == root.path().join(p), "{}", msg),
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+
+        let fifo = rustix::fs::mknodat(
+            rustix::fs::CWD,
+            root.path().join("a/fifo"),
+            rustix::fs::FileType::Fifo,
+            rustix::fs::Mode::from_raw_mode(0o644),
+            0,
+        );
+        if fifo.is_ok() {
+            let options = ShareOptions::default();
+            assert!(open_shared(&root, "a/fifo", &options).is_ok());
+        }
+
+        // /proc is another mount on the host, share it to cross into /proc/self
+        let proc_root = Root::new("/proc").unwrap();
+        let options = ShareOptions { same_mount: true };
+        assert!(open_shared(&proc_root, "self/status", &options).is_ok());
+        let options = ShareOptions { same_mount: false };
+        assert!(open_shared(&Root::new("/").unwrap(), "/proc/self/status", &options).is_ok());
+        let options = ShareOptions { same_mount: true };
+        assert!(matches!(
+            open_shared(&Root::new("/").unwrap(), "/proc/self/status", &options),
+            Err(Error::CrossMount(_))
+        ));
+    }
+}
@@ -0,0 +1,206 @@
+// Building an update of a path of a rootfs out of sight and putting it in place at once, so a
+// half written update is never visible in the rootfs. The content is built in a hidden
+// temporary directory next to the destination, on the same filesystem, and promoted with
+// renameat2(RENAME_EXCHANGE), or with two renames where the filesystem doesn't support it.
+use std::fs;
+use std::io;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use crate::audit::AuditEvent;
+use crate::error::{Error, Result};
+use crate::root::Root;
+use crate::sys;
+use crate::temp::TempDir;
+
+// The mode of a staged directory promoted to a new path, an existing one keeps its mode.
+const DEFAULT_MODE: u32 = 0o755;
+
+// A staged update of the directory `dest`, removed on drop unless it was promoted.
+#[derive(Debug)]
+pub struct Staging {
+    root: Root,
+    dest: PathBuf,
+    dir: TempDir,
+    // The device and inode of the staging directory.
+    inode: (u64, u64),
+}
+
+impl Staging {
+    // Create the staging directory for `dest`, a path inside of the rootfs whose parent
+    // directories are created.
+    pub(crate) fn new<P: AsRef<Path>>(root: &Root, dest: P) -> Result<Staging> {
+        let dest = dest.as_ref().to_path_buf();
+        let parent = dest.parent().unwrap_or_else(|| Path::new(""));
+        root.mkdir_all(parent)?;
+        let dir = root.tempdir_in(parent)?;
+        let m = fs::symlink_metadata(dir.path())?;
+
+        Ok(Staging {
+            root: root.clone(),
+            dest,
+            dir,
+            inode: (m.dev(), m.ino()),
+        })
+    }
+
+    // The host path of the staging directory, to build the content in.
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    // A `Root` at the staging directory, to build the content confined in it.
+    pub fn root(&self) -> Result<Root> {
+        Root::new(self.dir.path())
+    }
+
+    // Put the staged directory in place of `dest`, at once, and remove what was there before.
+    // The destination must still resolve next to the staging directory, which must not have
+    // been moved, else this fails with `Error::Race`. If it can't be put in place, `dest` is
+    // left as it was and the staged content is removed.
+    pub fn promote(self) -> Result<()> {
+        let staged = self.dir.path().to_path_buf();
+        let dest = self.root.join_parent(&self.dest)?;
+        let same = fs::symlink_metadata(&staged).is_ok_and(|m| (m.dev(), m.ino()) == self.inode);
+        if !same || dest.parent() != staged.parent() {
+            return Err(Error::Race(self.dest.clone()));
+        }
+
+        let old = match fs::symlink_metadata(&dest) {
+            Ok(m) => Some(m),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(Error::Io(e)),
+        };
+        let mode = match &old {
+            Some(m) if m.is_dir() => m.permissions().mode() & 0o7777,
+            _ => DEFAULT_MODE,
+        };
+        fs::set_permissions(&staged, fs::Permissions::from_mode(mode))?;
+
+        match old {
+            None => sys::rename(&staged, &dest)?,
+            Some(_) => match sys::exchange(&staged, &dest) {
+                // the old content is now at the staging path
+                Ok(()) => remove(&staged)?,
+                Err(e) if is_unsupported(&e) => swap(&self.root, &staged, &dest)?,
+                Err(e) => return Err(Error::Io(e)),
+            },
+        }
+        self.dir.keep();
+        self.root.audit(&dest, AuditEvent::Rename { from: staged });
+
+        Ok(())
+    }
+}
+
+fn is_unsupported(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::Unsupported || e.kind() == io::ErrorKind::InvalidInput
+}
+
+// Replace `dest` by `staged` with two renames, moving `dest` aside first and back if the
+// second rename fails.
+fn swap(root: &Root, staged: &Path, dest: &Path) -> Result<()> {
+    let parent = dest.parent().unwrap_or_else(|| Path::new(""));
+    let parent = parent.strip_prefix(root.path()).unwrap_or(parent);
+    let aside = root.tempdir_in(parent)?.keep();
+
+    sys::rename(dest, &aside)?;
+    if let Err(e) = sys::rename(staged, dest) {
+        sys::rename(&aside, dest)?;
+        return Err(Error::Io(e));
+    }
+
+    remove(&aside)
+}
+
+// Remove the replaced entry `path`, which isn't followed.
+fn remove(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path)?.is_dir() {
+        true => fs::remove_dir_all(path)?,
+        false => fs::remove_file(path)?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+
+    #[test]
+    fn test_staging() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            // the tree the update of "etc/app" is promoted into
+            tree: fn() -> TreeBuilder,
+            ok: bool,
+        }
+
+        let tests = &[
+            TestData {
+                name: "new directory",
+                tree: || TreeBuilder::new().dir("etc"),
+                ok: true,
+            },
+            TestData {
+                name: "existing directory",
+                tree: || TreeBuilder::new().file("etc/app/old", "old"),
+                ok: true,
+            },
+            TestData {
+                name: "existing symlink",
+                tree: || {
+                    TreeBuilder::new()
+                        .dir("etc/real")
+                        .symlink("etc/app", "real")
+                },
+                ok: true,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}", i, t.name);
+
+            let tree = (t.tree)().build().unwrap();
+            let root = tree.root().unwrap();
+            let staging = root.staging("/etc/app").unwrap();
+            let staged = staging.path().to_path_buf();
+            assert!(staged.parent() == Some(&tree.path().join("etc")), "{}", msg);
+            staging.root().unwrap().write("conf", b"new").unwrap();
+            assert!(!tree.path().join("etc/app/conf").exists(), "{}", msg);
+
+            let result = staging.promote();
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            assert!(result.is_ok() == t.ok, "{}", msg);
+            let app = tree.path().join("etc/app");
+            assert!(fs::read(app.join("conf")).unwrap() == b"new", "{}", msg);
+            assert!(!app.join("old").exists(), "{}", msg);
+            assert!(!staged.exists(), "{}", msg);
+            assert!(fs::read_dir(tree.path().join("etc")).unwrap().all(|e| {
+                let name = e.unwrap().file_name();
+                name == "app" || name == "real"
+            }));
+        }
+
+        // dropped without being promoted
+        let tree = TreeBuilder::new()
+            .file("etc/app/conf", "old")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+        let staging = root.staging("etc/app").unwrap();
+        let staged = staging.path().to_path_buf();
+        drop(staging);
+        assert!(!staged.exists());
+        assert_eq!(fs::read(tree.path().join("etc/app/conf")).unwrap(), b"old");
+
+        // the destination moved away from the staging directory
+        let staging = root.staging("etc/app").unwrap();
+        fs::rename(tree.path().join("etc"), tree.path().join("old")).unwrap();
+        fs::create_dir(tree.path().join("etc")).unwrap();
+        assert!(matches!(staging.promote(), Err(Error::Race(_))));
+    }
+}
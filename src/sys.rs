@@ -0,0 +1,741 @@
+// The filesystem operations the resolver is built on. On Linux they are the `openat2`,
+// `statx` and `readlinkat` syscalls called through rustix on directory fds, so that every
+// lookup is relative to the directory found by the previous one instead of re-walking a
+// path string. Elsewhere they fall back to std's path based calls.
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// What a single component names inside of a directory, `D` is the handle of a directory or
+// file found.
+pub(crate) enum Entry<D = Dir> {
+    Dir(D),
+    // A symlink and its target, `magic` for a procfs magic link like "/proc/self/root".
+    Symlink { target: PathBuf, magic: bool },
+    // An overlayfs whiteout, a character device with the device number 0/0.
+    Whiteout,
+    // Any other file, opened like a directory.
+    Other(D),
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) use self::fallback::{
+    copy_file, exchange, get_xattr, is_opaque, lchmod, mknod, open_dir, reflink, remove_xattr,
+    rename, set_times, set_xattr, Dir, WalkDir,
+};
+#[cfg(target_os = "linux")]
+pub(crate) use self::linux::{
+    copy_file, exchange, get_xattr, is_opaque, lchmod, mknod, mount_id, open_dir, reflink,
+    remove_xattr, rename, set_times, set_xattr, Dir, WalkDir,
+};
+
+// The xattrs marking an overlayfs directory as opaque, the "user." one is used by the
+// mounts with the "userxattr" option.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+const OPAQUE_XATTRS: [&str; 2] = ["trusted.overlay.opaque", "user.overlay.opaque"];
+
+// The open() flag refusing to follow a symlink as the last component.
+pub(crate) fn o_nofollow() -> i32 {
+    #[cfg(target_os = "linux")]
+    return rustix::fs::OFlags::NOFOLLOW.bits() as i32;
+    #[cfg(not(target_os = "linux"))]
+    return 0x0100;
+}
+
+// The open() flag which doesn't wait for a writer of a FIFO.
+pub(crate) fn o_nonblock() -> i32 {
+    #[cfg(target_os = "linux")]
+    return rustix::fs::OFlags::NONBLOCK.bits() as i32;
+    #[cfg(not(target_os = "linux"))]
+    return 0x0004;
+}
+
+// Remove the child `name` of `dir` and, if it's a directory, everything below it, like
+// `fs::remove_dir_all` but relative to the fds of the directories, so a directory swapped for
+// a symlink meanwhile is unlinked instead of followed.
+pub(crate) fn remove_all(dir: &WalkDir, name: &OsStr) -> io::Result<()> {
+    if !dir.child_metadata(name)?.is_dir() {
+        return dir.remove_child(name, false);
+    }
+
+    let child = dir.open_child(name)?;
+    for (it, m) in child.entries()? {
+        match m.is_dir() {
+            true => remove_all(&child, &it)?,
+            false => child.remove_child(&it, false)?,
+        }
+    }
+
+    dir.remove_child(name, true)
+}
+
+// The error of a lookup below a non-directory.
+pub(crate) fn not_a_directory() -> io::Error {
+    #[cfg(target_os = "linux")]
+    return rustix::io::Errno::NOTDIR.into();
+    #[cfg(not(target_os = "linux"))]
+    return io::ErrorKind::NotADirectory.into();
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::*;
+    use rustix::fd::OwnedFd;
+    use rustix::fs::{
+        chmodat, copy_file_range, fchmod, fstatfs, ioctl_ficlone, lgetxattr, lremovexattr,
+        lsetxattr, makedev, mkdirat, mknodat, openat, openat2, readlinkat, renameat, renameat_with,
+        seek, statx, unlinkat, utimensat, AtFlags, FileType, Mode, OFlags, RenameFlags,
+        ResolveFlags, SeekFrom, StatxFlags, Timespec, Timestamps, XattrFlags, CWD,
+        PROC_SUPER_MAGIC, UTIME_OMIT,
+    };
+    use rustix::io::{retry_on_intr, Errno};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::os::unix::fs::FileExt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    // Whether openat2() is known to be unavailable, it's new in Linux 5.6.
+    static NO_OPENAT2: AtomicBool = AtomicBool::new(false);
+
+    // An opened O_PATH directory, or any other file found by a lookup.
+    #[derive(Debug)]
+    pub(crate) struct Dir(OwnedFd);
+
+    impl Dir {
+        pub(crate) fn open(path: &Path) -> io::Result<Dir> {
+            let flags = OFlags::PATH | OFlags::DIRECTORY | OFlags::CLOEXEC;
+            let fd = retry_on_intr(|| openat(CWD, path, flags, Mode::empty()))?;
+
+            Ok(Dir(fd))
+        }
+
+        // The statfs() magic number of the filesystem of the directory.
+        pub(crate) fn fs_type(&self) -> io::Result<u64> {
+            Ok(retry_on_intr(|| fstatfs(&self.0))?.f_type as u64)
+        }
+
+        // Whether `path` still names the opened file, and not another one renamed or swapped
+        // into its place since it was opened. The path is followed like the rootfs is, a
+        // symlink swapped in only names the file if it points back to it.
+        pub(crate) fn is_at(&self, path: &Path) -> io::Result<bool> {
+            let flags = StatxFlags::INO;
+            let opened = retry_on_intr(|| statx(&self.0, "", AtFlags::EMPTY_PATH, flags))?;
+            let at = match retry_on_intr(|| statx(CWD, path, AtFlags::empty(), flags)) {
+                Ok(stat) => stat,
+                Err(Errno::NOENT) | Err(Errno::NOTDIR) | Err(Errno::LOOP) => return Ok(false),
+                Err(e) => return Err(e.into()),
+            };
+
+            Ok(opened.stx_ino == at.stx_ino
+                && (opened.stx_dev_major, opened.stx_dev_minor)
+                    == (at.stx_dev_major, at.stx_dev_minor))
+        }
+
+        // Look up `name` without following it. The entry itself is opened first and only
+        // then inspected, so it can't be swapped between the checks.
+        pub(crate) fn lookup(&self, name: &OsStr) -> io::Result<Entry> {
+            let fd = open_nofollow(&self.0, name)?;
+
+            let stat = retry_on_intr(|| statx(&fd, "", AtFlags::EMPTY_PATH, StatxFlags::TYPE))?;
+            match FileType::from_raw_mode(stat.stx_mode as _) {
+                FileType::Directory => Ok(Entry::Dir(Dir(fd))),
+                FileType::Symlink => {
+                    let target = retry_on_intr(|| readlinkat(&fd, "", Vec::new()))?;
+                    let target = PathBuf::from(OsString::from_vec(target.into_bytes()));
+                    let magic = is_magic_link(&fd, &target)?;
+                    Ok(Entry::Symlink { target, magic })
+                }
+                FileType::CharacterDevice
+                    if stat.stx_rdev_major == 0 && stat.stx_rdev_minor == 0 =>
+                {
+                    Ok(Entry::Whiteout)
+                }
+                _ => Ok(Entry::Other(Dir(fd))),
+            }
+        }
+    }
+
+    // A directory opened for reading, whose children are looked up and opened relative to
+    // its fd without following them, so a directory swapped for a symlink during a walk
+    // isn't followed out of the rootfs.
+    #[derive(Debug)]
+    pub(crate) struct WalkDir(OwnedFd);
+
+    impl WalkDir {
+        // Open the directory `path`, e.g. a rootfs, which is followed.
+        pub(crate) fn open(path: &Path) -> io::Result<WalkDir> {
+            let flags = OFlags::RDONLY | OFlags::DIRECTORY | OFlags::CLOEXEC;
+
+            Ok(WalkDir(retry_on_intr(|| {
+                openat(CWD, path, flags, Mode::empty())
+            })?))
+        }
+
+        // Open the child directory `name`, failing if it's a symlink.
+        pub(crate) fn open_child(&self, name: &OsStr) -> io::Result<WalkDir> {
+            let flags = OFlags::RDONLY | OFlags::DIRECTORY | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+
+            Ok(WalkDir(retry_on_intr(|| {
+                openat(&self.0, name, flags, Mode::empty())
+            })?))
+        }
+
+        // Create the child directory `name` with `mode`, minus the umask like mkdir().
+        pub(crate) fn create_dir(&self, name: &OsStr, mode: u32) -> io::Result<()> {
+            let mode = Mode::from_raw_mode(mode);
+
+            Ok(retry_on_intr(|| mkdirat(&self.0, name, mode))?)
+        }
+
+        // Remove the child `name`, an empty directory with `dir`, which is not followed.
+        pub(crate) fn remove_child(&self, name: &OsStr, dir: bool) -> io::Result<()> {
+            let flags = match dir {
+                true => AtFlags::REMOVEDIR,
+                false => AtFlags::empty(),
+            };
+
+            Ok(retry_on_intr(|| unlinkat(&self.0, name, flags))?)
+        }
+
+        pub(crate) fn read_link(&self, name: &OsStr) -> io::Result<PathBuf> {
+            let target = retry_on_intr(|| readlinkat(&self.0, name, Vec::new()))?;
+
+            Ok(PathBuf::from(OsString::from_vec(target.into_bytes())))
+        }
+
+        // Set the permission bits of the directory itself.
+        pub(crate) fn set_mode(&self, mode: u32) -> io::Result<()> {
+            Ok(retry_on_intr(|| {
+                fchmod(&self.0, Mode::from_raw_mode(mode))
+            })?)
+        }
+
+        // Open the child `name` for reading, failing if it's a symlink and without waiting on
+        // a FIFO.
+        pub(crate) fn open_file(&self, name: &OsStr) -> io::Result<File> {
+            let flags = OFlags::RDONLY | OFlags::NOFOLLOW | OFlags::NONBLOCK | OFlags::CLOEXEC;
+
+            Ok(File::from(retry_on_intr(|| {
+                openat(&self.0, name, flags, Mode::empty())
+            })?))
+        }
+
+        pub(crate) fn metadata(&self) -> io::Result<Metadata> {
+            File::from(self.0.try_clone()?).metadata()
+        }
+
+        // The metadata of the child `name`, which is not followed.
+        pub(crate) fn child_metadata(&self, name: &OsStr) -> io::Result<Metadata> {
+            let flags = OFlags::PATH | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+            let fd = retry_on_intr(|| openat(&self.0, name, flags, Mode::empty()))?;
+
+            File::from(fd).metadata()
+        }
+
+        // The names and metadata of the children, the ones removed while they are read are
+        // skipped.
+        pub(crate) fn entries(&self) -> io::Result<Vec<(OsString, Metadata)>> {
+            let mut entries = Vec::new();
+            for it in rustix::fs::Dir::read_from(&self.0)? {
+                let it = it?;
+                let name = OsStr::from_bytes(it.file_name().to_bytes());
+                if name == "." || name == ".." {
+                    continue;
+                }
+                match self.child_metadata(name) {
+                    Ok(m) => entries.push((name.to_os_string(), m)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Ok(entries)
+        }
+    }
+
+    // An O_PATH fd of the directory `path`, which is not followed if it's a symlink.
+    pub(crate) fn open_dir(path: &Path) -> io::Result<OwnedFd> {
+        let flags = OFlags::PATH | OFlags::DIRECTORY | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+
+        Ok(retry_on_intr(|| openat(CWD, path, flags, Mode::empty()))?)
+    }
+
+    // The id of the mount `path` is on, from statx() STATX_MNT_ID which is new in Linux 5.8.
+    pub(crate) fn mount_id(path: &Path) -> io::Result<u64> {
+        let flags = AtFlags::SYMLINK_NOFOLLOW;
+        let stat = retry_on_intr(|| statx(CWD, path, flags, StatxFlags::MNT_ID))?;
+        if StatxFlags::from_bits_retain(stat.stx_mask).contains(StatxFlags::MNT_ID) {
+            Ok(stat.stx_mnt_id)
+        } else {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    // Whether the directory `path` is marked opaque by overlayfs, which hides the entries of
+    // the lower layers below it.
+    pub(crate) fn is_opaque(path: &Path) -> io::Result<bool> {
+        for name in OPAQUE_XATTRS {
+            let mut value = [0; 1];
+            match retry_on_intr(|| lgetxattr(path, name, &mut value)) {
+                Ok(1) if value[0] == b'y' => return Ok(true),
+                Ok(_) | Err(Errno::NODATA) | Err(Errno::NOTSUP) | Err(Errno::RANGE) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(false)
+    }
+
+    // Change the permission bits of `path` without following it, which fails for a symlink.
+    pub(crate) fn lchmod(path: &Path, mode: u32) -> io::Result<()> {
+        let mode = Mode::from_raw_mode(mode);
+        match retry_on_intr(|| chmodat(CWD, path, mode, AtFlags::SYMLINK_NOFOLLOW)) {
+            // without fchmodat2(), through an O_PATH fd of the entry like glibc does
+            Err(Errno::NOTSUP) => {}
+            result => return Ok(result?),
+        }
+
+        let flags = OFlags::PATH | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+        let fd = retry_on_intr(|| openat(CWD, path, flags, Mode::empty()))?;
+        let stat = retry_on_intr(|| statx(&fd, "", AtFlags::EMPTY_PATH, StatxFlags::TYPE))?;
+        if FileType::from_raw_mode(stat.stx_mode.into()) == FileType::Symlink {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        let proc_path = format!("/proc/self/fd/{}", std::os::fd::AsRawFd::as_raw_fd(&fd));
+
+        Ok(retry_on_intr(|| {
+            chmodat(CWD, proc_path.as_str(), mode, AtFlags::empty())
+        })?)
+    }
+
+    // The value of the xattr `name` of `path` without following it, None when it is unset.
+    pub(crate) fn get_xattr(path: &Path, name: &OsStr) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            let len = match retry_on_intr(|| lgetxattr(path, name, &mut [0u8; 0][..])) {
+                Ok(len) => len,
+                Err(Errno::NODATA) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let mut value = vec![0; len];
+            match retry_on_intr(|| lgetxattr(path, name, &mut value)) {
+                Ok(n) => {
+                    value.truncate(n);
+                    return Ok(Some(value));
+                }
+                // it grew in between
+                Err(Errno::RANGE) => continue,
+                Err(Errno::NODATA) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // Set the xattr `name` of `path` without following it.
+    pub(crate) fn set_xattr(path: &Path, name: &OsStr, value: &[u8]) -> io::Result<()> {
+        let flags = XattrFlags::empty();
+        Ok(retry_on_intr(|| lsetxattr(path, name, value, flags))?)
+    }
+
+    // Remove the xattr `name` of `path` without following it, an unset one is ignored.
+    pub(crate) fn remove_xattr(path: &Path, name: &OsStr) -> io::Result<()> {
+        match retry_on_intr(|| lremovexattr(path, name)) {
+            Ok(()) | Err(Errno::NODATA) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Rename the entry `from` to `to`, replacing it, with renameat() on the fds of their parent
+    // directories. The last components are not followed.
+    pub(crate) fn rename(from: &Path, to: &Path) -> io::Result<()> {
+        let (from_dir, from_name) = split_parent(from)?;
+        let (to_dir, to_name) = split_parent(to)?;
+
+        Ok(retry_on_intr(|| {
+            renameat(&from_dir.0, &from_name, &to_dir.0, &to_name)
+        })?)
+    }
+
+    // Swap the entries `a` and `b` at once with renameat2(RENAME_EXCHANGE), like `rename`. Both
+    // must exist, the filesystems which don't support it fail with EINVAL.
+    pub(crate) fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+        let (a_dir, a_name) = split_parent(a)?;
+        let (b_dir, b_name) = split_parent(b)?;
+
+        Ok(retry_on_intr(|| {
+            renameat_with(&a_dir.0, &a_name, &b_dir.0, &b_name, RenameFlags::EXCHANGE)
+        })?)
+    }
+
+    // The opened parent directory of `path` and its last component.
+    fn split_parent(path: &Path) -> io::Result<(Dir, PathBuf)> {
+        match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => Ok((Dir::open(parent)?, PathBuf::from(name))),
+            _ => Err(Errno::INVAL.into()),
+        }
+    }
+
+    // Copy the content of `src` to `dst`, from their start, with copy_file_range() so the data
+    // stays in the kernel, or is shared by the filesystem, when the two are on the same one.
+    // It falls back to pread() and pwrite() where the kernel or the filesystems don't support
+    // it, e.g. across filesystems before Linux 5.3. Only the data regions SEEK_DATA and
+    // SEEK_HOLE report are copied, so the holes of a sparse file stay holes in `dst`, which
+    // must be empty. Returns the size of the file.
+    pub(crate) fn copy_file(src: &File, dst: &File) -> io::Result<u64> {
+        let len = src.metadata()?.len();
+
+        let mut pos = 0;
+        while pos < len {
+            let data = match seek(src, SeekFrom::Data(pos)) {
+                Ok(data) => data,
+                // only holes are left
+                Err(Errno::NXIO) => break,
+                Err(_) => pos,
+            };
+            let hole = seek(src, SeekFrom::Hole(data)).unwrap_or(len).min(len);
+            if data >= hole {
+                break;
+            }
+            copy_range(src, dst, data, hole)?;
+            pos = hole;
+        }
+        // a trailing hole
+        dst.set_len(len)?;
+
+        Ok(len)
+    }
+
+    fn copy_range(src: &File, dst: &File, start: u64, end: u64) -> io::Result<()> {
+        let (mut off_in, mut off_out) = (start, start);
+        while off_in < end {
+            let n = (end - off_in).min(1 << 30) as usize;
+            match retry_on_intr(|| {
+                copy_file_range(src, Some(&mut off_in), dst, Some(&mut off_out), n)
+            }) {
+                // the file shrank
+                Ok(0) => return Ok(()),
+                Ok(_) => {}
+                Err(Errno::XDEV | Errno::NOSYS | Errno::INVAL | Errno::OPNOTSUPP | Errno::PERM) => {
+                    return copy_range_with_buffer(src, dst, off_in, end);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn copy_range_with_buffer(src: &File, dst: &File, start: u64, end: u64) -> io::Result<()> {
+        let mut buf = vec![0; 64 << 10];
+        let mut off = start;
+        while off < end {
+            let len = buf.len().min((end - off) as usize);
+            let n = match src.read_at(&mut buf[..len], off) {
+                Ok(0) => return Ok(()),
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            dst.write_all_at(&buf[..n], off)?;
+            off += n as u64;
+        }
+
+        Ok(())
+    }
+
+    // Set the access and modification times of `path` without following it, with
+    // utimensat(). A time left to None is kept.
+    pub(crate) fn set_times(
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> io::Result<()> {
+        let timespec = |t: Option<SystemTime>| -> Timespec {
+            let Some(t) = t else {
+                return Timespec {
+                    tv_sec: 0,
+                    tv_nsec: UTIME_OMIT,
+                };
+            };
+            match t.duration_since(SystemTime::UNIX_EPOCH) {
+                Ok(d) => Timespec {
+                    tv_sec: d.as_secs() as i64,
+                    tv_nsec: d.subsec_nanos().into(),
+                },
+                // before 1970, the nanoseconds are always positive
+                Err(e) => {
+                    let d = e.duration();
+                    let (secs, nanos) = (d.as_secs() as i64, i64::from(d.subsec_nanos()));
+                    match nanos {
+                        0 => Timespec {
+                            tv_sec: -secs,
+                            tv_nsec: 0,
+                        },
+                        _ => Timespec {
+                            tv_sec: -secs - 1,
+                            tv_nsec: 1_000_000_000 - nanos,
+                        },
+                    }
+                }
+            }
+        };
+        let times = Timestamps {
+            last_access: timespec(atime),
+            last_modification: timespec(mtime),
+        };
+
+        Ok(retry_on_intr(|| {
+            utimensat(CWD, path, &times, AtFlags::SYMLINK_NOFOLLOW)
+        })?)
+    }
+
+    // Share the whole content of `src` with `dst` with the FICLONE ioctl, an instant copy on
+    // write on the filesystems supporting it like btrfs or XFS. It fails with EOPNOTSUPP or
+    // EXDEV elsewhere, leaving `dst` untouched.
+    pub(crate) fn reflink(src: &File, dst: &File) -> io::Result<()> {
+        Ok(retry_on_intr(|| ioctl_ficlone(dst, src))?)
+    }
+
+    // Create the character or, with `block`, block device node `path` with the mode 0666.
+    pub(crate) fn mknod(path: &Path, block: bool, major: u32, minor: u32) -> io::Result<()> {
+        let kind = match block {
+            true => FileType::BlockDevice,
+            false => FileType::CharacterDevice,
+        };
+        let mode = Mode::from_raw_mode(0o666);
+
+        Ok(retry_on_intr(|| {
+            mknodat(CWD, path, kind, mode, makedev(major, minor))
+        })?)
+    }
+
+    // The magic links of procfs are the ones to other files, like "/proc/<pid>/root", which
+    // read as absolute paths or as "<type>:[<inode>]" for pipes, sockets and namespaces. The
+    // plain symlinks of procfs like "/proc/self" read as relative paths.
+    fn is_magic_link(fd: &OwnedFd, target: &Path) -> io::Result<bool> {
+        if fstatfs(fd)?.f_type != PROC_SUPER_MAGIC {
+            return Ok(false);
+        }
+
+        Ok(target.has_root() || target.as_os_str().as_bytes().contains(&b':'))
+    }
+
+    fn open_nofollow(dir: &OwnedFd, name: &OsStr) -> io::Result<OwnedFd> {
+        let flags = OFlags::PATH | OFlags::NOFOLLOW | OFlags::CLOEXEC;
+
+        if !NO_OPENAT2.load(Ordering::Relaxed) {
+            let resolve = ResolveFlags::BENEATH | ResolveFlags::NO_MAGICLINKS;
+            match retry_on_intr(|| openat2(dir, name, flags, Mode::empty(), resolve)) {
+                Err(Errno::NOSYS) => NO_OPENAT2.store(true, Ordering::Relaxed),
+                result => return Ok(result?),
+            }
+        }
+
+        Ok(retry_on_intr(|| openat(dir, name, flags, Mode::empty()))?)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod fallback {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    // The xattrs are not read without rustix.
+    pub(crate) fn is_opaque(_path: &Path) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    pub(crate) fn mknod(_path: &Path, _block: bool, _major: u32, _minor: u32) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub(crate) fn rename(from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    pub(crate) fn exchange(_a: &Path, _b: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub(crate) fn copy_file(src: &File, dst: &File) -> io::Result<u64> {
+        io::copy(&mut &*src, &mut &*dst)
+    }
+
+    pub(crate) fn reflink(_src: &File, _dst: &File) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    // Through an fd without utimensat(), the times of a symlink can't be set.
+    pub(crate) fn set_times(
+        path: &Path,
+        atime: Option<SystemTime>,
+        mtime: Option<SystemTime>,
+    ) -> io::Result<()> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        if fs::symlink_metadata(path)?.file_type().is_symlink() {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(o_nofollow())
+            .open(path)?;
+        let mut times = fs::FileTimes::new();
+        if let Some(atime) = atime {
+            times = times.set_accessed(atime);
+        }
+        if let Some(mtime) = mtime {
+            times = times.set_modified(mtime);
+        }
+
+        file.set_times(times)
+    }
+
+    // The symlinks are followed without fchmodat().
+    pub(crate) fn lchmod(path: &Path, mode: u32) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    pub(crate) fn get_xattr(_path: &Path, _name: &OsStr) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    pub(crate) fn set_xattr(_path: &Path, _name: &OsStr, _value: &[u8]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub(crate) fn remove_xattr(_path: &Path, _name: &OsStr) -> io::Result<()> {
+        Ok(())
+    }
+
+    // Without O_PATH, the directory is opened for reading.
+    pub(crate) fn open_dir(path: &Path) -> io::Result<std::os::fd::OwnedFd> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(o_nofollow())
+            .open(path)?;
+        if !file.metadata()?.is_dir() {
+            return Err(not_a_directory());
+        }
+
+        Ok(file.into())
+    }
+
+    // A directory only known by its path, see the Linux one.
+    #[derive(Debug)]
+    pub(crate) struct WalkDir(PathBuf);
+
+    impl WalkDir {
+        pub(crate) fn open(path: &Path) -> io::Result<WalkDir> {
+            match fs::metadata(path)?.is_dir() {
+                true => Ok(WalkDir(path.to_path_buf())),
+                false => Err(not_a_directory()),
+            }
+        }
+
+        pub(crate) fn open_child(&self, name: &OsStr) -> io::Result<WalkDir> {
+            let path = self.0.join(name);
+
+            match fs::symlink_metadata(&path)?.is_dir() {
+                true => Ok(WalkDir(path)),
+                false => Err(not_a_directory()),
+            }
+        }
+
+        pub(crate) fn create_dir(&self, name: &OsStr, mode: u32) -> io::Result<()> {
+            use std::os::unix::fs::DirBuilderExt;
+
+            fs::DirBuilder::new().mode(mode).create(self.0.join(name))
+        }
+
+        pub(crate) fn remove_child(&self, name: &OsStr, dir: bool) -> io::Result<()> {
+            match dir {
+                true => fs::remove_dir(self.0.join(name)),
+                false => fs::remove_file(self.0.join(name)),
+            }
+        }
+
+        pub(crate) fn read_link(&self, name: &OsStr) -> io::Result<PathBuf> {
+            fs::read_link(self.0.join(name))
+        }
+
+        pub(crate) fn set_mode(&self, mode: u32) -> io::Result<()> {
+            use std::os::unix::fs::PermissionsExt;
+
+            fs::set_permissions(&self.0, fs::Permissions::from_mode(mode))
+        }
+
+        pub(crate) fn open_file(&self, name: &OsStr) -> io::Result<File> {
+            use std::os::unix::fs::OpenOptionsExt;
+
+            fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(o_nofollow() | o_nonblock())
+                .open(self.0.join(name))
+        }
+
+        pub(crate) fn metadata(&self) -> io::Result<Metadata> {
+            fs::metadata(&self.0)
+        }
+
+        pub(crate) fn child_metadata(&self, name: &OsStr) -> io::Result<Metadata> {
+            fs::symlink_metadata(self.0.join(name))
+        }
+
+        pub(crate) fn entries(&self) -> io::Result<Vec<(OsString, Metadata)>> {
+            let mut entries = Vec::new();
+            for it in fs::read_dir(&self.0)? {
+                let it = it?;
+                match fs::symlink_metadata(it.path()) {
+                    Ok(m) => entries.push((it.file_name(), m)),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Ok(entries)
+        }
+    }
+
+    #[derive(Debug)]
+    pub(crate) struct Dir(PathBuf);
+
+    impl Dir {
+        pub(crate) fn open(path: &Path) -> io::Result<Dir> {
+            Ok(Dir(path.to_path_buf()))
+        }
+
+        // The filesystem type is unknown without statfs().
+        pub(crate) fn fs_type(&self) -> io::Result<u64> {
+            Ok(0)
+        }
+
+        // The directory is only known by its path, which always names it.
+        pub(crate) fn is_at(&self, _path: &Path) -> io::Result<bool> {
+            Ok(true)
+        }
+
+        pub(crate) fn lookup(&self, name: &OsStr) -> io::Result<Entry> {
+            let path = self.0.join(name);
+
+            let m = fs::symlink_metadata(&path)?;
+            if m.file_type().is_dir() {
+                Ok(Entry::Dir(Dir(path)))
+            } else if m.file_type().is_symlink() {
+                Ok(Entry::Symlink {
+                    target: fs::read_link(&path)?,
+                    magic: false,
+                })
+            } else if m.file_type().is_char_device() && m.rdev() == 0 {
+                Ok(Entry::Whiteout)
+            } else {
+                Ok(Entry::Other(Dir(path)))
+            }
+        }
+    }
+}
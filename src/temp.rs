@@ -0,0 +1,200 @@
+// Temporary files and directories created inside of a rootfs, see `Root::tempdir` and
+// `Root::tempfile_in`.
+use std::collections::hash_map::RandomState;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+use crate::error::Result;
+use crate::root::Root;
+
+// How many names are tried before giving up, like mkstemp() does.
+const RETRIES: u32 = 128;
+
+// A temporary directory inside of a rootfs, removed with its content on drop.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+    // None once kept
+    inode: Option<(u64, u64)>,
+}
+
+// A temporary file inside of a rootfs, removed on drop.
+#[derive(Debug)]
+pub struct TempFile {
+    file: File,
+    path: PathBuf,
+    // None once kept
+    inode: Option<(u64, u64)>,
+}
+
+impl TempDir {
+    // The host path of the directory.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Keep the directory, returns its host path.
+    pub fn keep(mut self) -> PathBuf {
+        self.inode = None;
+        std::mem::take(&mut self.path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if same_inode(&self.path, self.inode) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+impl TempFile {
+    // The host path of the file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The opened file.
+    pub fn as_file(&self) -> &File {
+        &self.file
+    }
+
+    pub fn as_file_mut(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    // Keep the file, returns the opened file and its host path.
+    pub fn keep(mut self) -> Result<(File, PathBuf)> {
+        let file = self.file.try_clone()?;
+        self.inode = None;
+
+        Ok((file, std::mem::take(&mut self.path)))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if same_inode(&self.path, self.inode) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+// Whether `path` still is the entry that was created, so that a parent directory swapped
+// for a symlink doesn't make the cleanup remove something out of the rootfs.
+fn same_inode(path: &Path, inode: Option<(u64, u64)>) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|m| Some((m.dev(), m.ino())) == inode)
+}
+
+fn random_name(prefix: &str) -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(process::id());
+    if let Ok(d) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(d.as_nanos());
+    }
+
+    format!("{}{:016x}", prefix, hasher.finish())
+}
+
+// Create a uniquely named entry in the directory `dir` of the rootfs with `create`, which
+// must fail with `AlreadyExists` if the name is taken.
+fn create_unique<T, F>(root: &Root, dir: &Path, prefix: &str, create: F) -> Result<(T, PathBuf)>
+where
+    F: Fn(&Path) -> io::Result<T>,
+{
+    let dir = root.join(dir)?;
+
+    for _ in 0..RETRIES {
+        let path = dir.join(random_name(prefix));
+        match create(&path) {
+            Ok(t) => return Ok((t, path)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "too many temporary names taken",
+    )
+    .into())
+}
+
+pub(crate) fn tempdir_in(root: &Root, dir: &Path) -> Result<TempDir> {
+    let ((), path) = create_unique(root, dir, ".tmp", |p| {
+        DirBuilder::new().mode(0o700).create(p)
+    })?;
+    let m = fs::symlink_metadata(&path)?;
+
+    Ok(TempDir {
+        path,
+        inode: Some((m.dev(), m.ino())),
+    })
+}
+
+pub(crate) fn tempfile_in(root: &Root, dir: &Path) -> Result<TempFile> {
+    let (file, path) = create_unique(root, dir, ".tmp", |p| {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(p)
+    })?;
+    let m = file.metadata()?;
+
+    Ok(TempFile {
+        file,
+        path,
+        inode: Some((m.dev(), m.ino())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_temp() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        root.mkdir_all("tmp").unwrap();
+
+        let dir = root.tempdir().unwrap();
+        let dir_path = dir.path().to_path_buf();
+        assert_eq!(dir_path.parent().unwrap(), root.path());
+        fs::write(dir_path.join("f"), b"").unwrap();
+
+        let mut file = root.tempfile_in("../../tmp").unwrap();
+        let file_path = file.path().to_path_buf();
+        assert_eq!(file_path.parent().unwrap(), root.path().join("tmp"));
+        file.as_file_mut().write_all(b"data").unwrap();
+        assert_eq!(fs::read(&file_path).unwrap(), b"data");
+
+        let other = root.tempfile_in("tmp").unwrap();
+        assert_ne!(other.path(), file.path());
+
+        drop(dir);
+        drop(file);
+        assert!(!dir_path.exists());
+        assert!(!file_path.exists());
+
+        let (_, kept) = other.keep().unwrap();
+        assert!(kept.exists());
+
+        // an entry replaced after its creation isn't removed
+        let file = root.tempfile_in("tmp").unwrap();
+        let path = file.path().to_path_buf();
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, b"replaced").unwrap();
+        drop(file);
+        assert!(path.exists());
+    }
+}
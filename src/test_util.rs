@@ -0,0 +1,154 @@
+// Fixtures for the tests resolving paths, ours and the ones of the crates using this one with
+// the `test-util` feature: `TreeBuilder` declares the directories, files and symlinks of a
+// tree and creates them in a temporary directory.
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use tempfile::TempDir;
+
+use crate::error::Result;
+use crate::root::Root;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Dir(PathBuf),
+    File(PathBuf, Vec<u8>),
+    Symlink(PathBuf, PathBuf),
+}
+
+// A declared tree, the paths are relative to its top directory and the missing parents of
+// each entry are created as directories.
+#[derive(Debug, Clone, Default)]
+pub struct TreeBuilder {
+    nodes: Vec<Node>,
+}
+
+// A tree created by `TreeBuilder::build`, removed when dropped.
+#[derive(Debug)]
+pub struct Tree {
+    path: PathBuf,
+    _dir: TempDir,
+}
+
+impl TreeBuilder {
+    pub fn new() -> Self {
+        TreeBuilder::default()
+    }
+
+    // Add the directory `path`.
+    pub fn dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.nodes.push(Node::Dir(path.as_ref().to_path_buf()));
+        self
+    }
+
+    // Add the file `path` with `contents`.
+    pub fn file<P: AsRef<Path>, C: AsRef<[u8]>>(mut self, path: P, contents: C) -> Self {
+        let contents = contents.as_ref().to_vec();
+        self.nodes
+            .push(Node::File(path.as_ref().to_path_buf(), contents));
+        self
+    }
+
+    // Add the symlink `path` to `target`, which is written as is.
+    pub fn symlink<P: AsRef<Path>, T: AsRef<Path>>(mut self, path: P, target: T) -> Self {
+        let (path, target) = (path.as_ref().to_path_buf(), target.as_ref().to_path_buf());
+        self.nodes.push(Node::Symlink(path, target));
+        self
+    }
+
+    // Add a chain of symlinks, each of `links` pointing to the next one and the last one
+    // to `target`, e.g. for the tests of `resolve::MAX_SYMLINKS`.
+    pub fn symlink_chain<P: AsRef<Path>, T: AsRef<Path>>(mut self, links: &[P], target: T) -> Self {
+        for (i, link) in links.iter().enumerate() {
+            let next = match links.get(i + 1) {
+                Some(next) => Path::new("/").join(next),
+                None => target.as_ref().to_path_buf(),
+            };
+            self = self.symlink(link, next);
+        }
+        self
+    }
+
+    // Create the tree in a new temporary directory.
+    pub fn build(&self) -> Result<Tree> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().canonicalize()?;
+        self.build_in(&path)?;
+
+        Ok(Tree { path, _dir: dir })
+    }
+
+    // Create the tree in the existing directory `dir`.
+    pub fn build_in<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let dir = dir.as_ref();
+        let create_parent = |path: &Path| -> io::Result<()> {
+            match path.parent() {
+                Some(parent) => fs::create_dir_all(parent),
+                None => Ok(()),
+            }
+        };
+
+        for node in &self.nodes {
+            match node {
+                Node::Dir(p) => fs::create_dir_all(dir.join(p))?,
+                Node::File(p, contents) => {
+                    create_parent(&dir.join(p))?;
+                    fs::write(dir.join(p), contents)?;
+                }
+                Node::Symlink(p, target) => {
+                    create_parent(&dir.join(p))?;
+                    symlink(target, dir.join(p))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Tree {
+    // The canonicalized top directory of the tree.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // A `Root` on the top directory of the tree.
+    pub fn root(&self) -> Result<Root> {
+        Root::new(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_builder() {
+        let tree = TreeBuilder::new()
+            .dir("a/b")
+            .file("etc/passwd", "root:x:0:0::/root:/bin/sh\n")
+            .symlink("l", "/etc")
+            .symlink_chain(&["c/0", "c/1", "c/2"], "/etc/passwd")
+            .build()
+            .unwrap();
+
+        assert!(tree.path().join("a/b").is_dir());
+        assert_eq!(
+            fs::read_link(tree.path().join("c/1")).unwrap(),
+            Path::new("/c/2")
+        );
+
+        let root = tree.root().unwrap();
+        assert_eq!(
+            root.join("l/passwd").unwrap(),
+            tree.path().join("etc/passwd")
+        );
+        assert_eq!(root.join("c/0").unwrap(), tree.path().join("etc/passwd"));
+
+        let path = tree.path().to_path_buf();
+        drop(tree);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,246 @@
+// The steps of a resolution, reported by `Root::join_traced` and `Root::check_traced` so
+// that a review or an incident response can reconstruct how a path was derived, and summed
+// up in a single sentence by `Root::explain`. The inputs which had to be clamped or rejected
+// are reported as an `EscapeEvent` to the `RootOptions::on_escape_attempt` callback.
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::error::Result;
+
+// One step of a resolution, the paths are the host paths below the rootfs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    // A directory was entered.
+    Enter(PathBuf),
+    // A ".." went back up to the directory.
+    Parent(PathBuf),
+    // The symlink `link` was followed to `target`, as read from the link.
+    Symlink { link: PathBuf, target: PathBuf },
+    // A ".." climbing above the rootfs was clamped at it.
+    Clamp,
+    // A non-directory was found, the path ends with it unless a ".." follows.
+    File(PathBuf),
+    // The component doesn't exist, it and the components after it are joined as is.
+    Missing(PathBuf),
+    // The resolution ended on the fd of this file or directory, the last existing one of
+    // the result.
+    Pinned(PathBuf),
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Enter(p) => write!(f, "enter {:?}", p),
+            Step::Parent(p) => write!(f, "parent {:?}", p),
+            Step::Symlink { link, target } => write!(f, "symlink {:?} -> {:?}", link, target),
+            Step::Clamp => write!(f, "clamp \"..\" at the rootfs"),
+            Step::File(p) => write!(f, "file {:?}", p),
+            Step::Missing(p) => write!(f, "missing {:?}", p),
+            Step::Pinned(p) => write!(f, "pinned {:?}", p),
+        }
+    }
+}
+
+// What was done with an input trying to escape from the rootfs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeAction {
+    // A ".." climbing above the rootfs was clamped at it.
+    Clamped,
+    // A ".." climbing above the rootfs was rejected, by `Root::check`.
+    Rejected,
+    // A procfs magic link was refused, see `RootOptions::deny_magic_links`.
+    MagicLink,
+    // Too many symlinks were followed.
+    Loop,
+}
+
+impl fmt::Display for EscapeAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EscapeAction::Clamped => write!(f, "clamped"),
+            EscapeAction::Rejected => write!(f, "rejected"),
+            EscapeAction::MagicLink => write!(f, "magic-link"),
+            EscapeAction::Loop => write!(f, "loop"),
+        }
+    }
+}
+
+// An input which had to be clamped or rejected, passed to `RootOptions::on_escape_attempt`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscapeEvent<'a> {
+    // The rootfs of the resolution.
+    pub rootfs: &'a Path,
+    // The untrusted path as passed to the resolution.
+    pub path: &'a Path,
+    pub action: EscapeAction,
+}
+
+// The callback of `RootOptions::on_escape_attempt`, called inline by the resolution, so a
+// host IDS or agent can raise an alert without parsing the logs.
+#[derive(Clone)]
+pub struct EscapeHook(Arc<dyn Fn(&EscapeEvent) + Send + Sync>);
+
+impl EscapeHook {
+    pub fn new<F>(hook: F) -> Self
+    where
+        F: Fn(&EscapeEvent) + Send + Sync + 'static,
+    {
+        EscapeHook(Arc::new(hook))
+    }
+
+    pub(crate) fn call(&self, event: &EscapeEvent) {
+        (self.0)(event)
+    }
+}
+
+// A `Root` stays usable across catch_unwind(), like in the C API: a hook panicking leaves at
+// worst its own state inconsistent, which it can guard with a Mutex.
+impl std::panic::UnwindSafe for EscapeHook {}
+impl std::panic::RefUnwindSafe for EscapeHook {}
+
+impl fmt::Debug for EscapeHook {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EscapeHook")
+    }
+}
+
+// Explain the resolution of `unsafe_path` inside of `rootfs` from its `steps` and `result`:
+// the path it resolves to or why it is rejected, after the symlinks followed, the ".."
+// clamped and the first missing component.
+pub(crate) fn explain(
+    rootfs: &Path,
+    unsafe_path: &Path,
+    steps: &[Step],
+    result: &Result<PathBuf>,
+) -> String {
+    let in_root = |p: &Path| Path::new("/").join(p.strip_prefix(rootfs).unwrap_or(p));
+
+    // a symlink followed again, like in a loop, is explained once
+    let mut details: Vec<String> = Vec::new();
+    let mut push = |detail: String| {
+        if !details.contains(&detail) {
+            details.push(detail);
+        }
+    };
+    let mut clamped = 0;
+    let mut missing = false;
+    for step in steps {
+        if clamped > 0 && *step != Step::Clamp {
+            push(clamp(clamped));
+            clamped = 0;
+        }
+        match step {
+            Step::Symlink { link, target } if target.has_root() => push(format!(
+                "{:?} is a symlink to {:?} outside of the root, followed from the top of the root",
+                in_root(link),
+                target
+            )),
+            Step::Symlink { link, target } => {
+                push(format!("{:?} is a symlink to {:?}", in_root(link), target))
+            }
+            Step::Clamp => clamped += 1,
+            Step::Missing(p) if !missing => {
+                missing = true;
+                push(format!("{:?} doesn't exist", in_root(p)));
+            }
+            _ => {}
+        }
+    }
+    if clamped > 0 {
+        push(clamp(clamped));
+    }
+
+    let summary = match result {
+        Ok(p) => format!("{:?} resolves to {:?}", unsafe_path, in_root(p)),
+        Err(e) => format!("{:?} is rejected: {}", unsafe_path, e),
+    };
+    match details.is_empty() {
+        true => summary,
+        false => format!("{}, as {}", summary, details.join(", then ")),
+    }
+}
+
+fn clamp(n: usize) -> String {
+    match n {
+        1 => "a \"..\" climbing above the root is clamped at it".to_string(),
+        n => format!("{} \"..\" climbing above the root are clamped at it", n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::TreeBuilder;
+
+    #[test]
+    fn test_explain() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            path: &'a str,
+            strict: bool,
+            result: &'a str,
+        }
+
+        let tree = TreeBuilder::new()
+            .dir("etc")
+            .symlink("abs", "/etc")
+            .symlink("up", "../../etc")
+            .symlink("loop", "loop")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+
+        let tests = &[
+            TestData {
+                name: "plain path",
+                path: "etc",
+                strict: false,
+                result: r#""etc" resolves to "/etc""#,
+            },
+            TestData {
+                name: "missing path",
+                path: "etc/a/b",
+                strict: false,
+                result: r#""etc/a/b" resolves to "/etc/a/b", as "/etc/a" doesn't exist"#,
+            },
+            TestData {
+                name: "absolute symlink",
+                path: "abs/x",
+                strict: false,
+                result: r#""abs/x" resolves to "/etc/x", as "/abs" is a symlink to "/etc" outside of the root, followed from the top of the root, then "/etc/x" doesn't exist"#,
+            },
+            TestData {
+                name: "relative symlink clamped",
+                path: "up",
+                strict: false,
+                result: r#""up" resolves to "/etc", as "/up" is a symlink to "../../etc", then 2 ".." climbing above the root are clamped at it"#,
+            },
+            TestData {
+                name: "relative symlink rejected",
+                path: "up",
+                strict: true,
+                result: r#""up" is rejected: path escapes from the rootfs: up, as "/up" is a symlink to "../../etc""#,
+            },
+            TestData {
+                name: "symlink loop",
+                path: "loop",
+                strict: false,
+                result: r#""loop" is rejected: too many levels of symbolic links: loop, as "/loop" is a symlink to "loop""#,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = match t.strict {
+                true => root.explain_check(t.path),
+                false => root.explain(t.path),
+            };
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            assert!(result == t.result, "{}", msg);
+        }
+    }
+}
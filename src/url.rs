@@ -0,0 +1,182 @@
+// The path handling of a web server serving a document root: a raw URL path from a request
+// is percent-decoded strictly, optionally normalized and then resolved inside of the root.
+use std::path::PathBuf;
+
+use crate::error::{Error, Result};
+use crate::root::Root;
+
+// Options of `decode_path` and `join_url_path`.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    // Normalize the decoded path to Unicode NFC, so that differently composed names of the
+    // same file map to one path.
+    #[cfg(feature = "unicode-normalization")]
+    pub nfc: bool,
+}
+
+// Percent-decode the raw URL path `raw`, any query or fragment is dropped.
+//
+// The decoding is strict: a "%" must be followed by two hex digits, the decoded path must be
+// UTF-8 and a NUL, encoded or not, is rejected. So is double-encoding, a decoded "%" which
+// starts another escape like in "%252e%252e", as a second decoding further down would turn
+// it into "..". All of these fail with `Error::InvalidPath`.
+pub fn decode_path(raw: &str, options: &DecodeOptions) -> Result<String> {
+    let invalid = || Error::InvalidPath(PathBuf::from(raw));
+
+    let path = raw.split(['?', '#']).next().unwrap_or_default().as_bytes();
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let c = match path[i] {
+            b'%' => {
+                let c = path
+                    .get(i + 1..i + 3)
+                    .and_then(hex_byte)
+                    .ok_or_else(invalid)?;
+                if c == b'%' && path.get(i + 3..i + 5).and_then(hex_byte).is_some() {
+                    return Err(invalid());
+                }
+                i += 3;
+                c
+            }
+            c => {
+                i += 1;
+                c
+            }
+        };
+        if c == 0 {
+            return Err(invalid());
+        }
+        decoded.push(c);
+    }
+
+    let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+
+    #[cfg(feature = "unicode-normalization")]
+    if options.nfc {
+        use unicode_normalization::UnicodeNormalization;
+        return Ok(decoded.nfc().collect());
+    }
+    #[cfg(not(feature = "unicode-normalization"))]
+    let _ = options;
+
+    Ok(decoded)
+}
+
+// Decode the raw URL path `raw` with `decode_path` and resolve it inside of the document
+// root. A ".." climbing above the document root is rejected with `Error::Escape`, like
+// `Root::check` does, rather than clamped.
+pub fn join_url_path(root: &Root, raw: &str, options: &DecodeOptions) -> Result<PathBuf> {
+    root.check(decode_path(raw, options)?)
+}
+
+fn hex_byte(hex: &[u8]) -> Option<u8> {
+    let s = std::str::from_utf8(hex).ok()?;
+    if !s.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    u8::from_str_radix(s, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_join_url_path() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            raw: &'a str,
+            result: Option<&'a str>,
+        }
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+
+        let tests = &[
+            TestData {
+                name: "plain path",
+                raw: "/docs/index.html",
+                result: Some("docs/index.html"),
+            },
+            TestData {
+                name: "encoded space and query",
+                raw: "/a%20b.txt?x=%00#frag",
+                result: Some("a b.txt"),
+            },
+            TestData {
+                name: "encoded utf-8",
+                raw: "/caf%C3%A9",
+                result: Some("caf\u{e9}"),
+            },
+            TestData {
+                name: "encoded .. above the root",
+                raw: "/%2e%2e/%2E%2E/etc/passwd",
+                result: None,
+            },
+            TestData {
+                name: "encoded .. inside of the root",
+                raw: "/a/%2e%2e/b",
+                result: Some("b"),
+            },
+            TestData {
+                name: "encoded NUL",
+                raw: "/a%00.txt",
+                result: None,
+            },
+            TestData {
+                name: "double-encoding",
+                raw: "/%252e%252e/etc",
+                result: None,
+            },
+            TestData {
+                name: "encoded % not starting an escape",
+                raw: "/100%25",
+                result: Some("100%"),
+            },
+            TestData {
+                name: "truncated escape",
+                raw: "/a%2",
+                result: None,
+            },
+            TestData {
+                name: "invalid hex digits",
+                raw: "/a%+1",
+                result: None,
+            },
+            TestData {
+                name: "invalid utf-8",
+                raw: "/a%ff",
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = join_url_path(&root, t.raw, &DecodeOptions::default());
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some(p) => assert!(result.unwrap() == root.path().join(p), "{}", msg),
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+    }
+
+    #[cfg(feature = "unicode-normalization")]
+    #[test]
+    fn test_decode_path_nfc() {
+        let options = DecodeOptions { nfc: true };
+        assert_eq!(decode_path("/cafe%CC%81", &options).unwrap(), "/caf\u{e9}");
+        let options = DecodeOptions { nfc: false };
+        assert_eq!(
+            decode_path("/cafe%CC%81", &options).unwrap(),
+            "/cafe\u{301}"
+        );
+    }
+}
@@ -0,0 +1,410 @@
+// The filesystem the path based resolver of `resolve_in` works on. It only needs to tell
+// the type of an entry and read symlinks, so a test or a tool can resolve paths inside of an
+// in-memory tree, an image or a remote filesystem, like SecureJoinVFS of Go's securejoin.
+// `MockVfs` is such an in-memory tree, parsed from a text listing, so that fuzzers and
+// property tests can run `resolve_with` deterministically.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::Result;
+use crate::resolve::resolve_vfs;
+use crate::trace::Step;
+
+// The type of an entry, as returned by lstat().
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    Dir,
+    Symlink,
+    // Any other file.
+    File,
+}
+
+// A filesystem of absolute paths. The errors of a missing entry must have the kind
+// `io::ErrorKind::NotFound`.
+pub trait Vfs {
+    // The type of `path`, without following a trailing symlink.
+    fn lstat(&self, path: &Path) -> io::Result<FileKind>;
+
+    // The target of the symlink `path`.
+    fn readlink(&self, path: &Path) -> io::Result<PathBuf>;
+
+    // Whether `path` exists, a dangling symlink does.
+    fn exists(&self, path: &Path) -> bool {
+        self.lstat(path).is_ok()
+    }
+}
+
+// The real filesystem, through std's path based calls.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsVfs;
+
+impl Vfs for OsVfs {
+    fn lstat(&self, path: &Path) -> io::Result<FileKind> {
+        let file_type = fs::symlink_metadata(path)?.file_type();
+        if file_type.is_dir() {
+            Ok(FileKind::Dir)
+        } else if file_type.is_symlink() {
+            Ok(FileKind::Symlink)
+        } else {
+            Ok(FileKind::File)
+        }
+    }
+
+    fn readlink(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+}
+
+// Resolve `unsafe_path` inside of `rootfs` on `vfs`, like `Root::join`. The rootfs is used as
+// is, it's not canonicalized.
+pub fn resolve_in<V, P, Q>(vfs: &V, rootfs: P, unsafe_path: Q) -> Result<PathBuf>
+where
+    V: Vfs + ?Sized,
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    resolve_vfs(
+        vfs,
+        rootfs.as_ref(),
+        unsafe_path.as_ref(),
+        false,
+        &mut |_| {},
+    )
+    .map(|(p, _)| p)
+}
+
+// A resolution by `resolve_with`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    pub path: PathBuf,
+    // The number of symlinks followed.
+    pub symlinks: u32,
+    pub steps: Vec<Step>,
+}
+
+// Resolve `unsafe_path` inside of `root` on `vfs` like `resolve_in`, also returns the steps
+// taken. Nothing but `vfs` is accessed, so the result only depends on the arguments with a
+// `MockVfs`.
+pub fn resolve_with<V, P, Q>(vfs: &V, root: P, unsafe_path: Q) -> Result<Resolution>
+where
+    V: Vfs + ?Sized,
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut steps = Vec::new();
+    let (path, symlinks) =
+        resolve_vfs(vfs, root.as_ref(), unsafe_path.as_ref(), false, &mut |s| {
+            steps.push(s)
+        })?;
+
+    Ok(Resolution {
+        path,
+        symlinks,
+        steps,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MockEntry {
+    Dir,
+    File,
+    Symlink(PathBuf),
+}
+
+// An in-memory tree of absolute paths. The parents of every entry exist as directories.
+//
+// It's parsed from and displayed as one entry per line: a directory ends with "/", a
+// symlink is written "<path> -> <target>", anything else is a file. Empty lines and the
+// lines starting with "#" are skipped, a relative path is taken as absolute, so any input
+// parses into a tree.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MockVfs {
+    entries: BTreeMap<PathBuf, MockEntry>,
+}
+
+impl MockVfs {
+    pub fn new() -> Self {
+        MockVfs::default()
+    }
+
+    // Parse the listing `s`, for a fuzzer e.g. from `String::from_utf8_lossy` of its input.
+    pub fn parse(s: &str) -> MockVfs {
+        let mut vfs = MockVfs::new();
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once(" -> ") {
+                Some((link, target)) => vfs.add_symlink(link, target),
+                None if line.ends_with('/') => vfs.add_dir(line),
+                None => vfs.add_file(line),
+            }
+        }
+        vfs
+    }
+
+    pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) {
+        self.add(path.as_ref(), MockEntry::Dir);
+    }
+
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) {
+        self.add(path.as_ref(), MockEntry::File);
+    }
+
+    pub fn add_symlink<P: AsRef<Path>, T: AsRef<Path>>(&mut self, path: P, target: T) {
+        let target = target.as_ref().to_path_buf();
+        self.add(path.as_ref(), MockEntry::Symlink(target));
+    }
+
+    // Add the entry with its missing parents, replacing an existing one.
+    fn add(&mut self, path: &Path, entry: MockEntry) {
+        let path = normalize(path);
+        for parent in path.ancestors().skip(1) {
+            self.entries
+                .entry(parent.to_path_buf())
+                .or_insert(MockEntry::Dir);
+        }
+        self.entries.insert(path, entry);
+    }
+}
+
+// Make `path` absolute, without "." and trailing "/".
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for it in path.components() {
+        match it {
+            Component::Normal(c) => normalized.push(c),
+            Component::ParentDir => normalized.push(".."),
+            _ => continue,
+        }
+    }
+    normalized
+}
+
+impl Vfs for MockVfs {
+    fn lstat(&self, path: &Path) -> io::Result<FileKind> {
+        match self.entries.get(&normalize(path)) {
+            Some(MockEntry::Dir) => Ok(FileKind::Dir),
+            Some(MockEntry::File) => Ok(FileKind::File),
+            Some(MockEntry::Symlink(_)) => Ok(FileKind::Symlink),
+            None if path == Path::new("/") => Ok(FileKind::Dir),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn readlink(&self, path: &Path) -> io::Result<PathBuf> {
+        match self.entries.get(&normalize(path)) {
+            Some(MockEntry::Symlink(target)) => Ok(target.clone()),
+            Some(_) => Err(io::ErrorKind::InvalidInput.into()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+impl fmt::Display for MockVfs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (path, entry) in &self.entries {
+            match entry {
+                MockEntry::Dir if path == Path::new("/") => continue,
+                MockEntry::Dir => writeln!(f, "{}/", path.display())?,
+                MockEntry::File => writeln!(f, "{}", path.display())?,
+                MockEntry::Symlink(target) => {
+                    writeln!(f, "{} -> {}", path.display(), target.display())?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    // The directories are implied by the entries below them.
+    struct MapVfs(HashMap<&'static str, Option<&'static str>>);
+
+    impl Vfs for MapVfs {
+        fn lstat(&self, path: &Path) -> io::Result<FileKind> {
+            let path = path.to_str().unwrap();
+            match self.0.get(path) {
+                Some(Some(_)) => Ok(FileKind::Symlink),
+                Some(None) => Ok(FileKind::File),
+                None if self.0.keys().any(|k| k.starts_with(&format!("{}/", path))) => {
+                    Ok(FileKind::Dir)
+                }
+                None => Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+
+        fn readlink(&self, path: &Path) -> io::Result<PathBuf> {
+            match self.0.get(path.to_str().unwrap()) {
+                Some(Some(target)) => Ok(PathBuf::from(target)),
+                _ => Err(io::ErrorKind::InvalidInput.into()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve_in() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            unsafe_path: &'a str,
+            result: Option<&'a str>,
+        }
+
+        let vfs = MapVfs(
+            [
+                ("/root/etc/passwd", None),
+                ("/root/abs", Some("/etc")),
+                ("/root/rel", Some("../../../etc")),
+                ("/root/loop", Some("loop")),
+                ("/etc/passwd", None),
+            ]
+            .iter()
+            .copied()
+            .collect(),
+        );
+
+        let tests = &[
+            TestData {
+                name: "plain path",
+                unsafe_path: "etc/passwd",
+                result: Some("/root/etc/passwd"),
+            },
+            TestData {
+                name: "absolute symlink",
+                unsafe_path: "abs/passwd",
+                result: Some("/root/etc/passwd"),
+            },
+            TestData {
+                name: "relative symlink beyond the rootfs",
+                unsafe_path: "rel/passwd",
+                result: Some("/root/etc/passwd"),
+            },
+            TestData {
+                name: "missing path",
+                unsafe_path: "a/../../b",
+                result: Some("/root/b"),
+            },
+            TestData {
+                name: "below a file",
+                unsafe_path: "etc/passwd/x",
+                result: None,
+            },
+            TestData {
+                name: "symlink loop",
+                unsafe_path: "loop",
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = resolve_in(&vfs, "/root", t.unsafe_path);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some(p) => assert!(result.unwrap() == Path::new(p), "{}", msg),
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+        assert!(vfs.exists(Path::new("/root/loop")));
+
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let rootfs = rootfs_dir.path().canonicalize().unwrap();
+        symlink("/etc", rootfs.join("l")).unwrap();
+        let result = resolve_in(&OsVfs, &rootfs, "l/../../x").unwrap();
+        assert_eq!(result, rootfs.join("x"));
+    }
+
+    #[test]
+    fn test_resolve_with() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            unsafe_path: &'a str,
+            result: Option<(&'a str, u32)>,
+        }
+
+        let listing = "\
+            # a rootfs below /root\n\
+            /root/etc/passwd\n\
+            /root/abs -> /etc\n\
+            /root/rel -> ../../../etc\n\
+            /root/chain -> abs\n\
+            /root/loop -> loop\n\
+            /root/tmp/\n";
+        let vfs = MockVfs::parse(listing);
+
+        let tests = &[
+            TestData {
+                name: "plain path",
+                unsafe_path: "etc/passwd",
+                result: Some(("/root/etc/passwd", 0)),
+            },
+            TestData {
+                name: "chained symlinks",
+                unsafe_path: "chain/passwd",
+                result: Some(("/root/etc/passwd", 2)),
+            },
+            TestData {
+                name: "relative symlink beyond the rootfs",
+                unsafe_path: "rel/../tmp",
+                result: Some(("/root/tmp", 1)),
+            },
+            TestData {
+                name: "symlink loop",
+                unsafe_path: "loop",
+                result: None,
+            },
+            TestData {
+                name: "below a file",
+                unsafe_path: "etc/passwd/x",
+                result: None,
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+
+            let result = resolve_with(&vfs, "/root", t.unsafe_path);
+
+            let msg = format!("{}, result: {:?}", msg, result);
+
+            match t.result {
+                Some((p, symlinks)) => {
+                    let result = result.unwrap();
+                    assert!(result.path == Path::new(p), "{}", msg);
+                    assert!(result.symlinks == symlinks, "{}", msg);
+                    assert!(
+                        result.steps.last() == Some(&Step::Pinned(PathBuf::from(p))),
+                        "{}",
+                        msg
+                    );
+                }
+                None => assert!(result.is_err(), "{}", msg),
+            }
+        }
+
+        // the listing is displayed in a canonical order and parses back into the same tree
+        let displayed = vfs.to_string();
+        assert!(
+            displayed.starts_with("/root/\n/root/abs -> /etc\n"),
+            "{}",
+            displayed
+        );
+        assert_eq!(MockVfs::parse(&displayed), vfs);
+        let garbage = MockVfs::parse("x -> \n\0/\n../..\n -> -> \n");
+        let _ = resolve_with(&garbage, "/", "x/../../..");
+    }
+}
@@ -0,0 +1,461 @@
+// A recursive directory walker confined to a rootfs, see `Root::walk`.
+use std::collections::HashSet;
+use std::fs::{self, FileType, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::error::{Error, Result};
+use crate::root::Root;
+use crate::sys::WalkDir;
+
+// An entry yielded by `Walk`.
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    path: PathBuf,
+    file_type: FileType,
+    depth: usize,
+    metadata: Metadata,
+}
+
+impl WalkEntry {
+    // The path of the entry relative to the rootfs.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The type of the entry, symlinks are reported as such and never followed.
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    // The depth of the entry, the children of the starting directory have depth 1.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    // The metadata of the entry, read relative to its directory without following it.
+    pub(crate) fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+// The disk usage of a subtree of a rootfs, see `Root::disk_usage`. A file with several
+// hardlinks in the subtree is counted once, like du does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    // The sum of the sizes of the entries, as ls reports them.
+    pub apparent_size: u64,
+    // The blocks allocated to the entries, in 512-byte units like st_blocks.
+    pub blocks: u64,
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    // The devices, fifos and sockets.
+    pub others: u64,
+}
+
+impl DiskUsage {
+    // The bytes allocated to the entries.
+    pub fn allocated_size(&self) -> u64 {
+        self.blocks.saturating_mul(512)
+    }
+
+    fn add(&mut self, m: &Metadata, seen: &mut HashSet<(u64, u64)>) {
+        if !m.is_dir() && m.nlink() > 1 && !seen.insert((m.dev(), m.ino())) {
+            return;
+        }
+
+        self.apparent_size = self.apparent_size.saturating_add(m.len());
+        self.blocks = self.blocks.saturating_add(m.blocks());
+        let file_type = m.file_type();
+        match () {
+            _ if file_type.is_file() => self.files += 1,
+            _ if file_type.is_dir() => self.dirs += 1,
+            _ if file_type.is_symlink() => self.symlinks += 1,
+            _ => self.others += 1,
+        }
+    }
+}
+
+// The disk usage of `path` and of its subtree, `path` being resolved and relative to the
+// rootfs.
+pub(crate) fn disk_usage(root: &Root, path: &Path) -> Result<DiskUsage> {
+    let mut usage = DiskUsage::default();
+    let mut seen = HashSet::new();
+    let m = match path.file_name() {
+        Some(name) => {
+            open_dir(root, path.parent().unwrap_or(Path::new("")))?.child_metadata(name)?
+        }
+        None => open_dir(root, path)?.metadata()?,
+    };
+    usage.add(&m, &mut seen);
+
+    if m.is_dir() {
+        for entry in Walk::new_in(root, path, 0) {
+            usage.add(entry?.metadata(), &mut seen);
+        }
+    }
+
+    Ok(usage)
+}
+
+// Options of `Root::find_with`.
+#[derive(Debug, Clone, Default)]
+pub struct FindOptions {
+    // Don't look at entries deeper than `max_depth`, counted from the rootfs.
+    pub max_depth: Option<usize>,
+    // Descend into the symlinks to directories, if they resolve inside of the rootfs
+    // without `Error::Escape`. The entries below them are reported through the symlink,
+    // each directory being visited once.
+    pub follow_symlinks: bool,
+}
+
+// The paths relative to the rootfs of the entries below `dir` for which `predicate` returns
+// true, `dir` being resolved and relative to the rootfs.
+pub(crate) fn find(
+    root: &Root,
+    dir: &Path,
+    options: &FindOptions,
+    predicate: &mut dyn FnMut(&WalkEntry) -> bool,
+) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut visited = HashSet::new();
+    let m = fs::metadata(root.path().join(dir))?;
+    visited.insert((m.dev(), m.ino()));
+
+    let mut dirs = vec![(dir.to_path_buf(), dir.to_path_buf(), 0)];
+    while let Some((resolved, shown, depth)) = dirs.pop() {
+        let mut walk = Walk::new_in(root, &resolved, depth);
+        walk.max_depth = options.max_depth;
+
+        for entry in walk {
+            let mut entry = entry?;
+            if let Ok(p) = entry.path.strip_prefix(&resolved) {
+                entry.path = shown.join(p);
+            }
+            if predicate(&entry) {
+                found.push(entry.path.clone());
+            }
+
+            let deeper = options.max_depth.is_none_or(|d| entry.depth < d);
+            if !options.follow_symlinks || !entry.file_type.is_symlink() || !deeper {
+                continue;
+            }
+            // a symlink escaping or to nothing is only reported
+            let Ok(target) = root.check(&entry.path) else {
+                continue;
+            };
+            match fs::metadata(&target) {
+                Ok(m) if m.is_dir() && visited.insert((m.dev(), m.ino())) => {
+                    let target = target.strip_prefix(root.path()).unwrap_or(Path::new(""));
+                    dirs.push((target.to_path_buf(), entry.path.clone(), entry.depth));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+// Open the directory `dir`, resolved and relative to the rootfs, one component at a time from
+// the rootfs without following any symlink.
+pub(crate) fn open_dir(root: &Root, dir: &Path) -> Result<WalkDir> {
+    let mut opened = WalkDir::open(root.path())?;
+    for it in dir.components() {
+        match it {
+            Component::Normal(c) => opened = opened.open_child(c)?,
+            Component::RootDir | Component::CurDir => continue,
+            _ => return Err(Error::Escape(dir.to_path_buf())),
+        }
+    }
+
+    Ok(opened)
+}
+
+type Prune<'a> = Box<dyn FnMut(&WalkEntry) -> bool + 'a>;
+
+// Walk is an iterator over the subtree of a rootfs. Entries are yielded in depth first
+// order with the children of a directory sorted by name. Symlinks are yielded but never
+// followed, so the walk can't leave the rootfs: each directory is opened relative to its
+// parent's fd without following it, even if it was swapped for a symlink meanwhile.
+pub struct Walk<'a> {
+    root: &'a Root,
+    // The entries left to yield, with the directory they are in.
+    stack: Vec<(Rc<WalkDir>, WalkEntry)>,
+    // The directory to read next, with its parent, which is None for the starting one.
+    expand: Option<(Option<Rc<WalkDir>>, PathBuf, usize)>,
+    max_depth: Option<usize>,
+    prune: Option<Prune<'a>>,
+}
+
+impl<'a> Walk<'a> {
+    pub(crate) fn new(root: &'a Root) -> Self {
+        Walk::new_in(root, Path::new(""), 0)
+    }
+
+    // Walk the subtree of the directory `dir`, resolved and relative to the rootfs, its
+    // children having the depth `depth` + 1.
+    pub(crate) fn new_in(root: &'a Root, dir: &Path, depth: usize) -> Self {
+        Walk {
+            root,
+            stack: Vec::new(),
+            expand: Some((None, dir.to_path_buf(), depth)),
+            max_depth: None,
+            prune: None,
+        }
+    }
+
+    // Don't yield entries deeper than `depth`.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    // Skip the entries for which `prune` returns true, together with their subtree.
+    pub fn prune<F>(mut self, prune: F) -> Self
+    where
+        F: FnMut(&WalkEntry) -> bool + 'a,
+    {
+        self.prune = Some(Box::new(prune));
+        self
+    }
+
+    fn read_dir(&mut self, parent: Option<Rc<WalkDir>>, dir: &Path, depth: usize) -> Result<()> {
+        let opened = match (parent, dir.file_name()) {
+            (Some(parent), Some(name)) => parent.open_child(name)?,
+            _ => open_dir(self.root, dir)?,
+        };
+        let mut entries = Vec::new();
+
+        for (name, metadata) in opened.entries()? {
+            let file_type = metadata.file_type();
+            if file_type.is_char_device()
+                && self.root.options().overlay_whiteouts
+                && metadata.rdev() == 0
+            {
+                continue;
+            }
+            entries.push(WalkEntry {
+                path: dir.join(name),
+                file_type,
+                depth: depth + 1,
+                metadata,
+            });
+        }
+        entries.sort_by(|a, b| b.path.cmp(&a.path));
+        let opened = Rc::new(opened);
+        self.stack
+            .extend(entries.into_iter().map(|e| (opened.clone(), e)));
+
+        Ok(())
+    }
+}
+
+impl Iterator for Walk<'_> {
+    type Item = Result<WalkEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some((parent, dir, depth)) = self.expand.take() {
+            if let Err(e) = self.read_dir(parent, &dir, depth) {
+                return Some(Err(e));
+            }
+        }
+
+        loop {
+            let (parent, entry) = self.stack.pop()?;
+            if let Some(prune) = self.prune.as_mut() {
+                if prune(&entry) {
+                    continue;
+                }
+            }
+
+            if entry.file_type.is_dir() && self.max_depth.is_none_or(|d| entry.depth < d) {
+                self.expand = Some((Some(parent), entry.path.clone(), entry.depth));
+            }
+
+            return Some(Ok(entry));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TreeBuilder;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_walk() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        let outside_dir = tempdir().expect("failed to create tmpdir");
+        fs::write(outside_dir.path().join("secret"), b"").unwrap();
+
+        root.mkdir_all("a/b/c").unwrap();
+        root.mkdir_all("d").unwrap();
+        fs::write(root.path().join("a/b/f"), b"").unwrap();
+        symlink(outside_dir.path(), root.path().join("a/out")).unwrap();
+        symlink("/", root.path().join("d/root")).unwrap();
+
+        let paths = |walk: Walk| {
+            walk.map(|e| e.unwrap().path().to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            paths(root.walk()),
+            vec!["a", "a/b", "a/b/c", "a/b/f", "a/out", "d", "d/root"]
+        );
+        assert_eq!(paths(root.walk().max_depth(1)), vec!["a", "d"]);
+        assert_eq!(
+            paths(root.walk().prune(|e| e.path() == Path::new("a/b"))),
+            vec!["a", "a/out", "d", "d/root"]
+        );
+
+        let link = root
+            .walk()
+            .find(|e| e.as_ref().unwrap().depth() == 2)
+            .unwrap();
+        assert!(link.unwrap().file_type().is_dir());
+        let link = root
+            .walk()
+            .map(|e| e.unwrap())
+            .find(|e| e.path() == Path::new("a/out"))
+            .unwrap();
+        assert!(link.file_type().is_symlink());
+
+        assert_eq!(
+            paths(root.walk_in("d/root/a/../a/b").unwrap()),
+            vec!["a/b/c", "a/b/f"]
+        );
+        assert_eq!(
+            root.walk_in("a/b")
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap()
+                .depth(),
+            1
+        );
+        assert!(root.walk_in("a/b/f").is_err());
+        assert!(root.walk_in("a/missing").is_err());
+
+        // a directory swapped for a symlink before it is read
+        let mut walk = root.walk();
+        let first = walk.next().unwrap().unwrap();
+        assert_eq!(first.path(), Path::new("a"));
+        fs::rename(root.path().join("a"), root.path().join("moved")).unwrap();
+        symlink(outside_dir.path(), root.path().join("a")).unwrap();
+        let rest: Vec<_> = walk.collect();
+        assert!(rest.first().is_some_and(|e| e.is_err()));
+        assert!(!rest
+            .iter()
+            .any(|e| e.as_ref().is_ok_and(|e| e.path() == Path::new("a/secret"))));
+    }
+
+    #[test]
+    fn test_find() {
+        #[derive(Debug)]
+        struct TestData<'a> {
+            name: &'a str,
+            max_depth: Option<usize>,
+            follow_symlinks: bool,
+            result: &'a [&'a str],
+        }
+
+        let tree = TreeBuilder::new()
+            .file("etc/app.conf", "")
+            .file("opt/app/etc/app.conf", "")
+            .file("opt/app/etc/other", "")
+            .symlink("srv/app", "/opt/app")
+            .symlink("srv/loop", "..")
+            .symlink("srv/out", "../../..")
+            .symlink("srv/conf", "/etc/app.conf")
+            .build()
+            .unwrap();
+        let root = tree.root().unwrap();
+
+        let tests = &[
+            TestData {
+                name: "no symlink followed",
+                max_depth: None,
+                follow_symlinks: false,
+                result: &["etc/app.conf", "opt/app/etc/app.conf"],
+            },
+            TestData {
+                name: "max depth",
+                max_depth: Some(2),
+                follow_symlinks: false,
+                result: &["etc/app.conf"],
+            },
+            TestData {
+                name: "symlinks inside of the rootfs followed once",
+                max_depth: None,
+                follow_symlinks: true,
+                result: &[
+                    "etc/app.conf",
+                    "opt/app/etc/app.conf",
+                    "srv/app/etc/app.conf",
+                ],
+            },
+            TestData {
+                name: "max depth below a symlink",
+                max_depth: Some(3),
+                follow_symlinks: true,
+                result: &["etc/app.conf"],
+            },
+        ];
+
+        for (i, t) in tests.iter().enumerate() {
+            let msg = format!("test[{}] {}: {:?}", i, t.name, t);
+            let options = FindOptions {
+                max_depth: t.max_depth,
+                follow_symlinks: t.follow_symlinks,
+            };
+
+            let result = root.find_with(&options, |e| e.path().ends_with("etc/app.conf"));
+
+            let msg = format!("{}, result: {:?}", msg, result);
+            let result = result.unwrap();
+            assert!(
+                result == t.result.iter().map(PathBuf::from).collect::<Vec<_>>(),
+                "{}",
+                msg
+            );
+        }
+
+        let result = root.find(|e| e.file_type().is_symlink()).unwrap();
+        assert_eq!(result.len(), 4);
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let tree = TreeBuilder::new()
+            .file("a/f", vec![0; 5000])
+            .file("a/b/g", "12345")
+            .symlink("a/out", "/../../etc")
+            .symlink("a/up", "..")
+            .dir("c")
+            .build()
+            .unwrap();
+        fs::hard_link(tree.path().join("a/f"), tree.path().join("a/b/h")).unwrap();
+        let root = tree.root().unwrap();
+
+        let usage = root.disk_usage("a").unwrap();
+        assert_eq!((usage.files, usage.dirs, usage.symlinks), (2, 2, 2));
+        let links = "/../../etc".len() + "..".len();
+        let dirs = fs::metadata(tree.path().join("a")).unwrap().len()
+            + fs::metadata(tree.path().join("a/b")).unwrap().len();
+        assert_eq!(usage.apparent_size, 5005 + (links as u64) + dirs);
+        assert!(usage.allocated_size() >= 4096 || usage.blocks == 0);
+
+        let usage = root.disk_usage("l/../a/b/g").unwrap();
+        assert_eq!(usage.apparent_size, 5);
+        assert_eq!(usage.files, 1);
+        assert_eq!(root.disk_usage("/").unwrap().dirs, 4);
+        assert!(root.disk_usage("missing").is_err());
+    }
+}
@@ -0,0 +1,251 @@
+// Filesystem watches on paths resolved inside of a rootfs.
+//
+// The watched paths are untrusted, so they are resolved inside of the rootfs before the
+// watch is set up. Whenever something is renamed or moved, every watched path is resolved
+// again and its watch is moved if it now resolves to another inode, so swapping a watched
+// directory for a symlink can't redirect the watch outside of the rootfs. A watched path
+// which disappeared is watched again once a later rename brings it back. The paths of the
+// events are reported relative to the rootfs.
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use notify::event::{EventKind, ModifyKind};
+use notify::{Config, Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::error::{Error, Result};
+use crate::root::Root;
+
+// An event on the watched paths, the paths are relative to the rootfs.
+#[derive(Debug, Clone)]
+pub struct WatchEvent {
+    pub kind: EventKind,
+    pub paths: Vec<PathBuf>,
+}
+
+struct Watch {
+    unsafe_path: PathBuf,
+    path: PathBuf,
+    inode: Option<(u64, u64)>,
+    mode: RecursiveMode,
+}
+
+fn inode(path: &Path) -> Option<(u64, u64)> {
+    fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+// RootWatcher delivers the events on paths watched inside of the rootfs.
+pub struct RootWatcher {
+    root: Root,
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    watches: Vec<Watch>,
+}
+
+fn notify_error(e: notify::Error) -> Error {
+    match e.kind {
+        notify::ErrorKind::Io(e) => Error::Io(e),
+        _ => Error::Io(io::Error::other(e)),
+    }
+}
+
+impl RootWatcher {
+    pub fn new(root: &Root) -> Result<RootWatcher> {
+        let (tx, events) = channel();
+        // a recursive watch mustn't descend into the symlinks, e.g. "etc -> /etc" of the host
+        let config = Config::default().with_follow_symlinks(false);
+        let watcher = RecommendedWatcher::new(tx, config).map_err(notify_error)?;
+
+        Ok(RootWatcher {
+            root: root.clone(),
+            watcher,
+            events,
+            watches: Vec::new(),
+        })
+    }
+
+    // Watch `unsafe_path` inside of the rootfs, and its subtree if `recursive` is set.
+    pub fn watch<P: AsRef<Path>>(&mut self, unsafe_path: P, recursive: bool) -> Result<()> {
+        let unsafe_path = unsafe_path.as_ref().to_path_buf();
+        let path = self.root.join(&unsafe_path)?;
+        let mode = if recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+
+        self.watcher.watch(&path, mode).map_err(notify_error)?;
+        self.watches.push(Watch {
+            unsafe_path,
+            inode: inode(&path),
+            path,
+            mode,
+        });
+
+        Ok(())
+    }
+
+    // Stop watching `unsafe_path`.
+    pub fn unwatch<P: AsRef<Path>>(&mut self, unsafe_path: P) -> Result<()> {
+        let unsafe_path = unsafe_path.as_ref();
+
+        if let Some(i) = self
+            .watches
+            .iter()
+            .position(|w| w.unsafe_path == unsafe_path)
+        {
+            let w = self.watches.remove(i);
+            self.watcher.unwatch(&w.path).map_err(notify_error)?;
+        }
+
+        Ok(())
+    }
+
+    // Wait for the next event.
+    pub fn recv(&mut self) -> Result<WatchEvent> {
+        loop {
+            let event = self
+                .events
+                .recv()
+                .map_err(|e| Error::Io(io::Error::other(e)))?;
+            if let Some(event) = self.handle(event)? {
+                return Ok(event);
+            }
+        }
+    }
+
+    // Wait for the next event for at most `timeout`, returns None if none came.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<WatchEvent>> {
+        loop {
+            let event = match self.events.recv_timeout(timeout) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(e) => return Err(Error::Io(io::Error::other(e))),
+            };
+            if let Some(event) = self.handle(event)? {
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    fn handle(&mut self, event: notify::Result<Event>) -> Result<Option<WatchEvent>> {
+        let event = event.map_err(notify_error)?;
+
+        if let EventKind::Modify(ModifyKind::Name(_)) = event.kind {
+            self.revalidate()?;
+        }
+
+        let paths = event
+            .paths
+            .iter()
+            .filter_map(|p| p.strip_prefix(self.root.path()).ok())
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        if paths.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(WatchEvent {
+            kind: event.kind,
+            paths,
+        }))
+    }
+
+    // Resolve all the watched paths again and move the watches which now resolve elsewhere.
+    fn revalidate(&mut self) -> Result<()> {
+        for w in self.watches.iter_mut() {
+            let path = self.root.join(&w.unsafe_path)?;
+            let ino = inode(&path);
+            if path == w.path && ino == w.inode {
+                continue;
+            }
+
+            // the old watch may already be gone together with the renamed inode
+            if w.inode.is_some() {
+                let _ = self.watcher.unwatch(&w.path);
+            }
+            w.path = path;
+            w.inode = ino;
+            if w.inode.is_some() {
+                self.watcher.watch(&w.path, w.mode).map_err(notify_error)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_root_watcher() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        root.mkdir_all("data").unwrap();
+        root.mkdir_all("real").unwrap();
+
+        let mut watcher = RootWatcher::new(&root).unwrap();
+        watcher.watch("/../data", false).unwrap();
+        watcher.watch("/", false).unwrap();
+
+        let wait_for = |watcher: &mut RootWatcher, p: &str| loop {
+            let event = watcher
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap()
+                .expect("timed out waiting for an event");
+            if event.paths.iter().any(|it| it == Path::new(p)) {
+                break;
+            }
+        };
+
+        fs::write(root.path().join("data/a"), b"").unwrap();
+        wait_for(&mut watcher, "data/a");
+
+        // swap the watched directory for a symlink climbing out of the rootfs
+        fs::rename(root.path().join("data"), root.path().join("old")).unwrap();
+        wait_for(&mut watcher, "old");
+        symlink("../../../../real", root.path().join("tmp")).unwrap();
+        fs::rename(root.path().join("tmp"), root.path().join("data")).unwrap();
+        wait_for(&mut watcher, "data");
+
+        fs::write(root.path().join("real/c"), b"").unwrap();
+        wait_for(&mut watcher, "real/c");
+    }
+
+    #[test]
+    fn test_root_watcher_symlinks() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path()).unwrap();
+        let host_dir = tempdir().expect("failed to create tmpdir");
+        root.mkdir_all("data").unwrap();
+        symlink(host_dir.path(), root.path().join("etc")).unwrap();
+
+        let mut watcher = RootWatcher::new(&root).unwrap();
+        watcher.watch("/", true).unwrap();
+
+        // the events are delivered in order, the one of the host would come first
+        fs::write(host_dir.path().join("passwd"), b"").unwrap();
+        fs::write(root.path().join("data/a"), b"").unwrap();
+        loop {
+            let event = watcher
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap()
+                .expect("timed out waiting for an event");
+            assert!(
+                !event.paths.iter().any(|p| p.starts_with("etc")),
+                "{:?}",
+                event
+            );
+            if event.paths.iter().any(|p| p == Path::new("data/a")) {
+                break;
+            }
+        }
+    }
+}
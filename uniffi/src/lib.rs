@@ -0,0 +1,169 @@
+/*!
+# Secure Path UniFFI bindings
+
+UniFFI scaffolding exposing the resolver to Kotlin and Swift. Build the library, then
+generate the sources of the bindings from it:
+
+```sh
+cargo build -p secure-path-uniffi --release
+cargo run -p secure-path-uniffi --features secure-path-uniffi/bindgen --bin uniffi-bindgen -- \
+    generate --library target/release/libsecure_path_uniffi.so --language kotlin --out-dir out
+```
+
+Paths cross the FFI boundary as strings, a path which isn't valid UTF-8 is reported as
+`SecurePathError::InvalidPath`.
+*/
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use secure_path::error::Error;
+
+uniffi::setup_scaffolding!();
+
+// The errors of the bindings, mirroring `secure_path::error::Error`.
+#[derive(Debug, uniffi::Error)]
+pub enum SecurePathError {
+    Io { message: String },
+    InvalidPath { path: String },
+    Escape { path: String },
+    TooManySymlinks { path: String },
+    CrossMount { path: String },
+    Denied { path: String },
+    Race { path: String },
+    Dotfile { path: String },
+    LimitExceeded { limit: String, path: String },
+}
+
+impl fmt::Display for SecurePathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SecurePathError::Io { message } => write!(f, "{}", message),
+            SecurePathError::InvalidPath { path } => write!(f, "invalid path: {}", path),
+            SecurePathError::Escape { path } => {
+                write!(f, "path escapes from the rootfs: {}", path)
+            }
+            SecurePathError::TooManySymlinks { path } => {
+                write!(f, "too many levels of symbolic links: {}", path)
+            }
+            SecurePathError::CrossMount { path } => {
+                write!(f, "path crosses into another mount: {}", path)
+            }
+            SecurePathError::Denied { path } => write!(f, "path denied by policy: {}", path),
+            SecurePathError::Race { path } => {
+                write!(f, "path changed during the resolution: {}", path)
+            }
+            SecurePathError::Dotfile { path } => {
+                write!(f, "path has a dotfile component: {}", path)
+            }
+            SecurePathError::LimitExceeded { limit, path } => {
+                write!(f, "extraction limit exceeded, {}: {}", limit, path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SecurePathError {}
+
+impl From<Error> for SecurePathError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::Io(e) => SecurePathError::Io {
+                message: e.to_string(),
+            },
+            Error::InvalidPath(p) => SecurePathError::InvalidPath {
+                path: p.display().to_string(),
+            },
+            Error::Escape(p) => SecurePathError::Escape {
+                path: p.display().to_string(),
+            },
+            Error::TooManySymlinks(p) => SecurePathError::TooManySymlinks {
+                path: p.display().to_string(),
+            },
+            Error::CrossMount(p) => SecurePathError::CrossMount {
+                path: p.display().to_string(),
+            },
+            Error::Denied(p) => SecurePathError::Denied {
+                path: p.display().to_string(),
+            },
+            Error::Race(p) => SecurePathError::Race {
+                path: p.display().to_string(),
+            },
+            Error::Dotfile(p) => SecurePathError::Dotfile {
+                path: p.display().to_string(),
+            },
+            Error::LimitExceeded(limit, p) => SecurePathError::LimitExceeded {
+                limit: limit.to_string(),
+                path: p.display().to_string(),
+            },
+        }
+    }
+}
+
+fn string(p: &Path) -> Result<String, SecurePathError> {
+    p.to_str()
+        .map(str::to_string)
+        .ok_or_else(|| SecurePathError::InvalidPath {
+            path: p.display().to_string(),
+        })
+}
+
+// Resolve `unsafe_path` inside of `rootfs`, see `secure_path::secure_join::secure_join`.
+#[uniffi::export]
+pub fn secure_join(rootfs: String, unsafe_path: String) -> String {
+    secure_path::secure_join::secure_join(&rootfs, &unsafe_path)
+}
+
+// A handle to a rootfs directory, see `secure_path::root::Root`.
+#[derive(uniffi::Object)]
+pub struct Root(secure_path::root::Root);
+
+#[uniffi::export]
+impl Root {
+    #[uniffi::constructor]
+    pub fn new(rootfs: String) -> Result<Arc<Self>, SecurePathError> {
+        Ok(Arc::new(Root(secure_path::root::Root::new(rootfs)?)))
+    }
+
+    pub fn path(&self) -> Result<String, SecurePathError> {
+        string(self.0.path())
+    }
+
+    pub fn join(&self, unsafe_path: String) -> Result<String, SecurePathError> {
+        string(&self.0.join(unsafe_path)?)
+    }
+
+    pub fn check(&self, unsafe_path: String) -> Result<String, SecurePathError> {
+        string(&self.0.check(unsafe_path)?)
+    }
+
+    pub fn mkdir_all(&self, unsafe_path: String) -> Result<String, SecurePathError> {
+        string(&self.0.mkdir_all(unsafe_path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_root() {
+        let rootfs_dir = tempdir().expect("failed to create tmpdir");
+        let root = Root::new(rootfs_dir.path().to_str().unwrap().to_string()).unwrap();
+        let rootfs = root.path().unwrap();
+
+        assert_eq!(
+            root.join("../../a/b".to_string()).unwrap(),
+            format!("{}/a/b", rootfs)
+        );
+        assert!(matches!(
+            root.check("../../a/b".to_string()),
+            Err(SecurePathError::Escape { .. })
+        ));
+        assert!(matches!(
+            Root::new("/nonexistent/rootfs".to_string()),
+            Err(SecurePathError::Io { .. })
+        ));
+    }
+}